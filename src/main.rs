@@ -1,21 +1,61 @@
+mod topology;
+
 use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::io::{self, Write};
-use std::path::PathBuf;
-use std::process::{Command, exit};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, exit};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use directories::{ProjectDirs, UserDirs};
+use directories::{BaseDirs, ProjectDirs, UserDirs};
 #[cfg(target_os = "linux")]
 use std::os::unix::fs::PermissionsExt;
 
 const PROFILE_FILE_NAME: &str = "profiles.json";
+const PROFILES_DIR_NAME: &str = "profiles.d";
 const TEMP_PROFILE_PREFIX: &str = "__temp_";
+/// How long profiles saved with `run --save-temp` last unless `--days` says otherwise
+const TEMP_PROFILE_DAYS: u64 = 7;
+/// The longest `--days` a temporary profile can be saved for, about ten years
+const MAX_TEMP_PROFILE_DAYS: u64 = 3650;
+/// How long the temporary profile for an elevated relaunch is kept if the
+/// elevated instance never gets to use it
+#[cfg(target_os = "windows")]
+const ELEVATION_PROFILE_LIFETIME: Duration = Duration::from_secs(10 * 60);
+const UNDO_FILE_NAME: &str = "undo.json";
+const RULES_FILE_NAME: &str = "rules.json";
+const DAEMON_STATE_FILE_NAME: &str = "daemon_state.json";
+const SESSION_FILE_NAME: &str = "session.json";
+/// How long `stop-all` waits for programs to close by themselves
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long programs in `kill_before` get to close before they are killed
+const KILL_BEFORE_TIMEOUT: Duration = Duration::from_secs(5);
+const SETTINGS_FILE_NAME: &str = "settings.json";
+const SECRET_SERVICE: &str = "affinity-rs";
+const TRASH_FILE_NAME: &str = "trash.json";
+/// How long deleted profiles can be restored with `undelete`
+const TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const HISTORY_FILE_NAME: &str = "history.jsonl";
 const ELEVATION_CLEANUP_FLAG: &str = "--cleanup-temp";
+#[cfg(target_os = "windows")]
+const PERFORMANCE_POWER_PLAN: &str = "SCHEME_MIN";
+#[cfg(target_os = "linux")]
+const PERFORMANCE_POWER_PLAN: &str = "performance";
+const SHORTCUT_SCRIPT_DIR: &str = "shortcuts";
+#[cfg(target_os = "windows")]
+const TASK_FOLDER: &str = "affinity-rs";
+/// Makes a scheduled task's launch pick up the arguments its shortcut saved.
+#[cfg(target_os = "windows")]
+const TASK_ARGS_FLAG: &str = "--task-args";
+/// Upper bound on CPU indices in a CPU list, well above any real machine.
+const MAX_CPU_INDEX: usize = 4096;
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 enum ProcessPriority {
     Idle,
@@ -27,6 +67,15 @@ enum ProcessPriority {
 }
 
 impl ProcessPriority {
+    const ALL: [ProcessPriority; 6] = [
+        Self::Idle,
+        Self::BelowNormal,
+        Self::Normal,
+        Self::AboveNormal,
+        Self::High,
+        Self::Realtime,
+    ];
+
     fn display_name(&self) -> &str {
         match self {
             Self::Idle => "Idle",
@@ -51,868 +100,12859 @@ impl ProcessPriority {
         }
     }
 
+    /// D3DKMT_SCHEDULINGPRIORITYCLASS value for GPU scheduling priority.
+    #[cfg(target_os = "windows")]
+    fn to_gpu_scheduling_class(&self) -> i32 {
+        match self {
+            Self::Idle => 0,
+            Self::BelowNormal => 1,
+            Self::Normal => 2,
+            Self::AboveNormal => 3,
+            Self::High => 4,
+            Self::Realtime => 5,
+        }
+    }
+
     #[cfg(target_os = "windows")]
     fn requires_elevation(&self) -> bool {
         matches!(self, Self::High | Self::Realtime)
     }
 
+    #[cfg(target_os = "windows")]
+    fn from_windows_class(class: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|priority| priority.to_windows_class() == class)
+    }
+
+    /// The nice value for this level, from the `nice_values` setting if it
+    /// has one.
     #[cfg(target_os = "linux")]
-    fn to_nice_value(&self) -> &str {
+    fn to_nice_value(&self) -> i32 {
+        if let Some(&nice) = settings().nice_values.get(self) {
+            return nice.clamp(-20, 19);
+        }
+
         match self {
-            Self::Idle => "19",
-            Self::BelowNormal => "10",
-            Self::Normal => "0",
-            Self::AboveNormal => "-5",
-            Self::High => "-10",
-            Self::Realtime => "-20",
+            Self::Idle => 19,
+            Self::BelowNormal => 10,
+            Self::Normal => 0,
+            Self::AboveNormal => -5,
+            Self::High => -10,
+            Self::Realtime => -20,
+        }
+    }
+
+    /// The priority level whose nice value is closest to `nice`.
+    #[cfg(target_os = "linux")]
+    fn from_nice_value(nice: i32) -> Self {
+        Self::ALL
+            .into_iter()
+            .min_by_key(|priority| (priority.to_nice_value() - nice).abs())
+            .unwrap_or(Self::Normal)
+    }
+
+    /// Parses a menu number (1-6) or a level name such as "above_normal".
+    fn parse(input: &str) -> Option<Self> {
+        let normalized = input.trim().to_lowercase().replace([' ', '-'], "_");
+
+        if let Ok(n) = normalized.parse::<usize>() {
+            return n.checked_sub(1).and_then(|i| Self::ALL.get(i)).cloned();
         }
+
+        Self::ALL
+            .into_iter()
+            .find(|priority| priority.display_name().to_lowercase().replace(' ', "_") == normalized)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 struct Profile {
+    // Either may come from the profile named in `extends`
+    #[serde(default)]
     path: PathBuf,
+    #[serde(default)]
     cpus: Vec<usize>,
     #[serde(default)]
     priority: Option<ProcessPriority>,
     #[serde(default)]
     retry_attempts: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    only: Option<ApplyOnly>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shortcut_dirs: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    elevated_task: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shortcut_actions: Vec<ShortcutAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<LaunchMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preset: Option<Preset>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timer_resolution_ms: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disable_priority_boost: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    power_plan: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gpu_priority: Option<ProcessPriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    game_mode: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    creation_flags: Vec<CreationFlag>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    compat_flags: Vec<CompatFlag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    job_priority_limit: Option<ProcessPriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slice: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nice: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    autogroup_nice: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    irq_check: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timer_slack_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rlimits: Option<Rlimits>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dscp: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inherit_affinity: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    follow_children: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    follow_window_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reapply_interval: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    focus_affinity: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    realtime_watchdog: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    window: Option<WindowPlacement>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    kill_on_exit: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    background: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    calm_background: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    kill_before: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    restart_killed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    log_level: Option<LogLevel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    log_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    prompt_args: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    last_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    locked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extends: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shortcut_pause: Option<PausePolicy>,
+    /// Unix time after which a temporary profile is removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
 }
 
-type Profiles = HashMap<String, Profile>;
+impl Profile {
+    /// Profile default arguments followed by any arguments passed at launch.
+    fn launch_args(&self, extra: &[OsString]) -> Vec<OsString> {
+        self.args
+            .iter()
+            .map(OsString::from)
+            .chain(extra.iter().cloned())
+            .collect()
+    }
 
-fn get_profile_path() -> Result<PathBuf> {
-    let proj_dirs = ProjectDirs::from("rs", "affinity", "AffinityRs")
-        .context("Could not find a valid home directory to store profiles")?;
+    /// Whether the program should be killed if affinity-rs goes away first,
+    /// which only makes sense while attached.
+    fn kills_on_exit(&self) -> bool {
+        self.kill_on_exit && self.mode.unwrap_or_default() == LaunchMode::Attach
+    }
 
-    let config_dir = proj_dirs.config_dir();
-    std::fs::create_dir_all(config_dir).context("Failed to create config directory")?;
+    /// The nice value to run with: the raw `nice` if set, otherwise the
+    /// priority's.
+    #[cfg(target_os = "linux")]
+    fn nice_value(&self) -> Option<i32> {
+        self.nice
+            .or_else(|| self.priority.as_ref().map(ProcessPriority::to_nice_value))
+    }
 
-    let mut config_file_path = config_dir.to_path_buf();
-    config_file_path.push(PROFILE_FILE_NAME);
-    Ok(config_file_path)
+    /// Whether this is a temporary profile, removed once it expires or by `clean`.
+    fn is_temporary(&self) -> bool {
+        self.expires.is_some()
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Whether the daemon acts on this profile; `enabled: false` turns it off
+    /// without losing its settings.
+    fn is_enabled(&self) -> bool {
+        self.enabled != Some(false)
+    }
+
+    /// The profile with its preset's values filled in for unset options.
+    /// With `only`, the other setting is cleared: no cores means the
+    /// affinity is left alone, no priority the priority.
+    fn resolved(&self) -> Profile {
+        let mut profile = self.clone();
+        if let Some(preset) = self.preset {
+            preset.apply_to(&mut profile);
+        }
+
+        match profile.only {
+            Some(ApplyOnly::Affinity) => {
+                profile.priority = None;
+                profile.nice = None;
+            }
+            Some(ApplyOnly::Priority) => profile.cpus.clear(),
+            None => {}
+        }
+        profile
+    }
 }
 
-fn load_profiles() -> Result<Profiles> {
-    let profile_path = get_profile_path()?;
+/// Fields describing a profile's own state rather than its tuning, which are
+/// never taken over through `extends`.
+const NOT_INHERITED: [&str; 9] = [
+    "extends",
+    "locked",
+    "enabled",
+    "checksum",
+    "last_args",
+    "shortcut_dirs",
+    "elevated_task",
+    "shortcut_pause",
+    "expires",
+];
+
+/// The profile with everything it leaves unset taken from the profile it
+/// `extends`, and so on up the chain. Environment variables are merged.
+fn inherited_profile(profiles: &Profiles, keyword: &str) -> Result<Profile> {
+    let mut profile = profiles
+        .get(keyword)
+        .context(format!("Profile '{}' not found", keyword))?;
+    let mut chain = vec![keyword];
+
+    while let Some(ref parent) = profile.extends {
+        if chain.contains(&parent.as_str()) {
+            bail!(
+                "Profile '{}' extends itself: {} -> {}",
+                keyword,
+                chain.join(" -> "),
+                parent
+            );
+        }
+        profile = profiles.get(parent).context(format!(
+            "Profile '{}' extends unknown profile '{}'",
+            chain[chain.len() - 1],
+            parent
+        ))?;
+        chain.push(parent);
+    }
 
-    if !profile_path.exists() {
-        return Ok(Profiles::new());
+    if chain.len() == 1 {
+        return Ok(profile.clone());
     }
 
-    let data = std::fs::read_to_string(&profile_path).context("Failed to read profiles file")?;
+    // Unset options are left out or empty when serialized, so overlaying the
+    // JSON of each profile from the base down keeps only what it sets
+    let mut merged = serde_json::Map::new();
+    for name in chain.iter().rev() {
+        let serde_json::Value::Object(fields) = serde_json::to_value(&profiles[*name])? else {
+            continue;
+        };
+
+        for (key, value) in fields {
+            let unset = match value {
+                serde_json::Value::Null => true,
+                serde_json::Value::Array(ref items) => items.is_empty(),
+                serde_json::Value::String(ref text) => text.is_empty(),
+                _ => false,
+            };
+            if unset || (*name != keyword && NOT_INHERITED.contains(&key.as_str())) {
+                continue;
+            }
 
-    serde_json::from_str(&data).context("Failed to parse profiles JSON")
+            match (merged.get_mut(&key), value) {
+                (Some(serde_json::Value::Object(inherited)), serde_json::Value::Object(own))
+                    if key == "env" =>
+                {
+                    inherited.extend(own)
+                }
+                (_, value) => {
+                    merged.insert(key, value);
+                }
+            }
+        }
+    }
+
+    serde_json::from_value(serde_json::Value::Object(merged))
+        .with_context(|| format!("Failed to combine profile '{}' with its base", keyword))
 }
 
-fn save_profiles(profiles: &Profiles) -> Result<()> {
-    let profile_path = get_profile_path()?;
-    let data = serde_json::to_string_pretty(profiles).context("Failed to serialize profiles")?;
-    std::fs::write(profile_path, data).context("Failed to write profiles to disk")?;
-    Ok(())
+/// Flags given before the profile name that change how a launch is done.
+#[derive(Debug, Default)]
+struct LaunchOptions {
+    /// Launch without any affinity/priority manipulation
+    plain: bool,
+    /// Record the executable's current checksum as the accepted one
+    rehash: bool,
+    /// File to write the launched PID to
+    pid_file: Option<PathBuf>,
+    /// Print a JSON summary of the launched process on stdout
+    json: bool,
+    /// Allow changes to locked profiles
+    unlock: bool,
+    /// Overrides the `pause` setting for this run
+    pause: Option<PausePolicy>,
+    /// Overrides the profile's `only` for this run
+    only: Option<ApplyOnly>,
+    /// Report how long each launch step took
+    timings: bool,
+    /// Reuse the arguments of the previous launch when none are given
+    same_args: bool,
+    /// Show what the launch would do without starting anything
+    dry_run: bool,
 }
 
-fn pause_before_exit() {
-    print!("\nPress Enter to exit...");
-    let _ = io::stdout().flush();
-    let mut dummy = String::new();
-    let _ = io::stdin().read_line(&mut dummy);
+impl LaunchOptions {
+    /// The flags to pass on when affinity-rs relaunches itself.
+    #[cfg(target_os = "windows")]
+    fn to_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(path) = PROFILE_FILE.get() {
+            args.push("--profile-file".into());
+            args.push(path.clone().into_os_string());
+        }
+        if self.plain {
+            args.push("--plain".into());
+        }
+        if self.json {
+            args.push("--json".into());
+        }
+        if let Some(ref pid_file) = self.pid_file {
+            args.push("--pid-file".into());
+            args.push(pid_file.clone().into_os_string());
+        }
+        if let Some(pause) = self.pause {
+            args.push("--pause".into());
+            args.push(pause.to_arg().into());
+        }
+        if let Some(only) = self.only {
+            args.push("--only".into());
+            args.push(only.to_arg().into());
+        }
+        if self.timings {
+            args.push("--timings".into());
+        }
+        if ASSUME_YES.load(std::sync::atomic::Ordering::Relaxed) {
+            args.push("--yes".into());
+        } else if non_interactive() {
+            args.push("--non-interactive".into());
+        }
+        args
+    }
 }
 
-fn read_line(prompt: &str) -> Result<String> {
-    print!("{}", prompt);
-    io::stdout().flush().context("Failed to flush stdout")?;
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .context("Failed to read input")?;
-    Ok(input.trim().to_string())
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Preset {
+    CompetitiveFps,
+    SinglePlayer,
+    Streaming,
+    Background,
 }
 
-fn get_cpu_input() -> Result<Vec<usize>> {
-    loop {
-        let input = read_line("Enter CPU cores (comma-separated, e.g., 0,1,2,3): ")?;
-        let trimmed = input.trim();
+impl Preset {
+    const ALL: [Preset; 4] = [
+        Self::CompetitiveFps,
+        Self::SinglePlayer,
+        Self::Streaming,
+        Self::Background,
+    ];
 
-        if trimmed.is_empty() {
-            eprintln!("Error: CPU cores cannot be empty.");
-            continue;
+    fn display_name(&self) -> &str {
+        match self {
+            Self::CompetitiveFps => "Competitive FPS",
+            Self::SinglePlayer => "Single Player",
+            Self::Streaming => "Streaming",
+            Self::Background => "Background Task",
         }
+    }
 
-        let is_valid = trimmed
-            .chars()
-            .all(|c| c.is_ascii_digit() || c == ',' || c.is_whitespace());
+    fn description(&self) -> &str {
+        match self {
+            Self::CompetitiveFps => {
+                "one cache domain, High priority, 1 ms timer, no priority boost, performance power plan, Game Mode"
+            }
+            Self::SinglePlayer => {
+                "all cores, Above Normal priority, performance power plan, Game Mode"
+            }
+            Self::Streaming => "one cache domain at Above Normal, other cores left for the encoder",
+            Self::Background => {
+                "efficiency cores, Idle priority, idle I/O, EcoQoS and low memory priority"
+            }
+        }
+    }
 
-        if !is_valid {
-            eprintln!("Error: only numbers, commas, and spaces allowed.");
-            continue;
+    /// Fills every option the profile leaves unset with this preset's value.
+    fn apply_to(&self, profile: &mut Profile) {
+        if profile.cpus.is_empty() {
+            profile.cpus = match self {
+                Self::CompetitiveFps | Self::Streaming => {
+                    topology::current().primary_cache_domain()
+                }
+                Self::SinglePlayer => topology::current().logical_cpus(),
+                Self::Background => topology::current().efficiency_cores(),
+            };
         }
 
-        let cpus: Vec<usize> = trimmed
-            .split(',')
-            .filter_map(|s| s.trim().parse().ok())
-            .collect();
+        profile.priority.get_or_insert(match self {
+            Self::CompetitiveFps => ProcessPriority::High,
+            Self::SinglePlayer | Self::Streaming => ProcessPriority::AboveNormal,
+            Self::Background => ProcessPriority::Idle,
+        });
 
-        if cpus.is_empty() {
-            eprintln!("Error: no valid cores provided.");
-            continue;
+        if *self == Self::Background {
+            profile.background.get_or_insert(true);
         }
 
-        return Ok(cpus);
+        // Power plan and timer changes are reverted on exit, which needs attached mode
+        if matches!(self, Self::CompetitiveFps | Self::SinglePlayer) {
+            profile.mode.get_or_insert(LaunchMode::Attach);
+            profile.game_mode.get_or_insert(true);
+            profile
+                .power_plan
+                .get_or_insert_with(|| PERFORMANCE_POWER_PLAN.to_string());
+        }
+
+        if *self == Self::CompetitiveFps {
+            profile.timer_resolution_ms.get_or_insert(1);
+            profile.disable_priority_boost.get_or_insert(true);
+            profile.irq_check.get_or_insert(true);
+        }
     }
 }
 
-fn get_priority_input() -> Result<Option<ProcessPriority>> {
-    println!("\nProcess Priority options:");
-    println!("  1. Idle");
-    println!("  2. Below Normal");
-    println!("  3. Normal [default]");
-    println!("  4. Above Normal");
+/// Starting points for `add --template`. Unlike presets they are copied into
+/// the new profile once, so the result can be tweaked freely afterwards.
+#[derive(Debug, Clone, Copy)]
+enum Template {
+    Gaming,
+    Background,
+    Server,
+    Benchmark,
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        println!("  5. High [requires admin]");
-        println!("  6. Realtime [requires admin - WARNING: Can freeze your system!]");
-    }
+impl Template {
+    const ALL: [Template; 4] = [
+        Self::Gaming,
+        Self::Background,
+        Self::Server,
+        Self::Benchmark,
+    ];
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        println!("  5. High [may require sudo]");
-        println!("  6. Realtime [may require sudo - WARNING: Can freeze your system!]");
+    fn name(&self) -> &str {
+        match self {
+            Self::Gaming => "gaming",
+            Self::Background => "background",
+            Self::Server => "server",
+            Self::Benchmark => "benchmark",
+        }
     }
 
-    let input = read_line("Enter priority (1-6, or press Enter for Normal): ")?;
-    let trimmed = input.trim();
-
-    if trimmed.is_empty() {
-        return Ok(None);
+    fn description(&self) -> &str {
+        match self {
+            Self::Gaming => {
+                "one thread per core of the main cache domain, Above Normal priority, extra retries for launchers"
+            }
+            Self::Background => "efficiency cores, Idle priority, idle I/O",
+            Self::Server => {
+                "all cores except CPU 0, Above Normal priority, attached for exit codes"
+            }
+            Self::Benchmark => {
+                "one thread per physical core, High priority, attached for repeatable runs"
+            }
+        }
     }
 
-    let priority = match trimmed {
-        "1" => ProcessPriority::Idle,
-        "2" => ProcessPriority::BelowNormal,
-        "3" => ProcessPriority::Normal,
-        "4" => ProcessPriority::AboveNormal,
-        "5" => ProcessPriority::High,
-        "6" => {
-            println!("\nWARNING: Realtime priority can make your system unresponsive!");
-            println!("Only use this if you understand the risks.");
-            ProcessPriority::Realtime
+    /// Parses a menu number (1-4) or a template name.
+    fn parse(input: &str) -> Option<Self> {
+        let input = input.trim().to_lowercase();
+        match input.parse::<usize>() {
+            Ok(n) => n.checked_sub(1).and_then(|i| Self::ALL.get(i)).copied(),
+            Err(_) => Self::ALL.into_iter().find(|t| t.name() == input),
         }
-        _ => {
-            eprintln!("Invalid selection, using Normal priority");
-            ProcessPriority::Normal
+    }
+
+    /// A new profile for `path` filled in from this template.
+    fn profile(&self, path: PathBuf) -> Profile {
+        // SMT siblings share a core's execution units, so take one of each
+        let first_threads = |cores: Vec<Vec<usize>>| -> Vec<usize> {
+            cores
+                .into_iter()
+                .filter_map(|core| core.first().copied())
+                .collect()
+        };
+
+        let cpus = match self {
+            Self::Gaming => {
+                let domain = topology::current().primary_cache_domain();
+                first_threads(topology::current().physical_cores())
+                    .into_iter()
+                    .filter(|cpu| domain.contains(cpu))
+                    .collect()
+            }
+            Self::Background => topology::current().efficiency_cores(),
+            // CPU 0 handles most interrupts and housekeeping
+            Self::Server => {
+                let mut cpus = topology::current().logical_cpus();
+                if cpus.len() > 2 {
+                    cpus.retain(|&cpu| cpu != 0);
+                }
+                cpus
+            }
+            Self::Benchmark => first_threads(topology::current().physical_cores()),
+        };
+
+        let mut profile = Profile {
+            path,
+            cpus,
+            ..Default::default()
+        };
+
+        match self {
+            Self::Gaming => {
+                profile.priority = Some(ProcessPriority::AboveNormal);
+                profile.retry_attempts = Some(10);
+            }
+            Self::Background => {
+                profile.priority = Some(ProcessPriority::Idle);
+                profile.retry_attempts = Some(3);
+                profile.background = Some(true);
+            }
+            Self::Server => {
+                profile.priority = Some(ProcessPriority::AboveNormal);
+                profile.mode = Some(LaunchMode::Attach);
+            }
+            Self::Benchmark => {
+                profile.priority = Some(ProcessPriority::High);
+                profile.mode = Some(LaunchMode::Attach);
+            }
         }
-    };
 
-    Ok(Some(priority))
+        profile
+    }
 }
 
-#[cfg(target_os = "windows")]
-fn is_elevated() -> bool {
-    use windows_sys::Win32::Foundation::CloseHandle;
-    use windows_sys::Win32::Security::{
-        GetTokenInformation, TOKEN_ELEVATION, TOKEN_QUERY, TokenElevation,
+/// Parses a CPU list such as "0,2,4-7" into sorted, de-duplicated indices.
+fn parse_cpu_list(input: &str) -> Result<Vec<usize>> {
+    let parse_index = |s: &str| {
+        let index = s
+            .trim()
+            .parse::<usize>()
+            .with_context(|| format!("Invalid CPU index '{}'", s.trim()))?;
+        if index >= MAX_CPU_INDEX {
+            bail!("CPU index {} is out of range", index);
+        }
+        Ok(index)
     };
-    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
 
-    unsafe {
-        let mut token = std::ptr::null_mut();
-        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
-            return false;
+    let mut cpus = Vec::new();
+    for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse_index(start)?, parse_index(end)?);
+                if start > end {
+                    bail!("Invalid CPU range '{}'", part);
+                }
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(parse_index(part)?),
         }
+    }
 
-        let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
-        let mut return_length: u32 = 0;
-
-        let result = GetTokenInformation(
-            token,
-            TokenElevation,
-            &mut elevation as *mut _ as *mut _,
-            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
-            &mut return_length,
-        );
+    if cpus.is_empty() {
+        bail!("No CPUs specified");
+    }
 
-        CloseHandle(token);
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
 
-        result != 0 && elevation.TokenIsElevated != 0
+/// Formats CPU indices compactly, e.g. "0-3,8", the inverse of `parse_cpu_list`.
+fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cpu => *end = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
     }
-}
 
-#[cfg(target_os = "windows")]
-fn relaunch_elevated(profile_name: &str, args: &[String]) -> Result<()> {
-    use windows_sys::Win32::Foundation::HWND;
-    use windows_sys::Win32::UI::Shell::ShellExecuteW;
-    use windows_sys::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+    ranges
+        .iter()
+        .map(|&(start, end)| match end - start {
+            0 => start.to_string(),
+            1 => format!("{},{}", start, end),
+            _ => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
-    let current_exe_str = current_exe
-        .to_str()
-        .context("Executable path contains invalid UTF-8")?;
+/// Prints the sockets, NUMA nodes, cores and caches of this machine, to help
+/// choose the CPUs of a profile.
+fn show_topology(json: bool) {
+    let topology = topology::current();
+    let efficiency = topology.efficiency_cores();
+    let hybrid = efficiency.len() < topology.cpus.len();
+    let core_type = |core: &[usize]| match (hybrid, core.iter().any(|cpu| efficiency.contains(cpu)))
+    {
+        (false, _) => None,
+        (true, true) => Some("efficiency"),
+        (true, false) => Some("performance"),
+    };
+    let packages = topology.packages();
+    let cores = topology.physical_cores();
+    let caches = topology.caches();
+
+    if json {
+        let package_of = |core: &[usize]| {
+            topology
+                .cpus
+                .iter()
+                .find(|cpu| cpu.id == core[0])
+                .map_or(0, |cpu| cpu.package)
+        };
+        let summary = serde_json::json!({
+            "logical_cpus": topology.logical_cpus(),
+            "offline": topology.offline,
+            "sockets": packages
+                .iter()
+                .map(|(id, cpus)| serde_json::json!({ "id": id, "cpus": cpus }))
+                .collect::<Vec<_>>(),
+            "numa_nodes": topology
+                .nodes
+                .iter()
+                .map(|(id, cpus)| serde_json::json!({ "id": id, "cpus": cpus }))
+                .collect::<Vec<_>>(),
+            "cores": cores
+                .iter()
+                .map(|core| serde_json::json!({
+                    "cpus": core,
+                    "socket": package_of(core),
+                    "type": core_type(core),
+                }))
+                .collect::<Vec<_>>(),
+            "caches": caches
+                .iter()
+                .map(|cache| serde_json::json!({
+                    "level": cache.level,
+                    "type": cache.kind,
+                    "cpus": cache.cpus,
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", summary);
+        return;
+    }
 
-    println!("\nAdministrator privileges required for this priority level.");
-    println!("Requesting elevation...\n");
+    println!(
+        "{} logical CPUs, {} physical cores, {} socket(s), {} NUMA node(s)",
+        topology.cpus.len(),
+        cores.len(),
+        packages.len(),
+        topology.nodes.len().max(1)
+    );
+    if !topology.offline.is_empty() {
+        println!("Offline CPUs: {}", format_cpu_list(&topology.offline));
+    }
 
-    // Build parameters: profile_name + cleanup flag + any additional args
-    let mut params = vec![profile_name.to_string(), ELEVATION_CLEANUP_FLAG.to_string()];
-    params.extend_from_slice(args);
-    let params_str = params.join(" ");
+    println!("\nSockets:");
+    for (id, cpus) in &packages {
+        println!("  {:<4} CPUs {}", id, format_cpu_list(cpus));
+    }
 
-    unsafe {
-        // Convert strings to wide strings for Windows API
-        let operation: Vec<u16> = "runas\0".encode_utf16().collect();
-        let file: Vec<u16> = current_exe_str.encode_utf16().chain(Some(0)).collect();
-        let parameters: Vec<u16> = params_str.encode_utf16().chain(Some(0)).collect();
+    if !topology.nodes.is_empty() {
+        println!("\nNUMA nodes:");
+        for (id, cpus) in &topology.nodes {
+            match cpus.is_empty() {
+                true => println!("  {:<4} memory only", id),
+                false => println!("  {:<4} CPUs {}", id, format_cpu_list(cpus)),
+            }
+        }
+    }
 
-        let result = ShellExecuteW(
-            0 as HWND,
-            operation.as_ptr(),
-            file.as_ptr(),
-            parameters.as_ptr(),
-            std::ptr::null(),
-            SW_SHOWNORMAL as i32,
+    println!("\nPhysical cores (SMT siblings together):");
+    for (index, core) in cores.iter().enumerate() {
+        let kind = core_type(core).map(|kind| format!("  {}", kind));
+        println!(
+            "  {:<4} CPUs {}{}",
+            index,
+            format_cpu_list(core),
+            kind.unwrap_or_default()
         );
+    }
+    if !hybrid {
+        println!("  All cores are of the same type.");
+    }
 
-        // ShellExecuteW returns > 32 on success
-        let result_code = result as isize;
-        if result_code > 32 {
-            println!("Elevated process launched successfully.");
-            println!("This window will now close.\n");
-            thread::sleep(Duration::from_millis(500));
-            Ok(())
-        } else {
-            // Handle specific error codes
-            match result_code {
-                0 => bail!("Out of memory or resources"),
-                2 => bail!("File not found"),
-                3 => bail!("Path not found"),
-                5 => bail!("Access denied - User may have cancelled UAC prompt"),
-                8 => bail!("Out of memory"),
-                31 => bail!("No application associated with this file type"),
-                _ => bail!("ShellExecuteW failed with error code: {}", result_code),
-            }
+    if !caches.is_empty() {
+        println!("\nCaches (CPUs sharing each):");
+        let mut kinds: Vec<(u32, &str)> = caches
+            .iter()
+            .map(|cache| (cache.level, cache.kind.as_str()))
+            .collect();
+        kinds.dedup();
+        for (level, kind) in kinds {
+            let groups: Vec<String> = caches
+                .iter()
+                .filter(|cache| cache.level == level && cache.kind == kind)
+                .map(|cache| format_cpu_list(&cache.cpus))
+                .collect();
+            println!(
+                "  {:<15} {}",
+                format!("L{} {}", level, kind),
+                groups.join(" | ")
+            );
         }
     }
 }
 
-fn validate_profile(profile: &Profile) -> Result<()> {
-    if !profile.path.exists() {
-        bail!(
-            "Executable not found: {}\nThe file may have been moved or deleted.",
-            profile.path.display()
-        );
+/// Splits the CPUs into two non-overlapping sets: by cache domain when there
+/// are several (the first for `a`), otherwise by physical core.
+fn propose_partition() -> Result<(Vec<usize>, Vec<usize>)> {
+    let domains = topology::current().cache_domains();
+
+    if domains.len() >= 2 {
+        let a = domains[0].clone();
+        let mut b: Vec<usize> = domains[1..].concat();
+        b.sort();
+        return Ok((a, b));
     }
 
-    if profile.cpus.is_empty() {
-        bail!("Profile has no CPU cores configured");
+    let cores = topology::current().physical_cores();
+    if cores.len() < 2 {
+        bail!("At least two physical cores are needed to partition");
     }
 
-    // Check if CPU indices are reasonable
-    let max_cpu = profile.cpus.iter().max().unwrap();
-    let system_cpu_count = num_cpus::get();
+    // The first workload, typically the game, gets the extra core on odd counts
+    let split = cores.len().div_ceil(2);
+    Ok((cores[..split].concat(), cores[split..].concat()))
+}
 
-    if *max_cpu >= system_cpu_count {
-        eprintln!(
-            "Warning: Profile references CPU {}, but system only has {} logical CPUs",
-            max_cpu, system_cpu_count
-        );
-        eprintln!("Some CPU assignments may be ignored by the OS.");
+fn partition_profiles(profiles: &mut Profiles, a: &str, b: &str, unlock: bool) -> Result<()> {
+    if a == b {
+        bail!("Choose two different profiles");
+    }
+    for keyword in [a, b] {
+        let profile = profiles
+            .get(keyword)
+            .context(format!("Profile '{}' not found", keyword))?;
+        ensure_unlocked(profile, keyword, unlock)?;
+    }
+
+    let (cpus_a, cpus_b) = propose_partition()?;
+
+    println!("Proposed split:");
+    println!("  {:<20} CPUs {:?}", a, cpus_a);
+    println!("  {:<20} CPUs {:?}", b, cpus_b);
+
+    let answer = read_line_or("Apply this split? [Y/n]: ", "y")?;
+    if answer.eq_ignore_ascii_case("n") {
+        println!("No changes made.");
+        return Ok(());
     }
 
+    profiles.get_mut(a).unwrap().cpus = cpus_a;
+    profiles.get_mut(b).unwrap().cpus = cpus_b;
+    save_profiles(profiles)?;
+
+    println!("Profiles '{}' and '{}' updated.", a, b);
     Ok(())
 }
 
-fn launch_with_retry<F>(attempts: usize, initial_delay_ms: u64, mut operation: F) -> Result<bool>
-where
-    F: FnMut(usize) -> Result<bool>,
-{
-    for attempt in 1..=attempts {
-        let delay = if attempt == 1 {
-            initial_delay_ms
-        } else {
-            // Exponential backoff with cap at 1000ms
-            (initial_delay_ms * 2_u64.pow((attempt - 1) as u32)).min(1000)
-        };
+/// The CPU the calling thread is running on right now.
+fn current_cpu() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let cpu = unsafe { libc::sched_getcpu() };
+        (cpu >= 0).then_some(cpu as usize)
+    }
 
-        thread::sleep(Duration::from_millis(delay));
+    #[cfg(target_os = "windows")]
+    {
+        Some(unsafe { windows_sys::Win32::System::Threading::GetCurrentProcessorNumber() } as usize)
+    }
+}
 
-        match operation(attempt) {
-            Ok(true) => return Ok(true), // Success
-            Ok(false) => continue,       // Retry
-            Err(e) => {
-                if attempt == attempts {
-                    return Err(e);
+/// Pins affinity-rs itself to `cpus`, keeps worker threads busy on them and
+/// checks where the OS actually ran them. Returns whether the OS complied.
+fn run_selftest(cpus: &[usize], duration: Duration) -> Result<bool> {
+    let pid = std::process::id();
+    set_process_affinity(pid, cpus).context("Could not restrict affinity-rs itself")?;
+
+    // Twice as many workers as CPUs, so the scheduler is tempted to spill over
+    let workers = cpus.len() * 2;
+    println!(
+        "Running {} busy threads on CPUs {:?} for {}s...",
+        workers,
+        cpus,
+        duration.as_secs()
+    );
+
+    let deadline = Instant::now() + duration;
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut samples: HashMap<usize, u64> = HashMap::new();
+                while Instant::now() < deadline {
+                    if let Some(cpu) = current_cpu() {
+                        *samples.entry(cpu).or_default() += 1;
+                    }
+                    // A bit of work between samples so sampling isn't all we measure
+                    std::hint::black_box((0..1000u64).sum::<u64>());
                 }
-                eprintln!(
-                    "Attempt {}/{} failed: {}. Retrying...",
-                    attempt, attempts, e
-                );
-            }
+                samples
+            })
+        })
+        .collect();
+
+    let mut samples: HashMap<usize, u64> = HashMap::new();
+    for handle in handles {
+        let worker = handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Worker thread panicked"))?;
+        for (cpu, count) in worker {
+            *samples.entry(cpu).or_default() += count;
         }
     }
 
-    Ok(false)
-}
-
-#[cfg(target_os = "linux")]
-fn launch_profile_linux(profile: &Profile, args: &[String]) -> Result<()> {
-    let cpu_str = profile
-        .cpus
-        .iter()
-        .map(usize::to_string)
-        .collect::<Vec<_>>()
-        .join(",");
+    let total: u64 = samples.values().sum();
+    if total == 0 {
+        bail!("The current CPU could not be determined on this system");
+    }
 
-    let mut cmd = Command::new("taskset");
-    cmd.arg("-c").arg(&cpu_str);
+    let mut used: Vec<usize> = samples.keys().copied().collect();
+    used.sort();
 
-    // Wrap with nice if priority is specified
-    if let Some(ref priority) = profile.priority {
-        let nice_value = priority.to_nice_value();
-        let mut nice_cmd = Command::new("nice");
-        nice_cmd
-            .arg("-n")
-            .arg(nice_value)
-            .arg("taskset")
-            .arg("-c")
-            .arg(&cpu_str)
-            .arg(&profile.path)
-            .args(args);
-        cmd = nice_cmd;
-    } else {
-        cmd.arg(&profile.path).args(args);
+    println!("\n{:<6} {:>8}  Allowed", "CPU", "Time");
+    for &cpu in &used {
+        println!(
+            "{:<6} {:>7.1}%  {}",
+            cpu,
+            samples[&cpu] as f64 * 100.0 / total as f64,
+            if cpus.contains(&cpu) { "yes" } else { "NO" }
+        );
     }
 
-    let child = cmd
-        .spawn()
-        .context("Failed to spawn process. Is 'taskset' installed?")?;
+    let outside: Vec<usize> = used
+        .iter()
+        .copied()
+        .filter(|cpu| !cpus.contains(cpu))
+        .collect();
+    let unused: Vec<usize> = cpus
+        .iter()
+        .copied()
+        .filter(|cpu| !used.contains(cpu))
+        .collect();
 
-    println!("Process launched with PID: {}", child.id());
-    println!("Program is running independently.\n");
+    println!();
+    if !unused.is_empty() {
+        println!(
+            "Note: CPUs {:?} were allowed but not used (offline or busy with other work).",
+            unused
+        );
+    }
 
-    Ok(())
+    if outside.is_empty() {
+        println!("PASS: All work ran on the requested CPUs.");
+        Ok(true)
+    } else {
+        println!(
+            "FAIL: Work ran on CPUs {:?} outside the requested set.",
+            outside
+        );
+        Ok(false)
+    }
 }
 
-#[cfg(target_os = "windows")]
-fn launch_profile_windows(profile: &Profile, args: &[String]) -> Result<()> {
-    use windows_sys::Win32::Foundation::CloseHandle;
-    use windows_sys::Win32::System::Threading::{
-        GetPriorityClass, GetProcessAffinityMask, OpenProcess, PROCESS_QUERY_INFORMATION,
-        PROCESS_SET_INFORMATION, SetPriorityClass, SetProcessAffinityMask,
-    };
+fn current_power_plan() -> Result<String> {
+    #[cfg(target_os = "windows")]
+    {
+        let output = Command::new("powercfg")
+            .arg("/getactivescheme")
+            .output()
+            .context("Failed to run powercfg")?;
+
+        // "Power Scheme GUID: 381b4222-f694-41f0-9685-ff5bb260df2e  (Balanced)"
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .find(|word| word.len() == 36 && word.matches('-').count() == 4)
+            .map(str::to_string)
+            .context("Could not determine the active power plan")
+    }
 
-    // Calculate affinity mask
-    let mut affinity_mask: usize = 0;
-    for &cpu in &profile.cpus {
-        if cpu >= (std::mem::size_of::<usize>() * 8) {
-            eprintln!(
-                "Warning: CPU index {} is out of bounds for this system and will be ignored.",
-                cpu
-            );
-            continue;
+    #[cfg(target_os = "linux")]
+    {
+        let output = Command::new("powerprofilesctl")
+            .arg("get")
+            .output()
+            .context("Failed to run powerprofilesctl")?;
+
+        if !output.status.success() {
+            bail!("powerprofilesctl could not report the active profile");
         }
-        affinity_mask |= 1 << cpu;
-    }
 
-    if affinity_mask == 0 {
-        bail!("No valid CPUs specified after validation");
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
     }
+}
 
-    let child = Command::new(&profile.path)
-        .args(args)
-        .spawn()
-        .context("Failed to spawn process")?;
-
-    let pid = child.id();
-    println!("Process launched with PID: {}", pid);
+fn set_power_plan(plan: &str) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    let status = Command::new("powercfg")
+        .args(["/setactive", plan])
+        .status()
+        .context("Failed to run powercfg")?;
 
-    let retry_attempts = profile.retry_attempts.unwrap_or(5);
-    let mut affinity_set = false;
-    let mut priority_set = false;
+    #[cfg(target_os = "linux")]
+    let status = Command::new("powerprofilesctl")
+        .args(["set", plan])
+        .status()
+        .context("Failed to run powerprofilesctl")?;
 
-    // Try multiple times to handle launcher -> game transitions
-    let success = launch_with_retry(retry_attempts, 100, |attempt| {
-        unsafe {
-            let handle = OpenProcess(PROCESS_SET_INFORMATION | PROCESS_QUERY_INFORMATION, 0, pid);
+    if !status.success() {
+        bail!("Could not switch to power plan '{}'", plan);
+    }
 
-            if handle.is_null() {
-                if attempt == retry_attempts {
-                    eprintln!(
-                        "Warning: Could not open process handle after {} attempts",
-                        retry_attempts
-                    );
-                    eprintln!("The process may have exited or is protected.");
-                    return Ok(false);
-                }
-                return Ok(false); // Retry
-            }
+    Ok(())
+}
 
-            // Set CPU affinity
-            if !affinity_set {
-                let result = SetProcessAffinityMask(handle, affinity_mask);
-                if result == 0 {
-                    let err = std::io::Error::last_os_error();
-                    CloseHandle(handle);
-                    return Err(anyhow::anyhow!("Failed to set CPU affinity: {}", err));
-                }
+/// Matches a process name against a pattern with `*` wildcards, ignoring case
+/// and a trailing ".exe" so the same pattern works on every platform.
+fn process_name_matches(pattern: &str, name: &str) -> bool {
+    let normalize = |s: &str| {
+        let s = s.to_lowercase();
+        s.strip_suffix(".exe").map(str::to_string).unwrap_or(s)
+    };
+    let (pattern, name) = (normalize(pattern), normalize(name));
 
-                // Verify affinity
-                let mut process_affinity: usize = 0;
-                let mut system_affinity: usize = 0;
-                GetProcessAffinityMask(handle, &mut process_affinity, &mut system_affinity);
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let Some((first, rest)) = parts.split_first() else {
+        return false;
+    };
+    let Some(mut remaining) = name.strip_prefix(first) else {
+        return false;
+    };
+    let Some((last, middle)) = rest.split_last() else {
+        return remaining.is_empty();
+    };
 
-                if process_affinity == affinity_mask {
-                    println!("CPU affinity set and verified: 0x{:X}", process_affinity);
-                    affinity_set = true;
-                } else {
-                    println!(
-                        "Warning: Affinity mismatch - Requested: 0x{:X}, Actual: 0x{:X}",
-                        affinity_mask, process_affinity
-                    );
-                    affinity_set = true; // Don't retry if it was partially successful
-                }
-            }
+    for part in middle {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(last)
+}
 
-            // Set process priority if specified
-            if let Some(ref priority) = profile.priority {
-                if !priority_set {
-                    let priority_class = priority.to_windows_class();
-                    let priority_result = SetPriorityClass(handle, priority_class);
+/// A background app whose priority `calm_background` lowered.
+struct CalmedProcess {
+    pid: u32,
+    start_time: Option<u64>,
+    priority: ProcessPriority,
+}
 
-                    if priority_result == 0 {
-                        let err = std::io::Error::last_os_error();
-                        eprintln!("Failed to set process priority: {}", err);
+/// Lowers running background apps to Below Normal, skipping the profile's own
+/// program and apps that already run lower. Returns what to restore.
+fn calm_background_apps(profile: &Profile) -> Vec<CalmedProcess> {
+    let patterns = settings().background_apps();
+    let processes = match list_processes() {
+        Ok(processes) => processes,
+        Err(e) => {
+            eprintln!("Warning: Could not calm background apps: {:#}", e);
+            return Vec::new();
+        }
+    };
 
-                        if priority.requires_elevation() && !is_elevated() {
-                            eprintln!(
-                                "Note: {} priority requires administrator privileges.",
-                                priority.display_name()
-                            );
-                        }
-                    } else {
-                        // Verify priority after a short delay
-                        thread::sleep(Duration::from_millis(100));
-                        let actual_priority = GetPriorityClass(handle);
-
-                        if actual_priority == priority_class {
-                            println!("Process priority set to: {}", priority.display_name());
-                            priority_set = true;
-                        } else if actual_priority == 0 {
-                            eprintln!("Could not verify priority (GetPriorityClass failed)");
-                            priority_set = true; // Don't keep retrying
-                        } else {
-                            println!("Note: Process reset its priority to a different value.");
-                            println!(
-                                "This is normal for some applications (especially games with launchers)."
-                            );
-                            priority_set = true;
-                        }
-                    }
-                }
-            }
+    let calm = ProcessPriority::BelowNormal;
+    let rank = |priority: &ProcessPriority| ProcessPriority::ALL.iter().position(|p| p == priority);
+    let mut calmed = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    #[cfg(target_os = "linux")]
+    let mut skipped: Vec<String> = Vec::new();
+
+    for process in processes {
+        if process.pid == std::process::id()
+            || process.exe.as_deref() == Some(profile.path.as_path())
+            || !patterns
+                .iter()
+                .any(|pattern| process_name_matches(pattern, &process.name))
+        {
+            continue;
+        }
 
-            CloseHandle(handle);
+        let Ok(priority) = get_process_priority(process.pid) else {
+            continue;
+        };
+        if rank(&priority) <= rank(&calm) {
+            continue;
+        }
 
-            // Check if process still exists for next attempt
-            if attempt < retry_attempts && !affinity_set {
-                thread::sleep(Duration::from_millis(200));
-                let check_handle = OpenProcess(PROCESS_QUERY_INFORMATION, 0, pid);
-                if check_handle.is_null() {
-                    println!(
-                        "Note: Initial process (PID {}) has exited. This is likely a launcher.",
-                        pid
-                    );
-                    println!("The actual game may be running with a different PID.");
-                    println!(
-                        "Tip: Try launching the game's actual .exe directly for better results."
-                    );
-                    return Ok(false); // Stop retrying
-                }
-                CloseHandle(check_handle);
+        // A nice value can't be lowered again without privileges, so such
+        // apps are left alone rather than kept lowered after the program exits
+        #[cfg(target_os = "linux")]
+        if !can_lower_nice_to(priority.to_nice_value()) {
+            if !skipped.contains(&process.name) {
+                skipped.push(process.name.clone());
             }
+            continue;
+        }
 
-            // Success if affinity was set
-            Ok(affinity_set)
+        if set_process_priority(process.pid, &calm).is_err() {
+            continue;
         }
-    })?;
 
-    if !success {
-        eprintln!("\nWarning: Could not fully configure the process.");
-        eprintln!("The application may be using a launcher or may have restricted access.");
+        if !names.contains(&process.name) {
+            names.push(process.name.clone());
+        }
+        calmed.push(CalmedProcess {
+            pid: process.pid,
+            start_time: process_start_time(process.pid),
+            priority,
+        });
     }
 
-    println!("\nProgram is running independently.\n");
-    Ok(())
-}
-
-fn launch_profile(profile: &Profile, args: &[String]) -> Result<()> {
-    println!("\nLaunching: {}", profile.path.display());
-    println!("CPU affinity: {:?}", profile.cpus);
-
-    if let Some(ref priority) = profile.priority {
-        println!("Priority: {}", priority.display_name());
+    if calmed.is_empty() {
+        println!(
+            "Background apps: none running above {}",
+            calm.display_name()
+        );
+    } else {
+        names.sort();
+        println!(
+            "Background apps lowered to {}: {}",
+            calm.display_name(),
+            names.join(", ")
+        );
     }
 
-    if !args.is_empty() {
-        println!("Arguments: {:?}", args);
+    #[cfg(target_os = "linux")]
+    if !skipped.is_empty() {
+        skipped.sort();
+        eprintln!(
+            "Note: Not lowering {}: their priority couldn't be raised back afterwards without root or CAP_SYS_NICE.",
+            skipped.join(", ")
+        );
     }
 
-    println!();
+    calmed
+}
 
-    #[cfg(target_os = "linux")]
-    return launch_profile_linux(profile, args);
+/// A program closed by `kill_before`, with what it takes to start it again.
+#[derive(Debug, PartialEq)]
+struct ClosedProgram {
+    exe: PathBuf,
+    /// Arguments after the program name
+    args: Vec<OsString>,
+    cwd: Option<PathBuf>,
+}
 
-    #[cfg(target_os = "windows")]
-    return launch_profile_windows(profile, args);
+/// The arguments (after the program name) and working directory `pid` was
+/// started with, as far as they can be read.
+#[cfg(target_os = "linux")]
+fn process_launch_details(pid: u32) -> (Vec<OsString>, Option<PathBuf>) {
+    use std::os::unix::ffi::OsStringExt;
+
+    // Arguments are separated and terminated by NULs
+    let args = std::fs::read(format!("/proc/{}/cmdline", pid))
+        .map(|cmdline| {
+            let cmdline = cmdline.strip_suffix(&[0]).unwrap_or(&cmdline);
+            cmdline
+                .split(|&byte| byte == 0)
+                .skip(1)
+                .map(|arg| OsString::from_vec(arg.to_vec()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let cwd = std::fs::read_link(format!("/proc/{}/cwd", pid)).ok();
+    (args, cwd)
 }
 
-fn launch_or_exit(
-    profile: &Profile,
-    args: &[String],
-    profile_name: Option<&str>,
-    should_cleanup: bool,
-) -> ! {
-    // Validate profile before attempting launch
-    if let Err(e) = validate_profile(profile) {
-        eprintln!("Profile validation failed: {:#}", e);
+#[cfg(target_os = "windows")]
+fn process_launch_details(pid: u32) -> (Vec<OsString>, Option<PathBuf>) {
+    use windows_sys::Win32::System::Threading::{PROCESS_QUERY_INFORMATION, PROCESS_VM_READ};
 
-        if profile_name.is_some() {
-            eprintln!("\nWould you like to:");
-            eprintln!("  1. Update the profile path");
-            eprintln!("  2. Delete this profile");
-            eprintln!("  3. Exit");
+    let Ok(handle) = ProcessHandle::open(pid, PROCESS_QUERY_INFORMATION | PROCESS_VM_READ) else {
+        return (Vec::new(), None);
+    };
+    (
+        process_arguments(&handle).unwrap_or_default(),
+        process_cwd(&handle),
+    )
+}
 
-            if let Ok(choice) = read_line("Enter choice (1-3): ") {
-                match choice.as_str() {
-                    "1" => {
-                        if let Ok(new_path) = read_line("Enter new executable path: ") {
-                            let new_path = new_path.trim_matches('"');
-                            if PathBuf::from(new_path).exists() {
-                                if let Ok(mut profiles) = load_profiles() {
-                                    if let Some(name) = profile_name {
-                                        if let Some(p) = profiles.get_mut(name) {
-                                            p.path = PathBuf::from(new_path);
-                                            if save_profiles(&profiles).is_ok() {
-                                                println!(
-                                                    "Profile updated! Please run the command again."
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                            } else {
-                                eprintln!("Error: Path does not exist.");
-                            }
-                        }
-                    }
-                    "2" => {
-                        if let (Ok(mut profiles), Some(name)) = (load_profiles(), profile_name) {
-                            profiles.remove(name);
-                            let _ = save_profiles(&profiles);
-                            println!("Profile deleted.");
-                        }
-                    }
-                    _ => {}
-                }
-            }
+/// The arguments of a process after the program name, split the way the
+/// program itself would.
+#[cfg(target_os = "windows")]
+fn process_arguments(handle: &ProcessHandle) -> Option<Vec<OsString>> {
+    use std::os::windows::ffi::OsStringExt;
+    use windows_sys::Wdk::System::Threading::{
+        NtQueryInformationProcess, ProcessCommandLineInformation,
+    };
+    use windows_sys::Win32::Foundation::{LocalFree, UNICODE_STRING};
+    use windows_sys::Win32::UI::Shell::CommandLineToArgvW;
+
+    unsafe {
+        let mut needed = 0;
+        NtQueryInformationProcess(
+            handle.0,
+            ProcessCommandLineInformation,
+            std::ptr::null_mut(),
+            0,
+            &mut needed,
+        );
+        if needed == 0 {
+            return None;
         }
 
-        pause_before_exit();
-        exit(1);
+        // A UNICODE_STRING pointing into the rest of the buffer; u64s keep it aligned
+        let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+        if NtQueryInformationProcess(
+            handle.0,
+            ProcessCommandLineInformation,
+            buffer.as_mut_ptr().cast(),
+            needed,
+            &mut needed,
+        ) < 0
+        {
+            return None;
+        }
+        let string = &*(buffer.as_ptr() as *const UNICODE_STRING);
+        let mut command_line =
+            std::slice::from_raw_parts(string.Buffer, string.Length as usize / 2).to_vec();
+        command_line.push(0);
+
+        let mut count = 0;
+        let argv = CommandLineToArgvW(command_line.as_ptr(), &mut count);
+        if argv.is_null() {
+            return None;
+        }
+        let args = (1..count.max(0) as usize)
+            .map(|index| {
+                let arg = *argv.add(index);
+                let len = (0..).take_while(|&n| *arg.add(n) != 0).count();
+                OsString::from_wide(std::slice::from_raw_parts(arg, len))
+            })
+            .collect();
+        LocalFree(argv.cast());
+        Some(args)
     }
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        // Check if elevation is needed
-        if let Some(ref priority) = profile.priority {
-            if priority.requires_elevation() && !is_elevated() {
-                // Create temp profile if needed
-                let name = match profile_name {
-                    Some(n) => n.to_string(),
-                    None => {
-                        // Create temporary profile for elevation
-                        println!("\nNote: Using temporary profile for elevation.");
-                        println!(
-                            "Consider saving this profile if you'll use these settings again.\n"
-                        );
+/// The current directory of a process, read from its process parameters.
+#[cfg(target_os = "windows")]
+fn process_cwd(handle: &ProcessHandle) -> Option<PathBuf> {
+    use std::os::windows::ffi::OsStringExt;
+    use windows_sys::Wdk::System::Threading::{NtQueryInformationProcess, ProcessBasicInformation};
+    use windows_sys::Win32::Foundation::UNICODE_STRING;
+    use windows_sys::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+    use windows_sys::Win32::System::Threading::{PEB, PROCESS_BASIC_INFORMATION};
+
+    // CurrentDirectory.DosPath in RTL_USER_PROCESS_PARAMETERS, which the SDK
+    // only declares as reserved fields
+    const CURRENT_DIRECTORY: usize = if cfg!(target_pointer_width = "64") {
+        0x38
+    } else {
+        0x24
+    };
 
-                        let temp_name = format!("{}{}", TEMP_PROFILE_PREFIX, std::process::id());
+    let read = |address: usize, buffer: *mut std::ffi::c_void, size: usize| unsafe {
+        ReadProcessMemory(
+            handle.0,
+            address as *const _,
+            buffer,
+            size,
+            std::ptr::null_mut(),
+        ) != 0
+    };
 
-                        if let Ok(mut profiles) = load_profiles() {
-                            profiles.insert(temp_name.clone(), profile.clone());
-                            if let Err(e) = save_profiles(&profiles) {
-                                eprintln!("Error: Failed to save temporary profile: {}", e);
-                                pause_before_exit();
-                                exit(1);
-                            }
-                        } else {
-                            eprintln!("Error: Failed to load profiles for elevation");
-                            pause_before_exit();
-                            exit(1);
-                        }
+    unsafe {
+        let mut info: PROCESS_BASIC_INFORMATION = std::mem::zeroed();
+        if NtQueryInformationProcess(
+            handle.0,
+            ProcessBasicInformation,
+            &mut info as *mut _ as *mut _,
+            std::mem::size_of::<PROCESS_BASIC_INFORMATION>() as u32,
+            std::ptr::null_mut(),
+        ) < 0
+        {
+            return None;
+        }
 
-                        temp_name
-                    }
-                };
+        let mut peb: PEB = std::mem::zeroed();
+        let mut directory: UNICODE_STRING = std::mem::zeroed();
+        if !read(
+            info.PebBaseAddress as usize,
+            &mut peb as *mut _ as *mut _,
+            std::mem::size_of::<PEB>(),
+        ) || !read(
+            peb.ProcessParameters as usize + CURRENT_DIRECTORY,
+            &mut directory as *mut _ as *mut _,
+            std::mem::size_of::<UNICODE_STRING>(),
+        ) {
+            return None;
+        }
 
-                match relaunch_elevated(&name, args) {
-                    Ok(_) => exit(0),
-                    Err(e) => {
-                        // Clean up temp profile if elevation failed
-                        if name.starts_with(TEMP_PROFILE_PREFIX) {
-                            if let Ok(mut profiles) = load_profiles() {
-                                profiles.remove(&name);
-                                let _ = save_profiles(&profiles);
-                            }
-                        }
+        let mut path = vec![0u16; directory.Length as usize / 2];
+        if !read(
+            directory.Buffer as usize,
+            path.as_mut_ptr().cast(),
+            directory.Length as usize,
+        ) {
+            return None;
+        }
+        Some(PathBuf::from(OsString::from_wide(&path)))
+    }
+}
 
-                        eprintln!("\nError requesting elevation: {:#}", e);
-                        eprintln!("\nOptions:");
-                        eprintln!("  1. Run this program as Administrator");
-                        eprintln!("  2. Choose a lower priority (Normal or Above Normal)");
-                        eprintln!("  3. Launch anyway with Normal priority");
-
-                        if let Ok(choice) = read_line("\nEnter choice (1-3): ") {
-                            if choice == "3" {
-                                println!("\nLaunching with Normal priority instead...");
-                                let mut fallback_profile = profile.clone();
-                                fallback_profile.priority = Some(ProcessPriority::Normal);
-
-                                match launch_profile(&fallback_profile, args) {
-                                    Ok(_) => exit(0),
-                                    Err(e) => {
-                                        eprintln!("Error launching program: {:#}", e);
-                                        pause_before_exit();
-                                        exit(1);
-                                    }
-                                }
-                            }
-                        }
+/// Closes the running processes matching `kill_before`, killing those that
+/// don't close in time. Returns the programs that were closed.
+fn close_before_launch(profile: &Profile) -> Vec<ClosedProgram> {
+    let processes = match list_processes() {
+        Ok(processes) => processes,
+        Err(e) => {
+            eprintln!("Warning: Could not close the kill_before programs: {:#}", e);
+            return Vec::new();
+        }
+    };
 
-                        pause_before_exit();
-                        exit(1);
-                    }
-                }
+    let mut running: Vec<(ProcessInfo, Option<u64>)> = processes
+        .into_iter()
+        .filter(|process| process.pid != std::process::id())
+        .filter(|process| {
+            profile
+                .kill_before
+                .iter()
+                .any(|pattern| process_name_matches(pattern, &process.name))
+        })
+        .map(|process| {
+            let start_time = process_start_time(process.pid);
+            (process, start_time)
+        })
+        .collect();
+
+    let mut closed: Vec<ClosedProgram> = Vec::new();
+    for (process, _) in &running {
+        // Read before the process goes away
+        let (args, cwd) = process_launch_details(process.pid);
+
+        println!("Closing {} (PID {})", process.name, process.pid);
+        if let Err(e) = terminate_process(process.pid, false) {
+            eprintln!("Warning: {:#}", e);
+        }
+        if let Some(ref exe) = process.exe {
+            let program = ClosedProgram {
+                exe: exe.clone(),
+                args,
+                cwd,
+            };
+            if !closed.contains(&program) {
+                closed.push(program);
             }
         }
     }
 
-    // Launch the profile
-    match launch_profile(profile, args) {
-        Ok(_) => {
-            // Clean up temp profile if requested
-            if should_cleanup {
-                if let Some(name) = profile_name {
-                    if name.starts_with(TEMP_PROFILE_PREFIX) {
-                        if let Ok(mut profiles) = load_profiles() {
-                            profiles.remove(name);
-                            let _ = save_profiles(&profiles);
-                        }
-                    }
-                }
-            }
-            exit(0)
+    let deadline = Instant::now() + KILL_BEFORE_TIMEOUT;
+    loop {
+        running.retain(|(process, start_time)| process_start_time(process.pid) == *start_time);
+        if running.is_empty() || Instant::now() >= deadline {
+            break;
         }
-        Err(e) => {
-            eprintln!("Error launching program: {:#}", e);
-            pause_before_exit();
-            exit(1);
+        thread::sleep(Duration::from_millis(250));
+    }
+
+    for (process, _) in &running {
+        eprintln!(
+            "{} didn't close within {}s, killing it.",
+            process.name,
+            KILL_BEFORE_TIMEOUT.as_secs()
+        );
+        if let Err(e) = terminate_process(process.pid, true) {
+            eprintln!("Warning: {:#}", e);
         }
     }
-}
 
-fn delete_profile(profiles: &mut Profiles, keyword: &str) -> Result<()> {
-    if profiles.remove(keyword).is_some() {
-        save_profiles(profiles).context("Failed to save profiles after deletion")?;
-        println!("Profile '{}' deleted successfully.", keyword);
+    closed
+}
 
-        // Try to delete associated desktop shortcut
-        if let Some(user_dirs) = UserDirs::new() {
-            if let Some(desktop_dir) = user_dirs.desktop_dir() {
-                #[cfg(target_os = "windows")]
-                let shortcut_path = desktop_dir.join(format!("{}.bat", keyword));
+/// System-wide tweaks held for the lifetime of an attached launch and
+/// reverted when dropped.
+#[derive(Default)]
+struct SessionTweaks {
+    previous_power_plan: Option<String>,
+    calmed: Vec<CalmedProcess>,
+    /// Programs closed by `kill_before` to start again
+    restart: Vec<ClosedProgram>,
+    #[cfg(target_os = "linux")]
+    dscp_rule: Option<DscpRule>,
+    #[cfg(target_os = "windows")]
+    timer_resolution_ms: Option<u32>,
+}
 
-                #[cfg(target_os = "linux")]
-                let shortcut_path = desktop_dir.join(format!("{}.desktop", keyword));
-
-                if shortcut_path.exists() {
-                    match std::fs::remove_file(&shortcut_path) {
-                        Ok(_) => println!(
-                            "Associated desktop shortcut deleted: {}",
-                            shortcut_path.display()
-                        ),
-                        Err(e) => eprintln!("Warning: Could not delete shortcut: {}", e),
+impl SessionTweaks {
+    fn apply(profile: &Profile) -> Self {
+        let mut tweaks = Self::default();
+
+        if let Some(ref plan) = profile.power_plan {
+            let previous = current_power_plan();
+            match set_power_plan(plan) {
+                Ok(_) => {
+                    println!("Power plan set to: {}", plan);
+                    match previous {
+                        Ok(previous) => tweaks.previous_power_plan = Some(previous),
+                        Err(e) => {
+                            eprintln!("Warning: Previous power plan won't be restored: {:#}", e)
+                        }
                     }
                 }
+                Err(e) => eprintln!("Warning: {:#}", e),
             }
         }
-    } else {
-        println!("Profile '{}' not found.", keyword);
-    }
-    Ok(())
-}
-
-fn list_profiles(profiles: &Profiles) {
-    if profiles.is_empty() {
-        println!("No saved profiles.");
-        return;
-    }
 
-    println!("Saved profiles:\n");
+        #[cfg(target_os = "windows")]
+        if let Some(ms) = profile.timer_resolution_ms {
+            use windows_sys::Win32::Media::timeBeginPeriod;
 
-    for (name, profile) in profiles {
-        // Skip temp profiles
-        if name.starts_with(TEMP_PROFILE_PREFIX) {
-            continue;
+            if unsafe { timeBeginPeriod(ms) } == 0 {
+                println!("Timer resolution held at {} ms", ms);
+                tweaks.timer_resolution_ms = Some(ms);
+            } else {
+                eprintln!("Warning: Could not set timer resolution to {} ms", ms);
+            }
         }
 
-        println!("Profile: {}", name);
-        println!("  Path: {}", profile.path.display());
-        println!("  CPUs: {:?}", profile.cpus);
+        #[cfg(not(target_os = "windows"))]
+        if profile.timer_resolution_ms.is_some() {
+            eprintln!("Note: timer_resolution_ms only applies on Windows.");
+        }
 
-        let priority_str = profile
-            .priority
-            .as_ref()
-            .map(|p| p.display_name())
-            .unwrap_or("Normal");
+        if profile.calm_background == Some(true) {
+            tweaks.calmed = calm_background_apps(profile);
+        }
 
-        #[cfg(target_os = "windows")]
-        let admin_note = if profile
-            .priority
-            .as_ref()
-            .map(|p| p.requires_elevation())
-            .unwrap_or(false)
-        {
-            " [requires admin]"
-        } else {
-            ""
-        };
+        tweaks
+    }
+}
 
-        #[cfg(not(target_os = "windows"))]
-        let admin_note = "";
+impl Drop for SessionTweaks {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(ref rule) = self.dscp_rule {
+            rule.remove();
+        }
 
-        println!("  Priority: {}{}", priority_str, admin_note);
+        #[cfg(target_os = "windows")]
+        if let Some(ms) = self.timer_resolution_ms {
+            unsafe { windows_sys::Win32::Media::timeEndPeriod(ms) };
+        }
 
-        if let Some(attempts) = profile.retry_attempts {
-            println!("  Retry attempts: {}", attempts);
+        if let Some(ref plan) = self.previous_power_plan {
+            match set_power_plan(plan) {
+                Ok(_) => println!("Power plan restored to: {}", plan),
+                Err(e) => eprintln!("Warning: Failed to restore power plan: {:#}", e),
+            }
         }
 
-        // Validate path exists
-        if !profile.path.exists() {
-            println!("  WARNING: Executable not found!");
+        for program in &self.restart {
+            let exe = &program.exe;
+            let mut cmd = Command::new(exe);
+            cmd.args(&program.args);
+            #[cfg(target_os = "windows")]
+            cmd.env_remove(SHORTCUT_ENV_VAR);
+            if let Some(ref cwd) = program.cwd {
+                cmd.current_dir(cwd);
+            }
+            let started = cmd
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn();
+            match started {
+                Ok(_) => println!("Restarted {}", exe.display()),
+                Err(e) => eprintln!("Warning: Could not restart {}: {}", exe.display(), e),
+            }
         }
 
-        println!();
+        if !self.calmed.is_empty() {
+            // Apps that exited in the meantime, or whose PID was reused, are skipped
+            let failed = self
+                .calmed
+                .iter()
+                .filter(|calmed| process_start_time(calmed.pid) == calmed.start_time)
+                .filter(|calmed| set_process_priority(calmed.pid, &calmed.priority).is_err())
+                .count();
+
+            match failed {
+                0 => println!("Background apps restored to their previous priority."),
+                n => eprintln!(
+                    "Warning: Could not restore the priority of {} background process(es).",
+                    n
+                ),
+            }
+        }
     }
 }
 
-fn create_shortcut(profiles: &Profiles, keyword: &str) -> Result<()> {
-    let profile = profiles
-        .get(keyword)
-        .context(format!("Profile '{}' not found", keyword))?;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum LaunchMode {
+    /// Configure the process, then exit and leave it running on its own
+    #[default]
+    Detach,
+    /// Stay alive supervising the process until it exits
+    Attach,
+}
 
-    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
-    let current_exe_str = current_exe
-        .to_str()
-        .context("Executable path contains invalid UTF-8")?;
+impl LaunchMode {
+    fn display_name(&self) -> &str {
+        match self {
+            Self::Detach => "Detached",
+            Self::Attach => "Attached",
+        }
+    }
+}
 
-    let user_dirs = UserDirs::new().context("Could not find user directories")?;
-    let desktop_dir = user_dirs
-        .desktop_dir()
-        .context("Could not find Desktop directory")?;
+/// Which of the two main settings a profile applies when only one of them
+/// should be touched.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ApplyOnly {
+    /// Pin to the profile's cores, leave the priority as it is
+    Affinity,
+    /// Set the priority, leave the cores as they are
+    Priority,
+}
+
+impl ApplyOnly {
+    fn parse(input: &str) -> Option<Self> {
+        match input.trim().to_lowercase().as_str() {
+            "affinity" => Some(Self::Affinity),
+            "priority" => Some(Self::Priority),
+            _ => None,
+        }
+    }
 
     #[cfg(target_os = "windows")]
-    {
-        let bat_path = desktop_dir.join(format!("{}.bat", keyword));
+    fn to_arg(self) -> &'static str {
+        match self {
+            Self::Affinity => "affinity",
+            Self::Priority => "priority",
+        }
+    }
+}
 
-        // Check if elevation is needed
-        let needs_admin = profile
-            .priority
-            .as_ref()
-            .map(|p| p.requires_elevation())
-            .unwrap_or(false);
+/// How much of a launch is logged, from least to most.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    /// Also shows the extra detail in the console
+    Debug,
+}
 
-        let content = if needs_admin {
-            // Create elevated shortcut
-            format!(
-                "@echo off\r\n\
-                 echo Requesting administrator privileges for {}...\r\n\
-                 powershell -Command \"Start-Process -FilePath '{}' -ArgumentList '{}' -Verb RunAs\"\r\n",
-                keyword, current_exe_str, keyword
-            )
-        } else {
-            format!("@echo off\r\n\"{}\" {}\r\n", current_exe_str, keyword)
-        };
+impl LogLevel {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+}
 
-        std::fs::write(&bat_path, content).context("Failed to write shortcut file")?;
+/// Where the launch of the current profile is logged, from its `log_level`
+/// and `log_file`.
+struct LaunchLog {
+    level: LogLevel,
+    file: Option<std::sync::Mutex<std::fs::File>>,
+}
 
-        println!("Shortcut created: {}", bat_path.display());
+static LAUNCH_LOG: OnceLock<LaunchLog> = OnceLock::new();
 
-        if needs_admin {
-            println!("Note: This shortcut will request administrator privileges when launched.");
-            println!("Alternatively, you can:");
-            println!(
-                "  - Right-click the .bat file > Properties > Advanced > Run as administrator"
-            );
-            println!("  - Create a scheduled task to run without UAC prompts");
+/// Sets up logging for the profile about to be launched. Nothing is logged
+/// for profiles without a `log_level` or `log_file`.
+fn init_launch_log(name: Option<&str>, profile: &Profile) {
+    if profile.log_level.is_none() && profile.log_file.is_none() {
+        return;
+    }
+
+    let file = profile.log_file.as_ref().and_then(|path| {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
         }
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            Ok(file) => Some(std::sync::Mutex::new(file)),
+            Err(e) => {
+                eprintln!("Warning: Could not open log file {}: {}", path.display(), e);
+                None
+            }
+        }
+    });
+
+    let _ = LAUNCH_LOG.set(LaunchLog {
+        level: profile.log_level.unwrap_or_default(),
+        file,
+    });
+    log_event(
+        LogLevel::Info,
+        &format!(
+            "Launching profile '{}': {}",
+            name.unwrap_or("(unsaved)"),
+            profile.path.display()
+        ),
+    );
+}
+
+/// Writes a line to the profile's log file if its level is enabled. Debug
+/// lines are shown on stderr as well, as nothing else prints them.
+fn log_event(level: LogLevel, message: &str) {
+    let Some(log) = LAUNCH_LOG.get() else {
+        return;
+    };
+    if level > log.level {
+        return;
     }
 
-    #[cfg(target_os = "linux")]
+    if level == LogLevel::Debug {
+        eprintln!("Debug: {}", message);
+    }
+
+    if let Some(ref file) = log.file
+        && let Ok(mut file) = file.lock()
     {
-        let shortcut_path = desktop_dir.join(format!("{}.desktop", keyword));
-        let content = format!(
-            "[Desktop Entry]\n\
-             Version=1.0\n\
-             Name={}\n\
-             Comment=Launch {} with CPU affinity and priority settings\n\
-             Exec=\"{}\" {}\n\
-             Terminal=false\n\
-             Type=Application\n\
-             Categories=Utility;\n",
-            keyword,
-            profile.path.display(),
-            current_exe_str,
-            keyword
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = writeln!(
+            file,
+            "{}.{:03} [{}] {} {}",
+            now.as_secs(),
+            now.subsec_millis(),
+            std::process::id(),
+            level.label(),
+            message
         );
+    }
+}
 
-        std::fs::write(&shortcut_path, &content).context("Failed to write .desktop file")?;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum CreationFlag {
+    /// Console programs get no window (Windows)
+    NoWindow,
+    /// Console programs don't share or get a console (Windows)
+    DetachedProcess,
+    /// Ctrl+C in the launching console isn't delivered to the program
+    NewProcessGroup,
+    /// Start at Below Normal priority instead of raising it later (Windows)
+    BelowNormal,
+    /// Run in a new session without a controlling terminal (Linux setsid);
+    /// on Windows, in a new process group with a console of its own
+    NewSession,
+}
 
-        let mut perms = std::fs::metadata(&shortcut_path)?.permissions();
-        perms.set_mode(0o755);
-        std::fs::set_permissions(&shortcut_path, perms)
-            .context("Failed to set executable permissions")?;
+impl CreationFlag {
+    #[cfg(target_os = "windows")]
+    fn to_windows_flag(self) -> u32 {
+        use windows_sys::Win32::System::Threading::{
+            BELOW_NORMAL_PRIORITY_CLASS, CREATE_NEW_CONSOLE, CREATE_NEW_PROCESS_GROUP,
+            CREATE_NO_WINDOW, DETACHED_PROCESS,
+        };
 
-        println!("Shortcut created: {}", shortcut_path.display());
+        match self {
+            Self::NoWindow => CREATE_NO_WINDOW,
+            Self::DetachedProcess => DETACHED_PROCESS,
+            Self::NewProcessGroup => CREATE_NEW_PROCESS_GROUP,
+            Self::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+            // The closest to setsid: away from the launching console and its Ctrl+C
+            Self::NewSession => CREATE_NEW_PROCESS_GROUP | CREATE_NEW_CONSOLE,
+        }
     }
 
-    Ok(())
+    /// Creation flag bits for all of `flags` together.
+    #[cfg(target_os = "windows")]
+    fn windows_bits<'a>(flags: impl IntoIterator<Item = &'a CreationFlag>) -> u32 {
+        use windows_sys::Win32::System::Threading::{CREATE_NEW_CONSOLE, DETACHED_PROCESS};
+
+        let bits = flags
+            .into_iter()
+            .fold(0, |bits, flag| bits | flag.to_windows_flag());
+        // CreateProcess rejects both at once; without a console is what was asked for
+        if bits & DETACHED_PROCESS != 0 {
+            bits & !CREATE_NEW_CONSOLE
+        } else {
+            bits
+        }
+    }
 }
 
-fn show_help() {
-    println!();
-    println!("========== affinity-rs v3 ==========");
-    println!("CPU affinity and process priority launcher with profile support.\n");
-    println!("USAGE:");
-    println!("  affinity-rs <command>");
-    println!("  affinity-rs <profile_name> [program_args...]\n");
-    println!("COMMANDS:");
-    println!("  list                 List all saved profiles");
-    println!("  delete <profile>     Delete a saved profile and its shortcut");
-    println!("  shortcut <profile>   Create a desktop shortcut for a profile");
-    println!("  help                 Show this help message\n");
-    println!("EXAMPLES:");
-    println!("  affinity-rs list");
-    println!("  affinity-rs my_game");
-    println!("  affinity-rs my_game --windowed");
-    println!("  affinity-rs delete my_game");
-    println!("  affinity-rs shortcut my_game\n");
-    println!("CREATING PROFILES:");
-    println!("  Run 'affinity-rs <new_name>' to create a new profile interactively.");
-    println!("  You'll be prompted for:");
-    println!("    - Executable path");
-    println!("    - CPU cores to use");
-    println!("    - Process priority level\n");
+/// Per-executable compatibility settings from the Properties > Compatibility tab.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum CompatFlag {
+    /// "Disable fullscreen optimizations"
+    DisableFullscreenOptimizations,
+    /// High DPI scaling performed by the application
+    DpiApplication,
+    /// High DPI scaling performed by the system
+    DpiSystem,
+    /// High DPI scaling performed by the system (Enhanced)
+    DpiSystemEnhanced,
+}
+
+impl CompatFlag {
+    /// AppCompatFlags layers written for the setting.
+    #[cfg(target_os = "windows")]
+    fn layers(self) -> &'static [&'static str] {
+        match self {
+            Self::DisableFullscreenOptimizations => &["DISABLEDXMAXIMIZEDWINDOWEDMODE"],
+            Self::DpiApplication => &["HIGHDPIAWARE"],
+            Self::DpiSystem => &["DPIUNAWARE"],
+            Self::DpiSystemEnhanced => &["GDIDPISCALING", "DPIUNAWARE"],
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn is_dpi_override(self) -> bool {
+        self != Self::DisableFullscreenOptimizations
+    }
+}
+
+/// Applies the profile's process creation flags to a command about to be spawned.
+fn apply_creation_flags(cmd: &mut Command, flags: &[CreationFlag]) {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+
+        cmd.creation_flags(CreationFlag::windows_bits(flags));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::process::CommandExt;
+
+        if flags.contains(&CreationFlag::NewSession) {
+            // SAFETY: setsid is async-signal-safe and touches no parent state
+            unsafe {
+                cmd.pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        } else if flags.contains(&CreationFlag::NewProcessGroup) {
+            cmd.process_group(0);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ShortcutAction {
+    name: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// Resource limits set for the program on Linux, as `ulimit` would in a
+/// wrapper script.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct Rlimits {
+    /// Open files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nofile: Option<RlimitValue>,
+    /// Core dump size in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    core: Option<RlimitValue>,
+    /// Locked memory in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memlock: Option<RlimitValue>,
+    /// Highest realtime priority the program may request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rtprio: Option<RlimitValue>,
+}
+
+/// Where to put the program's main window once it appears (Windows only).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct WindowPlacement {
+    /// Monitor to move the window to: 1 is the primary monitor, the others
+    /// follow from left to right
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    monitor: Option<usize>,
+    /// Drop the window's borders and cover the whole monitor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    borderless: Option<bool>,
+    /// How long to wait for the window, 60 seconds by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wait_secs: Option<u64>,
+}
+
+/// A limit, or `"unlimited"`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+enum RlimitValue {
+    Value(u64),
+    Named(Unlimited),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Unlimited {
+    Unlimited,
+}
+
+#[cfg(target_os = "linux")]
+impl RlimitValue {
+    fn to_rlim(self) -> libc::rlim_t {
+        match self {
+            Self::Value(value) => value as libc::rlim_t,
+            Self::Named(Unlimited::Unlimited) => libc::RLIM_INFINITY,
+        }
+    }
+}
+
+type Profiles = HashMap<String, Profile>;
+
+fn get_config_dir() -> Result<PathBuf> {
+    let proj_dirs = ProjectDirs::from("rs", "affinity", "AffinityRs")
+        .context("Could not find a valid home directory to store profiles")?;
+
+    let config_dir = proj_dirs.config_dir();
+    std::fs::create_dir_all(config_dir).context("Failed to create config directory")?;
+
+    Ok(config_dir.to_path_buf())
+}
+
+/// Profiles file given with `--profile-file`, used instead of the usual store.
+static PROFILE_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+fn get_profile_path() -> Result<PathBuf> {
+    if let Some(path) = PROFILE_FILE.get() {
+        return Ok(path.clone());
+    }
+
+    let mut config_file_path = get_config_dir()?;
+    config_file_path.push(PROFILE_FILE_NAME);
+    Ok(config_file_path)
+}
+
+/// Machine-wide profiles an administrator provides for every account. An
+/// alternate profiles file stands on its own, without them.
+fn system_profile_path() -> Option<PathBuf> {
+    if PROFILE_FILE.get().is_some() {
+        return None;
+    }
+
+    #[cfg(target_os = "linux")]
+    return Some(PathBuf::from("/etc/affinity-rs").join(PROFILE_FILE_NAME));
+
+    #[cfg(target_os = "windows")]
+    return std::env::var_os("ProgramData").map(|dir| {
+        PathBuf::from(dir)
+            .join("affinity-rs")
+            .join(PROFILE_FILE_NAME)
+    });
+}
+
+/// Profile files in profiles.d, one per game or category, in the order
+/// they are merged. An alternate profiles file stands on its own, without
+/// them.
+fn profiles_dir_files() -> Vec<PathBuf> {
+    profiles_dir_with_extension("json")
+}
+
+/// Files in profiles.d with the given extension, sorted by name.
+fn profiles_dir_with_extension(extension: &str) -> Vec<PathBuf> {
+    if PROFILE_FILE.get().is_some() {
+        return Vec::new();
+    }
+    let Ok(entries) =
+        get_config_dir().and_then(|dir| Ok(dir.join(PROFILES_DIR_NAME).read_dir()?))
+    else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    files
+}
+
+/// A profile from the system-wide store or profiles.d, files affinity-rs
+/// only reads.
+struct SharedProfile {
+    source: PathBuf,
+    profile: Profile,
+}
+
+impl SharedProfile {
+    /// Where the profile comes from, for listings.
+    fn origin(&self) -> String {
+        if system_profile_path().as_ref() == Some(&self.source) {
+            return "system-wide".to_string();
+        }
+        let file = self.source.file_name().unwrap_or_default();
+        format!("from {}", file.to_string_lossy())
+    }
+}
+
+/// The system-wide profiles with those of profiles.d on top, later files
+/// taking precedence. Files that can't be read are skipped, saying so if
+/// `warn` is set.
+fn load_shared_profiles(warn: bool) -> HashMap<String, SharedProfile> {
+    let system = system_profile_path().filter(|path| path.exists());
+    let mut shared = HashMap::new();
+
+    if warn {
+        for path in profiles_dir_with_extension("toml") {
+            eprintln!(
+                "Warning: Skipping {}: profile files have to be JSON",
+                path.display()
+            );
+        }
+    }
+
+    for path in system.into_iter().chain(profiles_dir_files()) {
+        let profiles = std::fs::read_to_string(&path)
+            .context("Failed to read the file")
+            .and_then(|data| {
+                serde_json::from_str::<Profiles>(&data).context("Failed to parse the file")
+            });
+        match profiles {
+            Ok(profiles) => {
+                for (name, profile) in profiles {
+                    let source = path.clone();
+                    shared.insert(name, SharedProfile { source, profile });
+                }
+            }
+            Err(e) if warn => {
+                eprintln!("Warning: Skipping profiles in {}: {:#}", path.display(), e)
+            }
+            Err(_) => {}
+        }
+    }
+    shared
+}
+
+fn same_profile(a: &Profile, b: &Profile) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Fields in which a user's copy of a system-wide profile differs from it.
+/// A field the user cleared is `null`.
+fn profile_overrides(
+    profile: &Profile,
+    system: &Profile,
+) -> serde_json::Map<String, serde_json::Value> {
+    let (Ok(serde_json::Value::Object(own)), Ok(serde_json::Value::Object(base))) =
+        (serde_json::to_value(profile), serde_json::to_value(system))
+    else {
+        return serde_json::Map::new();
+    };
+
+    let mut overrides: serde_json::Map<String, serde_json::Value> = own
+        .iter()
+        .filter(|(key, value)| base.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    for key in base.keys().filter(|key| !own.contains_key(*key)) {
+        overrides.insert(key.clone(), serde_json::Value::Null);
+    }
+    overrides
+}
+
+/// A system-wide profile with the user's overridden fields applied.
+fn with_overrides(system: &Profile, overrides: serde_json::Value) -> Result<Profile> {
+    let mut fields = match serde_json::to_value(system)? {
+        serde_json::Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    };
+
+    let serde_json::Value::Object(overrides) = overrides else {
+        bail!("Expected an object of overridden fields");
+    };
+    for (key, value) in overrides {
+        if value.is_null() {
+            fields.remove(&key);
+        } else {
+            fields.insert(key, value);
+        }
+    }
+
+    Ok(serde_json::from_value(serde_json::Value::Object(fields))?)
+}
+
+/// The system-wide and profiles.d profiles with the user's own on top. For
+/// those, the user's entry only holds the fields they changed.
+fn load_profiles() -> Result<Profiles> {
+    let profile_path = get_profile_path()?;
+
+    let mut profiles: Profiles = load_shared_profiles(true)
+        .into_iter()
+        .map(|(name, shared)| (name, shared.profile))
+        .collect();
+
+    if !profile_path.exists() {
+        return Ok(profiles);
+    }
+
+    let data = std::fs::read_to_string(&profile_path).context("Failed to read profiles file")?;
+
+    if settings().integrity_check {
+        check_profiles_integrity(&profile_path, data.as_bytes());
+    }
+
+    let user: HashMap<String, serde_json::Value> =
+        serde_json::from_str(&data).context("Failed to parse profiles JSON")?;
+    for (name, value) in user {
+        // Overrides outliving their shared profile still load on their own
+        let base = profiles.get(&name).cloned().unwrap_or_default();
+        let profile = with_overrides(&base, value)
+            .with_context(|| format!("Failed to parse profile '{}'", name))?;
+        profiles.insert(name, profile);
+    }
+    Ok(profiles)
+}
+
+/// Keyring entry holding the key profiles.json is signed with.
+const INTEGRITY_SECRET: &str = "profiles-integrity-key";
+
+/// Set once profiles.json failed its integrity check, so saving the
+/// tampered content doesn't sign it as if it were the user's own.
+static INTEGRITY_FAILED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Signature kept next to the profiles file, e.g. profiles.sig.
+fn signature_path(profile_path: &Path) -> PathBuf {
+    profile_path.with_extension("sig")
+}
+
+/// The signing key from the OS keyring. A new one is only made with
+/// `create`, so an unavailable keyring can't silently replace the key.
+fn integrity_key(create: bool) -> Result<Vec<u8>> {
+    match read_secret(INTEGRITY_SECRET) {
+        Ok(hex) => from_hex(hex.trim()).context("The integrity key in the keyring is damaged"),
+        Err(_) if create => {
+            let key = random_bytes(32)?;
+            store_secret(INTEGRITY_SECRET, &to_hex(&key))
+                .context("Failed to store the integrity key")?;
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Records an HMAC of the profiles file, keyed with a secret from the OS
+/// keyring, so changes made behind affinity-rs's back can be noticed.
+fn sign_profiles(profile_path: &Path, data: &[u8]) -> Result<()> {
+    let signature_path = signature_path(profile_path);
+    let key = integrity_key(!signature_path.exists())?;
+    std::fs::write(&signature_path, to_hex(&hmac_sha256(&key, data)))
+        .context("Failed to write the profiles signature")
+}
+
+/// Warns when the profiles file doesn't match the signature affinity-rs
+/// recorded the last time it wrote the file. The stored paths are executed,
+/// possibly elevated, so silent edits by other programs should stand out.
+/// Only the user's profiles.json is covered; the system-wide file and
+/// profiles.d are edited by hand and left to their file permissions.
+fn check_profiles_integrity(profile_path: &Path, data: &[u8]) {
+    let signature_path = signature_path(profile_path);
+    if !signature_path.exists() {
+        // With a key already in the keyring the signature was there once,
+        // and deleting it must not be a way to get changes accepted
+        if integrity_key(false).is_ok() {
+            INTEGRITY_FAILED.store(true, std::sync::atomic::Ordering::Relaxed);
+            eprintln!(
+                "Warning: The integrity signature of {} is missing.",
+                profile_path.display()
+            );
+            eprintln!("Review the file, then run 'affinity-rs sign-profiles' if it is yours.");
+            return;
+        }
+
+        match sign_profiles(profile_path, data) {
+            Ok(_) => eprintln!(
+                "Note: Recorded an integrity signature for {}",
+                profile_path.display()
+            ),
+            Err(e) => eprintln!("Warning: Could not sign the profiles: {:#}", e),
+        }
+        return;
+    }
+
+    let signature = std::fs::read_to_string(&signature_path).unwrap_or_default();
+    let matches =
+        integrity_key(false).map(|key| to_hex(&hmac_sha256(&key, data)) == signature.trim());
+
+    match matches {
+        Ok(true) => {}
+        Ok(false) => {
+            INTEGRITY_FAILED.store(true, std::sync::atomic::Ordering::Relaxed);
+            eprintln!(
+                "Warning: {} was changed outside of affinity-rs since it was last saved.",
+                profile_path.display()
+            );
+            eprintln!("Review it, then run 'affinity-rs sign-profiles' if the changes are yours.");
+        }
+        Err(e) => eprintln!("Warning: Could not check the profiles' integrity: {:#}", e),
+    }
+}
+
+/// Writes the user's profiles. Of system-wide and profiles.d profiles, only
+/// the fields the user changed are saved, so later updates to the others
+/// still reach them.
+fn save_profiles(profiles: &Profiles) -> Result<()> {
+    let shared = load_shared_profiles(false);
+    let mut user = serde_json::Map::new();
+    for (name, profile) in profiles {
+        let value = match shared.get(name) {
+            Some(shared) => {
+                let overrides = profile_overrides(profile, &shared.profile);
+                if overrides.is_empty() {
+                    continue;
+                }
+                serde_json::Value::Object(overrides)
+            }
+            None => serde_json::to_value(profile).context("Failed to serialize profiles")?,
+        };
+        user.insert(name.clone(), value);
+    }
+
+    let profile_path = get_profile_path()?;
+    let data = serde_json::to_string_pretty(&user).context("Failed to serialize profiles")?;
+    std::fs::write(&profile_path, &data).context("Failed to write profiles to disk")?;
+
+    if settings().integrity_check && !INTEGRITY_FAILED.load(std::sync::atomic::Ordering::Relaxed) {
+        sign_profiles(&profile_path, data.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Global settings that apply to every profile.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Settings {
+    /// Log every applied change to the Event Log / syslog
+    #[serde(default)]
+    audit_log: bool,
+    /// Sign profiles.json and warn when it changes outside of affinity-rs
+    #[serde(default)]
+    integrity_check: bool,
+    /// Shared limits for profiles with a matching `slice`
+    #[serde(default)]
+    slices: HashMap<String, SliceSettings>,
+    /// Whether to wait for Enter before the console closes; unset pauses
+    /// after errors only in a console opened for affinity-rs
+    #[serde(default)]
+    pause: Option<PausePolicy>,
+    /// Where `preset fetch <name>` looks for bundles; `{name}` is replaced
+    #[serde(default)]
+    preset_source: Option<String>,
+    /// Nice values replacing the built-in ones for priority levels on Linux
+    #[serde(default)]
+    nice_values: HashMap<ProcessPriority, i32>,
+    /// Process name patterns lowered by `calm_background`; empty for the
+    /// built-in list
+    #[serde(default)]
+    background_apps: Vec<String>,
+}
+
+/// Programs `calm_background` lowers unless `background_apps` says otherwise:
+/// browsers, chat clients, sync tools and updaters.
+const DEFAULT_BACKGROUND_APPS: &[&str] = &[
+    "chrome",
+    "firefox",
+    "msedge",
+    "brave",
+    "opera",
+    "discord",
+    "slack",
+    "teams",
+    "ms-teams",
+    "spotify",
+    "onedrive",
+    "dropbox",
+    "googledrivefs",
+    "googleupdate",
+    "microsoftedgeupdate",
+    "*updater*",
+];
+
+impl Settings {
+    fn background_apps(&self) -> Vec<&str> {
+        if self.background_apps.is_empty() {
+            DEFAULT_BACKGROUND_APPS.to_vec()
+        } else {
+            self.background_apps.iter().map(String::as_str).collect()
+        }
+    }
+}
+
+/// When affinity-rs waits for Enter before exiting, so output stays readable
+/// in a console window opened by a shortcut.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum PausePolicy {
+    /// After every run
+    Always,
+    /// Only after an error
+    #[default]
+    OnError,
+    /// Never, for scripts
+    Never,
+    /// Close this many seconds after every run without waiting for Enter
+    AfterSecs(u64),
+}
+
+impl PausePolicy {
+    /// Parses `always`, `on-error`, `never` or a number of seconds.
+    fn parse(input: &str) -> Option<Self> {
+        match input.trim().to_lowercase().replace('_', "-").as_str() {
+            "always" => Some(Self::Always),
+            "on-error" => Some(Self::OnError),
+            "never" => Some(Self::Never),
+            secs => secs.parse().ok().map(Self::AfterSecs),
+        }
+    }
+
+    fn to_arg(self) -> String {
+        match self {
+            Self::Always => "always".to_string(),
+            Self::OnError => "on-error".to_string(),
+            Self::Never => "never".to_string(),
+            Self::AfterSecs(secs) => secs.to_string(),
+        }
+    }
+}
+
+/// Set from `--pause`, falling back to the global setting.
+static PAUSE_POLICY: OnceLock<PausePolicy> = OnceLock::new();
+
+fn pause_policy() -> PausePolicy {
+    *PAUSE_POLICY.get_or_init(|| {
+        settings().pause.unwrap_or_else(|| {
+            // In a terminal someone already had open, the output stays on
+            // screen anyway, and scripts would hang
+            if started_in_own_console() {
+                PausePolicy::OnError
+            } else {
+                PausePolicy::Never
+            }
+        })
+    })
+}
+
+/// Set by the .bat of Windows shortcuts, whose console cmd opens for them.
+/// It is removed again for the programs affinity-rs starts.
+#[cfg(target_os = "windows")]
+const SHORTCUT_ENV_VAR: &str = "AFFINITY_RS_SHORTCUT";
+
+/// Whether the console was opened just for affinity-rs, as when its .exe or
+/// a shortcut is double-clicked, so it closes as soon as affinity-rs exits.
+#[cfg(target_os = "windows")]
+fn started_in_own_console() -> bool {
+    use windows_sys::Win32::System::Console::GetConsoleProcessList;
+
+    if std::env::var_os(SHORTCUT_ENV_VAR).is_some() {
+        return true;
+    }
+
+    // A console opened from Explorer has no other process attached, and one
+    // opened for a .bat only the cmd running it. Shortcuts made by older
+    // versions don't set SHORTCUT_ENV_VAR, so they are recognized this way.
+    let mut pids = [0u32; 3];
+    let count = unsafe { GetConsoleProcessList(pids.as_mut_ptr(), pids.len() as u32) };
+    match count {
+        1 => true,
+        2 => {
+            let own = std::process::id();
+            let parent = process_snapshot().ok().and_then(|processes| {
+                let parent_pid = processes.iter().find(|p| p.pid == own)?.parent_pid;
+                processes.iter().find(|p| p.pid == parent_pid).cloned()
+            });
+            parent.is_some_and(|parent| {
+                pids.contains(&parent.pid)
+                    && parent.name.eq_ignore_ascii_case("cmd.exe")
+                    && runs_batch_file(parent.pid)
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Whether a cmd.exe was started with `/c` to run a .bat or .cmd script, as
+/// Explorer does for a double-clicked one.
+#[cfg(target_os = "windows")]
+fn runs_batch_file(pid: u32) -> bool {
+    use windows_sys::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let Some(args) = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)
+        .ok()
+        .and_then(|handle| process_arguments(&handle))
+    else {
+        return false;
+    };
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().to_lowercase())
+        .collect();
+    args.first().is_some_and(|arg| arg == "/c")
+        && args.iter().any(|arg| {
+            let arg = arg.trim_matches(|c: char| c == '"' || c.is_whitespace());
+            arg.ends_with(".bat") || arg.ends_with(".cmd")
+        })
+}
+
+/// Terminal emulators that start affinity-rs directly, as they do for
+/// double-clicked .desktop files with `Terminal=true` or "Run in Terminal".
+/// Names are cut to the 15 characters the kernel keeps.
+#[cfg(target_os = "linux")]
+const TERMINAL_EMULATORS: &[&str] = &[
+    "gnome-terminal-",
+    "kgx",
+    "ptyxis-agent",
+    "konsole",
+    "xfce4-terminal",
+    "mate-terminal",
+    "lxterminal",
+    "qterminal",
+    "tilix",
+    "terminator",
+    "xterm",
+    "uxterm",
+    "urxvt",
+    "kitty",
+    "alacritty",
+    "foot",
+    "wezterm-gui",
+    "ghostty",
+];
+
+/// Whether a terminal emulator was opened just for affinity-rs rather than
+/// it being run from a shell in a terminal that stays open.
+#[cfg(target_os = "linux")]
+fn started_in_own_console() -> bool {
+    // Without a terminal nobody can press Enter
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return false;
+    }
+
+    read_proc_stat(std::os::unix::process::parent_id())
+        .is_some_and(|(name, _)| TERMINAL_EMULATORS.contains(&name.as_str()))
+}
+
+/// Aggregate limits for all programs launched into one slice.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SliceSettings {
+    #[serde(default)]
+    cpu_weight: Option<u32>,
+    #[serde(default)]
+    cpus: Vec<usize>,
+}
+
+fn load_settings() -> Result<Settings> {
+    let path = get_config_dir()?.join(SETTINGS_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read settings file")?;
+    serde_json::from_str(&data).context("Failed to parse settings JSON")
+}
+
+/// Settings loaded once per run; falls back to defaults if the file is unreadable.
+fn settings() -> &'static Settings {
+    static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+    SETTINGS.get_or_init(|| {
+        load_settings().unwrap_or_else(|e| {
+            eprintln!("Warning: {:#}. Using default settings.", e);
+            Settings::default()
+        })
+    })
+}
+
+/// Called before exiting after an error.
+fn pause_before_exit() {
+    match pause_policy() {
+        PausePolicy::Always | PausePolicy::OnError => wait_for_enter(),
+        PausePolicy::Never => {}
+        PausePolicy::AfterSecs(secs) => close_after(secs),
+    }
+}
+
+/// Called before exiting after a successful run.
+fn pause_after_success() {
+    match pause_policy() {
+        PausePolicy::Always => wait_for_enter(),
+        PausePolicy::OnError | PausePolicy::Never => {}
+        PausePolicy::AfterSecs(secs) => close_after(secs),
+    }
+}
+
+fn wait_for_enter() {
+    print!("\nPress Enter to exit...");
+    let _ = io::stdout().flush();
+    let mut dummy = String::new();
+    let _ = io::stdin().read_line(&mut dummy);
+}
+
+fn close_after(secs: u64) {
+    println!("\nClosing in {} seconds...", secs);
+    thread::sleep(Duration::from_secs(secs));
+}
+
+/// Set by `--yes`/`--non-interactive`: nothing waits for input.
+static NON_INTERACTIVE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Set by `--yes` only: confirmations are answered with yes.
+static ASSUME_YES: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+fn non_interactive() -> bool {
+    NON_INTERACTIVE.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Fails right away in non-interactive mode instead of waiting for an answer.
+fn read_line(prompt: &str) -> Result<String> {
+    if non_interactive() {
+        bail!(
+            "'{}' needs an answer, but affinity-rs runs non-interactively",
+            prompt.trim().trim_end_matches(':')
+        );
+    }
+
+    print!("{}", prompt);
+    io::stdout().flush().context("Failed to flush stdout")?;
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .context("Failed to read input")?;
+    Ok(input.trim().to_string())
+}
+
+/// Like `read_line`, but in non-interactive mode `answer` is taken as the
+/// answer, and shown after the prompt.
+fn read_line_or(prompt: &str, answer: &str) -> Result<String> {
+    if non_interactive() {
+        println!("{}{} (non-interactive)", prompt, answer);
+        return Ok(answer.to_string());
+    }
+    read_line(prompt)
+}
+
+/// Asks a `[y/N]` question. Without anyone to answer it is no, unless
+/// `--yes` was given.
+fn confirm(prompt: &str) -> Result<bool> {
+    let default = if ASSUME_YES.load(std::sync::atomic::Ordering::Relaxed) {
+        "y"
+    } else {
+        "n"
+    };
+    Ok(read_line_or(prompt, default)?.eq_ignore_ascii_case("y"))
+}
+
+/// Reads a line without echoing it to the terminal.
+fn read_secret_line(prompt: &str) -> Result<String> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let fd = io::stdin().as_raw_fd();
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+
+        if unsafe { libc::tcgetattr(fd, &mut original) } == 0 {
+            let mut silent = original;
+            silent.c_lflag &= !libc::ECHO;
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+
+            let input = read_line(prompt);
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+            println!();
+            return input;
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use windows_sys::Win32::System::Console::{
+            ENABLE_ECHO_INPUT, GetConsoleMode, GetStdHandle, STD_INPUT_HANDLE, SetConsoleMode,
+        };
+
+        unsafe {
+            let handle = GetStdHandle(STD_INPUT_HANDLE);
+            let mut mode = 0;
+
+            if GetConsoleMode(handle, &mut mode) != 0 {
+                SetConsoleMode(handle, mode & !ENABLE_ECHO_INPUT);
+                let input = read_line(prompt);
+                SetConsoleMode(handle, mode);
+                println!();
+                return input;
+            }
+        }
+    }
+
+    // Not a terminal, e.g. piped input
+    read_line(prompt)
+}
+
+/// Splits a command line into arguments, honouring double and single quotes.
+/// Backslashes escape double quotes as in CommandLineToArgvW: before a `"`,
+/// each pair stands for one backslash and an odd one out makes the quote
+/// literal. Other backslashes, and anything in single quotes, are kept as is.
+fn split_args(input: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && quote != Some('\'') {
+            let mut backslashes = 1;
+            while chars.next_if_eq(&'\\').is_some() {
+                backslashes += 1;
+            }
+            in_arg = true;
+            if chars.peek() == Some(&'"') {
+                current.extend(std::iter::repeat_n('\\', backslashes / 2));
+                if backslashes % 2 == 1 {
+                    current.push('"');
+                    chars.next();
+                }
+            } else {
+                current.extend(std::iter::repeat_n('\\', backslashes));
+            }
+            continue;
+        }
+
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// Joins arguments into a command line that `split_args` takes apart again,
+/// quoted like on Windows.
+fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            // split_args also splits on other whitespace and takes single quotes
+            if arg.contains(|c: char| c.is_whitespace() || c == '\'') {
+                quoted_arg(arg)
+            } else {
+                command_line_arg(arg)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Asks for this session's extra arguments, offering the previous answer as
+/// the default, and with `remember` keeps the answer for next time.
+fn prompt_for_args(profiles: &mut Profiles, keyword: &str, remember: bool) -> Result<Vec<String>> {
+    let profile = profiles
+        .get_mut(keyword)
+        .context(format!("Profile '{}' not found", keyword))?;
+
+    let previous = join_args(&profile.last_args);
+    let prompt = if previous.is_empty() {
+        "Additional arguments: ".to_string()
+    } else {
+        format!("Additional arguments ('-' for none) [{}]: ", previous)
+    };
+
+    let answer = read_line_or(&prompt, "")?;
+    let args = match answer.as_str() {
+        "" => profile.last_args.clone(),
+        "-" => Vec::new(),
+        answer => split_args(answer),
+    };
+
+    if remember {
+        remember_args(profiles, keyword, &args)?;
+    }
+    Ok(args)
+}
+
+/// Stores the arguments of this launch as the profile's `last_args`.
+fn remember_args(profiles: &mut Profiles, keyword: &str, args: &[String]) -> Result<()> {
+    let profile = profiles
+        .get_mut(keyword)
+        .context(format!("Profile '{}' not found", keyword))?;
+
+    if profile.last_args != args {
+        profile.last_args = args.to_vec();
+        save_profiles(profiles)?;
+    }
+    Ok(())
+}
+
+fn get_cpu_input() -> Result<Vec<usize>> {
+    loop {
+        let input = read_line("Enter CPU cores (comma-separated, e.g., 0,1,2,3): ")?;
+        let trimmed = input.trim();
+
+        if trimmed.is_empty() {
+            eprintln!("Error: CPU cores cannot be empty.");
+            continue;
+        }
+
+        let is_valid = trimmed
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ',' || c.is_whitespace());
+
+        if !is_valid {
+            eprintln!("Error: only numbers, commas, and spaces allowed.");
+            continue;
+        }
+
+        let cpus: Vec<usize> = trimmed
+            .split(',')
+            .filter_map(|s| s.trim().parse().ok())
+            .collect();
+
+        if cpus.is_empty() {
+            eprintln!("Error: no valid cores provided.");
+            continue;
+        }
+
+        return Ok(cpus);
+    }
+}
+
+fn get_priority_input() -> Result<Option<ProcessPriority>> {
+    println!("\nProcess Priority options:");
+    println!("  1. Idle");
+    println!("  2. Below Normal");
+    println!("  3. Normal [default]");
+    println!("  4. Above Normal");
+
+    #[cfg(target_os = "windows")]
+    {
+        println!("  5. High [requires admin]");
+        println!("  6. Realtime [requires admin - WARNING: Can freeze your system!]");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        println!("  5. High [may require sudo]");
+        println!("  6. Realtime [may require sudo - WARNING: Can freeze your system!]");
+    }
+
+    let input = read_line("Enter priority (1-6, or press Enter for Normal): ")?;
+    let trimmed = input.trim();
+
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+
+    let priority = match trimmed {
+        "1" => ProcessPriority::Idle,
+        "2" => ProcessPriority::BelowNormal,
+        "3" => ProcessPriority::Normal,
+        "4" => ProcessPriority::AboveNormal,
+        "5" => ProcessPriority::High,
+        "6" => {
+            println!("\nWARNING: Realtime priority can make your system unresponsive!");
+            println!("Only use this if you understand the risks.");
+            ProcessPriority::Realtime
+        }
+        _ => {
+            eprintln!("Invalid selection, using Normal priority");
+            ProcessPriority::Normal
+        }
+    };
+
+    Ok(Some(priority))
+}
+
+fn get_template_input() -> Result<Option<Template>> {
+    println!("\nTemplates:");
+    for (i, template) in Template::ALL.iter().enumerate() {
+        println!(
+            "  {}. {} - {}",
+            i + 1,
+            template.name(),
+            template.description()
+        );
+    }
+
+    let input = read_line(&format!(
+        "Choose a template (1-{}, or press Enter to configure manually): ",
+        Template::ALL.len()
+    ))?;
+
+    if input.trim().is_empty() {
+        return Ok(None);
+    }
+
+    match Template::parse(&input) {
+        Some(template) => Ok(Some(template)),
+        None => {
+            eprintln!("Invalid selection, configuring manually");
+            Ok(None)
+        }
+    }
+}
+
+fn get_preset_input() -> Result<Option<Preset>> {
+    println!("\nPerformance presets:");
+    for (i, preset) in Preset::ALL.iter().enumerate() {
+        println!(
+            "  {}. {} - {}",
+            i + 1,
+            preset.display_name(),
+            preset.description()
+        );
+    }
+
+    let input = read_line(&format!(
+        "Choose a preset (1-{}, or press Enter to configure manually): ",
+        Preset::ALL.len()
+    ))?;
+    let trimmed = input.trim();
+
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+
+    match trimmed.parse::<usize>() {
+        Ok(n) if (1..=Preset::ALL.len()).contains(&n) => Ok(Some(Preset::ALL[n - 1])),
+        _ => {
+            eprintln!("Invalid selection, configuring manually");
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn is_elevated() -> bool {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::Security::{
+        GetTokenInformation, TOKEN_ELEVATION, TOKEN_QUERY, TokenElevation,
+    };
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    unsafe {
+        let mut token = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+
+        let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+        let mut return_length: u32 = 0;
+
+        let result = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut _ as *mut _,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut return_length,
+        );
+
+        CloseHandle(token);
+
+        result != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn relaunch_elevated(profile_name: &str, args: &[OsString], options: &LaunchOptions) -> Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::HWND;
+    use windows_sys::Win32::UI::Shell::ShellExecuteW;
+    use windows_sys::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
+
+    println!("\nAdministrator privileges required for this priority level.");
+    println!("Requesting elevation...\n");
+
+    // Build parameters: launch options + profile_name + cleanup flag + any additional args
+    let mut params = options.to_args();
+    params.extend([profile_name.into(), ELEVATION_CLEANUP_FLAG.into()]);
+    params.extend_from_slice(args);
+
+    unsafe {
+        // Paths and arguments stay UTF-16 all the way, so nothing is lost in conversion
+        let operation: Vec<u16> = "runas\0".encode_utf16().collect();
+        let file: Vec<u16> = current_exe
+            .as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let parameters: Vec<u16> = windows_command_line(&params).chain(Some(0)).collect();
+
+        let result = ShellExecuteW(
+            0 as HWND,
+            operation.as_ptr(),
+            file.as_ptr(),
+            parameters.as_ptr(),
+            std::ptr::null(),
+            SW_SHOWNORMAL,
+        );
+
+        // ShellExecuteW returns > 32 on success
+        let result_code = result as isize;
+        if result_code > 32 {
+            println!("Elevated process launched successfully.");
+            println!("This window will now close.\n");
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        } else {
+            // Handle specific error codes
+            match result_code {
+                0 => bail!("Out of memory or resources"),
+                2 => bail!("File not found"),
+                3 => bail!("Path not found"),
+                5 => bail!("Access denied - User may have cancelled UAC prompt"),
+                8 => bail!("Out of memory"),
+                31 => bail!("No application associated with this file type"),
+                _ => bail!("ShellExecuteW failed with error code: {}", result_code),
+            }
+        }
+    }
+}
+
+/// Quotes arguments the way the C runtime splits a command line again:
+/// arguments with spaces or quotes are wrapped in quotes, with backslashes
+/// doubled only where they precede a quote.
+#[cfg(target_os = "windows")]
+fn windows_command_line(args: &[OsString]) -> impl Iterator<Item = u16> + '_ {
+    use std::os::windows::ffi::OsStrExt;
+
+    const SPACE: u16 = b' ' as u16;
+    const TAB: u16 = b'\t' as u16;
+    const QUOTE: u16 = b'"' as u16;
+    const BACKSLASH: u16 = b'\\' as u16;
+
+    args.iter().enumerate().flat_map(|(index, arg)| {
+        let arg: Vec<u16> = arg.encode_wide().collect();
+        let mut quoted = Vec::with_capacity(arg.len() + 3);
+        if index > 0 {
+            quoted.push(SPACE);
+        }
+
+        if !arg.is_empty() && !arg.iter().any(|&c| c == SPACE || c == TAB || c == QUOTE) {
+            quoted.extend(arg);
+            return quoted;
+        }
+
+        quoted.push(QUOTE);
+        let mut backslashes = 0;
+        for c in arg {
+            if c == BACKSLASH {
+                backslashes += 1;
+            } else {
+                if c == QUOTE {
+                    quoted.extend(std::iter::repeat_n(BACKSLASH, backslashes + 1));
+                }
+                backslashes = 0;
+            }
+            quoted.push(c);
+        }
+        quoted.extend(std::iter::repeat_n(BACKSLASH, backslashes));
+        quoted.push(QUOTE);
+        quoted
+    })
+}
+
+/// A running process as seen by `top` and the other live-process commands.
+#[derive(Debug, Clone)]
+struct ProcessInfo {
+    pid: u32,
+    parent_pid: u32,
+    name: String,
+    exe: Option<PathBuf>,
+    /// Total user + kernel CPU time consumed so far
+    cpu_time: Duration,
+    #[cfg(target_os = "linux")]
+    session: u32,
+}
+
+#[cfg(target_os = "linux")]
+fn clock_ticks_per_second() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// Fields of /proc/<pid>/stat after the parenthesised command name, which
+/// may itself contain spaces. Index 0 is field 3 (state) of proc(5).
+#[cfg(target_os = "linux")]
+fn read_proc_stat(pid: u32) -> Option<(String, Vec<String>)> {
+    read_proc_stat_path(&format!("/proc/{}/stat", pid))
+}
+
+/// Same as `read_proc_stat`, for a stat file of any process or thread.
+#[cfg(target_os = "linux")]
+fn read_proc_stat_path(path: &str) -> Option<(String, Vec<String>)> {
+    let stat = std::fs::read_to_string(path).ok()?;
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat[open + 1..close].to_string();
+    let fields = stat[close + 1..]
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    Some((name, fields))
+}
+
+#[cfg(target_os = "linux")]
+fn list_processes() -> Result<Vec<ProcessInfo>> {
+    let ticks = clock_ticks_per_second();
+    let mut processes = Vec::new();
+
+    for entry in std::fs::read_dir("/proc").context("Failed to read /proc")? {
+        let Ok(entry) = entry else { continue };
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Some((name, fields)) = read_proc_stat(pid) else {
+            continue;
+        };
+
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|f| f.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+
+        // utime and stime are fields 14 and 15
+        let cpu_ticks = field(11) + field(12);
+
+        processes.push(ProcessInfo {
+            pid,
+            parent_pid: field(1) as u32,
+            name,
+            exe: std::fs::read_link(format!("/proc/{}/exe", pid)).ok(),
+            cpu_time: Duration::from_millis(cpu_ticks * 1000 / ticks),
+            // session is field 6
+            session: field(3) as u32,
+        });
+    }
+
+    Ok(processes)
+}
+
+/// Thread IDs of a process; affinity and nice are per-thread on Linux.
+#[cfg(target_os = "linux")]
+fn process_threads(pid: u32) -> Vec<u32> {
+    let mut threads: Vec<u32> = std::fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| e.file_name().to_str().and_then(|s| s.parse().ok()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if threads.is_empty() {
+        threads.push(pid);
+    }
+
+    threads
+}
+
+#[cfg(target_os = "linux")]
+fn get_process_affinity(pid: u32) -> Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(
+            pid as libc::pid_t,
+            std::mem::size_of::<libc::cpu_set_t>(),
+            &mut set,
+        ) != 0
+        {
+            bail!(
+                "Failed to read CPU affinity of PID {}: {}",
+                pid,
+                io::Error::last_os_error()
+            );
+        }
+
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_process_affinity(pid: u32, cpus: &[usize]) -> Result<()> {
+    let set = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &cpu in cpus {
+            if cpu < libc::CPU_SETSIZE as usize {
+                libc::CPU_SET(cpu, &mut set);
+            }
+        }
+        set
+    };
+
+    for tid in process_threads(pid) {
+        let result = unsafe {
+            libc::sched_setaffinity(
+                tid as libc::pid_t,
+                std::mem::size_of::<libc::cpu_set_t>(),
+                &set,
+            )
+        };
+        if result != 0 {
+            bail!(
+                "Failed to set CPU affinity of PID {}: {}",
+                pid,
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn get_process_priority(pid: u32) -> Result<ProcessPriority> {
+    Ok(ProcessPriority::from_nice_value(get_process_nice(pid)?))
+}
+
+#[cfg(target_os = "linux")]
+fn get_process_nice(pid: u32) -> Result<i32> {
+    // nice is field 19
+    read_proc_stat(pid)
+        .and_then(|(_, fields)| fields.get(16).and_then(|f| f.parse::<i32>().ok()))
+        .with_context(|| format!("Failed to read priority of PID {}", pid))
+}
+
+/// Whether affinity-rs may set a nice value as low as `nice`, which takes
+/// root, CAP_SYS_NICE or an RLIMIT_NICE allowing it.
+#[cfg(target_os = "linux")]
+fn can_lower_nice_to(nice: i32) -> bool {
+    const CAP_SYS_NICE: u32 = 23;
+
+    let capable = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let caps = status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))?;
+            u64::from_str_radix(caps.trim(), 16).ok()
+        })
+        .is_some_and(|caps| caps & (1 << CAP_SYS_NICE) != 0);
+
+    // RLIMIT_NICE allows nice values down to 20 - limit
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    let allowed = unsafe { libc::getrlimit(libc::RLIMIT_NICE, &mut limit) } == 0
+        && 20 - (limit.rlim_cur.min(40) as i32) <= nice;
+
+    let root = unsafe { libc::geteuid() } == 0;
+    root || capable || allowed
+}
+
+#[cfg(target_os = "linux")]
+fn set_process_priority(pid: u32, priority: &ProcessPriority) -> Result<()> {
+    set_process_nice(pid, priority.to_nice_value())
+}
+
+#[cfg(target_os = "linux")]
+fn set_process_nice(pid: u32, nice: i32) -> Result<()> {
+    for tid in process_threads(pid) {
+        let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) };
+        if result != 0 {
+            bail!(
+                "Failed to set priority of PID {}: {}",
+                pid,
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens a process handle, closing it again when dropped.
+#[cfg(target_os = "windows")]
+struct ProcessHandle(windows_sys::Win32::Foundation::HANDLE);
+
+#[cfg(target_os = "windows")]
+impl ProcessHandle {
+    fn open(pid: u32, access: u32) -> Result<Self> {
+        let handle = unsafe { windows_sys::Win32::System::Threading::OpenProcess(access, 0, pid) };
+        if handle.is_null() {
+            bail!("Failed to open PID {}: {}", pid, io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn process_exe_path(handle: &ProcessHandle) -> Option<PathBuf> {
+    use std::os::windows::ffi::OsStringExt;
+    use windows_sys::Win32::System::Threading::{PROCESS_NAME_WIN32, QueryFullProcessImageNameW};
+
+    let mut buffer = [0u16; 1024];
+    let mut size = buffer.len() as u32;
+    let ok = unsafe {
+        QueryFullProcessImageNameW(handle.0, PROCESS_NAME_WIN32, buffer.as_mut_ptr(), &mut size)
+    };
+
+    (ok != 0).then(|| PathBuf::from(std::ffi::OsString::from_wide(&buffer[..size as usize])))
+}
+
+#[cfg(target_os = "windows")]
+fn process_cpu_time(handle: &ProcessHandle) -> Duration {
+    use windows_sys::Win32::Foundation::FILETIME;
+    use windows_sys::Win32::System::Threading::GetProcessTimes;
+
+    let empty = FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut creation, mut exit_time, mut kernel, mut user) = (empty, empty, empty, empty);
+
+    let ok = unsafe {
+        GetProcessTimes(
+            handle.0,
+            &mut creation,
+            &mut exit_time,
+            &mut kernel,
+            &mut user,
+        )
+    };
+    if ok == 0 {
+        return Duration::ZERO;
+    }
+
+    // FILETIME durations are in 100 ns units
+    let to_u64 = |t: FILETIME| ((t.dwHighDateTime as u64) << 32) | t.dwLowDateTime as u64;
+    Duration::from_nanos((to_u64(kernel) + to_u64(user)) * 100)
+}
+
+#[cfg(target_os = "windows")]
+fn list_processes() -> Result<Vec<ProcessInfo>> {
+    use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, PROCESSENTRY32W, Process32FirstW, Process32NextW,
+        TH32CS_SNAPPROCESS,
+    };
+    use windows_sys::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let mut processes = Vec::new();
+
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            bail!(
+                "Failed to enumerate processes: {}",
+                io::Error::last_os_error()
+            );
+        }
+
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+
+        let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+        while more {
+            let name_len = entry
+                .szExeFile
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(entry.szExeFile.len());
+            let name = String::from_utf16_lossy(&entry.szExeFile[..name_len]);
+            let pid = entry.th32ProcessID;
+
+            let (exe, cpu_time) = match ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)
+            {
+                Ok(handle) => (process_exe_path(&handle), process_cpu_time(&handle)),
+                Err(_) => (None, Duration::ZERO),
+            };
+
+            processes.push(ProcessInfo {
+                pid,
+                parent_pid: entry.th32ParentProcessID,
+                name,
+                exe,
+                cpu_time,
+            });
+
+            more = Process32NextW(snapshot, &mut entry) != 0;
+        }
+
+        CloseHandle(snapshot);
+    }
+
+    Ok(processes)
+}
+
+#[cfg(target_os = "windows")]
+fn get_process_affinity(pid: u32) -> Result<Vec<usize>> {
+    use windows_sys::Win32::System::Threading::{
+        GetProcessAffinityMask, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    let handle = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)?;
+    let mut process_mask: usize = 0;
+    let mut system_mask: usize = 0;
+
+    if unsafe { GetProcessAffinityMask(handle.0, &mut process_mask, &mut system_mask) } == 0 {
+        bail!(
+            "Failed to read CPU affinity of PID {}: {}",
+            pid,
+            io::Error::last_os_error()
+        );
+    }
+
+    Ok((0..usize::BITS as usize)
+        .filter(|&cpu| process_mask & (1 << cpu) != 0)
+        .collect())
+}
+
+#[cfg(target_os = "windows")]
+fn set_process_affinity(pid: u32, cpus: &[usize]) -> Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SET_INFORMATION, SetProcessAffinityMask,
+    };
+
+    let mask = cpus
+        .iter()
+        .filter(|&&cpu| cpu < usize::BITS as usize)
+        .fold(0usize, |mask, &cpu| mask | (1 << cpu));
+    if mask == 0 {
+        bail!("No valid CPUs specified");
+    }
+
+    let handle = ProcessHandle::open(
+        pid,
+        PROCESS_SET_INFORMATION | PROCESS_QUERY_LIMITED_INFORMATION,
+    )?;
+    if unsafe { SetProcessAffinityMask(handle.0, mask) } == 0 {
+        bail!(
+            "Failed to set CPU affinity of PID {}: {}",
+            pid,
+            io::Error::last_os_error()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn get_process_priority(pid: u32) -> Result<ProcessPriority> {
+    use windows_sys::Win32::System::Threading::{
+        GetPriorityClass, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    let handle = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)?;
+    let class = unsafe { GetPriorityClass(handle.0) };
+
+    ProcessPriority::from_windows_class(class)
+        .with_context(|| format!("Failed to read priority of PID {}", pid))
+}
+
+#[cfg(target_os = "windows")]
+fn set_process_priority(pid: u32, priority: &ProcessPriority) -> Result<()> {
+    use windows_sys::Win32::System::Threading::{PROCESS_SET_INFORMATION, SetPriorityClass};
+
+    let handle = ProcessHandle::open(pid, PROCESS_SET_INFORMATION)?;
+    if unsafe { SetPriorityClass(handle.0, priority.to_windows_class()) } == 0 {
+        bail!(
+            "Failed to set priority of PID {}: {}",
+            pid,
+            io::Error::last_os_error()
+        );
+    }
+
+    Ok(())
+}
+
+/// Process start time in platform ticks, used to tell a process apart from a
+/// later one that reused its PID.
+#[cfg(target_os = "linux")]
+fn process_start_time(pid: u32) -> Option<u64> {
+    // starttime is field 22
+    read_proc_stat(pid).and_then(|(_, fields)| fields.get(19)?.parse().ok())
+}
+
+#[cfg(target_os = "windows")]
+fn process_start_time(pid: u32) -> Option<u64> {
+    use windows_sys::Win32::Foundation::FILETIME;
+    use windows_sys::Win32::System::Threading::{
+        GetProcessTimes, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    let handle = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION).ok()?;
+    let empty = FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut creation, mut exit_time, mut kernel, mut user) = (empty, empty, empty, empty);
+
+    let ok = unsafe {
+        GetProcessTimes(
+            handle.0,
+            &mut creation,
+            &mut exit_time,
+            &mut kernel,
+            &mut user,
+        )
+    };
+
+    (ok != 0).then_some(((creation.dwHighDateTime as u64) << 32) | creation.dwLowDateTime as u64)
+}
+
+#[cfg(target_os = "linux")]
+fn process_name(pid: u32) -> Option<String> {
+    read_proc_stat(pid).map(|(name, _)| name)
+}
+
+#[cfg(target_os = "linux")]
+fn process_exe(pid: u32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{}/exe", pid)).ok()
+}
+
+#[cfg(target_os = "windows")]
+fn process_exe(pid: u32) -> Option<PathBuf> {
+    use windows_sys::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let handle = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION).ok()?;
+    process_exe_path(&handle)
+}
+
+#[cfg(target_os = "windows")]
+fn process_name(pid: u32) -> Option<String> {
+    process_exe(pid).and_then(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+}
+
+/// Original settings of a process affinity-rs changed after it was started.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AppliedChange {
+    pid: u32,
+    #[serde(default)]
+    start_time: Option<u64>,
+    name: String,
+    original_cpus: Option<Vec<usize>>,
+    original_priority: Option<ProcessPriority>,
+}
+
+fn get_undo_journal_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join(UNDO_FILE_NAME))
+}
+
+fn load_undo_journal() -> Result<Vec<AppliedChange>> {
+    let path = get_undo_journal_path()?;
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read undo journal")?;
+    serde_json::from_str(&data).context("Failed to parse undo journal")
+}
+
+fn save_undo_journal(journal: &[AppliedChange]) -> Result<()> {
+    let data = serde_json::to_string_pretty(journal).context("Failed to serialize undo journal")?;
+    let path = get_undo_journal_path()?;
+    // The daemon applies over and over; an unchanged journal is left alone
+    if std::fs::read_to_string(&path).is_ok_and(|current| current == data) {
+        return Ok(());
+    }
+    std::fs::write(path, data).context("Failed to write undo journal")?;
+    Ok(())
+}
+
+/// Writes an audit record of a change to the OS log, if enabled in settings.
+fn audit_change(
+    initiator: &str,
+    pid: u32,
+    process: Option<&str>,
+    old_cpus: Option<&[usize]>,
+    new_cpus: Option<&[usize]>,
+    old_priority: Option<&ProcessPriority>,
+    new_priority: Option<&ProcessPriority>,
+) {
+    if !settings().audit_log {
+        return;
+    }
+
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "?".to_string());
+    // Looked up for this one process only when the caller doesn't know it
+    let exe = match process {
+        Some(process) => process.to_string(),
+        None => process_exe(pid)
+            .map(|exe| exe.display().to_string())
+            .or_else(|| process_name(pid))
+            .unwrap_or_else(|| "?".to_string()),
+    };
+
+    let mut message = format!("{} by {}: PID {} ({})", initiator, user, pid, exe);
+    if let Some(new_cpus) = new_cpus {
+        message.push_str(&format!(
+            ", affinity {} -> {}",
+            old_cpus.map_or("?".to_string(), cpu_mask_string),
+            cpu_mask_string(new_cpus)
+        ));
+    }
+    if let Some(new_priority) = new_priority {
+        message.push_str(&format!(
+            ", priority {} -> {}",
+            old_priority.map_or("?", |p| p.display_name()),
+            new_priority.display_name()
+        ));
+    }
+
+    write_audit_log(&message);
+}
+
+#[cfg(target_os = "linux")]
+fn write_audit_log(message: &str) {
+    let Ok(message) = std::ffi::CString::new(message) else {
+        return;
+    };
+
+    // journald picks syslog messages up as well
+    unsafe {
+        libc::openlog(c"affinity-rs".as_ptr(), libc::LOG_PID, libc::LOG_USER);
+        libc::syslog(libc::LOG_NOTICE, c"%s".as_ptr(), message.as_ptr());
+        libc::closelog();
+    }
+}
+
+/// Event log source the audit records are written under.
+#[cfg(target_os = "windows")]
+const EVENT_SOURCE_KEY: &str =
+    r"HKLM\SYSTEM\CurrentControlSet\Services\EventLog\Application\affinity-rs";
+
+/// Registers the `affinity-rs` event source in the Application log, so Event
+/// Viewer shows the records' text. EventCreate.exe's messages pass the text
+/// through as is. Writing the key needs administrator rights, so this is
+/// tried once per run; until it succeeds, records still land in the
+/// Application log, just with a note about the missing source.
+#[cfg(target_os = "windows")]
+fn register_event_source() {
+    static REGISTERED: OnceLock<()> = OnceLock::new();
+
+    REGISTERED.get_or_init(|| {
+        let exists = Command::new("reg")
+            .args(["query", EVENT_SOURCE_KEY])
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if exists {
+            return;
+        }
+
+        let values = [
+            (
+                "EventMessageFile",
+                "REG_EXPAND_SZ",
+                r"%SystemRoot%\System32\EventCreate.exe",
+            ),
+            ("TypesSupported", "REG_DWORD", "7"),
+        ];
+        let registered = values.iter().all(|(name, kind, data)| {
+            Command::new("reg")
+                .args([
+                    "add",
+                    EVENT_SOURCE_KEY,
+                    "/v",
+                    name,
+                    "/t",
+                    kind,
+                    "/d",
+                    data,
+                    "/f",
+                ])
+                .output()
+                .is_ok_and(|output| output.status.success())
+        });
+        if !registered {
+            eprintln!(
+                "Note: Run affinity-rs once as administrator to register its event log source."
+            );
+        }
+    });
+}
+
+#[cfg(target_os = "windows")]
+fn write_audit_log(message: &str) {
+    use windows_sys::Win32::System::EventLog::{
+        DeregisterEventSource, EVENTLOG_INFORMATION_TYPE, RegisterEventSourceW, ReportEventW,
+    };
+
+    register_event_source();
+
+    let source: Vec<u16> = "affinity-rs".encode_utf16().chain(Some(0)).collect();
+    let message: Vec<u16> = message.encode_utf16().chain(Some(0)).collect();
+    let strings = [message.as_ptr()];
+
+    unsafe {
+        let log = RegisterEventSourceW(std::ptr::null(), source.as_ptr());
+        if log.is_null() {
+            return;
+        }
+
+        ReportEventW(
+            log,
+            EVENTLOG_INFORMATION_TYPE,
+            0,
+            1000,
+            std::ptr::null_mut(),
+            1,
+            0,
+            strings.as_ptr(),
+            std::ptr::null(),
+        );
+        DeregisterEventSource(log);
+    }
+}
+
+/// Records the current settings of `pid` unless they were already recorded,
+/// so undo always returns to the state before affinity-rs first touched it.
+fn remember_original_settings(pid: u32) -> Result<()> {
+    let mut journal = load_undo_journal()?;
+    let start_time = process_start_time(pid);
+
+    if journal
+        .iter()
+        .any(|change| change.pid == pid && change.start_time == start_time)
+    {
+        return Ok(());
+    }
+
+    // Entries for a different process that reused this PID are stale
+    journal.retain(|change| change.pid != pid);
+    journal.push(AppliedChange {
+        pid,
+        start_time,
+        name: process_name(pid).unwrap_or_else(|| "?".to_string()),
+        original_cpus: get_process_affinity(pid).ok(),
+        original_priority: get_process_priority(pid).ok(),
+    });
+
+    save_undo_journal(&journal)
+}
+
+/// Changes the settings of an already running process, remembering the
+/// originals for `undo`. `initiator` names the command for the audit log.
+fn apply_to_pid(
+    pid: u32,
+    cpus: Option<&[usize]>,
+    priority: Option<&ProcessPriority>,
+    initiator: &str,
+) -> Result<()> {
+    // No cores means the affinity is left as it is
+    let cpus = cpus.filter(|cpus| !cpus.is_empty());
+
+    if let Err(e) = remember_original_settings(pid) {
+        eprintln!(
+            "Warning: Original settings of PID {} could not be recorded: {:#}",
+            pid, e
+        );
+    }
+
+    let old_cpus = get_process_affinity(pid).ok();
+    let old_priority = get_process_priority(pid).ok();
+
+    if let Some(cpus) = cpus {
+        set_process_affinity(pid, cpus)?;
+    }
+
+    if let Some(priority) = priority {
+        set_process_priority(pid, priority)?;
+    }
+
+    audit_change(
+        initiator,
+        pid,
+        None,
+        old_cpus.as_deref(),
+        cpus,
+        old_priority.as_ref(),
+        priority,
+    );
+
+    Ok(())
+}
+
+/// Applies a profile's affinity and priority to one running process.
+fn apply_profile_settings(profile: &Profile, pid: u32) -> Result<()> {
+    apply_to_pid(pid, Some(&profile.cpus), profile.priority.as_ref(), "apply")?;
+
+    #[cfg(target_os = "linux")]
+    if let Some(nice) = profile.nice {
+        set_process_nice(pid, nice)?;
+    }
+
+    Ok(())
+}
+
+fn apply_profile_to_pid(
+    profiles: &Profiles,
+    keyword: &str,
+    pid: u32,
+    migrate_memory: bool,
+    tree: bool,
+    only: Option<ApplyOnly>,
+) -> Result<()> {
+    let mut profile = inherited_profile(profiles, keyword)?;
+    if only.is_some() {
+        profile.only = only;
+    }
+    let profile = profile.resolved();
+
+    apply_profile_settings(&profile, pid)?;
+
+    println!(
+        "Applied profile '{}' to PID {} ({})",
+        keyword,
+        pid,
+        process_name(pid).unwrap_or_else(|| "?".to_string())
+    );
+    if profile.cpus.is_empty() {
+        println!("CPU affinity: unchanged");
+    } else {
+        println!("CPU affinity: {:?}", profile.cpus);
+    }
+    if let Some(ref priority) = profile.priority {
+        println!("Priority: {}", priority.display_name());
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(nice) = profile.nice {
+        println!("Nice: {}", nice);
+    }
+
+    // Everything the process started, such as a browser's renderers
+    let descendants = if tree {
+        ProcessTree::new(pid).update(&list_processes()?)
+    } else {
+        Vec::new()
+    };
+
+    let mut applied = 0;
+    for &child in &descendants {
+        // Processes may exit while the tree is walked
+        match apply_profile_settings(&profile, child) {
+            Ok(_) => applied += 1,
+            Err(e) if process_name(child).is_some() => eprintln!("Warning: {:#}", e),
+            Err(_) => {}
+        }
+    }
+    if tree {
+        println!(
+            "Applied to {} of {} descendant process(es)",
+            applied,
+            descendants.len()
+        );
+    }
+
+    if migrate_memory && profile.cpus.is_empty() {
+        eprintln!("Note: --migrate-memory needs the profile's cores, which were left unchanged.");
+    } else if migrate_memory {
+        // The affinity already holds, so a failed migration is only worth a warning
+        for pid in std::iter::once(pid).chain(descendants) {
+            if let Err(e) = migrate_memory_to_cpus(pid, &profile.cpus) {
+                eprintln!("Warning: Could not migrate memory: {:#}", e);
+            }
+        }
+    }
+
+    if applied > 0 {
+        println!("Run 'affinity-rs undo --all' to restore the original settings.");
+    } else {
+        println!(
+            "Run 'affinity-rs undo {}' to restore the original settings.",
+            pid
+        );
+    }
+
+    Ok(())
+}
+
+/// Status of a Windows service, including its type and the PID it runs in.
+#[cfg(target_os = "windows")]
+fn service_status(
+    name: &str,
+) -> Result<windows_sys::Win32::System::Services::SERVICE_STATUS_PROCESS> {
+    use windows_sys::Win32::System::Services::{
+        CloseServiceHandle, OpenSCManagerW, OpenServiceW, QueryServiceStatusEx, SC_MANAGER_CONNECT,
+        SC_STATUS_PROCESS_INFO, SERVICE_QUERY_STATUS, SERVICE_STATUS_PROCESS,
+    };
+
+    let service_name: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+
+    unsafe {
+        let manager = OpenSCManagerW(std::ptr::null(), std::ptr::null(), SC_MANAGER_CONNECT);
+        if manager.is_null() {
+            bail!(
+                "Failed to open the service manager: {}",
+                io::Error::last_os_error()
+            );
+        }
+
+        let service = OpenServiceW(manager, service_name.as_ptr(), SERVICE_QUERY_STATUS);
+        if service.is_null() {
+            let error = io::Error::last_os_error();
+            CloseServiceHandle(manager);
+            bail!("Could not open service '{}': {}", name, error);
+        }
+
+        let mut status: SERVICE_STATUS_PROCESS = std::mem::zeroed();
+        let mut needed: u32 = 0;
+        let ok = QueryServiceStatusEx(
+            service,
+            SC_STATUS_PROCESS_INFO,
+            &mut status as *mut _ as *mut u8,
+            std::mem::size_of::<SERVICE_STATUS_PROCESS>() as u32,
+            &mut needed,
+        );
+        let error = io::Error::last_os_error();
+
+        CloseServiceHandle(service);
+        CloseServiceHandle(manager);
+
+        if ok == 0 {
+            bail!("Could not query service '{}': {}", name, error);
+        }
+        Ok(status)
+    }
+}
+
+/// Whether the service is configured to share an svchost process with others.
+#[cfg(target_os = "windows")]
+fn is_shared_service(
+    status: &windows_sys::Win32::System::Services::SERVICE_STATUS_PROCESS,
+) -> bool {
+    use windows_sys::Win32::System::Services::SERVICE_WIN32_SHARE_PROCESS;
+
+    status.dwServiceType & SERVICE_WIN32_SHARE_PROCESS != 0
+}
+
+/// PID of a running Windows service, looked up by its service name. Services
+/// sharing a process are refused, as the profile would hit all of them.
+#[cfg(target_os = "windows")]
+fn service_pid(name: &str) -> Result<u32> {
+    let status = service_status(name)?;
+    if is_shared_service(&status) {
+        bail!(
+            "Service '{}' shares its process with other services, which would be changed too. \
+             Give it a process of its own with 'sc config {} type= own' and restart it.",
+            name,
+            name
+        );
+    }
+    if status.dwProcessId == 0 {
+        bail!("Service '{}' is not running", name);
+    }
+    Ok(status.dwProcessId)
+}
+
+/// Main PID of a running systemd service, system-wide or of the current user.
+#[cfg(target_os = "linux")]
+fn service_pid(name: &str) -> Result<u32> {
+    for scope in ["--system", "--user"] {
+        let output = Command::new("systemctl")
+            .args([scope, "show", "--property=MainPID", "--value", name])
+            .output()
+            .context("Failed to run systemctl")?;
+
+        let pid = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse::<u32>()
+            .unwrap_or(0);
+        if pid != 0 {
+            return Ok(pid);
+        }
+    }
+
+    bail!("Service '{}' is not running", name)
+}
+
+/// Moves the pages a process already allocated to the NUMA node(s) of the
+/// given CPUs. Affinity alone only places new allocations there.
+#[cfg(target_os = "linux")]
+fn migrate_memory_to_cpus(pid: u32, cpus: &[usize]) -> Result<()> {
+    let nodes = &topology::current().nodes;
+    if nodes.len() < 2 {
+        println!("Only one NUMA node on this machine: no memory to migrate.");
+        return Ok(());
+    }
+
+    let targets: Vec<usize> = nodes
+        .iter()
+        .filter(|(_, node_cpus)| node_cpus.iter().any(|cpu| cpus.contains(cpu)))
+        .map(|(node, _)| *node)
+        .collect();
+    if targets.is_empty() {
+        bail!("The profile's CPUs belong to no NUMA node");
+    }
+
+    // Node masks as the kernel's unsigned long bitmaps
+    let max_node = nodes.last().map_or(0, |(node, _)| *node) + 1;
+    let words = max_node.div_ceil(libc::c_ulong::BITS as usize);
+    let mask = |nodes: &mut dyn Iterator<Item = usize>| {
+        let mut mask: Vec<libc::c_ulong> = vec![0; words];
+        for node in nodes {
+            mask[node / libc::c_ulong::BITS as usize] |= 1 << (node % libc::c_ulong::BITS as usize);
+        }
+        mask
+    };
+    let from = mask(&mut nodes.iter().map(|(node, _)| *node));
+    let to = mask(&mut targets.iter().copied());
+
+    let not_moved = unsafe {
+        libc::syscall(
+            libc::SYS_migrate_pages,
+            pid as libc::pid_t,
+            (words * libc::c_ulong::BITS as usize) as libc::c_ulong,
+            from.as_ptr(),
+            to.as_ptr(),
+        )
+    };
+    if not_moved < 0 {
+        bail!(
+            "Failed to migrate memory of PID {}: {}",
+            pid,
+            io::Error::last_os_error()
+        );
+    }
+
+    println!("Memory migrated to NUMA node(s) {:?}", targets);
+    if not_moved > 0 {
+        println!(
+            "{} page(s) could not be moved (shared or locked).",
+            not_moved
+        );
+    }
+    Ok(())
+}
+
+/// Windows has no supported way to move another process's existing pages;
+/// they follow the threads' ideal nodes only for new allocations.
+#[cfg(target_os = "windows")]
+fn migrate_memory_to_cpus(_pid: u32, _cpus: &[usize]) -> Result<()> {
+    bail!("Moving memory that is already allocated is only supported on Linux")
+}
+
+/// Parses a duration such as "90s", "10m", "1h" or a plain number of seconds.
+fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => input.split_at(index),
+        None => (input, "s"),
+    };
+
+    let value: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", input))?;
+    let seconds = match unit {
+        "s" => Some(value),
+        "m" => value.checked_mul(60),
+        "h" => value.checked_mul(3600),
+        _ => bail!("Invalid duration unit '{}': use s, m or h", unit),
+    };
+
+    seconds
+        .map(Duration::from_secs)
+        .with_context(|| format!("Duration '{}' is too long", input))
+}
+
+/// Registers a global hotkey such as `ctrl+alt+b` and returns a channel that
+/// receives every press.
+#[cfg(target_os = "windows")]
+fn watch_hotkey(hotkey: &str) -> Result<mpsc::Receiver<()>> {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+        MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN, RegisterHotKey, VK_F1,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+
+    let mut modifiers = MOD_NOREPEAT;
+    let mut key = None;
+    for part in hotkey.split('+').map(str::to_ascii_lowercase) {
+        match part.as_str() {
+            "ctrl" | "control" => modifiers |= MOD_CONTROL,
+            "alt" => modifiers |= MOD_ALT,
+            "shift" => modifiers |= MOD_SHIFT,
+            "win" => modifiers |= MOD_WIN,
+            // Letters and digits are their own virtual-key codes
+            name if name.len() == 1 && name.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                key = Some(name.to_ascii_uppercase().as_bytes()[0] as u32)
+            }
+            name => match name.strip_prefix('f').and_then(|n| n.parse::<u32>().ok()) {
+                Some(n @ 1..=24) => key = Some((VK_F1 as u32) + n - 1),
+                _ => bail!("Unknown key '{}' in hotkey '{}'", name, hotkey),
+            },
+        }
+    }
+    let Some(key) = key else {
+        bail!("Hotkey '{}' has no key besides the modifiers", hotkey);
+    };
+
+    // WM_HOTKEY goes to the thread that registered the hotkey
+    let (presses, receiver) = mpsc::channel();
+    let (registered, result) = mpsc::channel();
+    thread::spawn(move || unsafe {
+        if RegisterHotKey(std::ptr::null_mut(), 1, modifiers, key) == 0 {
+            let _ = registered.send(Err(io::Error::last_os_error()));
+            return;
+        }
+        let _ = registered.send(Ok(()));
+
+        let mut message: MSG = std::mem::zeroed();
+        while GetMessageW(&mut message, std::ptr::null_mut(), 0, 0) > 0 {
+            if message.message == WM_HOTKEY && presses.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    result
+        .recv()
+        .context("Hotkey thread ended")?
+        .with_context(|| format!("Could not register hotkey '{}' (already in use?)", hotkey))?;
+    Ok(receiver)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn watch_hotkey(_hotkey: &str) -> Result<mpsc::Receiver<()>> {
+    bail!("--hotkey is only available on Windows")
+}
+
+/// Applies a profile to a running process for a limited time, then puts the
+/// process back the way it was. Pressing Enter ends the boost early. With a
+/// `hotkey`, the boost starts when it is pressed and ends when it is pressed
+/// again.
+fn boost_pid(
+    profiles: &Profiles,
+    keyword: &str,
+    pid: u32,
+    duration: Duration,
+    hotkey: Option<&str>,
+) -> Result<()> {
+    let profile = inherited_profile(profiles, keyword)?.resolved();
+
+    let presses = hotkey.map(watch_hotkey).transpose()?;
+    if let (Some(presses), Some(hotkey)) = (&presses, hotkey) {
+        println!("Press {} to boost PID {}.", hotkey, pid);
+        presses.recv().context("Hotkey thread ended")?;
+    }
+
+    // Compared before reverting, in case the PID was reused in the meantime
+    let start_time = process_start_time(pid);
+    let previous_cpus = get_process_affinity(pid).ok();
+    let previous_priority = get_process_priority(pid).ok();
+
+    apply_to_pid(pid, Some(&profile.cpus), profile.priority.as_ref(), "boost")?;
+
+    println!(
+        "Boosted PID {} ({}) with profile '{}' for {}s.",
+        pid,
+        process_name(pid).unwrap_or_else(|| "?".to_string()),
+        keyword,
+        duration.as_secs()
+    );
+    match hotkey {
+        Some(hotkey) => println!("Press {} or Enter to end the boost early.", hotkey),
+        None => println!("Press Enter to end the boost early."),
+    }
+
+    // Ctrl+C would leave the process boosted, so only Enter or the timer end it
+    ignore_console_interrupts();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    if let Some(presses) = presses {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            if presses.recv().is_ok() {
+                let _ = sender.send(());
+            }
+        });
+    }
+    thread::spawn(move || {
+        let mut line = String::new();
+        // Without a console (EOF) the boost just runs for its duration
+        if io::stdin().read_line(&mut line).is_ok_and(|read| read > 0) {
+            let _ = sender.send(());
+        }
+    });
+    let deadline = Instant::now() + duration;
+    if let Err(std::sync::mpsc::RecvTimeoutError::Disconnected) = receiver.recv_timeout(duration) {
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+
+    if start_time.is_none() || process_start_time(pid) != start_time {
+        println!("PID {} has exited; nothing to revert.", pid);
+        return Ok(());
+    }
+
+    let current_cpus = get_process_affinity(pid).ok();
+    let current_priority = get_process_priority(pid).ok();
+
+    if let Some(ref cpus) = previous_cpus {
+        set_process_affinity(pid, cpus)?;
+    }
+    if let Some(ref priority) = previous_priority {
+        set_process_priority(pid, priority)?;
+    }
+
+    audit_change(
+        "boost revert",
+        pid,
+        None,
+        current_cpus.as_deref(),
+        previous_cpus.as_deref(),
+        current_priority.as_ref(),
+        previous_priority.as_ref(),
+    );
+
+    println!("Boost ended; PID {} restored.", pid);
+    Ok(())
+}
+
+/// Restores recorded original settings for one PID, or for all when `None`.
+fn undo_changes(target: Option<u32>) -> Result<()> {
+    let journal = load_undo_journal()?;
+    let (selected, mut remaining): (Vec<_>, Vec<_>) = journal
+        .into_iter()
+        .partition(|change| target.is_none_or(|pid| change.pid == pid));
+
+    if selected.is_empty() {
+        match target {
+            Some(pid) => println!("No recorded changes for PID {}.", pid),
+            None => println!("No recorded changes to undo."),
+        }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+
+    for change in selected {
+        if process_start_time(change.pid) != change.start_time {
+            println!(
+                "PID {} ({}) is no longer running; nothing to restore.",
+                change.pid, change.name
+            );
+            continue;
+        }
+
+        let old_cpus = get_process_affinity(change.pid).ok();
+        let old_priority = get_process_priority(change.pid).ok();
+
+        let result = (|| -> Result<()> {
+            if let Some(ref cpus) = change.original_cpus {
+                set_process_affinity(change.pid, cpus)?;
+            }
+            if let Some(ref priority) = change.original_priority {
+                set_process_priority(change.pid, priority)?;
+            }
+            Ok(())
+        })();
+
+        if result.is_ok() {
+            audit_change(
+                "undo",
+                change.pid,
+                Some(&change.name),
+                old_cpus.as_deref(),
+                change.original_cpus.as_deref(),
+                old_priority.as_ref(),
+                change.original_priority.as_ref(),
+            );
+        }
+
+        match result {
+            Ok(_) => println!(
+                "Restored PID {} ({}) to its original settings.",
+                change.pid, change.name
+            ),
+            Err(e) => {
+                eprintln!("Error restoring PID {}: {:#}", change.pid, e);
+                failed += 1;
+                // Keep the entry so the undo can be retried
+                remaining.push(change);
+            }
+        }
+    }
+
+    save_undo_journal(&remaining)?;
+
+    if failed > 0 {
+        bail!("{} process(es) could not be restored", failed);
+    }
+
+    Ok(())
+}
+
+/// A program started by affinity-rs, for `stop-all`. The start time tells it
+/// apart from a later process that got the same PID.
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionProcess {
+    pid: u32,
+    #[serde(default)]
+    start_time: Option<u64>,
+    name: String,
+}
+
+impl SessionProcess {
+    /// Whether the recorded process still runs. Without a recorded start
+    /// time a reused PID can't be told apart, so it never counts as running.
+    fn is_running(&self) -> bool {
+        self.start_time.is_some() && process_start_time(self.pid) == self.start_time
+    }
+}
+
+fn load_session() -> Result<Vec<SessionProcess>> {
+    let path = get_config_dir()?.join(SESSION_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read session file")?;
+    serde_json::from_str(&data).context("Failed to parse session file")
+}
+
+fn save_session(session: &[SessionProcess]) -> Result<()> {
+    let data = serde_json::to_string_pretty(session).context("Failed to serialize session")?;
+    std::fs::write(get_config_dir()?.join(SESSION_FILE_NAME), data)
+        .context("Failed to write session file")?;
+    Ok(())
+}
+
+/// Adds a launched program to the session, dropping programs that exited.
+fn remember_session_process(pid: u32, profile: &Profile) -> Result<()> {
+    let mut session = load_session()?;
+    session.retain(|process| process.pid != pid && process.is_running());
+
+    session.push(SessionProcess {
+        pid,
+        start_time: process_start_time(pid),
+        name: profile
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    });
+    save_session(&session)
+}
+
+/// Asks a process to exit, or kills it with `force`.
+#[cfg(target_os = "linux")]
+fn terminate_process(pid: u32, force: bool) -> Result<()> {
+    let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        bail!("Failed to stop PID {}: {}", pid, io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Asks a process to exit, or kills it with `force`.
+#[cfg(target_os = "windows")]
+fn terminate_process(pid: u32, force: bool) -> Result<()> {
+    // Without /F, taskkill closes the windows as clicking X would
+    let mut cmd = Command::new("taskkill");
+    cmd.args(["/PID", &pid.to_string()]);
+    if force {
+        cmd.arg("/F");
+    }
+
+    let output = cmd.output().context("Failed to run taskkill")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to stop PID {}: {}",
+            pid,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Stops every program affinity-rs launched that is still running: asks
+/// them to close and waits for them, or kills them outright with `force`.
+fn stop_all(force: bool) -> Result<()> {
+    let (unknown, session): (Vec<SessionProcess>, Vec<SessionProcess>) = load_session()?
+        .into_iter()
+        .partition(|process| process.start_time.is_none());
+    for process in &unknown {
+        eprintln!(
+            "Skipping {} (PID {}): its start time wasn't recorded, so the PID may belong to another program now",
+            process.name, process.pid
+        );
+    }
+    let mut running: Vec<SessionProcess> = session
+        .into_iter()
+        .filter(SessionProcess::is_running)
+        .collect();
+
+    if running.is_empty() {
+        println!("No programs launched by affinity-rs are running.");
+        return save_session(&[]);
+    }
+
+    running.retain(|process| match terminate_process(process.pid, force) {
+        Ok(_) => true,
+        Err(e) => {
+            // Already gone, or not ours to stop
+            if process.is_running() {
+                eprintln!("Warning: {:#}", e);
+            }
+            false
+        }
+    });
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    loop {
+        running.retain(|process| {
+            let exited = !process.is_running();
+            if exited {
+                println!("Stopped {} (PID {})", process.name, process.pid);
+            }
+            !exited
+        });
+
+        if running.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+
+    for process in &running {
+        eprintln!(
+            "Still running after {}s: {} (PID {})",
+            STOP_TIMEOUT.as_secs(),
+            process.name,
+            process.pid
+        );
+    }
+    if !running.is_empty() && !force {
+        eprintln!("Run 'affinity-rs stop-all --force' to kill them.");
+    }
+
+    save_session(&running)
+}
+
+/// Formats a CPU list as a hex affinity mask, e.g. [0, 1, 3] -> "0xB".
+fn cpu_mask_string(cpus: &[usize]) -> String {
+    if cpus.iter().any(|&cpu| cpu >= 128) {
+        return format!("{} CPUs", cpus.len());
+    }
+
+    let mask = cpus.iter().fold(0u128, |mask, &cpu| mask | (1 << cpu));
+    format!("0x{:X}", mask)
+}
+
+#[cfg(target_os = "linux")]
+fn read_secret(name: &str) -> Result<String> {
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", SECRET_SERVICE, "key", name])
+        .output()
+        .context("Failed to run 'secret-tool'. Is libsecret-tools installed?")?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        bail!("Secret '{}' not found in the keyring", name);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn store_secret(name: &str, value: &str) -> Result<()> {
+    let mut child = Command::new("secret-tool")
+        .args([
+            "store",
+            &format!("--label=affinity-rs: {}", name),
+            "service",
+            SECRET_SERVICE,
+            "key",
+            name,
+        ])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to run 'secret-tool'. Is libsecret-tools installed?")?;
+
+    child
+        .stdin
+        .take()
+        .context("Failed to open secret-tool input")?
+        .write_all(value.as_bytes())
+        .context("Failed to pass secret to secret-tool")?;
+
+    if !child.wait()?.success() {
+        bail!("secret-tool could not store the secret");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn delete_secret(name: &str) -> Result<()> {
+    let status = Command::new("secret-tool")
+        .args(["clear", "service", SECRET_SERVICE, "key", name])
+        .status()
+        .context("Failed to run 'secret-tool'. Is libsecret-tools installed?")?;
+
+    if !status.success() {
+        bail!("secret-tool could not remove the secret");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn secret_target(name: &str) -> Vec<u16> {
+    format!("{}:{}", SECRET_SERVICE, name)
+        .encode_utf16()
+        .chain(Some(0))
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn read_secret(name: &str) -> Result<String> {
+    use windows_sys::Win32::Security::Credentials::{
+        CRED_TYPE_GENERIC, CREDENTIALW, CredFree, CredReadW,
+    };
+
+    let target = secret_target(name);
+    let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+
+    unsafe {
+        if CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+            bail!("Secret '{}' not found in Credential Manager", name);
+        }
+
+        // Stored as UTF-16, the same as `cmdkey /generic:affinity-rs:<name>` does
+        let blob = std::slice::from_raw_parts(
+            (*credential).CredentialBlob as *const u16,
+            (*credential).CredentialBlobSize as usize / 2,
+        );
+        let value = String::from_utf16_lossy(blob);
+        CredFree(credential as *const _);
+
+        Ok(value)
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn store_secret(name: &str, value: &str) -> Result<()> {
+    use windows_sys::Win32::Security::Credentials::{
+        CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC, CREDENTIALW, CredWriteW,
+    };
+
+    let mut target = secret_target(name);
+    let mut user: Vec<u16> = SECRET_SERVICE.encode_utf16().chain(Some(0)).collect();
+    let mut blob: Vec<u16> = value.encode_utf16().collect();
+
+    unsafe {
+        let mut credential: CREDENTIALW = std::mem::zeroed();
+        credential.Type = CRED_TYPE_GENERIC;
+        credential.TargetName = target.as_mut_ptr();
+        credential.UserName = user.as_mut_ptr();
+        credential.CredentialBlobSize = (blob.len() * 2) as u32;
+        credential.CredentialBlob = blob.as_mut_ptr() as *mut u8;
+        credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+
+        if CredWriteW(&credential, 0) == 0 {
+            bail!(
+                "Failed to store secret: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn delete_secret(name: &str) -> Result<()> {
+    use windows_sys::Win32::Security::Credentials::{CRED_TYPE_GENERIC, CredDeleteW};
+
+    let target = secret_target(name);
+    if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+        bail!(
+            "Failed to remove secret: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Replaces `{secret:<name>}` placeholders with values from the OS keyring.
+fn resolve_secrets(value: &str) -> Result<String> {
+    const PREFIX: &str = "{secret:";
+
+    let mut resolved = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find(PREFIX) {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + PREFIX.len()..start + len];
+
+        resolved.push_str(&rest[..start]);
+        resolved.push_str(&read_secret(name)?);
+        rest = &rest[start + len + 1..];
+    }
+
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// Copy of the profile and arguments with secret placeholders filled in,
+/// made right before spawning so secrets are never printed or saved.
+fn with_secrets(profile: &Profile, args: &[OsString]) -> Result<(Profile, Vec<OsString>)> {
+    let mut profile = profile.clone();
+    for value in profile.env.values_mut() {
+        *value = resolve_secrets(value)?;
+    }
+
+    // Arguments that aren't valid Unicode can't hold a placeholder and pass as is
+    let args = args
+        .iter()
+        .map(|arg| match arg.to_str() {
+            Some(arg) => resolve_secrets(arg).map(OsString::from),
+            None => Ok(arg.clone()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((profile, args))
+}
+
+/// SHA-256 of a file as lowercase hex.
+fn file_sha256(path: &Path) -> Result<String> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let digest = sha256(file).context("Failed to read executable")?;
+    Ok(to_hex(&digest))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// SHA-256 of everything `reader` yields.
+fn sha256(mut reader: impl io::Read) -> io::Result<[u8; 32]> {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut pending = Vec::with_capacity(128);
+    let mut length: u64 = 0;
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        length += read as u64;
+
+        let mut data = &buffer[..read];
+        if !pending.is_empty() {
+            let take = (64 - pending.len()).min(data.len());
+            pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if pending.len() == 64 {
+                compress(&mut state, &pending);
+                pending.clear();
+            }
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            compress(&mut state, block);
+        }
+        pending.extend_from_slice(blocks.remainder());
+    }
+
+    pending.push(0x80);
+    while pending.len() % 64 != 56 {
+        pending.push(0);
+    }
+    pending.extend_from_slice(&(length * 8).to_be_bytes());
+    for block in pending.chunks_exact(64) {
+        compress(&mut state, block);
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    Ok(digest)
+}
+
+/// HMAC-SHA-256 (RFC 2104) of `data`.
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    // Keys longer than a block are replaced by their hash
+    let hashed_key;
+    let key = if key.len() > 64 {
+        hashed_key = sha256(key).unwrap_or_default();
+        &hashed_key[..]
+    } else {
+        key
+    };
+
+    let mut inner_pad = [0x36u8; 64];
+    let mut outer_pad = [0x5cu8; 64];
+    for (i, byte) in key.iter().enumerate().take(64) {
+        inner_pad[i] ^= byte;
+        outer_pad[i] ^= byte;
+    }
+
+    // Reading from memory can't fail
+    let inner = sha256(io::Read::chain(&inner_pad[..], data)).unwrap_or_default();
+    sha256(io::Read::chain(&outer_pad[..], &inner[..])).unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    io::Read::read_exact(
+        &mut std::fs::File::open("/dev/urandom").context("Failed to open /dev/urandom")?,
+        &mut bytes,
+    )
+    .context("Failed to read /dev/urandom")?;
+    Ok(bytes)
+}
+
+#[cfg(target_os = "windows")]
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    use windows_sys::Win32::Security::Cryptography::{
+        BCRYPT_USE_SYSTEM_PREFERRED_RNG, BCryptGenRandom,
+    };
+
+    let mut bytes = vec![0u8; len];
+    let status = unsafe {
+        BCryptGenRandom(
+            std::ptr::null_mut(),
+            bytes.as_mut_ptr(),
+            len as u32,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    };
+    if status != 0 {
+        bail!(
+            "Failed to generate random bytes (NTSTATUS 0x{:08X})",
+            status as u32
+        );
+    }
+    Ok(bytes)
+}
+
+/// Refuses changes to a locked profile unless `--unlock` was given.
+fn ensure_unlocked(profile: &Profile, keyword: &str, unlock: bool) -> Result<()> {
+    if profile.locked && !unlock {
+        bail!(
+            "Profile '{}' is locked. Pass --unlock to change it anyway.",
+            keyword
+        );
+    }
+    Ok(())
+}
+
+/// Compares the executable against the checksum recorded in the profile.
+/// Returns whether the launch should go ahead.
+fn check_executable_checksum(
+    profiles: &mut Profiles,
+    keyword: &str,
+    options: &LaunchOptions,
+) -> Result<bool> {
+    let Some(profile) = profiles.get_mut(keyword) else {
+        return Ok(true);
+    };
+
+    // A missing executable is reported by profile validation
+    if !profile.path.exists() {
+        return Ok(true);
+    }
+
+    if options.rehash {
+        ensure_unlocked(profile, keyword, options.unlock)?;
+        let checksum = file_sha256(&profile.path)?;
+        if options.dry_run {
+            println!(
+                "Would record checksum for {}: {}",
+                profile.path.display(),
+                checksum
+            );
+            return Ok(true);
+        }
+        println!(
+            "Recorded checksum for {}: {}",
+            profile.path.display(),
+            checksum
+        );
+        profile.checksum = Some(checksum);
+        save_profiles(profiles)?;
+        return Ok(true);
+    }
+
+    let Some(ref expected) = profile.checksum else {
+        return Ok(true);
+    };
+
+    if file_sha256(&profile.path)? == *expected {
+        return Ok(true);
+    }
+
+    eprintln!(
+        "Warning: {} has changed since its checksum was recorded.",
+        profile.path.display()
+    );
+    eprintln!(
+        "This is expected after a game update, but could also mean the file was tampered with."
+    );
+    eprintln!(
+        "Run 'affinity-rs --rehash {}' to accept the new executable.",
+        keyword
+    );
+
+    let answer = read_line_or("Launch anyway? [y/N]: ", "n")?;
+    Ok(answer.eq_ignore_ascii_case("y"))
+}
+
+fn validate_profile(profile: &Profile) -> Result<()> {
+    check_profile_settings(profile)?;
+
+    // Check the CPU indices against the CPUs this machine actually has
+    let topology = topology::current();
+    let (offline, missing) = unavailable_cpus(profile);
+
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: Profile references CPUs {:?}, which this system does not have ({} logical CPUs)",
+            missing,
+            topology.cpus.len() + topology.offline.len()
+        );
+    }
+    if !offline.is_empty() {
+        eprintln!("Warning: CPUs {:?} are offline right now", offline);
+    }
+    if !missing.is_empty() || !offline.is_empty() {
+        eprintln!("Some CPU assignments may be ignored by the OS.");
+    }
+
+    // Spreading over every CPU is deliberate, a subset crossing nodes usually isn't
+    let spanned: Vec<usize> = topology
+        .nodes
+        .iter()
+        .filter(|(_, cpus)| cpus.iter().any(|cpu| profile.cpus.contains(cpu)))
+        .map(|(node, _)| *node)
+        .collect();
+    if spanned.len() > 1 && profile.cpus.len() < topology.cpus.len() {
+        eprintln!(
+            "Note: The profile's CPUs span NUMA nodes {:?}; memory access across nodes is slower.",
+            spanned
+        );
+    }
+
+    Ok(())
+}
+
+/// The checks of `validate_profile` that make a profile unusable, without
+/// printing anything.
+fn check_profile_settings(profile: &Profile) -> Result<()> {
+    if !profile.path.exists() {
+        bail!(
+            "Executable not found: {}\nThe file may have been moved or deleted.",
+            profile.path.display()
+        );
+    }
+
+    if profile.cpus.is_empty() && profile.only != Some(ApplyOnly::Priority) {
+        bail!("Profile has no CPU cores configured");
+    }
+
+    if profile.dscp.is_some_and(|dscp| dscp > 63) {
+        bail!("dscp must be between 0 and 63");
+    }
+
+    if profile.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+        bail!("nice must be between -20 and 19");
+    }
+
+    if profile.reapply_interval == Some(0) {
+        bail!("reapply_interval must be at least 1 second");
+    }
+
+    Ok(())
+}
+
+/// The profile's CPUs that are offline, and those this machine doesn't have.
+fn unavailable_cpus(profile: &Profile) -> (Vec<usize>, Vec<usize>) {
+    let topology = topology::current();
+    profile
+        .cpus
+        .iter()
+        .copied()
+        .filter(|&cpu| !topology.is_online(cpu))
+        .partition(|cpu| topology.offline.contains(cpu))
+}
+
+#[cfg(target_os = "windows")]
+#[link(name = "gdi32")]
+unsafe extern "system" {
+    // Not exposed by windows-sys; returns an NTSTATUS
+    fn D3DKMTSetProcessSchedulingPriorityClass(
+        process: windows_sys::Win32::Foundation::HANDLE,
+        priority: i32,
+    ) -> i32;
+}
+
+#[cfg(target_os = "windows")]
+fn launch_with_retry<F>(attempts: usize, initial_delay_ms: u64, mut operation: F) -> Result<bool>
+where
+    F: FnMut(usize) -> Result<bool>,
+{
+    for attempt in 1..=attempts {
+        let delay = if attempt == 1 {
+            initial_delay_ms
+        } else {
+            // Exponential backoff with cap at 1000ms
+            (initial_delay_ms * 2_u64.pow((attempt - 1) as u32)).min(1000)
+        };
+
+        thread::sleep(Duration::from_millis(delay));
+
+        match operation(attempt) {
+            Ok(true) => return Ok(true), // Success
+            Ok(false) => continue,       // Retry
+            Err(e) => {
+                if attempt == attempts {
+                    return Err(e);
+                }
+                eprintln!(
+                    "Attempt {}/{} failed: {}. Retrying...",
+                    attempt, attempts, e
+                );
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(target_os = "linux")]
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// Registry key under which Windows Game Mode and Game Bar look up games.
+#[cfg(target_os = "windows")]
+const GAME_CONFIG_STORE_KEY: &str = r"HKCU\System\GameConfigStore\Children";
+
+/// Stable per-executable key name, so registering twice updates one entry.
+#[cfg(target_os = "windows")]
+fn game_config_key(exe: &Path) -> String {
+    // FNV-1a, which unlike DefaultHasher stays the same across Rust releases
+    let normalized = exe.display().to_string().to_lowercase();
+    let hash = normalized
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    format!(r"{}\affinity-rs-{:016x}", GAME_CONFIG_STORE_KEY, hash)
+}
+
+/// Marks the executable as a game in the system game configuration store.
+/// Microsoft doesn't document the store, so this is best-effort: Game Mode
+/// may ignore the entry, and callers only warn when it can't be written.
+#[cfg(target_os = "windows")]
+fn register_game(exe: &Path) -> Result<()> {
+    let key = game_config_key(exe);
+
+    let already_registered = Command::new("reg")
+        .args(["query", &key])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+
+    if already_registered {
+        return Ok(());
+    }
+
+    let exe_str = exe.display().to_string();
+    let values: [(&str, &str, &str); 2] = [
+        ("MatchedExeFullPath", "REG_SZ", exe_str.as_str()),
+        ("Type", "REG_DWORD", "1"),
+    ];
+
+    for (name, kind, data) in values {
+        let status = Command::new("reg")
+            .args(["add", &key, "/v", name, "/t", kind, "/d", data, "/f"])
+            .output()
+            .context("Failed to run reg.exe")?
+            .status;
+
+        if !status.success() {
+            bail!("Could not write {}\\{}", key, name);
+        }
+    }
+
+    println!(
+        "Registered {} as a game for Windows Game Mode",
+        exe.display()
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn unregister_game(exe: &Path) -> Result<()> {
+    let status = Command::new("reg")
+        .args(["delete", &game_config_key(exe), "/f"])
+        .output()
+        .context("Failed to run reg.exe")?
+        .status;
+
+    if !status.success() {
+        bail!("Game registration not found");
+    }
+
+    Ok(())
+}
+
+/// Registry key holding the Compatibility tab settings, one value per executable path.
+#[cfg(target_os = "windows")]
+const APP_COMPAT_LAYERS_KEY: &str =
+    r"HKCU\Software\Microsoft\Windows NT\CurrentVersion\AppCompatFlags\Layers";
+
+/// Compatibility layers currently set for the executable, without the `~` marker.
+#[cfg(target_os = "windows")]
+fn read_compat_layers(exe: &Path) -> Vec<String> {
+    let Ok(output) = Command::new("reg")
+        .args(["query", APP_COMPAT_LAYERS_KEY, "/v"])
+        .arg(exe)
+        .output()
+    else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.split_once("REG_SZ").map(|(_, data)| data.to_string()))
+        .map(|data| {
+            data.split_whitespace()
+                .filter(|layer| *layer != "~")
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "windows")]
+fn write_compat_layers(exe: &Path, layers: &[String]) -> Result<()> {
+    let mut cmd = Command::new("reg");
+    if layers.is_empty() {
+        cmd.args(["delete", APP_COMPAT_LAYERS_KEY, "/v"])
+            .arg(exe)
+            .arg("/f");
+    } else {
+        // "~" marks the layers as set by the user rather than by Windows itself
+        cmd.args(["add", APP_COMPAT_LAYERS_KEY, "/v"])
+            .arg(exe)
+            .args([
+                "/t",
+                "REG_SZ",
+                "/d",
+                &format!("~ {}", layers.join(" ")),
+                "/f",
+            ]);
+    }
+
+    let status = cmd.output().context("Failed to run reg.exe")?.status;
+    if !status.success() {
+        bail!("Could not write the compatibility settings");
+    }
+    Ok(())
+}
+
+/// Sets the profile's compatibility settings for the executable, keeping any
+/// others the user chose by hand. Returns whether anything changed.
+#[cfg(target_os = "windows")]
+fn apply_compat_flags(exe: &Path, flags: &[CompatFlag]) -> Result<bool> {
+    let current = read_compat_layers(exe);
+    let mut layers = current.clone();
+
+    for flag in flags {
+        // Only one DPI override can be in effect
+        if flag.is_dpi_override() {
+            let dpi_layers = ["HIGHDPIAWARE", "DPIUNAWARE", "GDIDPISCALING"];
+            layers.retain(|layer| !dpi_layers.contains(&layer.as_str()));
+        }
+        for layer in flag.layers() {
+            if !layers.iter().any(|l| l == layer) {
+                layers.push(layer.to_string());
+            }
+        }
+    }
+
+    if layers == current {
+        return Ok(false);
+    }
+    write_compat_layers(exe, &layers)?;
+    Ok(true)
+}
+
+/// Takes the profile's compatibility settings off the executable again.
+#[cfg(target_os = "windows")]
+fn remove_compat_flags(exe: &Path, flags: &[CompatFlag]) -> Result<()> {
+    let current = read_compat_layers(exe);
+    let layers: Vec<String> = current
+        .iter()
+        .filter(|layer| {
+            !flags
+                .iter()
+                .any(|flag| flag.layers().contains(&layer.as_str()))
+        })
+        .cloned()
+        .collect();
+
+    if layers != current {
+        write_compat_layers(exe, &layers)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+#[link(name = "ntdll")]
+unsafe extern "system" {
+    // Not exposed by windows-sys; returns an NTSTATUS
+    fn NtSetInformationProcess(
+        process: windows_sys::Win32::Foundation::HANDLE,
+        class: u32,
+        information: *const std::ffi::c_void,
+        length: u32,
+    ) -> i32;
+}
+
+/// Marks a process as background work: very low I/O priority, EcoQoS
+/// (efficiency cores, lower clocks) and low memory priority.
+#[cfg(target_os = "windows")]
+fn apply_background_mode(pid: u32) -> Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        MEMORY_PRIORITY_INFORMATION, MEMORY_PRIORITY_LOW, PROCESS_POWER_THROTTLING_CURRENT_VERSION,
+        PROCESS_POWER_THROTTLING_EXECUTION_SPEED, PROCESS_POWER_THROTTLING_STATE,
+        PROCESS_SET_INFORMATION, ProcessMemoryPriority, ProcessPowerThrottling,
+        SetProcessInformation,
+    };
+
+    // PROCESS_INFORMATION_CLASS::ProcessIoPriority and IoPriorityVeryLow
+    const PROCESS_IO_PRIORITY: u32 = 33;
+    const IO_PRIORITY_VERY_LOW: u32 = 0;
+
+    let handle = ProcessHandle::open(pid, PROCESS_SET_INFORMATION)?;
+    let mut failures = Vec::new();
+
+    unsafe {
+        let io_priority = IO_PRIORITY_VERY_LOW;
+        if NtSetInformationProcess(
+            handle.0,
+            PROCESS_IO_PRIORITY,
+            &io_priority as *const _ as *const _,
+            std::mem::size_of::<u32>() as u32,
+        ) != 0
+        {
+            failures.push("I/O priority");
+        }
+
+        let throttling = PROCESS_POWER_THROTTLING_STATE {
+            Version: PROCESS_POWER_THROTTLING_CURRENT_VERSION,
+            ControlMask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+            StateMask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+        };
+        if SetProcessInformation(
+            handle.0,
+            ProcessPowerThrottling,
+            &throttling as *const _ as *const _,
+            std::mem::size_of::<PROCESS_POWER_THROTTLING_STATE>() as u32,
+        ) == 0
+        {
+            failures.push("EcoQoS");
+        }
+
+        let memory = MEMORY_PRIORITY_INFORMATION {
+            MemoryPriority: MEMORY_PRIORITY_LOW,
+        };
+        if SetProcessInformation(
+            handle.0,
+            ProcessMemoryPriority,
+            &memory as *const _ as *const _,
+            std::mem::size_of::<MEMORY_PRIORITY_INFORMATION>() as u32,
+        ) == 0
+        {
+            failures.push("memory priority");
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!("Could not set {}", failures.join(", "));
+    }
+    Ok(())
+}
+
+/// Whether the kernel groups processes by session for scheduling, which makes
+/// per-process nice values only matter within a session.
+#[cfg(target_os = "linux")]
+fn autogroup_enabled() -> bool {
+    std::fs::read_to_string("/proc/sys/kernel/sched_autogroup_enabled")
+        .is_ok_and(|value| value.trim() == "1")
+}
+
+/// A device interrupt busy enough to disturb a latency-sensitive program.
+#[cfg(target_os = "linux")]
+struct HeavyIrq {
+    irq: String,
+    device: String,
+    cpus: Vec<usize>,
+}
+
+/// Interrupts with at least 1% of all device interrupts, and the CPUs that
+/// currently service them.
+#[cfg(target_os = "linux")]
+fn heavy_irqs() -> Result<Vec<HeavyIrq>> {
+    let data =
+        std::fs::read_to_string("/proc/interrupts").context("Failed to read /proc/interrupts")?;
+    let mut lines = data.lines();
+    let cpu_columns = lines
+        .next()
+        .map_or(0, |header| header.split_whitespace().count());
+
+    let mut irqs = Vec::new();
+    for line in lines {
+        let mut fields = line.split_whitespace();
+        let Some(irq) = fields.next().and_then(|f| f.strip_suffix(':')) else {
+            continue;
+        };
+        // Architecture counters such as LOC and NMI have no IRQ number
+        if !irq.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let fields: Vec<&str> = fields.collect();
+        let count: u64 = fields
+            .iter()
+            .take(cpu_columns)
+            .filter_map(|f| f.parse::<u64>().ok())
+            .sum();
+        let device = fields.last().copied().unwrap_or("?").to_string();
+        irqs.push((irq.to_string(), device, count));
+    }
+
+    let total: u64 = irqs.iter().map(|(_, _, count)| count).sum();
+
+    Ok(irqs
+        .into_iter()
+        .filter(|(_, _, count)| *count > 0 && *count * 100 >= total)
+        .filter_map(|(irq, device, _)| {
+            let list =
+                std::fs::read_to_string(format!("/proc/irq/{}/effective_affinity_list", irq))
+                    .or_else(|_| {
+                        std::fs::read_to_string(format!("/proc/irq/{}/smp_affinity_list", irq))
+                    })
+                    .ok()?;
+            let cpus = parse_cpu_list(list.trim()).ok()?;
+            Some(HeavyIrq { irq, device, cpus })
+        })
+        .collect())
+}
+
+/// Warns when busy device interrupts land on the profile's cores and suggests
+/// cores that are free of them.
+#[cfg(target_os = "linux")]
+fn warn_irq_conflicts(cpus: &[usize]) {
+    let irqs = match heavy_irqs() {
+        Ok(irqs) => irqs,
+        Err(e) => {
+            eprintln!("Warning: IRQ check skipped: {:#}", e);
+            return;
+        }
+    };
+
+    let mut conflict = false;
+    for irq in &irqs {
+        let shared: Vec<usize> = irq
+            .cpus
+            .iter()
+            .copied()
+            .filter(|cpu| cpus.contains(cpu))
+            .collect();
+
+        // An IRQ allowed on every core isn't pinned anywhere in particular
+        if !shared.is_empty() && irq.cpus.len() < num_cpus::get() {
+            eprintln!(
+                "Warning: IRQ {} ({}) is serviced on CPU {:?}, which this profile uses.",
+                irq.irq, irq.device, shared
+            );
+            conflict = true;
+        }
+    }
+
+    if !conflict {
+        return;
+    }
+
+    let free: Vec<usize> = (0..num_cpus::get())
+        .filter(|cpu| !irqs.iter().any(|irq| irq.cpus.contains(cpu)))
+        .collect();
+
+    if free.is_empty() {
+        eprintln!(
+            "Every core services a busy IRQ; consider moving IRQs with irqbalance or smp_affinity."
+        );
+    } else {
+        eprintln!("Cores without busy IRQs: {:?}", free);
+    }
+}
+
+/// systemd unit name of a profile slice, nested under affinity.slice.
+#[cfg(target_os = "linux")]
+fn slice_unit(name: &str) -> Result<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!(
+            "Invalid slice name '{}': use letters, digits and underscores",
+            name
+        );
+    }
+    Ok(format!("affinity-{}.slice", name))
+}
+
+/// Applies the slice's shared limits from settings.json.
+#[cfg(target_os = "linux")]
+fn configure_slice(name: &str) -> Result<()> {
+    let Some(slice) = settings().slices.get(name) else {
+        return Ok(());
+    };
+
+    let mut properties = Vec::new();
+    if let Some(weight) = slice.cpu_weight {
+        properties.push(format!("CPUWeight={}", weight));
+    }
+    if !slice.cpus.is_empty() {
+        let cpus: Vec<String> = slice.cpus.iter().map(usize::to_string).collect();
+        properties.push(format!("AllowedCPUs={}", cpus.join(",")));
+    }
+    if properties.is_empty() {
+        return Ok(());
+    }
+
+    let status = Command::new("systemctl")
+        .args(["--user", "set-property", "--runtime", &slice_unit(name)?])
+        .args(&properties)
+        .status()
+        .context("Failed to run systemctl")?;
+
+    if !status.success() {
+        bail!("systemctl could not configure slice '{}'", name);
+    }
+    Ok(())
+}
+
+/// Whether the unified cgroup v2 hierarchy is in use. Older distributions and
+/// some containers only mount the separate v1 controller hierarchies.
+#[cfg(target_os = "linux")]
+fn cgroup_v2() -> bool {
+    Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+}
+
+/// Mount point of a cgroup v1 controller hierarchy.
+#[cfg(target_os = "linux")]
+fn cgroup_v1_mount(controller: &str) -> Option<PathBuf> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (&mount, &kind, &options) = (fields.get(1)?, fields.get(2)?, fields.get(3)?);
+        (kind == "cgroup" && options.split(',').any(|option| option == controller))
+            .then(|| PathBuf::from(mount))
+    })
+}
+
+/// net_cls class ID of a slice under cgroup v1, which iptables matches on.
+#[cfg(target_os = "linux")]
+fn slice_classid(name: &str) -> u32 {
+    // Major 0xaf15, minor from an FNV-1a hash of the name
+    let minor = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    (0xaf15 << 16) | (minor & 0xffff)
+}
+
+/// Sets up a slice's groups in the cgroup v1 cpuset, cpu and net_cls
+/// hierarchies with the limits from settings.json, and returns the
+/// `cgroup.procs` files the program has to join. Needs root.
+#[cfg(target_os = "linux")]
+fn configure_slice_v1(name: &str) -> Result<Vec<PathBuf>> {
+    let group = slice_unit(name)?.trim_end_matches(".slice").to_string();
+    let slice = settings().slices.get(name);
+    let write = |path: PathBuf, value: String| {
+        std::fs::write(&path, value).with_context(|| format!("Failed to write {}", path.display()))
+    };
+
+    let mut procs = Vec::new();
+
+    if let Some(mount) = cgroup_v1_mount("cpuset") {
+        let dir = mount.join(&group);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {} (root is required)", dir.display()))?;
+
+        // A new cpuset has no CPUs or memory nodes and takes no processes until given some
+        let cpus = match slice.filter(|slice| !slice.cpus.is_empty()) {
+            Some(slice) => slice
+                .cpus
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            None => std::fs::read_to_string(mount.join("cpuset.cpus"))?,
+        };
+        write(dir.join("cpuset.cpus"), cpus)?;
+        write(
+            dir.join("cpuset.mems"),
+            std::fs::read_to_string(mount.join("cpuset.mems"))?,
+        )?;
+        procs.push(dir.join("cgroup.procs"));
+    }
+
+    if let Some(mount) = cgroup_v1_mount("cpu") {
+        let dir = mount.join(&group);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {} (root is required)", dir.display()))?;
+
+        // cpu.shares of 1024 corresponds to the default CPUWeight of 100
+        if let Some(weight) = slice.and_then(|slice| slice.cpu_weight) {
+            write(
+                dir.join("cpu.shares"),
+                (weight as u64 * 1024 / 100).max(2).to_string(),
+            )?;
+        }
+        procs.push(dir.join("cgroup.procs"));
+    }
+
+    if let Some(mount) = cgroup_v1_mount("net_cls") {
+        let dir = mount.join(&group);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {} (root is required)", dir.display()))?;
+        write(dir.join("net_cls.classid"), slice_classid(name).to_string())?;
+        procs.push(dir.join("cgroup.procs"));
+    }
+
+    if procs.is_empty() {
+        bail!("No cgroup controllers are mounted");
+    }
+    Ok(procs)
+}
+
+#[cfg(target_os = "windows")]
+fn qos_policy_name(exe: &Path) -> String {
+    let file_name = exe.file_name().unwrap_or_default().to_string_lossy();
+    format!("affinity-rs {}", file_name).replace('\'', "''")
+}
+
+/// Tags the executable's outgoing traffic with a DSCP value through a QoS
+/// policy in the active (non-persistent) store. Needs administrator rights.
+#[cfg(target_os = "windows")]
+fn set_qos_policy(exe: &Path, dscp: u8) -> Result<()> {
+    let file_name = exe
+        .file_name()
+        .context("Executable has no file name")?
+        .to_string_lossy()
+        .replace('\'', "''");
+    let name = qos_policy_name(exe);
+
+    let script = format!(
+        "Remove-NetQosPolicy -Name '{name}' -PolicyStore ActiveStore -Confirm:$false -ErrorAction SilentlyContinue; \
+         New-NetQosPolicy -Name '{name}' -AppPathNameMatchCondition '{file_name}' -DSCPAction {dscp} \
+         -NetworkProfile All -PolicyStore ActiveStore -ErrorAction Stop | Out-Null"
+    );
+
+    let status = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()
+        .context("Failed to run PowerShell")?;
+
+    if !status.success() {
+        bail!("Could not create QoS policy (administrator rights are required)");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn remove_qos_policy(exe: &Path) -> Result<()> {
+    let script = format!(
+        "Remove-NetQosPolicy -Name '{}' -PolicyStore ActiveStore -Confirm:$false -ErrorAction Stop",
+        qos_policy_name(exe)
+    );
+
+    let status = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()
+        .context("Failed to run PowerShell")?;
+
+    if !status.success() {
+        bail!("Could not remove QoS policy");
+    }
+    Ok(())
+}
+
+/// iptables rules tagging the traffic of a slice's cgroup with a DSCP value.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct DscpRule {
+    /// Directory of the cgroup the rule matches
+    cgroup_dir: PathBuf,
+    rule: Vec<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl DscpRule {
+    /// Deletes the rules again, unless other programs still run in the slice.
+    fn remove(&self) {
+        let populated = if cgroup_v2() {
+            std::fs::read_to_string(self.cgroup_dir.join("cgroup.events"))
+                .is_ok_and(|events| events.lines().any(|line| line == "populated 1"))
+        } else {
+            std::fs::read_to_string(self.cgroup_dir.join("cgroup.procs"))
+                .is_ok_and(|procs| !procs.trim().is_empty())
+        };
+        if populated {
+            return;
+        }
+
+        for tool in ["iptables", "ip6tables"] {
+            let removed = Command::new(tool)
+                .args(["-t", "mangle", "-D"])
+                .args(&self.rule)
+                .output()
+                .is_ok_and(|output| output.status.success());
+            if !removed {
+                eprintln!("Warning: {} could not remove the DSCP rule", tool);
+            }
+        }
+    }
+}
+
+/// Tags traffic from every process in the slice's cgroup with a DSCP value
+/// using an iptables mangle rule. Needs root.
+#[cfg(target_os = "linux")]
+fn set_slice_dscp(slice: &str, dscp: u8) -> Result<DscpRule> {
+    // cgroup v2 is matched by path, v1 by the net_cls class ID
+    let (cgroup_dir, option, value) = if cgroup_v2() {
+        // Asked of systemd rather than read from the program, which may not
+        // have reached its scope yet
+        let output = Command::new("systemctl")
+            .args(["--user", "show", "--property", "ControlGroup", "--value"])
+            .arg(slice_unit(slice)?)
+            .output()
+            .context("Failed to run systemctl")?;
+        let control_group = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let Some(path) = control_group
+            .strip_prefix('/')
+            .filter(|path| !path.is_empty())
+        else {
+            bail!("Slice {} is not running", slice_unit(slice)?);
+        };
+        (
+            Path::new("/sys/fs/cgroup").join(path),
+            "--path",
+            path.to_string(),
+        )
+    } else {
+        let Some(mount) = cgroup_v1_mount("net_cls") else {
+            bail!("The net_cls cgroup controller is not mounted");
+        };
+        let group = slice_unit(slice)?.trim_end_matches(".slice").to_string();
+        (
+            mount.join(group),
+            "--cgroup",
+            slice_classid(slice).to_string(),
+        )
+    };
+
+    let rule: Vec<String> = [
+        "OUTPUT",
+        "-m",
+        "cgroup",
+        option,
+        &value,
+        "-j",
+        "DSCP",
+        "--set-dscp",
+        &dscp.to_string(),
+    ]
+    .map(str::to_string)
+    .into();
+
+    for tool in ["iptables", "ip6tables"] {
+        let exists = Command::new(tool)
+            .args(["-t", "mangle", "-C"])
+            .args(&rule)
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if exists {
+            continue;
+        }
+
+        let status = Command::new(tool)
+            .args(["-t", "mangle", "-A"])
+            .args(&rule)
+            .status()
+            .with_context(|| format!("Failed to run {}", tool))?;
+        if !status.success() {
+            bail!("{} could not add the DSCP rule (root is required)", tool);
+        }
+    }
+    Ok(DscpRule { cgroup_dir, rule })
+}
+
+/// The wrappers and program that make up the Linux launch command, without
+/// the program's arguments.
+#[cfg(target_os = "linux")]
+fn linux_command_line(profile: &Profile) -> Result<Vec<OsString>> {
+    let cpu_str = profile
+        .cpus
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // Run under Feral GameMode, the Linux counterpart of Windows Game Mode
+    let gamemoderun = if profile.game_mode == Some(true) {
+        let found = find_in_path("gamemoderun");
+        if found.is_none() {
+            eprintln!("Note: game_mode is enabled but 'gamemoderun' is not installed.");
+        }
+        found
+    } else {
+        None
+    };
+
+    let mut wrappers: Vec<String> = Vec::new();
+
+    // Run inside a shared systemd slice (cgroup) if requested. systemd can't
+    // delegate the v1 controllers, so there the groups are joined directly.
+    let slice_v1 = profile.slice.is_some() && !cgroup_v2();
+    if let Some(ref slice) = profile.slice
+        && !slice_v1
+    {
+        wrappers.extend([
+            "systemd-run".to_string(),
+            "--user".to_string(),
+            "--scope".to_string(),
+            "--quiet".to_string(),
+            format!("--slice={}", slice_unit(slice)?),
+        ]);
+    }
+
+    // Idle I/O class, only served when no other process wants the disk
+    if profile.background == Some(true) {
+        wrappers.extend(["ionice".to_string(), "-c".to_string(), "3".to_string()]);
+    }
+
+    // Wrap with nice if a priority or raw nice value is specified. nice -n
+    // adds to our own value, so the difference gets the program the one asked for.
+    if let Some(nice) = profile.nice_value() {
+        let own = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        wrappers.extend([
+            "nice".to_string(),
+            "-n".to_string(),
+            (nice - own).to_string(),
+        ]);
+    }
+
+    if !profile.cpus.is_empty() {
+        wrappers.extend(["taskset".to_string(), "-c".to_string(), cpu_str]);
+    }
+
+    let mut command_line: Vec<OsString> = wrappers.into_iter().map(OsString::from).collect();
+    command_line.extend(gamemoderun.map(PathBuf::into_os_string));
+    command_line.push(profile.path.clone().into_os_string());
+    Ok(command_line)
+}
+
+#[cfg(target_os = "linux")]
+fn launch_profile_linux(
+    profile: &Profile,
+    args: &[OsString],
+    verify: bool,
+) -> Result<(Child, LaunchOutcome)> {
+    let slice_v1 = profile.slice.is_some() && !cgroup_v2();
+    let command_line = linux_command_line(profile)?;
+
+    let mut cmd = Command::new(&command_line[0]);
+    cmd.stdout(program_stdout());
+    cmd.args(&command_line[1..]);
+
+    let use_autogroup = profile.nice_value().is_some() && autogroup_enabled();
+    if use_autogroup && profile.autogroup_nice.is_none() {
+        eprintln!("Note: Kernel autogrouping is active, so the priority only affects");
+        eprintln!("      processes in the same session. Set \"autogroup_nice\": true in the");
+        eprintln!("      profile to prioritize the program against other sessions too.");
+    }
+    let use_autogroup = use_autogroup && profile.autogroup_nice == Some(true);
+
+    cmd.args(args).envs(&profile.env);
+
+    // A new session gets its own autogroup, so ours isn't reniced with it
+    let mut creation_flags = profile.creation_flags.clone();
+    if use_autogroup && !creation_flags.contains(&CreationFlag::NewSession) {
+        creation_flags.push(CreationFlag::NewSession);
+    }
+    apply_creation_flags(&mut cmd, &creation_flags);
+
+    if profile.kills_on_exit() {
+        use std::os::unix::process::CommandExt;
+
+        // Processes orphaned below the program are reparented to affinity-rs,
+        // so they can be found and killed when the program exits
+        if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } == -1 {
+            eprintln!(
+                "Warning: Processes the program leaves behind may survive it: {}",
+                io::Error::last_os_error()
+            );
+        }
+
+        // SAFETY: prctl is async-signal-safe and only affects the child
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Timer slack is inherited across exec, so set it in the child beforehand
+    if let Some(slack) = profile.timer_slack_ns {
+        use std::os::unix::process::CommandExt;
+
+        // SAFETY: prctl is async-signal-safe and only affects the child
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_TIMERSLACK, slack as libc::c_ulong, 0, 0, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        println!("Timer slack: {} ns", slack);
+    }
+
+    // Limits are inherited across exec as well
+    if let Some(ref rlimits) = profile.rlimits {
+        use std::os::unix::process::CommandExt;
+
+        let limits: Vec<_> = [
+            ("nofile", libc::RLIMIT_NOFILE, rlimits.nofile),
+            ("core", libc::RLIMIT_CORE, rlimits.core),
+            ("memlock", libc::RLIMIT_MEMLOCK, rlimits.memlock),
+            ("rtprio", libc::RLIMIT_RTPRIO, rlimits.rtprio),
+        ]
+        .into_iter()
+        .filter_map(|(name, resource, value)| Some((name, resource, value?.to_rlim())))
+        .collect();
+
+        // Only root can raise a hard limit; otherwise the soft limit goes as
+        // high as the hard one allows
+        let root = unsafe { libc::geteuid() } == 0;
+        for &(name, resource, value) in &limits {
+            let mut current: libc::rlimit = unsafe { std::mem::zeroed() };
+            if !root
+                && unsafe { libc::getrlimit(resource, &mut current) } == 0
+                && value > current.rlim_max
+            {
+                eprintln!(
+                    "Warning: {} is capped at the hard limit {} (raising it needs root)",
+                    name, current.rlim_max
+                );
+            }
+        }
+
+        let names: Vec<&str> = limits.iter().map(|&(name, _, _)| name).collect();
+        println!("Resource limits: {}", names.join(", "));
+
+        // SAFETY: getrlimit and setrlimit are async-signal-safe and only
+        // affect the child
+        unsafe {
+            cmd.pre_exec(move || {
+                for &(_, resource, value) in &limits {
+                    let mut current: libc::rlimit = std::mem::zeroed();
+                    if libc::getrlimit(resource, &mut current) != 0 {
+                        continue;
+                    }
+
+                    // Lowering the hard limit can't be undone without root,
+                    // so it is only ever raised, and only by root
+                    if root && value > current.rlim_max {
+                        current.rlim_max = value;
+                    }
+                    current.rlim_cur = value.min(current.rlim_max);
+                    libc::setrlimit(resource, &current);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    if slice_v1 && let Some(ref slice) = profile.slice {
+        use std::os::unix::process::CommandExt;
+
+        match configure_slice_v1(slice) {
+            Ok(procs) => {
+                let procs: Vec<std::ffi::CString> = procs
+                    .iter()
+                    .filter_map(|path| std::ffi::CString::new(path.to_str()?).ok())
+                    .collect();
+
+                // SAFETY: open, write and close are async-signal-safe; writing 0
+                // moves the writing process. Best effort, like the v2 slice.
+                unsafe {
+                    cmd.pre_exec(move || {
+                        for path in &procs {
+                            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                            if fd >= 0 {
+                                libc::write(fd, b"0".as_ptr() as *const _, 1);
+                                libc::close(fd);
+                            }
+                        }
+                        Ok(())
+                    });
+                }
+                println!("Running in slice: {} (cgroup v1)", slice_unit(slice)?);
+            }
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    let started = Instant::now();
+    let child = cmd
+        .spawn()
+        .context("Failed to spawn process. Is 'taskset' installed?")?;
+    let spawn_time = started.elapsed();
+
+    println!("Process launched with PID: {}", child.id());
+
+    if use_autogroup && let Some(nice) = profile.nice_value() {
+        match std::fs::write(format!("/proc/{}/autogroup", child.id()), nice.to_string()) {
+            Ok(_) => println!("Autogroup nice set to: {}", nice),
+            Err(e) => eprintln!("Warning: Could not set autogroup nice: {}", e),
+        }
+    }
+
+    let mut dscp_rule = None;
+    if let Some(ref slice) = profile.slice {
+        // The slice exists once systemd-run has created the scope in it
+        if !slice_v1 {
+            thread::sleep(Duration::from_millis(200));
+            match configure_slice(slice) {
+                Ok(_) => println!("Running in slice: {}", slice_unit(slice)?),
+                Err(e) => eprintln!("Warning: {:#}", e),
+            }
+        }
+
+        if let Some(dscp) = profile.dscp {
+            match set_slice_dscp(slice, dscp) {
+                Ok(rule) => {
+                    println!("Network traffic tagged with DSCP {}", dscp);
+                    dscp_rule = Some(rule);
+                }
+                Err(e) => eprintln!("Warning: {:#}", e),
+            }
+        }
+    } else if profile.dscp.is_some() {
+        eprintln!("Note: dscp needs a slice on Linux, as traffic is matched by cgroup.");
+    }
+
+    // taskset applies the mask right before exec, after any other wrappers ran
+    let mut wanted: Vec<usize> = profile
+        .cpus
+        .iter()
+        .copied()
+        .filter(|&cpu| cpu < num_cpus::get())
+        .collect();
+    wanted.sort_unstable();
+    wanted.dedup();
+
+    let mut outcome = LaunchOutcome {
+        dscp_rule,
+        ..Default::default()
+    };
+    outcome.timings.push(("spawn".to_string(), spawn_time));
+    let wanted_nice = profile.nice_value();
+    if wanted.is_empty() && wanted_nice.is_none() {
+        return Ok((child, outcome));
+    }
+
+    // Reading the settings back means waiting for the wrappers to exec, which
+    // a detached launch shouldn't pay for unless timings were asked for
+    if !verify {
+        if !wanted.is_empty() {
+            println!("CPU affinity set: {}", format_cpu_list(&wanted));
+        }
+        if let Some(nice) = wanted_nice {
+            println!("Nice value set: {}", nice);
+        }
+        return Ok((child, outcome));
+    }
+
+    // Read back what the wrappers applied, once they have exec'd
+    let mut actual_cpus = None;
+    let mut actual_nice = None;
+    for attempt in 1..=profile.retry_attempts.unwrap_or(5) {
+        outcome.attempts = attempt;
+        let started = Instant::now();
+        match get_process_affinity(child.id()) {
+            Ok(cpus) => {
+                log_event(
+                    LogLevel::Debug,
+                    &format!("Attempt {}: affinity is {:?}", attempt, cpus),
+                );
+                actual_cpus = Some(cpus);
+            }
+            Err(e) => log_event(
+                LogLevel::Debug,
+                &format!("Attempt {}: could not read the affinity: {:#}", attempt, e),
+            ),
+        }
+        actual_nice = get_process_nice(child.id()).ok();
+        log_event(
+            LogLevel::Debug,
+            &format!("Attempt {}: nice is {:?}", attempt, actual_nice),
+        );
+        outcome.timings.push((
+            format!("attempt {}: check affinity and nice", attempt),
+            started.elapsed(),
+        ));
+
+        outcome.affinity_applied = !wanted.is_empty() && actual_cpus.as_ref() == Some(&wanted);
+        let nice_applied = wanted_nice.is_none_or(|nice| actual_nice == Some(nice));
+        // taskset runs after nice, so the nice value is final once the mask is
+        if outcome.affinity_applied || (wanted.is_empty() && nice_applied) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    if !wanted.is_empty() {
+        match actual_cpus {
+            _ if outcome.affinity_applied => println!(
+                "CPU affinity set and verified: {} (mask {})",
+                format_cpu_list(&wanted),
+                cpu_mask_string(&wanted)
+            ),
+            Some(ref actual) => eprintln!(
+                "Warning: Affinity mismatch - Requested: {}, Actual: {}",
+                format_cpu_list(&wanted),
+                format_cpu_list(actual)
+            ),
+            None => eprintln!("Warning: Could not read back the CPU affinity"),
+        }
+    }
+
+    if let Some(nice) = wanted_nice {
+        match actual_nice {
+            Some(actual) if actual == nice => println!("Nice value set and verified: {}", nice),
+            Some(actual) => {
+                eprintln!(
+                    "Warning: Nice value mismatch - Requested: {}, Actual: {}",
+                    nice, actual
+                );
+                if nice < actual {
+                    eprintln!(
+                        "Note: Raising the priority (lowering nice) needs root or CAP_SYS_NICE."
+                    );
+                }
+            }
+            None => eprintln!("Warning: Could not read back the nice value"),
+        }
+    }
+
+    Ok((child, outcome))
+}
+
+/// Puts the process in a Job Object, which also holds any helpers it starts
+/// later. With `priority_limit` all of them run at that priority class and
+/// cannot raise themselves above it; with `kill_on_close` they are all killed
+/// when affinity-rs exits, however it exits.
+#[cfg(target_os = "windows")]
+fn assign_job(
+    child: &Child,
+    priority_limit: Option<&ProcessPriority>,
+    kill_on_close: bool,
+) -> Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOB_OBJECT_LIMIT_PRIORITY_CLASS, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectExtendedLimitInformation, SetInformationJobObject,
+    };
+
+    unsafe {
+        let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+        if job.is_null() {
+            bail!(
+                "Failed to create job object: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+        if let Some(priority) = priority_limit {
+            limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PRIORITY_CLASS;
+            limits.BasicLimitInformation.PriorityClass = priority.to_windows_class();
+        }
+        if kill_on_close {
+            limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        }
+
+        let result = if SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &limits as *const _ as *const _,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        ) == 0
+        {
+            Err(anyhow::anyhow!(
+                "Failed to set job limits: {}",
+                std::io::Error::last_os_error()
+            ))
+        } else if AssignProcessToJobObject(job, child.as_raw_handle()) == 0 {
+            Err(anyhow::anyhow!(
+                "Failed to assign process to job: {}",
+                std::io::Error::last_os_error()
+            ))
+        } else {
+            Ok(())
+        };
+
+        // Without kill-on-close the job lives on as long as processes are
+        // assigned to it. With it, the handle stays open until the OS closes
+        // it as affinity-rs exits, which is what kills the job.
+        if !kill_on_close || result.is_err() {
+            CloseHandle(job);
+        }
+        result
+    }
+}
+
+/// Process creation flags for the profile. The program starts in its priority
+/// class right away instead of being raised once it runs; SetPriorityClass
+/// only re-asserts it. Only one class can be given, so it replaces the
+/// below_normal creation flag.
+#[cfg(target_os = "windows")]
+fn windows_creation_flags(profile: &Profile) -> u32 {
+    let priority_class = profile
+        .priority
+        .as_ref()
+        .map_or(0, ProcessPriority::to_windows_class);
+    priority_class
+        | CreationFlag::windows_bits(
+            profile
+                .creation_flags
+                .iter()
+                .filter(|&&flag| priority_class == 0 || flag != CreationFlag::BelowNormal),
+        )
+}
+
+#[cfg(target_os = "windows")]
+fn launch_profile_windows(profile: &Profile, args: &[OsString]) -> Result<(Child, LaunchOutcome)> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::WAIT_OBJECT_0;
+    use windows_sys::Win32::System::Threading::{
+        GetPriorityClass, GetProcessAffinityMask, SetPriorityClass, SetProcessAffinityMask,
+        SetProcessPriorityBoost, WaitForSingleObject,
+    };
+
+    // Calculate affinity mask
+    let mut affinity_mask: usize = 0;
+    for &cpu in &profile.cpus {
+        if cpu >= (std::mem::size_of::<usize>() * 8) {
+            eprintln!(
+                "Warning: CPU index {} is out of bounds for this system and will be ignored.",
+                cpu
+            );
+            continue;
+        }
+        affinity_mask |= 1 << cpu;
+    }
+
+    if affinity_mask == 0 && !profile.cpus.is_empty() {
+        bail!("No valid CPUs specified after validation");
+    }
+
+    // Only sockets opened after the policy exists are tagged
+    if let Some(dscp) = profile.dscp {
+        match set_qos_policy(&profile.path, dscp) {
+            Ok(_) => println!("Network traffic tagged with DSCP {}", dscp),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    if profile.game_mode == Some(true)
+        && let Err(e) = register_game(&profile.path)
+    {
+        eprintln!(
+            "Warning: Could not register with Windows Game Mode: {:#}",
+            e
+        );
+    }
+
+    // Windows reads the layers when the process starts, so they go in first
+    if !profile.compat_flags.is_empty() {
+        match apply_compat_flags(&profile.path, &profile.compat_flags) {
+            Ok(true) => println!("Compatibility settings applied"),
+            Ok(false) => {}
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    let mut cmd = Command::new(&profile.path);
+    cmd.args(args)
+        .env_remove(SHORTCUT_ENV_VAR)
+        .envs(&profile.env)
+        .stdout(program_stdout());
+
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(windows_creation_flags(profile));
+    }
+
+    let mut timings = Vec::new();
+    let started = Instant::now();
+    let child = cmd.spawn().context("Failed to spawn process")?;
+    timings.push(("spawn".to_string(), started.elapsed()));
+
+    let pid = child.id();
+    println!("Process launched with PID: {}", pid);
+
+    // Assign before the program gets a chance to start helpers
+    if profile.job_priority_limit.is_some() || profile.kills_on_exit() {
+        match assign_job(
+            &child,
+            profile.job_priority_limit.as_ref(),
+            profile.kills_on_exit(),
+        ) {
+            Ok(_) => {
+                if let Some(ref limit) = profile.job_priority_limit {
+                    println!(
+                        "Job priority limited to: {} (applies to child processes too)",
+                        limit.display_name()
+                    );
+                }
+            }
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    if profile.background == Some(true) {
+        match apply_background_mode(pid) {
+            Ok(_) => println!("Background mode: very low I/O, EcoQoS, low memory priority"),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    let retry_attempts = profile.retry_attempts.unwrap_or(5);
+    let mut attempts = 0;
+    // Without cores the affinity is left alone
+    let mut affinity_set = profile.cpus.is_empty();
+    let mut priority_set = false;
+    let mut boost_set = false;
+    let mut gpu_priority_set = false;
+
+    // The handle CreateProcess returned has full access, even to programs
+    // whose security descriptor refuses a later OpenProcess
+    let handle = child.as_raw_handle() as windows_sys::Win32::Foundation::HANDLE;
+
+    let mut configure = |attempt: usize, timings: &mut Vec<(String, Duration)>| -> Result<bool> {
+        attempts = attempt;
+        unsafe {
+            // Set CPU affinity
+            if !affinity_set {
+                let started = Instant::now();
+                let result = SetProcessAffinityMask(handle, affinity_mask);
+                timings.push((
+                    format!("attempt {}: set affinity", attempt),
+                    started.elapsed(),
+                ));
+                if result == 0 {
+                    let err = std::io::Error::last_os_error();
+                    return Err(anyhow::anyhow!("Failed to set CPU affinity: {}", err));
+                }
+
+                // Verify affinity
+                let mut process_affinity: usize = 0;
+                let mut system_affinity: usize = 0;
+                GetProcessAffinityMask(handle, &mut process_affinity, &mut system_affinity);
+                log_event(
+                    LogLevel::Debug,
+                    &format!(
+                        "Attempt {}: affinity mask is 0x{:X} (system 0x{:X})",
+                        attempt, process_affinity, system_affinity
+                    ),
+                );
+
+                if process_affinity == affinity_mask {
+                    println!("CPU affinity set and verified: 0x{:X}", process_affinity);
+                    affinity_set = true;
+                } else {
+                    println!(
+                        "Warning: Affinity mismatch - Requested: 0x{:X}, Actual: 0x{:X}",
+                        affinity_mask, process_affinity
+                    );
+                    affinity_set = true; // Don't retry if it was partially successful
+                }
+            }
+
+            if profile.disable_priority_boost == Some(true) && !boost_set {
+                if SetProcessPriorityBoost(handle, 1) == 0 {
+                    let err = std::io::Error::last_os_error();
+                    eprintln!("Warning: Failed to disable priority boost: {}", err);
+                } else {
+                    println!("Dynamic priority boost disabled");
+                }
+                boost_set = true;
+            }
+
+            if let Some(ref gpu_priority) = profile.gpu_priority
+                && !gpu_priority_set
+            {
+                let status = D3DKMTSetProcessSchedulingPriorityClass(
+                    handle,
+                    gpu_priority.to_gpu_scheduling_class(),
+                );
+
+                if status == 0 {
+                    println!("GPU priority set to: {}", gpu_priority.display_name());
+                } else {
+                    eprintln!(
+                        "Failed to set GPU priority (NTSTATUS 0x{:08X})",
+                        status as u32
+                    );
+                    if gpu_priority.requires_elevation() && !is_elevated() {
+                        eprintln!(
+                            "Note: {} GPU priority requires administrator privileges.",
+                            gpu_priority.display_name()
+                        );
+                    }
+                }
+                gpu_priority_set = true;
+            }
+
+            // Set process priority if specified
+            if let Some(ref priority) = profile.priority
+                && !priority_set
+                && GetPriorityClass(handle) == priority.to_windows_class()
+            {
+                println!(
+                    "Process priority set at creation: {}",
+                    priority.display_name()
+                );
+                priority_set = true;
+            }
+            if let Some(ref priority) = profile.priority
+                && !priority_set
+            {
+                let priority_class = priority.to_windows_class();
+                let started = Instant::now();
+                let priority_result = SetPriorityClass(handle, priority_class);
+                timings.push((
+                    format!("attempt {}: set priority", attempt),
+                    started.elapsed(),
+                ));
+
+                if priority_result == 0 {
+                    let err = std::io::Error::last_os_error();
+                    eprintln!("Failed to set process priority: {}", err);
+
+                    if priority.requires_elevation() && !is_elevated() {
+                        eprintln!(
+                            "Note: {} priority requires administrator privileges.",
+                            priority.display_name()
+                        );
+                    }
+                } else {
+                    // Verify priority after a short delay
+                    thread::sleep(Duration::from_millis(100));
+                    let actual_priority = GetPriorityClass(handle);
+
+                    if actual_priority == priority_class {
+                        println!("Process priority set to: {}", priority.display_name());
+                        priority_set = true;
+                    } else if actual_priority == 0 {
+                        eprintln!("Could not verify priority (GetPriorityClass failed)");
+                        priority_set = true; // Don't keep retrying
+                    } else {
+                        println!("Note: Process reset its priority to a different value.");
+                        println!(
+                            "This is normal for some applications (especially games with launchers)."
+                        );
+                        priority_set = true;
+                    }
+                }
+            }
+
+            // Check if process still exists for next attempt
+            if attempt < retry_attempts && !affinity_set {
+                thread::sleep(Duration::from_millis(200));
+                if WaitForSingleObject(handle, 0) == WAIT_OBJECT_0 {
+                    println!(
+                        "Note: Initial process (PID {}) has exited. This is likely a launcher.",
+                        pid
+                    );
+                    println!("The actual game may be running with a different PID.");
+                    if known_launcher(&profile.path).is_none() {
+                        println!(
+                            "Tip: Try launching the game's actual .exe directly for better results."
+                        );
+                    }
+                    return Ok(false); // Stop retrying
+                }
+            }
+
+            // Success if affinity was set
+            Ok(affinity_set)
+        }
+    };
+
+    // Try multiple times to handle launcher -> game transitions
+    let mut last_attempt = Instant::now();
+    let success = launch_with_retry(retry_attempts, 100, |attempt| {
+        let started = Instant::now();
+        timings.push((format!("attempt {}: wait", attempt), started - last_attempt));
+        let result = configure(attempt, &mut timings);
+        timings.push((format!("attempt {}", attempt), started.elapsed()));
+        last_attempt = Instant::now();
+        result
+    })?;
+
+    if !success {
+        eprintln!("\nWarning: Could not fully configure the process.");
+        eprintln!("The application may be using a launcher or may have restricted access.");
+    }
+
+    let outcome = LaunchOutcome {
+        affinity_applied: affinity_set,
+        attempts,
+        timings,
+    };
+    Ok((child, outcome))
+}
+
+/// Starts the executable with its args and env only, for ruling out the tuning
+/// as the cause of a problem.
+fn launch_plain(profile: &Profile, args: &[OsString]) -> Result<Child> {
+    let flags: Vec<CreationFlag> = profile
+        .creation_flags
+        .iter()
+        .copied()
+        .filter(|flag| *flag != CreationFlag::BelowNormal)
+        .collect();
+
+    let mut cmd = Command::new(&profile.path);
+    cmd.args(args);
+    #[cfg(target_os = "windows")]
+    cmd.env_remove(SHORTCUT_ENV_VAR);
+    cmd.envs(&profile.env).stdout(program_stdout());
+    apply_creation_flags(&mut cmd, &flags);
+
+    let child = cmd.spawn().context("Failed to spawn process")?;
+
+    println!("Process launched with PID: {}", child.id());
+
+    Ok(child)
+}
+
+/// A game launcher that starts the program worth tuning as a child process.
+struct KnownLauncher {
+    name: &'static str,
+    /// Lowercase executable file names
+    executables: &'static [&'static str],
+    /// Lowercase names of the launcher's own helper processes, without `.exe`
+    helpers: &'static [&'static str],
+    tip: &'static str,
+}
+
+const KNOWN_LAUNCHERS: &[KnownLauncher] = &[
+    KnownLauncher {
+        name: "Steam",
+        executables: &["steam.exe", "steam"],
+        helpers: &[
+            "steamwebhelper",
+            "steamservice",
+            "gameoverlayui",
+            "steamerrorreporter",
+            "steam-runtime-launcher-service",
+            "pressure-vessel-wrap",
+            "reaper",
+        ],
+        tip: "If Steam is already running it starts the game itself, out of reach of affinity-rs. \
+              Quit Steam first, or make a profile for the game's own executable and add it to rules.json.",
+    },
+    KnownLauncher {
+        name: "Epic Games Launcher",
+        executables: &["epicgameslauncher.exe"],
+        helpers: &[
+            "epicwebhelper",
+            "epiconlineservices",
+            "epiconlineserviceshost",
+            "epiconlineservicesuserhelper",
+            "unrealcefsubprocess",
+            "crashreportclient",
+        ],
+        tip: "Pass the game's launch URI as an argument (com.epicgames.launcher://apps/<id>?action=launch) \
+              and exit the launcher from the tray beforehand so the game starts as its child.",
+    },
+    KnownLauncher {
+        name: "Ubisoft Connect",
+        executables: &["upc.exe", "ubisoftconnect.exe", "uplay.exe"],
+        helpers: &["uplaywebcore", "uplaycrashreporter", "upc_crashreporter"],
+        tip: "Ubisoft Connect keeps running in the tray; close it before launching so the game \
+              starts as its child, or pass uplay://launch/<id> as an argument.",
+    },
+    KnownLauncher {
+        name: "EA app",
+        executables: &["eadesktop.exe", "ealauncher.exe"],
+        helpers: &[
+            "eabackgroundservice",
+            "eacefsubprocess",
+            "eaconnect_microsoft",
+            "qtwebengineprocess",
+        ],
+        tip: "The EA app hands games to its background service when it is already running. \
+              Quit it completely first, or profile the game's own executable.",
+    },
+    KnownLauncher {
+        name: "Battle.net",
+        executables: &["battle.net.exe", "battle.net launcher.exe"],
+        helpers: &[
+            "agent",
+            "battle.net helper",
+            "blizzarderror",
+            "blizzardbrowser",
+        ],
+        tip: "Battle.net starts games through its Agent service; use --exec=\"launch <code>\" as an argument \
+              and keep Battle.net closed beforehand.",
+    },
+    KnownLauncher {
+        name: "GOG Galaxy",
+        executables: &["galaxyclient.exe"],
+        helpers: &[
+            "galaxyclient helper",
+            "galaxycommunication",
+            "gog galaxy notifications renderer",
+        ],
+        tip: "Pass /command=runGame /gameId=<id> as arguments and close Galaxy beforehand so the \
+              game starts as its child.",
+    },
+    KnownLauncher {
+        name: "Lutris",
+        executables: &["lutris"],
+        helpers: &["wineserver", "winedevice", "services", "explorer"],
+        tip: "Pass lutris:rungame/<slug> as an argument so the game starts under affinity-rs.",
+    },
+];
+
+impl KnownLauncher {
+    /// Whether `process_name` is the launcher itself or one of its helpers,
+    /// which are left alone when following its children.
+    fn is_own_process(&self, process_name: &str) -> bool {
+        let name = process_name.to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+
+        self.executables
+            .iter()
+            .map(|exe| exe.strip_suffix(".exe").unwrap_or(exe))
+            .chain(self.helpers.iter().copied())
+            // Linux cuts process names down to 15 characters
+            .any(|own| own == name || (name.len() == 15 && own.starts_with(name)))
+    }
+}
+
+fn known_launcher(path: &Path) -> Option<&'static KnownLauncher> {
+    let file_name = path.file_name()?.to_str()?.to_lowercase();
+    KNOWN_LAUNCHERS
+        .iter()
+        .find(|launcher| launcher.executables.contains(&file_name.as_str()))
+}
+
+/// How long a detached launch waits for a launcher to start the actual program
+const FOLLOW_WINDOW: Duration = Duration::from_secs(60);
+
+/// The processes started by a launched program, directly or through other
+/// descendants. Members are remembered between scans, so they stay in the
+/// tree after their parent exits and they are reparented. On Linux, a
+/// program leading its own session also claims everything in that session.
+struct ProcessTree {
+    root: u32,
+    members: std::collections::HashSet<u32>,
+    #[cfg(target_os = "linux")]
+    session: Option<u32>,
+}
+
+impl ProcessTree {
+    fn new(root: u32) -> Self {
+        Self {
+            root,
+            members: std::collections::HashSet::new(),
+            // session is field 6; only a session leader owns its session
+            #[cfg(target_os = "linux")]
+            session: read_proc_stat(root)
+                .and_then(|(_, fields)| fields.get(3)?.parse().ok())
+                .filter(|&session| session == root),
+        }
+    }
+
+    /// Adds the processes that joined the tree since the last scan and returns them.
+    fn update(&mut self, processes: &[ProcessInfo]) -> Vec<u32> {
+        // Forget exited members before their PIDs can be reused
+        self.members
+            .retain(|pid| processes.iter().any(|p| p.pid == *pid));
+
+        let mut added = Vec::new();
+        loop {
+            let found = added.len();
+
+            for process in processes {
+                if process.pid == self.root || self.members.contains(&process.pid) {
+                    continue;
+                }
+
+                let in_tree =
+                    process.parent_pid == self.root || self.members.contains(&process.parent_pid);
+                #[cfg(target_os = "linux")]
+                let in_tree = in_tree || self.session == Some(process.session);
+
+                if in_tree {
+                    self.members.insert(process.pid);
+                    added.push(process.pid);
+                }
+            }
+
+            if added.len() == found {
+                return added;
+            }
+        }
+    }
+
+    /// Whether the root or any process started by it is still running.
+    fn is_running(&self, processes: &[ProcessInfo]) -> bool {
+        !self.members.is_empty() || processes.iter().any(|p| p.pid == self.root)
+    }
+}
+
+/// How old the shared process snapshot may get before a poller takes a new one.
+const SNAPSHOT_MAX_AGE: Duration = Duration::from_millis(250);
+
+/// The last process list taken by one of the threads watching a launch.
+static PROCESS_SNAPSHOT: std::sync::Mutex<Option<(Instant, std::sync::Arc<Vec<ProcessInfo>>)>> =
+    std::sync::Mutex::new(None);
+
+/// The running processes, shared by the threads watching a launched program
+/// so they don't each scan every process on their own schedule.
+fn process_snapshot() -> Result<std::sync::Arc<Vec<ProcessInfo>>> {
+    let mut snapshot = PROCESS_SNAPSHOT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((taken, ref processes)) = *snapshot
+        && taken.elapsed() < SNAPSHOT_MAX_AGE
+    {
+        return Ok(processes.clone());
+    }
+
+    let processes = std::sync::Arc::new(list_processes()?);
+    *snapshot = Some((Instant::now(), processes.clone()));
+    Ok(processes)
+}
+
+/// Bounds of each monitor: the primary one first, the others from left to right.
+#[cfg(target_os = "windows")]
+fn list_monitors() -> Vec<windows_sys::Win32::Foundation::RECT> {
+    use windows_sys::Win32::Foundation::{LPARAM, RECT};
+    use windows_sys::Win32::Graphics::Gdi::{
+        EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::MONITORINFOF_PRIMARY;
+
+    // Returns a BOOL; non-zero continues the enumeration
+    unsafe extern "system" fn visit(monitor: HMONITOR, _: HDC, _: *mut RECT, data: LPARAM) -> i32 {
+        let monitors = unsafe { &mut *(data as *mut Vec<(bool, RECT)>) };
+        let mut info: MONITORINFO = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
+        if unsafe { GetMonitorInfoW(monitor, &mut info) } != 0 {
+            monitors.push((info.dwFlags & MONITORINFOF_PRIMARY != 0, info.rcMonitor));
+        }
+        1
+    }
+
+    let mut monitors: Vec<(bool, RECT)> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            Some(visit),
+            &mut monitors as *mut _ as LPARAM,
+        );
+    }
+
+    monitors.sort_by_key(|(primary, rect)| (!primary, rect.left, rect.top));
+    monitors.into_iter().map(|(_, rect)| rect).collect()
+}
+
+/// The largest visible top-level window of any of `pids`.
+#[cfg(target_os = "windows")]
+fn main_window(
+    pids: &std::collections::HashSet<u32>,
+) -> Option<windows_sys::Win32::Foundation::HWND> {
+    use windows_sys::Win32::Foundation::{HWND, LPARAM, RECT};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        EnumWindows, GW_OWNER, GetWindow, GetWindowRect, GetWindowThreadProcessId, IsWindowVisible,
+    };
+
+    struct Search<'a> {
+        pids: &'a std::collections::HashSet<u32>,
+        best: Option<(HWND, i64)>,
+    }
+
+    // Returns a BOOL; non-zero continues the enumeration
+    unsafe extern "system" fn visit(window: HWND, data: LPARAM) -> i32 {
+        let search = unsafe { &mut *(data as *mut Search) };
+        let mut pid = 0;
+        unsafe { GetWindowThreadProcessId(window, &mut pid) };
+
+        // Owned windows are dialogs and splash screens of another window
+        if search.pids.contains(&pid)
+            && unsafe { IsWindowVisible(window) } != 0
+            && unsafe { GetWindow(window, GW_OWNER) }.is_null()
+        {
+            let mut rect: RECT = unsafe { std::mem::zeroed() };
+            if unsafe { GetWindowRect(window, &mut rect) } != 0 {
+                let area = (rect.right - rect.left) as i64 * (rect.bottom - rect.top) as i64;
+                if area > 0 && search.best.is_none_or(|(_, best)| area > best) {
+                    search.best = Some((window, area));
+                }
+            }
+        }
+        1
+    }
+
+    let mut search = Search { pids, best: None };
+    unsafe { EnumWindows(Some(visit), &mut search as *mut _ as LPARAM) };
+    search.best.map(|(window, _)| window)
+}
+
+/// A window `place_window` made borderless, with the style and bounds it had
+/// before. The handle is kept as an integer so it can leave the thread.
+#[cfg(target_os = "windows")]
+struct BorderlessWindow {
+    window: isize,
+    style: isize,
+    rect: windows_sys::Win32::Foundation::RECT,
+}
+
+#[cfg(target_os = "windows")]
+impl BorderlessWindow {
+    /// Gives the window its borders and bounds back, if it still exists,
+    /// which it does when it belonged to a launcher that outlives the program.
+    fn restore(&self) {
+        use windows_sys::Win32::Foundation::HWND;
+        use windows_sys::Win32::UI::WindowsAndMessaging::{
+            GWL_STYLE, IsWindow, SWP_FRAMECHANGED, SWP_NOACTIVATE, SWP_NOZORDER, SetWindowLongPtrW,
+            SetWindowPos,
+        };
+
+        let window = self.window as HWND;
+        unsafe {
+            if IsWindow(window) == 0 {
+                return;
+            }
+            SetWindowLongPtrW(window, GWL_STYLE, self.style);
+            SetWindowPos(
+                window,
+                std::ptr::null_mut(),
+                self.rect.left,
+                self.rect.top,
+                self.rect.right - self.rect.left,
+                self.rect.bottom - self.rect.top,
+                SWP_FRAMECHANGED | SWP_NOZORDER | SWP_NOACTIVATE,
+            );
+        }
+    }
+}
+
+/// Waits for the main window of `root` or a program it started and moves it
+/// to the chosen monitor, optionally borderless over the whole monitor.
+/// Returns the window's previous style and bounds when it was made borderless.
+#[cfg(target_os = "windows")]
+fn place_window(root: u32, placement: &WindowPlacement) -> Result<Option<BorderlessWindow>> {
+    use windows_sys::Win32::Foundation::RECT;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GWL_STYLE, GetWindowLongPtrW, GetWindowRect, IsZoomed, SW_MAXIMIZE, SW_RESTORE,
+        SWP_FRAMECHANGED, SWP_NOACTIVATE, SWP_NOSIZE, SWP_NOZORDER, SWP_SHOWWINDOW,
+        SetWindowLongPtrW, SetWindowPos, ShowWindow, WS_OVERLAPPEDWINDOW, WS_POPUP,
+    };
+
+    let monitors = list_monitors();
+    let number = placement.monitor.unwrap_or(1);
+    let Some(target) = number.checked_sub(1).and_then(|index| monitors.get(index)) else {
+        bail!(
+            "Monitor {} not found, this system has {}",
+            number,
+            monitors.len()
+        );
+    };
+
+    let wait = placement.wait_secs.unwrap_or(60);
+    let deadline = Instant::now() + Duration::from_secs(wait);
+    let mut tree = ProcessTree::new(root);
+    let window = loop {
+        if let Ok(processes) = process_snapshot() {
+            tree.update(&processes);
+        }
+        let mut pids = tree.members.clone();
+        pids.insert(root);
+        if let Some(window) = main_window(&pids) {
+            break window;
+        }
+
+        if Instant::now() >= deadline {
+            bail!("No window appeared within {} seconds", wait);
+        }
+        thread::sleep(Duration::from_millis(500));
+    };
+
+    // Programs often resize their window right after showing it, which
+    // would undo the move
+    thread::sleep(Duration::from_secs(1));
+
+    let (width, height) = (target.right - target.left, target.bottom - target.top);
+    unsafe {
+        if placement.borderless == Some(true) {
+            let mut rect: RECT = std::mem::zeroed();
+            GetWindowRect(window, &mut rect);
+            let original = GetWindowLongPtrW(window, GWL_STYLE);
+            let style = (original & !(WS_OVERLAPPEDWINDOW as isize)) | WS_POPUP as isize;
+            SetWindowLongPtrW(window, GWL_STYLE, style);
+            if SetWindowPos(
+                window,
+                std::ptr::null_mut(),
+                target.left,
+                target.top,
+                width,
+                height,
+                SWP_FRAMECHANGED | SWP_NOZORDER | SWP_SHOWWINDOW,
+            ) == 0
+            {
+                bail!(
+                    "Could not resize the window: {}",
+                    io::Error::last_os_error()
+                );
+            }
+            println!(
+                "Window made borderless on monitor {} ({}x{})",
+                number, width, height
+            );
+            return Ok(Some(BorderlessWindow {
+                window: window as isize,
+                style: original,
+                rect,
+            }));
+        }
+
+        // A maximized window is moved restored and maximized again on the new monitor
+        let maximized = IsZoomed(window) != 0;
+        if maximized {
+            ShowWindow(window, SW_RESTORE);
+        }
+
+        let mut rect: RECT = std::mem::zeroed();
+        GetWindowRect(window, &mut rect);
+        let x = target.left + (width - (rect.right - rect.left)).max(0) / 2;
+        let y = target.top + (height - (rect.bottom - rect.top)).max(0) / 2;
+        if SetWindowPos(
+            window,
+            std::ptr::null_mut(),
+            x,
+            y,
+            0,
+            0,
+            SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+        ) == 0
+        {
+            bail!("Could not move the window: {}", io::Error::last_os_error());
+        }
+
+        if maximized {
+            ShowWindow(window, SW_MAXIMIZE);
+        }
+    }
+    println!("Window moved to monitor {}", number);
+
+    Ok(None)
+}
+
+/// Applies the profile's affinity and priority to the programs `root` starts,
+/// skipping the launcher's own processes, until `window` has passed (or for
+/// as long as affinity-rs runs without one). With `first_only` it returns
+/// after configuring a single program.
+fn follow_children(
+    root: u32,
+    profile: &Profile,
+    launcher: Option<&KnownLauncher>,
+    window: Option<Duration>,
+    first_only: bool,
+) {
+    let deadline = window.map(|window| Instant::now() + window);
+    let mut tree = ProcessTree::new(root);
+
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        if let Ok(processes) = process_snapshot() {
+            let added = tree.update(&processes);
+
+            // Nothing left to follow once the launcher is gone without children
+            if !tree.is_running(&processes) {
+                return;
+            }
+
+            for pid in added {
+                let name = processes
+                    .iter()
+                    .find(|p| p.pid == pid)
+                    .map_or("", |p| p.name.as_str());
+                if launcher.is_some_and(|launcher| launcher.is_own_process(name)) {
+                    continue;
+                }
+
+                let affinity = if profile.cpus.is_empty() {
+                    Ok(())
+                } else {
+                    set_process_affinity(pid, &profile.cpus)
+                };
+                let result = affinity.and_then(|_| {
+                    profile
+                        .priority
+                        .as_ref()
+                        .map_or(Ok(()), |priority| set_process_priority(pid, priority))
+                });
+                match result {
+                    Ok(_) if first_only => {
+                        println!("Applied profile to PID {} ({})", pid, name);
+                        return;
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Warning: Could not configure PID {}: {:#}", pid, e),
+                }
+            }
+        }
+
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// The live affinity and priority of a process, and whether they differ
+/// from what a profile asks for.
+struct Drift {
+    cpus: Vec<usize>,
+    priority: Option<ProcessPriority>,
+    cpus_drifted: bool,
+    priority_drifted: bool,
+}
+
+impl Drift {
+    fn any(&self) -> bool {
+        self.cpus_drifted || self.priority_drifted
+    }
+}
+
+/// Compares a process with the wanted CPUs and priority. Empty `cpus` or no
+/// `priority` leave that part unchecked.
+fn process_drift(pid: u32, cpus: &[usize], priority: Option<&ProcessPriority>) -> Result<Drift> {
+    let mut wanted_cpus = cpus.to_vec();
+    wanted_cpus.sort_unstable();
+    wanted_cpus.dedup();
+
+    let live_cpus = get_process_affinity(pid)?;
+    let live_priority = get_process_priority(pid).ok();
+
+    Ok(Drift {
+        cpus_drifted: !wanted_cpus.is_empty() && live_cpus != wanted_cpus,
+        priority_drifted: priority.is_some() && live_priority.as_ref() != priority,
+        cpus: live_cpus,
+        priority: live_priority,
+    })
+}
+
+/// Restores the affinity and priority of a process that changed them since
+/// they were applied. Returns whether anything had drifted.
+fn fix_drift(
+    pid: u32,
+    cpus: &[usize],
+    priority: Option<&ProcessPriority>,
+    initiator: &str,
+) -> Result<bool> {
+    let drift = process_drift(pid, cpus, priority)?;
+
+    if drift.cpus_drifted {
+        set_process_affinity(pid, cpus)?;
+    }
+    if drift.priority_drifted
+        && let Some(priority) = priority
+    {
+        set_process_priority(pid, priority)?;
+    }
+
+    if drift.any() {
+        audit_change(
+            initiator,
+            pid,
+            None,
+            Some(&drift.cpus),
+            drift.cpus_drifted.then_some(cpus),
+            drift.priority.as_ref(),
+            priority.filter(|_| drift.priority_drifted),
+        );
+    }
+
+    Ok(drift.any())
+}
+
+/// How often attached launches and the daemon look for a wake-up from sleep.
+const RESUME_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time since boot including and excluding the time spent asleep.
+#[cfg(target_os = "linux")]
+fn boot_clocks() -> (Duration, Duration) {
+    let read = |clock| {
+        let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+        unsafe { libc::clock_gettime(clock, &mut time) };
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    };
+    (read(libc::CLOCK_BOOTTIME), read(libc::CLOCK_MONOTONIC))
+}
+
+/// Time since boot including and excluding the time spent asleep.
+#[cfg(target_os = "windows")]
+fn boot_clocks() -> (Duration, Duration) {
+    use windows_sys::Win32::System::WindowsProgramming::{
+        QueryInterruptTime, QueryUnbiasedInterruptTime,
+    };
+
+    // Both count in 100 ns units
+    let (mut total, mut awake) = (0u64, 0u64);
+    unsafe {
+        QueryInterruptTime(&mut total);
+        QueryUnbiasedInterruptTime(&mut awake);
+    }
+    (
+        Duration::from_nanos(total * 100),
+        Duration::from_nanos(awake * 100),
+    )
+}
+
+/// Notices when the machine slept since the last check. Settings are often
+/// reset on resume, and a growing gap between the clock that counts sleep
+/// and the one that doesn't catches every kind of suspend and hibernation.
+/// This is polled rather than driven by WM_POWERBROADCAST or logind's
+/// PrepareForSleep, which would need a message window or a D-Bus connection,
+/// so a wake-up is noticed up to RESUME_CHECK_INTERVAL late.
+struct ResumeDetector {
+    asleep: Duration,
+}
+
+impl ResumeDetector {
+    fn new() -> Self {
+        let (total, awake) = boot_clocks();
+        Self {
+            asleep: total.saturating_sub(awake),
+        }
+    }
+
+    fn resumed(&mut self) -> bool {
+        let (total, awake) = boot_clocks();
+        let asleep = total.saturating_sub(awake);
+        let slept = asleep.saturating_sub(self.asleep) >= Duration::from_secs(1);
+        self.asleep = asleep;
+        slept
+    }
+}
+
+/// Re-applies the profile to `root` and the processes it started wherever
+/// affinity or priority drifted, every `interval` if given and after each
+/// wake-up from sleep, for as long as affinity-rs stays attached. While the
+/// realtime watchdog has `yielded`, the priority is left to it.
+fn reapply_periodically(
+    root: u32,
+    profile: Profile,
+    launcher: Option<&'static KnownLauncher>,
+    interval: Option<Duration>,
+    yielded: std::sync::Arc<std::sync::atomic::AtomicBool>,
+) {
+    // Children given all cores back are not part of the managed tree
+    let whole_tree = profile.inherit_affinity != Some(false);
+
+    thread::spawn(move || {
+        let mut tree = ProcessTree::new(root);
+        let mut resume = ResumeDetector::new();
+        let mut last_applied = Instant::now();
+
+        loop {
+            thread::sleep(interval.map_or(RESUME_CHECK_INTERVAL, |interval| {
+                interval.min(RESUME_CHECK_INTERVAL)
+            }));
+
+            let resumed = resume.resumed();
+            if !resumed && interval.is_none_or(|interval| last_applied.elapsed() < interval) {
+                continue;
+            }
+            last_applied = Instant::now();
+
+            let Ok(processes) = process_snapshot() else {
+                continue;
+            };
+            tree.update(&processes);
+
+            let members = whole_tree.then_some(&tree.members).into_iter().flatten();
+            for &pid in std::iter::once(&root).chain(members) {
+                let name = processes
+                    .iter()
+                    .find(|p| p.pid == pid)
+                    .map_or("", |p| p.name.as_str());
+                if pid != root && launcher.is_some_and(|launcher| launcher.is_own_process(name)) {
+                    continue;
+                }
+
+                // Errors are expected for processes that exited in the meantime
+                let initiator = if resumed { "resume" } else { "reapply" };
+                let priority = profile
+                    .priority
+                    .as_ref()
+                    .filter(|_| !yielded.load(std::sync::atomic::Ordering::Relaxed));
+                if let Ok(true) = fix_drift(pid, &profile.cpus, priority, initiator) {
+                    if resumed {
+                        println!("Re-applied profile to PID {} ({}) after resume", pid, name);
+                    } else {
+                        println!("Re-applied profile to PID {} ({})", pid, name);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Processes that play sound or handle input, watched by the realtime watchdog.
+#[cfg(target_os = "linux")]
+const AUDIO_INPUT_PROCESSES: &[&str] = &[
+    "pipewire",
+    "pipewire-pulse",
+    "wireplumber",
+    "pulseaudio",
+    "jackd",
+    "jackdbus",
+    "Xorg",
+    "Xwayland",
+    "gnome-shell",
+    "kwin_wayland",
+    "kwin_x11",
+    "sway",
+];
+
+/// Processes that play sound, handle input and compose the desktop, watched
+/// by the realtime watchdog.
+#[cfg(target_os = "windows")]
+const AUDIO_INPUT_PROCESSES: &[&str] = &["audiodg.exe", "csrss.exe", "dwm.exe"];
+
+/// How often the realtime watchdog checks for starvation.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Waiting this long for a CPU within one check counts as starved.
+const STARVATION_DELAY: Duration = Duration::from_millis(200);
+
+/// How long nothing has to be starved before Realtime is restored.
+const WATCHDOG_RESTORE_AFTER: Duration = Duration::from_secs(10);
+
+/// Time each audio and input process spent waiting for a CPU so far, summed
+/// over its threads, from the scheduler statistics in /proc.
+#[cfg(target_os = "linux")]
+fn audio_input_wait_times(processes: &[ProcessInfo]) -> HashMap<u32, (String, Duration)> {
+    processes
+        .iter()
+        .filter(|process| {
+            AUDIO_INPUT_PROCESSES
+                .iter()
+                .any(|pattern| process_name_matches(pattern, &process.name))
+        })
+        .filter_map(|process| {
+            // schedstat: time on the CPU, time waiting for it and timeslices, in ns
+            let waited: u64 = process_threads(process.pid)
+                .iter()
+                .filter_map(|tid| {
+                    std::fs::read_to_string(format!("/proc/{}/task/{}/schedstat", process.pid, tid))
+                        .ok()?
+                        .split_whitespace()
+                        .nth(1)?
+                        .parse::<u64>()
+                        .ok()
+                })
+                .sum();
+            (waited > 0).then(|| {
+                (
+                    process.pid,
+                    (process.name.clone(), Duration::from_nanos(waited)),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Threads of `pid` running under SCHED_FIFO or SCHED_RR, with their policy
+/// and priority. Realtime in a profile is only nice -20, but the program may
+/// put threads of its own under these policies, which nice doesn't affect.
+#[cfg(target_os = "linux")]
+fn realtime_threads(pid: u32) -> Vec<(libc::pid_t, libc::c_int, libc::c_int)> {
+    process_threads(pid)
+        .into_iter()
+        .filter_map(|tid| {
+            let tid = tid as libc::pid_t;
+            let policy = unsafe { libc::sched_getscheduler(tid) };
+            let base = policy & !libc::SCHED_RESET_ON_FORK;
+            if base != libc::SCHED_FIFO && base != libc::SCHED_RR {
+                return None;
+            }
+            let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+            (unsafe { libc::sched_getparam(tid, &mut param) } == 0).then_some((
+                tid,
+                policy,
+                param.sched_priority,
+            ))
+        })
+        .collect()
+}
+
+/// Threads of the audio and input processes that are ready to run but have
+/// no CPU, with the name of their process, from the scheduler's thread states.
+#[cfg(target_os = "windows")]
+fn ready_audio_input_threads() -> HashMap<usize, String> {
+    use windows_sys::Wdk::System::SystemInformation::{
+        NtQuerySystemInformation, SystemProcessInformation,
+    };
+    use windows_sys::Win32::Foundation::STATUS_INFO_LENGTH_MISMATCH;
+    use windows_sys::Win32::System::WindowsProgramming::{
+        SYSTEM_PROCESS_INFORMATION, SYSTEM_THREAD_INFORMATION,
+    };
+
+    // Ready and DeferredReady
+    const READY_STATES: [u32; 2] = [1, 7];
+
+    // u64 keeps the entries aligned
+    let mut buffer: Vec<u64> = vec![0; 64 * 1024];
+    loop {
+        let mut needed = 0u32;
+        let status = unsafe {
+            NtQuerySystemInformation(
+                SystemProcessInformation,
+                buffer.as_mut_ptr().cast(),
+                (buffer.len() * 8) as u32,
+                &mut needed,
+            )
+        };
+        if status == STATUS_INFO_LENGTH_MISMATCH {
+            // Processes may start before the next try
+            buffer.resize(needed as usize / 8 + 16 * 1024, 0);
+            continue;
+        }
+        if status < 0 {
+            return HashMap::new();
+        }
+        break;
+    }
+
+    let mut ready = HashMap::new();
+    let mut offset = 0usize;
+    let base = buffer.as_ptr().cast::<u8>();
+    loop {
+        let process = unsafe { &*base.add(offset).cast::<SYSTEM_PROCESS_INFORMATION>() };
+        let name = if process.ImageName.Buffer.is_null() {
+            String::new()
+        } else {
+            String::from_utf16_lossy(unsafe {
+                std::slice::from_raw_parts(
+                    process.ImageName.Buffer,
+                    process.ImageName.Length as usize / 2,
+                )
+            })
+        };
+
+        if AUDIO_INPUT_PROCESSES
+            .iter()
+            .any(|pattern| process_name_matches(pattern, &name))
+        {
+            // The threads follow the process entry
+            let threads = unsafe {
+                std::slice::from_raw_parts(
+                    base.add(offset + std::mem::size_of::<SYSTEM_PROCESS_INFORMATION>())
+                        .cast::<SYSTEM_THREAD_INFORMATION>(),
+                    process.NumberOfThreads as usize,
+                )
+            };
+            for thread in threads {
+                if READY_STATES.contains(&thread.ThreadState) {
+                    ready.insert(thread.ClientId.UniqueThread as usize, name.clone());
+                }
+            }
+        }
+
+        if process.NextEntryOffset == 0 {
+            break;
+        }
+        offset += process.NextEntryOffset as usize;
+    }
+    ready
+}
+
+/// Longest time a thread of each audio and input process stayed ready to run
+/// without getting a CPU, sampled in short steps for one watchdog interval.
+#[cfg(target_os = "windows")]
+fn probe_scheduling_delay() -> HashMap<String, Duration> {
+    const STEP: Duration = Duration::from_millis(20);
+
+    let end = Instant::now() + WATCHDOG_INTERVAL;
+    let mut ready_since: HashMap<usize, Instant> = HashMap::new();
+    let mut worst: HashMap<String, Duration> = HashMap::new();
+    while Instant::now() < end {
+        let now = Instant::now();
+        let ready = ready_audio_input_threads();
+        ready_since.retain(|thread, _| ready.contains_key(thread));
+        for (thread, name) in ready {
+            let waited = now - *ready_since.entry(thread).or_insert(now);
+            let longest = worst.entry(name).or_default();
+            *longest = (*longest).max(waited);
+        }
+        thread::sleep(STEP);
+    }
+    worst
+}
+
+/// Watches for a Realtime program starving the processes that play sound and
+/// handle input. When they are, the program and everything it started drop
+/// to High until nothing has been starved for a while, then go back to
+/// Realtime. `yielded` is shared with the launch's reapply thread, so that it
+/// doesn't raise the program again in the meantime.
+fn watch_realtime_starvation(
+    root: u32,
+    profile: Profile,
+    launcher: Option<&'static KnownLauncher>,
+    yielded: std::sync::Arc<std::sync::atomic::AtomicBool>,
+) {
+    thread::spawn(move || {
+        // A starved watchdog would notice too late, so this thread, and only
+        // this thread, runs as high as it may
+        #[cfg(target_os = "linux")]
+        if let Some(nice) = profile.nice_value() {
+            // Without CAP_SYS_NICE this fails and it stays as is
+            unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice) };
+        }
+        #[cfg(target_os = "windows")]
+        unsafe {
+            use windows_sys::Win32::System::Threading::{
+                GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL,
+            };
+            SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL);
+        }
+
+        let mut tree = ProcessTree::new(root);
+        let mut last_starved = Instant::now();
+        // SCHED_FIFO and SCHED_RR threads moved to normal scheduling while yielded
+        #[cfg(target_os = "linux")]
+        let mut demoted = Vec::new();
+        #[cfg(target_os = "linux")]
+        let mut waited = process_snapshot()
+            .map(|processes| audio_input_wait_times(&processes))
+            .unwrap_or_default();
+
+        loop {
+            #[cfg(target_os = "windows")]
+            let starved: Vec<String> = probe_scheduling_delay()
+                .into_iter()
+                .filter(|(_, delay)| *delay >= STARVATION_DELAY)
+                .map(|(name, delay)| format!("{} waited {} ms", name, delay.as_millis()))
+                .collect();
+            #[cfg(target_os = "linux")]
+            thread::sleep(WATCHDOG_INTERVAL);
+
+            let Ok(processes) = process_snapshot() else {
+                continue;
+            };
+            if !tree.is_running(&processes) {
+                break;
+            }
+            tree.update(&processes);
+
+            #[cfg(target_os = "linux")]
+            let starved: Vec<String> = {
+                let now = audio_input_wait_times(&processes);
+                let starved = now
+                    .iter()
+                    .filter_map(|(pid, (name, total))| {
+                        let before = waited.get(pid).map_or(*total, |(_, before)| *before);
+                        let delay = total.saturating_sub(before);
+                        (delay >= STARVATION_DELAY)
+                            .then(|| format!("{} waited {} ms", name, delay.as_millis()))
+                    })
+                    .collect();
+                waited = now;
+                starved
+            };
+
+            let was_yielded = yielded.load(std::sync::atomic::Ordering::Relaxed);
+            if !starved.is_empty() {
+                last_starved = Instant::now();
+            }
+            let lower = !starved.is_empty();
+            let restore = was_yielded && last_starved.elapsed() >= WATCHDOG_RESTORE_AFTER;
+            if !lower && !restore {
+                continue;
+            }
+
+            if lower && !was_yielded {
+                println!(
+                    "Realtime watchdog: {} for a CPU; dropping to High",
+                    starved.join(", ")
+                );
+            } else if restore {
+                println!("Realtime watchdog: nothing starved anymore; back to Realtime");
+            }
+
+            // Processes started while yielded are lowered as well
+            let members = tree.members.iter().copied();
+            for pid in std::iter::once(root).chain(members) {
+                let name = processes
+                    .iter()
+                    .find(|p| p.pid == pid)
+                    .map_or("", |p| p.name.as_str());
+                if pid != root && launcher.is_some_and(|launcher| launcher.is_own_process(name)) {
+                    continue;
+                }
+
+                // Processes may exit in the meantime
+                let _ = if lower {
+                    #[cfg(target_os = "linux")]
+                    for (tid, policy, priority) in realtime_threads(pid) {
+                        let normal: libc::sched_param = unsafe { std::mem::zeroed() };
+                        if unsafe { libc::sched_setscheduler(tid, libc::SCHED_OTHER, &normal) } == 0
+                        {
+                            demoted.push((tid, policy, priority));
+                        }
+                    }
+                    set_process_priority(pid, &ProcessPriority::High)
+                } else {
+                    #[cfg(target_os = "linux")]
+                    let result = set_process_nice(pid, profile.nice_value().unwrap_or(-20));
+                    #[cfg(target_os = "windows")]
+                    let result = set_process_priority(
+                        pid,
+                        profile
+                            .priority
+                            .as_ref()
+                            .unwrap_or(&ProcessPriority::Realtime),
+                    );
+                    result
+                };
+            }
+
+            // Going back to a realtime policy takes CAP_SYS_NICE or RLIMIT_RTPRIO
+            #[cfg(target_os = "linux")]
+            if restore {
+                let mut failed = 0;
+                for (tid, policy, priority) in demoted.drain(..) {
+                    let param = libc::sched_param {
+                        sched_priority: priority,
+                    };
+                    if unsafe { libc::sched_setscheduler(tid, policy, &param) } != 0
+                        && io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+                    {
+                        failed += 1;
+                    }
+                }
+                if failed > 0 {
+                    eprintln!(
+                        "Warning: {} thread(s) could not get their realtime scheduling back",
+                        failed
+                    );
+                }
+            }
+
+            yielded.store(lower, std::sync::atomic::Ordering::Relaxed);
+        }
+    });
+}
+
+/// How often `focus_affinity` checks which window has focus.
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// PID of the process owning the focused window.
+#[cfg(target_os = "windows")]
+fn foreground_pid() -> Option<u32> {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GetForegroundWindow, GetWindowThreadProcessId,
+    };
+
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.is_null() {
+            return None;
+        }
+        let mut pid = 0;
+        GetWindowThreadProcessId(window, &mut pid);
+        (pid != 0).then_some(pid)
+    }
+}
+
+/// PID of the process owning the focused window, as told by the X11 window
+/// manager. Wayland compositors have no common way to ask.
+#[cfg(target_os = "linux")]
+fn foreground_pid() -> Option<u32> {
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3c00007
+    let output = Command::new("xprop")
+        .args(["-root", "_NET_ACTIVE_WINDOW"])
+        .output()
+        .ok()?;
+    let window = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .last()?
+        .to_string();
+
+    // _NET_WM_PID(CARDINAL) = 4242
+    let output = Command::new("xprop")
+        .args(["-id", &window, "_NET_WM_PID"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+/// Lets the program and the processes it started use all cores while none of
+/// their windows has focus, such as during loading screens and shader
+/// compilation, and pins them to the profile's cores while one does.
+fn switch_affinity_on_focus(root: u32, profile: Profile, launcher: Option<&'static KnownLauncher>) {
+    let all_cpus: Vec<usize> = (0..num_cpus::get()).collect();
+    let whole_tree = profile.inherit_affinity != Some(false);
+
+    thread::spawn(move || {
+        let mut tree = ProcessTree::new(root);
+        let mut focused = None;
+        let mut checked = false;
+
+        loop {
+            thread::sleep(FOCUS_POLL_INTERVAL);
+
+            let Ok(processes) = process_snapshot() else {
+                continue;
+            };
+            let added = tree.update(&processes);
+
+            // Without an answer the current cores stay as they are
+            let Some(foreground) = foreground_pid() else {
+                if !checked {
+                    eprintln!("Note: focus_affinity can't tell which window has focus here.");
+                    #[cfg(target_os = "linux")]
+                    eprintln!("      It needs 'xprop' and an X11 session.");
+                    checked = true;
+                }
+                continue;
+            };
+            checked = true;
+
+            let now_focused = foreground == root || tree.members.contains(&foreground);
+            let cpus = if now_focused {
+                &profile.cpus
+            } else {
+                &all_cpus
+            };
+
+            // On a change everything moves, otherwise only newcomers need the current cores
+            let targets: Vec<u32> = if focused != Some(now_focused) {
+                let members = whole_tree.then_some(&tree.members).into_iter().flatten();
+                std::iter::once(root).chain(members.copied()).collect()
+            } else if whole_tree {
+                added
+            } else {
+                Vec::new()
+            };
+
+            for pid in targets {
+                let name = processes
+                    .iter()
+                    .find(|p| p.pid == pid)
+                    .map_or("", |p| p.name.as_str());
+                if pid != root && launcher.is_some_and(|launcher| launcher.is_own_process(name)) {
+                    continue;
+                }
+
+                // Errors are expected for processes that exited in the meantime
+                let _ = set_process_affinity(pid, cpus);
+            }
+
+            if focused != Some(now_focused) {
+                if now_focused {
+                    println!("Window focused: pinned to CPUs {:?}", profile.cpus);
+                } else {
+                    println!("Window unfocused: using all cores");
+                }
+                focused = Some(now_focused);
+            }
+        }
+    });
+}
+
+/// Resets the affinity of processes started by `root` to all cores as they
+/// appear, for as long as affinity-rs stays attached.
+fn release_child_affinity(root: u32) {
+    let all_cpus: Vec<usize> = (0..num_cpus::get()).collect();
+
+    thread::spawn(move || {
+        let mut tree = ProcessTree::new(root);
+
+        loop {
+            if let Ok(processes) = process_snapshot() {
+                for pid in tree.update(&processes) {
+                    if let Err(e) = set_process_affinity(pid, &all_cpus) {
+                        eprintln!("Warning: Could not widen affinity of PID {}: {:#}", pid, e);
+                    }
+                }
+            }
+
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+/// Stops Ctrl+C from terminating affinity-rs, so it reaches only the attached
+/// program and session tweaks are still restored when the program exits.
+/// Must be called after spawning, since children inherit the setting.
+fn ignore_console_interrupts() {
+    #[cfg(target_os = "windows")]
+    unsafe {
+        windows_sys::Win32::System::Console::SetConsoleCtrlHandler(None, 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+    }
+}
+
+/// PID of the attached program that SIGTERM or SIGHUP to affinity-rs kill.
+#[cfg(target_os = "linux")]
+static KILL_ON_SIGNAL: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+#[cfg(target_os = "linux")]
+extern "C" fn kill_attached_program(_: libc::c_int) {
+    // Only async-signal-safe calls here; the rest of the tree goes once
+    // the wait for the program returns
+    let pid = KILL_ON_SIGNAL.load(std::sync::atomic::Ordering::Relaxed);
+    if pid > 0 {
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+}
+
+/// Kills every process left below affinity-rs. As a child subreaper it
+/// inherits what the program started, so this covers the whole tree like a
+/// kill-on-close job object does on Windows.
+#[cfg(target_os = "linux")]
+fn kill_descendants() {
+    let mut tree = ProcessTree::new(std::process::id());
+    // Processes reparented while the first pass ran are caught by the second
+    for _ in 0..2 {
+        let Ok(processes) = list_processes() else {
+            return;
+        };
+        tree.update(&processes);
+        for &pid in &tree.members {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        }
+    }
+}
+
+/// Waits for an attached process and returns the exit code to pass on. With
+/// `kill_tree`, whatever the program leaves running is killed as well.
+fn supervise(mut child: Child, kill_tree: bool) -> Result<i32> {
+    // The program shares our console handles, so it stays fully interactive
+    ignore_console_interrupts();
+
+    // A job object takes care of this on Windows
+    #[cfg(target_os = "linux")]
+    if kill_tree {
+        KILL_ON_SIGNAL.store(child.id() as i32, std::sync::atomic::Ordering::Relaxed);
+        let handler = kill_attached_program as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        unsafe {
+            libc::signal(libc::SIGTERM, handler);
+            libc::signal(libc::SIGHUP, handler);
+        }
+    }
+    #[cfg(target_os = "windows")]
+    let _ = kill_tree;
+
+    println!(
+        "Attached to PID {}. Waiting for it to exit...\n",
+        child.id()
+    );
+
+    let status = child.wait().context("Failed to wait for process")?;
+    println!("\nProcess exited with {}", status);
+
+    #[cfg(target_os = "linux")]
+    if kill_tree {
+        kill_descendants();
+    }
+
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Launches the profile and returns the exit code affinity-rs should exit
+/// with, along with how applying the profile went (none in safe mode).
+fn launch_profile(
+    profile: &Profile,
+    args: &[OsString],
+    options: &LaunchOptions,
+) -> Result<(i32, Option<LaunchOutcome>)> {
+    let args = &profile.launch_args(args);
+    let mode = profile.mode.unwrap_or_default();
+
+    println!("\nLaunching: {}", profile.path.display());
+    log_event(LogLevel::Debug, &format!("Arguments: {:?}", args));
+    if !profile.env.is_empty() {
+        let mut names: Vec<&str> = profile.env.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        log_event(
+            LogLevel::Debug,
+            &format!("Environment variables set: {}", names.join(", ")),
+        );
+    }
+
+    if options.plain {
+        println!("Safe mode: skipping affinity, priority and session tweaks");
+        if !args.is_empty() {
+            println!("Arguments: {:?}", args);
+        }
+        println!();
+
+        let (secret_profile, args) = with_secrets(profile, args)?;
+        let child = launch_plain(&secret_profile, &args)?;
+        return Ok((finish_launch(child, profile, mode, options)?, None));
+    }
+
+    if profile.cpus.is_empty() {
+        println!("CPU affinity: unchanged");
+    } else {
+        println!("CPU affinity: {:?}", profile.cpus);
+    }
+
+    if let Some(ref priority) = profile.priority {
+        println!("Priority: {}", priority.display_name());
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(nice) = profile.nice {
+        println!("Nice: {}", nice);
+    }
+
+    if let Some(ref gpu_priority) = profile.gpu_priority {
+        println!("GPU priority: {}", gpu_priority.display_name());
+
+        #[cfg(not(target_os = "windows"))]
+        eprintln!("Note: gpu_priority only applies on Windows.");
+    }
+
+    #[cfg(target_os = "linux")]
+    if profile.irq_check == Some(true) {
+        warn_irq_conflicts(&profile.cpus);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if profile.slice.is_some()
+        || profile.nice.is_some()
+        || profile.rlimits.is_some()
+        || profile.autogroup_nice.is_some()
+        || profile.timer_slack_ns.is_some()
+    {
+        eprintln!(
+            "Note: slice, nice, rlimits, autogroup_nice and timer_slack_ns only apply on Linux."
+        );
+    }
+
+    // Children inherit the nice value on Linux and can't lower it without privileges
+    #[cfg(not(target_os = "windows"))]
+    if profile.job_priority_limit.is_some() {
+        eprintln!("Note: job_priority_limit only applies on Windows.");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    if !profile.compat_flags.is_empty() {
+        eprintln!("Note: compat_flags only apply on Windows.");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    if profile.window.is_some() {
+        eprintln!("Note: window only applies on Windows.");
+    }
+
+    if !args.is_empty() {
+        println!("Arguments: {:?}", args);
+    }
+
+    println!();
+
+    if mode == LaunchMode::Attach
+        && profile
+            .creation_flags
+            .iter()
+            .any(|flag| matches!(flag, CreationFlag::NoWindow | CreationFlag::DetachedProcess))
+    {
+        eprintln!("Note: no_window/detached_process hide the program's console while attached.");
+    }
+
+    let closed = if profile.kill_before.is_empty() {
+        Vec::new()
+    } else {
+        close_before_launch(profile)
+    };
+
+    // Session tweaks are reverted when affinity-rs exits, so they need attached mode
+    let mut session = if mode == LaunchMode::Attach {
+        SessionTweaks::apply(profile)
+    } else {
+        if profile.power_plan.is_some()
+            || profile.timer_resolution_ms.is_some()
+            || profile.calm_background == Some(true)
+        {
+            eprintln!(
+                "Note: power_plan, timer_resolution_ms and calm_background only apply in attached mode."
+            );
+        }
+        if profile.restart_killed == Some(true) {
+            eprintln!("Note: restart_killed only applies in attached mode.");
+        }
+        SessionTweaks::default()
+    };
+    if mode == LaunchMode::Attach && profile.restart_killed == Some(true) && !closed.is_empty() {
+        // The programs would come back with affinity-rs's administrator rights
+        #[cfg(target_os = "windows")]
+        let elevated = is_elevated();
+        #[cfg(target_os = "linux")]
+        let elevated = unsafe { libc::geteuid() } == 0;
+
+        if elevated {
+            eprintln!(
+                "Note: Closed programs are not restarted, as affinity-rs runs with administrator/root rights."
+            );
+        } else {
+            session.restart = closed;
+        }
+    }
+
+    let (secret_profile, secret_args) = with_secrets(profile, args)?;
+
+    #[cfg(target_os = "linux")]
+    let (child, outcome) = launch_profile_linux(
+        &secret_profile,
+        &secret_args,
+        mode == LaunchMode::Attach || options.timings,
+    )?;
+
+    #[cfg(target_os = "windows")]
+    let (child, outcome) = launch_profile_windows(&secret_profile, &secret_args)?;
+
+    // The rule stays for detached launches, which nothing waits for
+    #[cfg(target_os = "linux")]
+    let mut outcome = outcome;
+    #[cfg(target_os = "linux")]
+    if mode == LaunchMode::Attach {
+        session.dscp_rule = outcome.dscp_rule.take();
+    }
+
+    if options.timings {
+        show_timings(&outcome.timings);
+    }
+
+    log_event(
+        LogLevel::Info,
+        &format!("Process launched with PID {}", child.id()),
+    );
+    if profile.cpus.is_empty() {
+        log_event(LogLevel::Info, "CPU affinity left unchanged");
+    } else if outcome.attempts == 0 {
+        log_event(
+            LogLevel::Info,
+            &format!("CPU affinity {:?} applied, not verified", profile.cpus),
+        );
+    } else if outcome.affinity_applied {
+        log_event(
+            LogLevel::Info,
+            &format!(
+                "CPU affinity {:?} applied after {} attempt(s)",
+                profile.cpus, outcome.attempts
+            ),
+        );
+    } else {
+        log_event(
+            LogLevel::Warn,
+            &format!(
+                "CPU affinity {:?} could not be confirmed after {} attempt(s)",
+                profile.cpus, outcome.attempts
+            ),
+        );
+    }
+    for (step, took) in &outcome.timings {
+        log_event(
+            LogLevel::Debug,
+            &format!("{} took {:.1} ms", step, took.as_secs_f64() * 1000.0),
+        );
+    }
+
+    audit_change(
+        "launch",
+        child.id(),
+        Some(&profile.path.display().to_string()),
+        None,
+        Some(&profile.cpus),
+        None,
+        profile.priority.as_ref(),
+    );
+
+    if profile.kill_on_exit && mode != LaunchMode::Attach {
+        eprintln!("Note: kill_on_exit only applies in attached mode.");
+    }
+
+    if profile.inherit_affinity == Some(false) {
+        if mode == LaunchMode::Attach {
+            release_child_affinity(child.id());
+        } else {
+            eprintln!("Note: inherit_affinity only applies in attached mode.");
+        }
+    }
+
+    let launcher = known_launcher(&profile.path);
+    if let Some(launcher) = launcher {
+        println!(
+            "{} detected: the profile is applied to the programs it starts.",
+            launcher.name
+        );
+        println!("Tip: {}", launcher.tip);
+    }
+
+    // Children inherit affinity and niceness, so other programs are only
+    // followed on request. Releasing children contradicts following them.
+    let follow = profile.follow_children.unwrap_or(launcher.is_some())
+        && profile.inherit_affinity != Some(false);
+    let window = profile.follow_window_secs.map(Duration::from_secs);
+    let pid = child.id();
+
+    if follow && mode == LaunchMode::Attach {
+        let profile = profile.clone();
+        thread::spawn(move || follow_children(pid, &profile, launcher, window, false));
+    }
+
+    // Shared by the realtime watchdog and the reapply thread of this launch
+    let yielded = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    if profile.priority == Some(ProcessPriority::Realtime)
+        && profile.realtime_watchdog != Some(false)
+        && !(cfg!(target_os = "linux") && profile.nice.is_some())
+    {
+        if mode == LaunchMode::Attach {
+            watch_realtime_starvation(pid, profile.clone(), launcher, yielded.clone());
+        } else if profile.realtime_watchdog == Some(true) {
+            eprintln!("Note: realtime_watchdog only applies in attached mode.");
+        }
+    }
+
+    // Detached launches only wait for the window when wait_secs asks for it
+    #[cfg(target_os = "windows")]
+    let mut placed = None;
+    #[cfg(target_os = "windows")]
+    if let Some(ref placement) = profile.window {
+        let wanted = placement.clone();
+        let place = move || match place_window(pid, &wanted) {
+            Ok(borderless) => borderless,
+            Err(e) => {
+                eprintln!("Warning: Could not place the window: {:#}", e);
+                None
+            }
+        };
+        if mode == LaunchMode::Attach {
+            placed = Some(thread::spawn(place));
+        } else if let Some(wait) = placement.wait_secs {
+            println!("Waiting up to {}s for the window to place it...", wait);
+            place();
+        } else {
+            eprintln!(
+                "Note: Detached launches only place the window with \"wait_secs\" set in \"window\"."
+            );
+        }
+    }
+
+    let focus_affinity = profile.focus_affinity == Some(true) && !profile.cpus.is_empty();
+    if focus_affinity {
+        if mode == LaunchMode::Attach {
+            switch_affinity_on_focus(pid, profile.clone(), launcher);
+        } else {
+            eprintln!("Note: focus_affinity only applies in attached mode.");
+        }
+    }
+
+    // Attached launches always recover from a reset on resume; drift in
+    // between is only fixed with a reapply_interval
+    if mode == LaunchMode::Attach && !focus_affinity {
+        reapply_periodically(
+            pid,
+            profile.clone(),
+            launcher,
+            profile.reapply_interval.map(Duration::from_secs),
+            yielded,
+        );
+    } else if profile.reapply_interval.is_some() {
+        if focus_affinity && mode == LaunchMode::Attach {
+            eprintln!("Note: reapply_interval is not used together with focus_affinity.");
+        } else {
+            eprintln!("Note: reapply_interval only applies in attached mode and under the daemon.");
+        }
+    }
+
+    let code = finish_launch(child, profile, mode, options)?;
+
+    // A window still waited for is left alone
+    #[cfg(target_os = "windows")]
+    if let Some(placed) = placed
+        && placed.is_finished()
+        && let Ok(Some(borderless)) = placed.join()
+    {
+        borderless.restore();
+    }
+
+    if follow && mode == LaunchMode::Detach {
+        let window = window.unwrap_or(FOLLOW_WINDOW);
+        println!(
+            "Waiting up to {}s for the program to be started (Ctrl+C to stop)...",
+            window.as_secs()
+        );
+        follow_children(pid, profile, launcher, Some(window), true);
+    }
+
+    Ok((code, Some(outcome)))
+}
+
+/// The real stdout once `--json` has moved affinity-rs's own messages to stderr.
+static JSON_STDOUT: OnceLock<std::fs::File> = OnceLock::new();
+
+/// Points affinity-rs's own stdout at stderr, so that with `--json` stdout
+/// carries nothing but the JSON summary and the launched program's output.
+fn keep_stdout_for_json() -> io::Result<()> {
+    if JSON_STDOUT.get().is_some() {
+        return Ok(());
+    }
+    io::stdout().flush()?;
+
+    #[cfg(target_os = "linux")]
+    let saved = {
+        use std::os::fd::AsFd;
+
+        let saved = io::stdout().as_fd().try_clone_to_owned()?;
+        if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        std::fs::File::from(saved)
+    };
+
+    // Rust looks the standard handles up on every write
+    #[cfg(target_os = "windows")]
+    let saved = {
+        use std::os::windows::io::AsHandle;
+        use windows_sys::Win32::System::Console::{
+            GetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE, SetStdHandle,
+        };
+
+        let saved = io::stdout().as_handle().try_clone_to_owned()?;
+        if unsafe { SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        std::fs::File::from(saved)
+    };
+
+    let _ = JSON_STDOUT.set(saved);
+    Ok(())
+}
+
+/// Where the launched program's stdout goes: the real stdout, also with `--json`.
+fn program_stdout() -> std::process::Stdio {
+    JSON_STDOUT
+        .get()
+        .and_then(|stdout| stdout.try_clone().ok())
+        .map_or_else(std::process::Stdio::inherit, std::process::Stdio::from)
+}
+
+/// Hands the launched PID to other tools if asked, then supervises or leaves
+/// the process according to the launch mode.
+fn finish_launch(
+    child: Child,
+    profile: &Profile,
+    mode: LaunchMode,
+    options: &LaunchOptions,
+) -> Result<i32> {
+    let pid = child.id();
+
+    if let Some(ref pid_file) = options.pid_file {
+        std::fs::write(pid_file, format!("{}\n", pid))
+            .with_context(|| format!("Failed to write PID file {}", pid_file.display()))?;
+    }
+
+    if let Err(e) = remember_session_process(pid, profile) {
+        eprintln!("Warning: stop-all won't know about this program: {:#}", e);
+    }
+
+    if options.json {
+        let summary = serde_json::json!({
+            "pid": pid,
+            "path": profile.path,
+            "cpus": profile.cpus,
+            "priority": profile.priority,
+            "mode": mode,
+        });
+        match JSON_STDOUT.get() {
+            Some(mut stdout) => {
+                let _ = writeln!(stdout, "{}", summary);
+            }
+            None => println!("{}", summary),
+        }
+    }
+
+    let code = match mode {
+        LaunchMode::Attach => supervise(child, profile.kills_on_exit()),
+        LaunchMode::Detach => {
+            println!("\nProgram is running independently.\n");
+            Ok(0)
+        }
+    };
+
+    // A stale PID file would point at an unrelated process later on
+    if (mode == LaunchMode::Attach || code.is_err())
+        && let Some(ref pid_file) = options.pid_file
+    {
+        let _ = std::fs::remove_file(pid_file);
+    }
+
+    code
+}
+
+/// The executable `run` starts: a path as given, or a bare name looked up in PATH.
+fn resolve_program(program: &OsString) -> PathBuf {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    }
+
+    let mut names = vec![program.clone()];
+    if cfg!(target_os = "windows") && path.extension().is_none() {
+        let mut exe = program.clone();
+        exe.push(".exe");
+        names.push(exe);
+    }
+
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+                .find(|candidate| candidate.is_file())
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Reads a one-shot profile from stdin for `run --stdin`, so scripts can
+/// launch with settings of their own without saving a profile. `extends`
+/// still refers to saved profiles.
+fn read_stdin_profile(profiles: &Profiles) -> Result<Profile> {
+    let mut text = String::new();
+    io::Read::read_to_string(&mut io::stdin(), &mut text).context("Failed to read stdin")?;
+    if text.trim().is_empty() {
+        bail!("Expected a profile as JSON on stdin");
+    }
+
+    let value: serde_json::Value =
+        serde_json::from_str(&text).context("stdin does not contain valid JSON")?;
+    let profile = parse_profile_strict("stdin", value)?;
+
+    if profile.extends.is_none() {
+        return Ok(profile);
+    }
+
+    let name = format!("{}{}", TEMP_PROFILE_PREFIX, std::process::id());
+    let mut profiles = profiles.clone();
+    profiles.insert(name.clone(), profile);
+    inherited_profile(&profiles, &name)
+}
+
+/// Prints what launching the profile would do: validation, CPU mask,
+/// priority, whether elevation is needed and the exact command line.
+/// Returns whether the profile is valid.
+fn show_dry_run(
+    profile: &Profile,
+    args: &[OsString],
+    profile_name: Option<&str>,
+    options: &LaunchOptions,
+) -> bool {
+    let program_args = profile.launch_args(args);
+
+    println!("Dry run: nothing is started or changed.\n");
+    println!("Profile: {}", profile_name.unwrap_or("(unsaved)"));
+
+    let valid = match check_profile_settings(profile) {
+        Ok(_) => {
+            println!("Validation: OK");
+            true
+        }
+        Err(e) => {
+            let error = format!("{:#}", e);
+            println!(
+                "Validation: failed, {}",
+                error.lines().next().unwrap_or_default()
+            );
+            false
+        }
+    };
+    let (offline, missing) = unavailable_cpus(profile);
+    if !missing.is_empty() {
+        println!(
+            "  CPUs {:?} don't exist on this system and are ignored",
+            missing
+        );
+    }
+    if !offline.is_empty() {
+        println!("  CPUs {:?} are offline right now", offline);
+    }
+
+    println!("Mode: {}", profile.mode.unwrap_or_default().display_name());
+
+    if options.plain {
+        println!("Safe mode: affinity, priority and session tweaks are skipped");
+    } else {
+        if profile.cpus.is_empty() {
+            println!("CPU affinity: unchanged");
+        } else {
+            println!(
+                "CPU affinity: {:?} (mask {})",
+                profile.cpus,
+                cpu_mask_string(&profile.cpus)
+            );
+        }
+
+        #[cfg(target_os = "windows")]
+        match profile.priority {
+            Some(ref priority) => println!(
+                "Priority: {} (priority class 0x{:X})",
+                priority.display_name(),
+                priority.to_windows_class()
+            ),
+            None => println!("Priority: unchanged"),
+        }
+        #[cfg(target_os = "linux")]
+        match profile.nice_value() {
+            Some(nice) => println!(
+                "Priority: {} (nice {})",
+                profile
+                    .priority
+                    .as_ref()
+                    .map_or("custom", |priority| priority.display_name()),
+                nice
+            ),
+            None => println!("Priority: unchanged"),
+        }
+    }
+
+    if !profile.kill_before.is_empty() && !options.plain {
+        println!("Closes first: {}", profile.kill_before.join(", "));
+    }
+    if !profile.env.is_empty() {
+        let mut names: Vec<&str> = profile.env.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        println!("Environment variables: {}", names.join(", "));
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let needs_admin = !options.plain
+            && profile
+                .priority
+                .as_ref()
+                .is_some_and(ProcessPriority::requires_elevation);
+        if !needs_admin {
+            println!("Elevation: not required");
+        } else if is_elevated() {
+            println!("Elevation: required, already running as administrator");
+        } else {
+            // Same parameters as relaunch_elevated
+            let name = profile_name.map_or_else(
+                || format!("{}{}", TEMP_PROFILE_PREFIX, std::process::id()),
+                str::to_string,
+            );
+            let mut params: Vec<OsString> = std::env::current_exe()
+                .map(PathBuf::into_os_string)
+                .into_iter()
+                .collect();
+            params.extend(options.to_args());
+            params.extend([name.into(), ELEVATION_CLEANUP_FLAG.into()]);
+            params.extend_from_slice(args);
+            println!("Elevation: required, affinity-rs relaunches itself as administrator (UAC):");
+            println!(
+                "  {}",
+                String::from_utf16_lossy(&windows_command_line(&params).collect::<Vec<u16>>())
+            );
+        }
+
+        let mut command_line = vec![profile.path.clone().into_os_string()];
+        command_line.extend(program_args.iter().cloned());
+        println!(
+            "Command line: {}",
+            String::from_utf16_lossy(&windows_command_line(&command_line).collect::<Vec<u16>>())
+        );
+        if !options.plain {
+            println!("Creation flags: 0x{:X}", windows_creation_flags(profile));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let lowers_nice = !options.plain
+            && profile
+                .nice_value()
+                .is_some_and(|nice| nice < unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) });
+        if lowers_nice && unsafe { libc::geteuid() } != 0 {
+            println!("Elevation: a lower nice value needs root or CAP_SYS_NICE");
+        } else {
+            println!("Elevation: not required");
+        }
+
+        let mut command_line = if options.plain {
+            vec![profile.path.clone().into_os_string()]
+        } else {
+            match linux_command_line(profile) {
+                Ok(command_line) => command_line,
+                Err(e) => {
+                    println!("Command line: could not be built, {:#}", e);
+                    return false;
+                }
+            }
+        };
+        command_line.extend(program_args.iter().cloned());
+        let command_line: Vec<String> = command_line
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        println!("Command line: {}", join_args(&command_line));
+    }
+
+    if program_args
+        .iter()
+        .any(|arg| arg.to_string_lossy().contains("{secret:"))
+    {
+        println!("Note: {{secret:...}} placeholders are filled in from the keyring at launch.");
+    }
+
+    valid
+}
+
+fn launch_or_exit(
+    profile: &Profile,
+    args: &[OsString],
+    profile_name: Option<&str>,
+    should_cleanup: bool,
+    options: &LaunchOptions,
+) -> ! {
+    let mut profile = profile.clone();
+    if options.only.is_some() {
+        profile.only = options.only;
+    }
+    let profile = &profile.resolved();
+
+    if options.dry_run {
+        exit(if show_dry_run(profile, args, profile_name, options) {
+            0
+        } else {
+            1
+        });
+    }
+
+    set_crash_context(profile_name, profile);
+    init_launch_log(profile_name, profile);
+
+    // Validate profile before attempting launch
+    if let Err(e) = validate_profile(profile) {
+        eprintln!("Profile validation failed: {:#}", e);
+        log_event(
+            LogLevel::Error,
+            &format!("Profile validation failed: {:#}", e),
+        );
+        record_launch(profile_name, HistoryEntry::failed(unix_now(), &e));
+
+        if profile_name.is_some() && !non_interactive() {
+            eprintln!("\nWould you like to:");
+            eprintln!("  1. Update the profile path");
+            eprintln!("  2. Delete this profile");
+            eprintln!("  3. Exit");
+
+            if let Ok(choice) = read_line("Enter choice (1-3): ") {
+                match choice.as_str() {
+                    "1" => {
+                        if let Ok(new_path) = read_line("Enter new executable path: ") {
+                            let new_path = new_path.trim_matches('"');
+                            if PathBuf::from(new_path).exists() {
+                                if let Ok(mut profiles) = load_profiles()
+                                    && let Some(name) = profile_name
+                                    && let Some(p) = profiles.get_mut(name)
+                                {
+                                    if let Err(e) = ensure_unlocked(p, name, options.unlock) {
+                                        eprintln!("Error: {}", e);
+                                        pause_before_exit();
+                                        exit(1);
+                                    }
+                                    p.path = PathBuf::from(new_path);
+                                    if save_profiles(&profiles).is_ok() {
+                                        println!("Profile updated! Please run the command again.");
+                                    }
+                                }
+                            } else {
+                                eprintln!("Error: Path does not exist.");
+                            }
+                        }
+                    }
+                    "2" => {
+                        if let (Ok(mut profiles), Some(name)) = (load_profiles(), profile_name)
+                            && let Err(e) = delete_profile(&mut profiles, name, options.unlock)
+                        {
+                            eprintln!("Error deleting profile: {:#}", e);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        pause_before_exit();
+        exit(1);
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // Check if elevation is needed
+        if let Some(ref priority) = profile.priority
+            && priority.requires_elevation()
+            && !options.plain
+            && !is_elevated()
+        {
+            // Create temp profile if needed
+            let name = match profile_name {
+                Some(n) => n.to_string(),
+                None => {
+                    // Create temporary profile for elevation
+                    println!("\nNote: Using temporary profile for elevation.");
+                    println!("Consider saving this profile if you'll use these settings again.\n");
+
+                    let temp_name = format!("{}{}", TEMP_PROFILE_PREFIX, std::process::id());
+
+                    // Removed by the elevated instance once launched, or
+                    // at a later start if it never gets that far
+                    if let Ok(mut profiles) = load_profiles() {
+                        let expires = unix_now() + ELEVATION_PROFILE_LIFETIME.as_secs();
+                        profiles.insert(
+                            temp_name.clone(),
+                            Profile {
+                                expires: Some(expires),
+                                ..profile.clone()
+                            },
+                        );
+                        if let Err(e) = save_profiles(&profiles) {
+                            eprintln!("Error: Failed to save temporary profile: {}", e);
+                            pause_before_exit();
+                            exit(1);
+                        }
+                    } else {
+                        eprintln!("Error: Failed to load profiles for elevation");
+                        pause_before_exit();
+                        exit(1);
+                    }
+
+                    temp_name
+                }
+            };
+
+            match relaunch_elevated(&name, args, options) {
+                Ok(_) => exit(0),
+                Err(e) => {
+                    // Clean up temp profile if elevation failed
+                    if name.starts_with(TEMP_PROFILE_PREFIX)
+                        && let Ok(mut profiles) = load_profiles()
+                    {
+                        profiles.remove(&name);
+                        let _ = save_profiles(&profiles);
+                    }
+
+                    eprintln!("\nError requesting elevation: {:#}", e);
+                    if non_interactive() {
+                        pause_before_exit();
+                        exit(1);
+                    }
+                    eprintln!("\nOptions:");
+                    eprintln!("  1. Run this program as Administrator");
+                    eprintln!("  2. Choose a lower priority (Normal or Above Normal)");
+                    eprintln!("  3. Launch anyway with Normal priority");
+
+                    if let Ok(choice) = read_line("\nEnter choice (1-3): ")
+                        && choice == "3"
+                    {
+                        println!("\nLaunching with Normal priority instead...");
+                        let mut fallback_profile = profile.clone();
+                        fallback_profile.priority = Some(ProcessPriority::Normal);
+
+                        let started_at = unix_now();
+                        match launch_profile(&fallback_profile, args, options) {
+                            Ok((code, outcome)) => {
+                                record_launch(
+                                    profile_name,
+                                    HistoryEntry::launched(started_at, outcome.as_ref()),
+                                );
+                                exit(code)
+                            }
+                            Err(e) => {
+                                record_launch(profile_name, HistoryEntry::failed(started_at, &e));
+                                eprintln!("Error launching program: {:#}", e);
+                                pause_before_exit();
+                                exit(1);
+                            }
+                        }
+                    }
+
+                    pause_before_exit();
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // Launch the profile
+    let started_at = unix_now();
+    let started = Instant::now();
+    match launch_profile(profile, args, options) {
+        Ok((code, outcome)) => {
+            let mut entry = HistoryEntry::launched(started_at, outcome.as_ref());
+            if profile.mode.unwrap_or_default() == LaunchMode::Attach {
+                entry.duration_secs = Some(started.elapsed().as_secs());
+            }
+            record_launch(profile_name, entry);
+            log_event(LogLevel::Info, &format!("Finished with exit code {}", code));
+
+            // Clean up temp profile if requested
+            if should_cleanup
+                && let Some(name) = profile_name
+                && name.starts_with(TEMP_PROFILE_PREFIX)
+                && let Ok(mut profiles) = load_profiles()
+            {
+                profiles.remove(name);
+                let _ = save_profiles(&profiles);
+            }
+
+            // The program's own exit code isn't an error of affinity-rs
+            pause_after_success();
+            exit(code)
+        }
+        Err(e) => {
+            eprintln!("Error launching program: {:#}", e);
+            log_event(LogLevel::Error, &format!("Launch failed: {:#}", e));
+            record_launch(profile_name, HistoryEntry::failed(started_at, &e));
+            pause_before_exit();
+            exit(1);
+        }
+    }
+}
+
+/// One launch recorded in the history log, which holds one JSON entry per line.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct HistoryEntry {
+    profile: String,
+    /// Seconds since the Unix epoch
+    started_at: u64,
+    /// How long the program ran, known only for attached launches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<u64>,
+    /// Whether the CPU affinity was confirmed; unset in safe mode and when
+    /// it wasn't checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    affinity_applied: Option<bool>,
+    /// Attempts needed to configure the process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attempts: Option<usize>,
+    /// Why the launch failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failure: Option<String>,
+}
+
+/// Prints the launch step timings on stderr, so they stay out of `--json` output.
+fn show_timings(timings: &[(String, Duration)]) {
+    eprintln!("\nTimings:");
+    for (step, took) in timings {
+        eprintln!("  {:<32} {:>9.1} ms", step, took.as_secs_f64() * 1000.0);
+    }
+    eprintln!();
+}
+
+/// How applying the profile went at launch.
+#[derive(Debug, Default)]
+struct LaunchOutcome {
+    affinity_applied: bool,
+    attempts: usize,
+    /// How long each launch step took, in order, for `--timings`
+    timings: Vec<(String, Duration)>,
+    /// The slice's DSCP rule, to be removed when the program exits
+    #[cfg(target_os = "linux")]
+    dscp_rule: Option<DscpRule>,
+}
+
+impl HistoryEntry {
+    fn launched(started_at: u64, outcome: Option<&LaunchOutcome>) -> Self {
+        Self {
+            started_at,
+            affinity_applied: outcome
+                .filter(|outcome| outcome.attempts > 0)
+                .map(|outcome| outcome.affinity_applied),
+            attempts: outcome
+                .filter(|outcome| outcome.attempts > 0)
+                .map(|outcome| outcome.attempts),
+            ..Default::default()
+        }
+    }
+
+    fn failed(started_at: u64, error: &anyhow::Error) -> Self {
+        Self {
+            started_at,
+            // Just the outermost message, so the same problem is counted together
+            failure: error.to_string().lines().next().map(str::to_string),
+            ..Default::default()
+        }
+    }
+}
+
+/// Adds a launch of a saved profile to the history log. Unsaved and
+/// temporary profiles aren't recorded.
+fn record_launch(profile_name: Option<&str>, entry: HistoryEntry) {
+    let Some(name) = profile_name.filter(|name| !name.starts_with(TEMP_PROFILE_PREFIX)) else {
+        return;
+    };
+
+    let entry = HistoryEntry {
+        profile: name.to_string(),
+        ..entry
+    };
+    if let Err(e) = record_history(&entry) {
+        eprintln!("Warning: Could not record launch: {:#}", e);
+    }
+}
+
+fn record_history(entry: &HistoryEntry) -> Result<()> {
+    let path = get_config_dir()?.join(HISTORY_FILE_NAME);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context("Failed to open history log")?;
+
+    writeln!(file, "{}", serde_json::to_string(entry)?).context("Failed to write history log")?;
+    Ok(())
+}
+
+fn load_history() -> Result<Vec<HistoryEntry>> {
+    let path = get_config_dir()?.join(HISTORY_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read history log")?;
+    // A line cut short by a crash shouldn't hide the rest
+    Ok(data
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Whether `name` is a temporary profile: one saved with an expiry, or a
+/// `__temp_` entry left behind by older versions.
+fn is_temporary_profile(name: &str, profile: &Profile) -> bool {
+    profile.is_temporary() || name.starts_with(TEMP_PROFILE_PREFIX)
+}
+
+/// Says when a temporary profile expires, e.g. "expires in 3 days".
+fn describe_expiry(expires: u64) -> String {
+    const DAY: u64 = 24 * 60 * 60;
+
+    match expires.saturating_sub(unix_now()) {
+        0 => "expired".to_string(),
+        left if left >= 2 * DAY => format!("expires in {} days", left / DAY),
+        left => format!("expires in {}", format_duration(left)),
+    }
+}
+
+/// Formats a number of seconds as e.g. "3h 12m" or "45s".
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Summarizes the history log across all profiles.
+fn show_stats() -> Result<()> {
+    let history = load_history()?;
+
+    if history.is_empty() {
+        println!("No launches recorded yet.");
+        return Ok(());
+    }
+
+    let mut names: Vec<&str> = history.iter().map(|entry| entry.profile.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+
+    println!("Launches recorded in {}:\n", HISTORY_FILE_NAME);
+    println!(
+        "{:<24} {:>8} {:>8} {:>12} {:>12} {:>10}",
+        "Profile", "Launches", "Failed", "Affinity OK", "Avg attempts", "Runtime"
+    );
+
+    for name in names {
+        let entries: Vec<&HistoryEntry> = history.iter().filter(|e| e.profile == name).collect();
+        let failed = entries.iter().filter(|e| e.failure.is_some()).count();
+
+        let checked: Vec<bool> = entries.iter().filter_map(|e| e.affinity_applied).collect();
+        let affinity_ok = if checked.is_empty() {
+            "-".to_string()
+        } else {
+            let ok = checked.iter().filter(|&&applied| applied).count();
+            format!("{}%", ok * 100 / checked.len())
+        };
+
+        let attempts: Vec<usize> = entries.iter().filter_map(|e| e.attempts).collect();
+        let average_attempts = if attempts.is_empty() {
+            "-".to_string()
+        } else {
+            format!(
+                "{:.1}",
+                attempts.iter().sum::<usize>() as f64 / attempts.len() as f64
+            )
+        };
+
+        let durations: Vec<u64> = entries.iter().filter_map(|e| e.duration_secs).collect();
+        let runtime = if durations.is_empty() {
+            "-".to_string()
+        } else {
+            format_duration(durations.iter().sum())
+        };
+
+        println!(
+            "{:<24} {:>8} {:>8} {:>12} {:>12} {:>10}",
+            name,
+            entries.len(),
+            failed,
+            affinity_ok,
+            average_attempts,
+            runtime
+        );
+    }
+
+    // Launches that went ahead without the affinity taking hold count as well
+    let mut reasons: HashMap<&str, usize> = HashMap::new();
+    for entry in &history {
+        if let Some(ref failure) = entry.failure {
+            *reasons.entry(failure).or_default() += 1;
+        } else if entry.affinity_applied == Some(false) {
+            *reasons
+                .entry("CPU affinity could not be confirmed")
+                .or_default() += 1;
+        }
+    }
+
+    if !reasons.is_empty() {
+        let mut reasons: Vec<(&str, usize)> = reasons.into_iter().collect();
+        reasons.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        println!("\nMost common problems:");
+        for (reason, count) in reasons.into_iter().take(5) {
+            println!("  {:>4}x  {}", count, reason);
+        }
+    }
+
+    Ok(())
+}
+
+fn show_profile_stats(keyword: &str) -> Result<()> {
+    let sessions: Vec<HistoryEntry> = load_history()?
+        .into_iter()
+        .filter(|entry| entry.profile == keyword && entry.duration_secs.is_some())
+        .collect();
+
+    if sessions.is_empty() {
+        println!("No attached sessions recorded for '{}'.", keyword);
+        println!("Runtime is tracked for profiles launched in attached mode.");
+        return Ok(());
+    }
+
+    let now = unix_now();
+    let runtime_since = |days: u64| -> u64 {
+        let cutoff = now.saturating_sub(days * 86400);
+        sessions
+            .iter()
+            .filter(|entry| entry.started_at >= cutoff)
+            .filter_map(|entry| entry.duration_secs)
+            .sum()
+    };
+    let total: u64 = sessions
+        .iter()
+        .filter_map(|entry| entry.duration_secs)
+        .sum();
+    let last = sessions
+        .iter()
+        .max_by_key(|entry| entry.started_at)
+        .unwrap();
+
+    println!("Runtime of '{}':\n", keyword);
+    println!("  Sessions:      {}", sessions.len());
+    println!("  Total:         {}", format_duration(total));
+    println!("  Last 7 days:   {}", format_duration(runtime_since(7)));
+    println!("  Last 30 days:  {}", format_duration(runtime_since(30)));
+    println!(
+        "  Average:       {}",
+        format_duration(total / sessions.len() as u64)
+    );
+    println!(
+        "  Last session:  {} ({} ago)",
+        format_duration(last.duration_secs.unwrap_or(0)),
+        format_duration(now.saturating_sub(last.started_at))
+    );
+    Ok(())
+}
+
+/// A deleted profile kept for `undelete`.
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashedProfile {
+    name: String,
+    /// Seconds since the Unix epoch
+    deleted_at: u64,
+    profile: Profile,
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Loads the trash, leaving out profiles deleted longer ago than the retention period.
+fn load_trash() -> Result<Vec<TrashedProfile>> {
+    let path = get_config_dir()?.join(TRASH_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read trash file")?;
+    let trash: Vec<TrashedProfile> =
+        serde_json::from_str(&data).context("Failed to parse trash JSON")?;
+
+    let cutoff = unix_now().saturating_sub(TRASH_RETENTION.as_secs());
+    Ok(trash
+        .into_iter()
+        .filter(|entry| entry.deleted_at >= cutoff)
+        .collect())
+}
+
+fn save_trash(trash: &[TrashedProfile]) -> Result<()> {
+    let data = serde_json::to_string_pretty(trash).context("Failed to serialize trash")?;
+    std::fs::write(get_config_dir()?.join(TRASH_FILE_NAME), data)
+        .context("Failed to write trash file")?;
+    Ok(())
+}
+
+/// Restores the most recently deleted profile called `keyword`.
+fn undelete_profile(profiles: &mut Profiles, keyword: &str) -> Result<()> {
+    if profiles.contains_key(keyword) {
+        bail!(
+            "A profile named '{}' already exists. Rename or delete it first.",
+            keyword
+        );
+    }
+
+    let mut trash = load_trash()?;
+    let index = trash
+        .iter()
+        .rposition(|entry| entry.name == keyword)
+        .context(format!(
+            "No deleted profile named '{}' in the trash",
+            keyword
+        ))?;
+    let entry = trash.remove(index);
+
+    profiles.insert(entry.name, entry.profile);
+    save_profiles(profiles)?;
+    save_trash(&trash)?;
+
+    println!("Profile '{}' restored.", keyword);
+    println!(
+        "Shortcuts are not restored; run 'affinity-rs shortcut {}' to recreate them.",
+        keyword
+    );
+    Ok(())
+}
+
+fn list_trash() -> Result<()> {
+    let trash = load_trash()?;
+
+    if trash.is_empty() {
+        println!("The trash is empty.");
+        return Ok(());
+    }
+
+    println!("Deleted profiles (restore with 'affinity-rs undelete <name>'):\n");
+    let now = unix_now();
+    for entry in &trash {
+        let days_left = (entry.deleted_at + TRASH_RETENTION.as_secs()).saturating_sub(now) / 86400;
+        println!(
+            "  {:<20} {} ({} days left)",
+            entry.name,
+            entry.profile.path.display(),
+            days_left
+        );
+    }
+    Ok(())
+}
+
+fn delete_profile(profiles: &mut Profiles, keyword: &str, unlock: bool) -> Result<()> {
+    if let Some(profile) = profiles.get(keyword) {
+        ensure_unlocked(profile, keyword, unlock)?;
+    }
+
+    // Only the user's own copy of a shared profile can go
+    if let Some(shared) = load_shared_profiles(false).remove(keyword) {
+        let system = system_profile_path().as_ref() == Some(&shared.source);
+        if profiles
+            .get(keyword)
+            .is_none_or(|profile| same_profile(profile, &shared.profile))
+        {
+            if system {
+                bail!(
+                    "Profile '{}' is system-wide and can only be removed by an administrator from {}",
+                    keyword,
+                    shared.source.display()
+                );
+            }
+            bail!(
+                "Profile '{}' comes from {} and can only be removed there",
+                keyword,
+                shared.source.display()
+            );
+        }
+
+        println!(
+            "Your changes to the {} profile '{}' were discarded.",
+            if system { "system-wide" } else { "shared" },
+            keyword
+        );
+        profiles.insert(keyword.to_string(), shared.profile);
+        save_profiles(profiles).context("Failed to save profiles")?;
+        return Ok(());
+    }
+
+    let mut dependents: Vec<&str> = profiles
+        .iter()
+        .filter(|(_, profile)| profile.extends.as_deref() == Some(keyword))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !dependents.is_empty() {
+        dependents.sort_unstable();
+        bail!(
+            "Profile '{}' is extended by {}. Change their 'extends' first.",
+            keyword,
+            dependents.join(", ")
+        );
+    }
+
+    if let Some(profile) = profiles.remove(keyword) {
+        // Into the trash first, so a failure can't lose the profile
+        let mut trash = load_trash()?;
+        trash.push(TrashedProfile {
+            name: keyword.to_string(),
+            deleted_at: unix_now(),
+            profile: profile.clone(),
+        });
+        save_trash(&trash)?;
+
+        save_profiles(profiles).context("Failed to save profiles after deletion")?;
+        println!("Profile '{}' deleted successfully.", keyword);
+        println!(
+            "It can be restored for 30 days with 'affinity-rs undelete {}'.",
+            keyword
+        );
+
+        // Try to delete associated shortcuts
+        for shortcut_path in shortcut_paths(keyword, Some(&profile)) {
+            if shortcut_path.exists() {
+                match std::fs::remove_file(&shortcut_path) {
+                    Ok(_) => println!("Associated shortcut deleted: {}", shortcut_path.display()),
+                    Err(e) => eprintln!("Warning: Could not delete shortcut: {}", e),
+                }
+            }
+        }
+
+        // Settings tied to the executable stay while another profile, such
+        // as the one a merge kept, still uses them
+        #[cfg(target_os = "windows")]
+        let still_used = |uses: fn(&Profile) -> bool, same: fn(&Path, &Path) -> bool| {
+            profiles
+                .values()
+                .any(|other| uses(other) && same(&other.path, &profile.path))
+        };
+        #[cfg(target_os = "windows")]
+        let same_exe = |a: &Path, b: &Path| {
+            a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+        };
+
+        #[cfg(target_os = "windows")]
+        if profile.dscp.is_some()
+            && !still_used(
+                |other| other.dscp.is_some(),
+                |a, b| qos_policy_name(a) == qos_policy_name(b),
+            )
+            && remove_qos_policy(&profile.path).is_ok()
+        {
+            println!("QoS policy removed.");
+        }
+
+        #[cfg(target_os = "windows")]
+        if !profile.compat_flags.is_empty()
+            && !still_used(|other| !other.compat_flags.is_empty(), same_exe)
+            && remove_compat_flags(&profile.path, &profile.compat_flags).is_ok()
+        {
+            println!("Compatibility settings removed.");
+        }
+
+        #[cfg(target_os = "windows")]
+        if profile.game_mode == Some(true)
+            && !still_used(|other| other.game_mode == Some(true), same_exe)
+            && unregister_game(&profile.path).is_ok()
+        {
+            println!("Windows Game Mode registration removed.");
+        }
+
+        #[cfg(target_os = "windows")]
+        if profile.elevated_task {
+            match delete_elevated_task(keyword) {
+                Ok(_) => println!("Associated scheduled task deleted."),
+                Err(e) => eprintln!(
+                    "Warning: {:#}\nRemove '{}' manually in Task Scheduler.",
+                    e,
+                    task_name(keyword)
+                ),
+            }
+        }
+    } else {
+        println!("Profile '{}' not found.", keyword);
+    }
+    Ok(())
+}
+
+/// Removes profiles whose executable is gone, leftover temporary profiles and
+/// shortcuts of profiles that no longer exist. Lists everything first and asks
+/// unless `force`; with `dry_run` only lists.
+fn clean_profiles(profiles: &mut Profiles, dry_run: bool, force: bool, unlock: bool) -> Result<()> {
+    let shared = load_shared_profiles(false);
+
+    let mut missing: Vec<String> = Vec::new();
+    let mut skipped: Vec<(String, &str)> = Vec::new();
+    let mut names: Vec<&String> = profiles
+        .iter()
+        .filter(|(name, profile)| !is_temporary_profile(name, profile))
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    for name in names {
+        let Ok(profile) = inherited_profile(profiles, name) else {
+            continue;
+        };
+        if profile.path.exists() {
+            continue;
+        }
+
+        if shared.contains_key(name) {
+            skipped.push((name.clone(), "shared, remove it from its own file"));
+        } else if profiles[name].locked && !unlock {
+            skipped.push((name.clone(), "locked, pass --unlock"));
+        } else {
+            missing.push(name.clone());
+        }
+    }
+
+    let mut temporary: Vec<String> = profiles
+        .iter()
+        .filter(|(name, profile)| is_temporary_profile(name, profile))
+        .map(|(name, _)| name.clone())
+        .collect();
+    temporary.sort();
+
+    let orphans = orphaned_shortcuts(profiles);
+
+    if missing.is_empty() && temporary.is_empty() && orphans.is_empty() {
+        println!("Nothing to clean up.");
+        for (name, reason) in &skipped {
+            println!(
+                "Kept '{}' despite its missing executable ({}).",
+                name, reason
+            );
+        }
+        return Ok(());
+    }
+
+    if !missing.is_empty() {
+        println!("Profiles whose executable no longer exists:");
+        for name in &missing {
+            println!("  {} ({})", name, profiles[name].path.display());
+        }
+    }
+    if !temporary.is_empty() {
+        println!("Temporary profiles:");
+        for name in &temporary {
+            match profiles[name].expires {
+                Some(expires) => println!("  {} ({})", name, describe_expiry(expires)),
+                None => println!("  {}", name),
+            }
+        }
+    }
+    if !orphans.is_empty() {
+        println!("Shortcuts for profiles that no longer exist:");
+        for path in &orphans {
+            println!("  {}", path.display());
+        }
+    }
+    for (name, reason) in &skipped {
+        println!(
+            "Keeping '{}' despite its missing executable ({}).",
+            name, reason
+        );
+    }
+
+    if dry_run {
+        println!("\nDry run, nothing was removed.");
+        return Ok(());
+    }
+
+    if !force && !confirm("\nRemove all of the above? [y/N]: ")? {
+        println!("Nothing removed.");
+        return Ok(());
+    }
+    println!();
+
+    let mut failed = 0;
+    // Deleted profiles go to the trash and can be restored with `undelete`
+    for name in &missing {
+        if let Err(e) = delete_profile(profiles, name, unlock) {
+            eprintln!("Error deleting profile '{}': {:#}", name, e);
+            failed += 1;
+        }
+    }
+
+    if !temporary.is_empty() {
+        for name in &temporary {
+            profiles.remove(name);
+        }
+        save_profiles(profiles).context("Failed to save profiles")?;
+        println!("Removed {} temporary profile(s).", temporary.len());
+    }
+
+    for path in &orphans {
+        match std::fs::remove_file(path) {
+            Ok(_) => println!("Shortcut deleted: {}", path.display()),
+            Err(e) => {
+                eprintln!("Error deleting {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} item(s) could not be removed", failed);
+    }
+    Ok(())
+}
+
+/// Renames a profile along with everything that refers to it by name:
+/// `extends` of other profiles, rules.json, shortcuts and the elevated task.
+fn rename_profile(profiles: &mut Profiles, old: &str, new: &str, unlock: bool) -> Result<()> {
+    let profile = profiles
+        .get(old)
+        .cloned()
+        .context(format!("Profile '{}' not found", old))?;
+    ensure_unlocked(&profile, old, unlock)?;
+
+    if new.is_empty() || new.starts_with(TEMP_PROFILE_PREFIX) || new.starts_with('-') {
+        bail!("'{}' can't be used as a profile name", new);
+    }
+    if profiles.contains_key(new) {
+        bail!("Profile '{}' already exists", new);
+    }
+    if let Some(shared) = load_shared_profiles(false).remove(old) {
+        bail!(
+            "Profile '{}' comes from {} and can only be renamed there",
+            old,
+            shared.source.display()
+        );
+    }
+
+    profiles.remove(old);
+    profiles.insert(new.to_string(), profile.clone());
+    let mut dependents = Vec::new();
+    for (name, other) in profiles.iter_mut() {
+        if other.extends.as_deref() == Some(old) {
+            other.extends = Some(new.to_string());
+            dependents.push(name.clone());
+        }
+    }
+    save_profiles(profiles).context("Failed to save profiles")?;
+    println!("Profile '{}' renamed to '{}'.", old, new);
+
+    if !dependents.is_empty() {
+        dependents.sort();
+        println!("Updated 'extends' in {}.", dependents.join(", "));
+    }
+
+    let mut rules = load_rules()?;
+    let renamed_rules = rules.iter().filter(|rule| rule.profile == old).count();
+    if renamed_rules > 0 {
+        for rule in rules.iter_mut().filter(|rule| rule.profile == old) {
+            rule.profile = new.to_string();
+        }
+        save_rules(&rules)?;
+        println!("Updated {} rule(s) in {}.", renamed_rules, RULES_FILE_NAME);
+    }
+
+    // Shortcuts run the profile by name, so they are written again under the new one
+    for dir in shortcut_dirs(Some(&profile)) {
+        if !shortcut_files_in(&dir, old)
+            .iter()
+            .any(|path| path.exists())
+        {
+            continue;
+        }
+        if let Err(e) = write_shortcut(&profile, new, &dir) {
+            eprintln!("Warning: Could not recreate shortcut: {:#}", e);
+        }
+    }
+    for path in shortcut_paths(old, Some(&profile)) {
+        if path.exists()
+            && let Err(e) = std::fs::remove_file(&path)
+        {
+            eprintln!(
+                "Warning: Could not remove old shortcut {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    if profile.elevated_task {
+        match register_elevated_task(new).and_then(|_| delete_elevated_task(old)) {
+            Ok(_) => println!("Scheduled task renamed."),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints everything about one profile, with what it inherits and its
+/// preset filled in.
+fn show_profile(profiles: &Profiles, keyword: &str) -> Result<()> {
+    let Some(stored) = profiles.get(keyword) else {
+        bail!("Profile '{}' not found", keyword);
+    };
+    let profile = inherited_profile(profiles, keyword)?.resolved();
+
+    println!("Profile: {}", keyword);
+    if let Some(shared) = load_shared_profiles(false).get(keyword) {
+        println!(
+            "  Source: {} ({})",
+            shared.origin(),
+            shared.source.display()
+        );
+    }
+    if let Some(ref base) = profile.extends {
+        println!("  Extends: {}", base);
+    }
+    if !profile.is_enabled() {
+        println!("  Enabled: no");
+    }
+    if stored.locked {
+        println!("  Locked: yes");
+    }
+    if let Some(expires) = stored.expires {
+        println!("  Temporary: {}", describe_expiry(expires));
+    }
+
+    let resolved = std::path::absolute(&profile.path).unwrap_or_else(|_| profile.path.clone());
+    println!("  Path: {}", resolved.display());
+    if resolved != profile.path {
+        println!("  Stored path: {}", profile.path.display());
+    }
+    if !profile.path.exists() {
+        println!("  WARNING: Executable not found!");
+    } else if !profile.path.is_file() {
+        println!("  WARNING: Path is not a file!");
+    }
+    if stored.checksum.is_some() {
+        println!("  Checksum: recorded");
+    }
+
+    if profile.cpus.is_empty() {
+        println!("  CPUs: unchanged");
+    } else {
+        println!(
+            "  CPUs: {} ({} total, mask {})",
+            format_cpu_list(&profile.cpus),
+            profile.cpus.len(),
+            cpu_mask_string(&profile.cpus)
+        );
+        let topology = topology::current();
+        let unavailable: Vec<usize> = profile
+            .cpus
+            .iter()
+            .copied()
+            .filter(|&cpu| !topology.is_online(cpu))
+            .collect();
+        if !unavailable.is_empty() {
+            println!(
+                "  WARNING: CPUs {} are offline or missing on this system",
+                format_cpu_list(&unavailable)
+            );
+        }
+    }
+    if let Some(only) = profile.only {
+        println!(
+            "  Only: {}",
+            match only {
+                ApplyOnly::Affinity => "affinity",
+                ApplyOnly::Priority => "priority",
+            }
+        );
+    }
+
+    match profile.priority {
+        Some(ref priority) => println!("  Priority: {}", priority.display_name()),
+        None => println!("  Priority: unchanged"),
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(nice) = profile.nice_value() {
+        println!("  Nice: {}", nice);
+    }
+    if let Some(ref gpu_priority) = profile.gpu_priority {
+        println!("  GPU priority: {}", gpu_priority.display_name());
+    }
+
+    let attempts = profile.retry_attempts.unwrap_or(5);
+    println!(
+        "  Retry attempts: {}{}",
+        attempts,
+        if profile.retry_attempts.is_none() {
+            " (default)"
+        } else {
+            ""
+        }
+    );
+    #[cfg(target_os = "windows")]
+    println!("  Retry delay: 100 ms, doubling up to 1000 ms");
+    #[cfg(target_os = "linux")]
+    println!("  Retry delay: 100 ms");
+
+    println!(
+        "  Mode: {}",
+        profile.mode.unwrap_or_default().display_name()
+    );
+    if let Some(preset) = profile.preset {
+        println!("  Preset: {}", preset.display_name());
+    }
+
+    let shortcuts: Vec<PathBuf> = shortcut_paths(keyword, Some(stored))
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect();
+    if shortcuts.is_empty() {
+        println!("  Shortcuts: none");
+    } else {
+        println!("  Shortcuts:");
+        for path in &shortcuts {
+            println!("    {}", path.display());
+        }
+    }
+    #[cfg(target_os = "windows")]
+    if stored.elevated_task {
+        println!("  Elevated task: {}", task_name(keyword));
+    }
+    if !profile.last_args.is_empty() {
+        println!("  Previous arguments: {}", join_args(&profile.last_args));
+    }
+
+    // Everything else that is set, as it would be saved
+    const SHOWN: [&str; 17] = [
+        "path",
+        "cpus",
+        "priority",
+        "nice",
+        "gpu_priority",
+        "retry_attempts",
+        "mode",
+        "preset",
+        "only",
+        "extends",
+        "enabled",
+        "locked",
+        "checksum",
+        "shortcut_dirs",
+        "elevated_task",
+        "last_args",
+        "env",
+    ];
+    let value = serde_json::to_value(&profile)?;
+    let mut others: Vec<(&String, &serde_json::Value)> = value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| !SHOWN.contains(&key.as_str()))
+        .collect();
+    others.sort_by_key(|(key, _)| key.as_str());
+    if !profile.env.is_empty() {
+        let mut names: Vec<&str> = profile.env.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        println!("  Environment: {}", names.join(", "));
+    }
+    if !others.is_empty() {
+        println!("  Other settings:");
+        for (key, value) in others {
+            println!("    {}: {}", key, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every saved profile the way a launch would and reports what is
+/// broken, plus shortcuts left behind by deleted profiles. Returns whether
+/// everything is fine.
+fn verify_profiles(profiles: &Profiles) -> bool {
+    let mut names: Vec<&String> = profiles
+        .keys()
+        .filter(|name| !name.starts_with(TEMP_PROFILE_PREFIX))
+        .collect();
+    names.sort();
+
+    let topology = topology::current();
+    let cpu_count = topology.cpus.len() + topology.offline.len();
+    let mut broken = 0;
+
+    println!("Verifying {} profile(s)...\n", names.len());
+    for name in &names {
+        let mut problems = Vec::new();
+        let mut notes = Vec::new();
+
+        match inherited_profile(profiles, name) {
+            Ok(profile) => {
+                let profile = profile.resolved();
+                if let Err(e) = check_profile_settings(&profile) {
+                    // Only the first line; the rest is advice for the launch prompt
+                    problems.push(
+                        format!("{:#}", e)
+                            .lines()
+                            .next()
+                            .unwrap_or_default()
+                            .to_string(),
+                    );
+                }
+
+                let (offline, missing) = unavailable_cpus(&profile);
+                if !missing.is_empty() {
+                    problems.push(format!(
+                        "CPUs {:?} are beyond this system's {} logical CPUs",
+                        missing, cpu_count
+                    ));
+                }
+                if !offline.is_empty() {
+                    notes.push(format!("CPUs {:?} are offline right now", offline));
+                }
+            }
+            Err(e) => problems.push(format!("{:#}", e)),
+        }
+
+        if problems.is_empty() {
+            println!("  OK      {}", name);
+        } else {
+            broken += 1;
+            println!("  BROKEN  {}", name);
+        }
+        for problem in &problems {
+            println!("          - {}", problem);
+        }
+        for note in &notes {
+            println!("          Note: {}", note);
+        }
+    }
+
+    let orphans = orphaned_shortcuts(profiles);
+    if !orphans.is_empty() {
+        println!("\nShortcuts for profiles that no longer exist:");
+        for path in &orphans {
+            println!("  {}", path.display());
+        }
+    }
+
+    println!(
+        "\n{} profile(s) checked, {} broken, {} dangling shortcut(s).",
+        names.len(),
+        broken,
+        orphans.len()
+    );
+
+    broken == 0 && orphans.is_empty()
+}
+
+/// The order `list` shows profiles in.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum ListSort {
+    #[default]
+    Name,
+    Path,
+    /// Highest priority first
+    Priority,
+}
+
+impl ListSort {
+    fn parse(input: &str) -> Option<Self> {
+        match input.trim().to_lowercase().as_str() {
+            "name" => Some(Self::Name),
+            "path" => Some(Self::Path),
+            "priority" => Some(Self::Priority),
+            _ => None,
+        }
+    }
+}
+
+/// How `list` orders and narrows down the profiles.
+#[derive(Debug, Default)]
+struct ListOptions {
+    sort: ListSort,
+    /// Only profiles whose name or path contains this, ignoring case
+    filter: Option<String>,
+    /// Only profiles whose executable no longer exists
+    missing_only: bool,
+    /// Temporary profiles too
+    all: bool,
+}
+
+impl ListOptions {
+    /// Names of the profiles to list, in order, leaving out temporary ones
+    /// unless `all` is set.
+    fn select<'a>(&self, profiles: &'a Profiles) -> Vec<&'a String> {
+        let filter = self.filter.as_ref().map(|filter| filter.to_lowercase());
+        let mut selected: Vec<(&String, Profile)> = profiles
+            .iter()
+            .filter(|(name, profile)| self.all || !is_temporary_profile(name, profile))
+            .map(|(name, profile)| {
+                let profile = inherited_profile(profiles, name).unwrap_or_else(|_| profile.clone());
+                (name, profile)
+            })
+            .filter(|(name, profile)| {
+                filter.as_ref().is_none_or(|filter| {
+                    name.to_lowercase().contains(filter)
+                        || profile
+                            .path
+                            .to_string_lossy()
+                            .to_lowercase()
+                            .contains(filter)
+                })
+            })
+            .filter(|(_, profile)| !self.missing_only || !profile.path.exists())
+            .collect();
+
+        // Profiles without a priority run at Normal
+        let rank = |profile: &Profile| {
+            let priority = profile
+                .priority
+                .as_ref()
+                .unwrap_or(&ProcessPriority::Normal);
+            ProcessPriority::ALL
+                .iter()
+                .position(|p| p == priority)
+                .unwrap_or_default()
+        };
+        selected.sort_by(|(a_name, a), (b_name, b)| {
+            let order = match self.sort {
+                ListSort::Name => std::cmp::Ordering::Equal,
+                ListSort::Path => a.path.cmp(&b.path),
+                ListSort::Priority => rank(b).cmp(&rank(a)),
+            };
+            order.then_with(|| a_name.to_lowercase().cmp(&b_name.to_lowercase()))
+        });
+
+        selected.into_iter().map(|(name, _)| name).collect()
+    }
+}
+
+/// Prints every profile as one JSON object keyed by name, with what it
+/// inherits filled in and whether its executable exists, for other tools.
+fn list_profiles_json(profiles: &Profiles, options: &ListOptions) -> Result<()> {
+    let list = profiles_json(profiles, &load_shared_profiles(false), options)?;
+    println!("{}", serde_json::to_string_pretty(&list)?);
+    Ok(())
+}
+
+/// The array printed by `list --json`, in the order `list` shows.
+fn profiles_json(
+    profiles: &Profiles,
+    shared: &HashMap<String, SharedProfile>,
+    options: &ListOptions,
+) -> Result<serde_json::Value> {
+    let mut list = Vec::new();
+
+    for name in options.select(profiles) {
+        let stored = &profiles[name];
+
+        let (profile, error) = match inherited_profile(profiles, name) {
+            Ok(profile) => (profile.resolved(), None),
+            Err(e) => (stored.clone(), Some(format!("{:#}", e))),
+        };
+        let mut value = serde_json::to_value(&profile)?;
+        if let Some(fields) = value.as_object_mut() {
+            fields.insert("name".into(), name.clone().into());
+            fields.insert("path_exists".into(), profile.path.is_file().into());
+            fields.insert("enabled".into(), profile.is_enabled().into());
+            if let Some(shared) = shared.get(name) {
+                fields.insert("source".into(), shared.source.display().to_string().into());
+            }
+            if let Some(error) = error {
+                fields.insert("error".into(), error.into());
+            }
+        }
+        list.push(value);
+    }
+
+    Ok(serde_json::Value::Array(list))
+}
+
+fn list_profiles(profiles: &Profiles, options: &ListOptions) {
+    if profiles.is_empty() {
+        println!("No saved profiles.");
+        return;
+    }
+
+    let names = options.select(profiles);
+    if names.is_empty() {
+        println!("No profiles match.");
+        return;
+    }
+
+    println!("Saved profiles:\n");
+
+    let shared = load_shared_profiles(false);
+
+    for name in names {
+        let profile = &profiles[name];
+        let shared = shared.get(name);
+        let overrides = shared.map(|shared| profile_overrides(profile, &shared.profile));
+        let origin = match (shared, &overrides) {
+            (Some(shared), Some(overrides)) if overrides.is_empty() => {
+                format!(" [{}]", shared.origin())
+            }
+            (Some(shared), Some(_)) => format!(" [{}, changed by you]", shared.origin()),
+            _ => String::new(),
+        };
+        let mut state = if profile.is_enabled() {
+            String::new()
+        } else {
+            " [disabled]".to_string()
+        };
+        if let Some(expires) = profile.expires {
+            state.push_str(&format!(" [temporary, {}]", describe_expiry(expires)));
+        } else if name.starts_with(TEMP_PROFILE_PREFIX) {
+            state.push_str(" [temporary]");
+        }
+
+        let profile = &inherited_profile(profiles, name)
+            .unwrap_or_else(|_| profile.clone())
+            .resolved();
+
+        println!("Profile: {}{}{}", name, origin, state);
+        if let Some(overrides) = overrides.filter(|overrides| !overrides.is_empty()) {
+            let mut fields: Vec<&String> = overrides.keys().collect();
+            fields.sort_unstable();
+            let fields: Vec<&str> = fields.into_iter().map(String::as_str).collect();
+            println!(
+                "  Your values: {} (the rest is {})",
+                fields.join(", "),
+                shared.map(SharedProfile::origin).unwrap_or_default()
+            );
+        }
+        if let Some(ref base) = profile.extends {
+            println!("  Extends: {}", base);
+        }
+        println!("  Path: {}", profile.path.display());
+        if profile.only == Some(ApplyOnly::Priority) {
+            println!("  CPUs: unchanged (priority only)");
+        } else {
+            println!("  CPUs: {:?}", profile.cpus);
+        }
+
+        if let Some(preset) = profile.preset {
+            println!("  Preset: {}", preset.display_name());
+        }
+
+        let priority_str = profile
+            .priority
+            .as_ref()
+            .map(|p| p.display_name())
+            .unwrap_or(if profile.only == Some(ApplyOnly::Affinity) {
+                "unchanged (affinity only)"
+            } else {
+                "Normal"
+            });
+
+        #[cfg(target_os = "windows")]
+        let admin_note = if profile
+            .priority
+            .as_ref()
+            .map(|p| p.requires_elevation())
+            .unwrap_or(false)
+        {
+            " [requires admin]"
+        } else {
+            ""
+        };
+
+        #[cfg(not(target_os = "windows"))]
+        let admin_note = "";
+
+        println!("  Priority: {}{}", priority_str, admin_note);
+
+        if let Some(nice) = profile.nice {
+            println!("  Nice: {}", nice);
+        }
+
+        if let Some(ref gpu_priority) = profile.gpu_priority {
+            println!("  GPU priority: {}", gpu_priority.display_name());
+        }
+
+        if let Some(attempts) = profile.retry_attempts {
+            println!("  Retry attempts: {}", attempts);
+        }
+
+        if let Some(mode) = profile.mode {
+            println!("  Mode: {}", mode.display_name());
+        }
+
+        // Validate path exists
+        if !profile.path.exists() {
+            println!("  WARNING: Executable not found!");
+        }
+
+        println!();
+    }
+
+    for names in duplicate_profiles(profiles) {
+        println!(
+            "Note: profiles {} launch the same executable. Combine them with 'affinity-rs merge {} {}'.",
+            names.join(", "),
+            names[0],
+            names[1]
+        );
+    }
+}
+
+/// Compares the settings of every running process of the selected profiles
+/// with what the profile asks for, and with `fix` applies the profile again
+/// where they differ. Returns whether no drift is left.
+fn check_running_profiles(profiles: &Profiles, options: &ListOptions, fix: bool) -> Result<bool> {
+    let processes = list_processes()?;
+    // Rules naming a process cover programs started through launchers
+    let rules = load_rules().unwrap_or_default();
+
+    let mut checked = 0;
+    let mut drifted = 0;
+    let mut failed = 0;
+
+    for name in options.select(profiles) {
+        let Ok(profile) = inherited_profile(profiles, name).map(|p| p.resolved()) else {
+            continue;
+        };
+        let canonical = profile.path.canonicalize().ok();
+        let running: Vec<&ProcessInfo> = processes
+            .iter()
+            .filter(|process| {
+                process.exe.is_some()
+                    && (process.exe.as_deref() == Some(profile.path.as_path())
+                        || process.exe == canonical)
+                    || rules.iter().any(|rule| {
+                        rule.profile == *name
+                            && rule.service.is_none()
+                            && rule.device.is_none()
+                            && rule.process.as_ref().is_some_and(|process_name| {
+                                process.name.eq_ignore_ascii_case(process_name)
+                            })
+                    })
+            })
+            .collect();
+
+        let mut wanted_cpus = profile.cpus.clone();
+        wanted_cpus.sort_unstable();
+        wanted_cpus.dedup();
+        // A raw nice value stands for the priority level closest to it
+        #[cfg(target_os = "linux")]
+        let priority = profile
+            .nice
+            .map(ProcessPriority::from_nice_value)
+            .or_else(|| profile.priority.clone());
+        #[cfg(target_os = "windows")]
+        let priority = profile.priority.clone();
+
+        for process in running {
+            // Processes may exit while they are checked
+            let Ok(drift) = process_drift(process.pid, &wanted_cpus, priority.as_ref()) else {
+                continue;
+            };
+            checked += 1;
+            let (live_cpus, cpus_drifted, priority_drifted) =
+                (&drift.cpus, drift.cpus_drifted, drift.priority_drifted);
+
+            #[cfg(target_os = "windows")]
+            let (wanted_priority, live_priority) = (
+                priority.as_ref().map(|p| p.display_name().to_string()),
+                drift
+                    .priority
+                    .as_ref()
+                    .map_or("unknown".to_string(), |p| p.display_name().to_string()),
+            );
+            #[cfg(target_os = "linux")]
+            let (wanted_priority, live_priority) = (
+                profile.nice_value().map(|nice| format!("nice {}", nice)),
+                get_process_nice(process.pid)
+                    .map(|nice| format!("nice {}", nice))
+                    .unwrap_or_else(|_| "unknown".to_string()),
+            );
+
+            let marker = |drifted: bool| if drifted { "  DRIFTED" } else { "" };
+            println!("Profile: {} (PID {}, {})", name, process.pid, process.name);
+            if wanted_cpus.is_empty() {
+                println!("  CPUs: {:?} (not set by the profile)", live_cpus);
+            } else {
+                println!(
+                    "  CPUs: {:?}, profile {:?}{}",
+                    live_cpus,
+                    wanted_cpus,
+                    marker(cpus_drifted)
+                );
+            }
+            match wanted_priority {
+                Some(ref wanted) => println!(
+                    "  Priority: {}, profile {}{}",
+                    live_priority,
+                    wanted,
+                    marker(priority_drifted)
+                ),
+                None => println!("  Priority: {} (not set by the profile)", live_priority),
+            }
+
+            if !drift.any() {
+                println!();
+                continue;
+            }
+            drifted += 1;
+
+            if fix {
+                match apply_profile_settings(&profile, process.pid) {
+                    Ok(_) => println!("  Fixed"),
+                    Err(e) => {
+                        eprintln!("  Could not fix: {:#}", e);
+                        failed += 1;
+                    }
+                }
+            }
+            println!();
+        }
+    }
+
+    if checked == 0 {
+        println!("No running processes of the selected profiles.");
+        return Ok(true);
+    }
+
+    println!(
+        "{} running process(es) checked, {} drifted{}.",
+        checked,
+        drifted,
+        if fix && drifted > 0 {
+            format!(", {} fixed", drifted - failed)
+        } else {
+            String::new()
+        }
+    );
+    if drifted > 0 && !fix {
+        println!("Run 'affinity-rs list --check --fix' to apply the profiles again.");
+    }
+
+    Ok(if fix { failed == 0 } else { drifted == 0 })
+}
+
+/// Groups of profiles (sorted by name) that point at the same executable.
+fn duplicate_profiles(profiles: &Profiles) -> Vec<Vec<String>> {
+    let mut by_path: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+    for (name, profile) in profiles {
+        if name.starts_with(TEMP_PROFILE_PREFIX) || profile.path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let path = profile
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| profile.path.clone());
+        // Windows paths are case-insensitive
+        #[cfg(target_os = "windows")]
+        let path = PathBuf::from(path.to_string_lossy().to_lowercase());
+
+        by_path.entry(path).or_default().push(name.clone());
+    }
+
+    let mut groups: Vec<Vec<String>> = by_path
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|mut names| {
+            names.sort();
+            names
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Combines `other` into `keep`, asking which value to use for every option
+/// the two set differently, then deletes `other`.
+fn merge_profiles(profiles: &mut Profiles, keep: &str, other: &str, unlock: bool) -> Result<()> {
+    if keep == other {
+        bail!("Choose two different profiles");
+    }
+
+    let fields = |name: &str| -> Result<serde_json::Map<String, serde_json::Value>> {
+        let profile = profiles
+            .get(name)
+            .context(format!("Profile '{}' not found", name))?;
+        ensure_unlocked(profile, name, unlock)?;
+
+        match serde_json::to_value(profile)? {
+            serde_json::Value::Object(fields) => Ok(fields),
+            _ => bail!("Failed to read profile '{}'", name),
+        }
+    };
+    let mut merged = fields(keep)?;
+    let theirs = fields(other)?;
+
+    let mut keys: Vec<String> = merged.keys().chain(theirs.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+
+    let show = |value: Option<&serde_json::Value>| match value {
+        None | Some(serde_json::Value::Null) => "(not set)".to_string(),
+        Some(value) => value.to_string(),
+    };
+
+    println!("Merging '{}' into '{}'.", other, keep);
+
+    for key in keys {
+        let ours = merged.get(&key).filter(|value| !value.is_null());
+        let other_value = theirs.get(&key).filter(|value| !value.is_null());
+        if ours == other_value {
+            continue;
+        }
+
+        println!("\n{}:", key);
+        println!("  1. {} ({})", show(ours), keep);
+        println!("  2. {} ({})", show(other_value), other);
+
+        let answer = read_line_or("Keep which value? (1-2, Enter for 1): ", "1")?;
+        if answer.trim() == "2" {
+            match other_value {
+                Some(value) => merged.insert(key, value.clone()),
+                None => merged.remove(&key),
+            };
+        }
+    }
+
+    let profile: Profile = serde_json::from_value(serde_json::Value::Object(merged))
+        .context("Failed to combine the profiles")?;
+    validate_profile(&profile)?;
+
+    if !confirm(&format!(
+        "\nSave the result as '{}' and delete '{}'? [y/N]: ",
+        keep, other
+    ))? {
+        println!("No changes made.");
+        return Ok(());
+    }
+
+    profiles.insert(keep.to_string(), profile);
+    delete_profile(profiles, other, unlock)?;
+
+    println!("Profile '{}' updated.", keep);
+    Ok(())
+}
+
+/// Options that only make sense on the machine that set them, dropped on import.
+const NOT_SHARED: [&str; 5] = [
+    "checksum",
+    "last_args",
+    "shortcut_dirs",
+    "elevated_task",
+    "locked",
+];
+
+/// A set of profiles shared by a community, as fetched by `preset fetch`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetBundle {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    /// CPU model the profiles were tuned for
+    #[serde(default)]
+    cpu: Option<String>,
+    profiles: HashMap<String, serde_json::Value>,
+}
+
+/// Reads a bundle from an https URL (with curl), a local file, or by name
+/// from the `preset_source` in settings.json.
+fn fetch_preset_text(source: &str) -> Result<String> {
+    let url = if source.starts_with("https://") {
+        source.to_string()
+    } else if source.starts_with("http://") {
+        bail!("Bundles are only downloaded over https://, so they can't be altered on the way");
+    } else if Path::new(source).is_file() {
+        return std::fs::read_to_string(source)
+            .with_context(|| format!("Failed to read {}", source));
+    } else if !source.is_empty()
+        && source
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        let Some(pattern) = settings().preset_source.clone() else {
+            bail!(
+                "'{}' is not a file, and no preset_source is set in settings.json to look it up",
+                source
+            );
+        };
+        if !pattern.starts_with("https://") {
+            bail!("preset_source in settings.json must be an https:// URL");
+        }
+        pattern.replace("{name}", source)
+    } else {
+        bail!("'{}' is neither a URL, a file nor a preset name", source);
+    };
+
+    println!("Downloading {}...", url);
+    let output = Command::new("curl")
+        // Redirects must stay on https as well
+        .args(["-fsSL", "--proto", "=https", "--proto-redir", "=https"])
+        .args(["--max-time", "30", &url])
+        .output()
+        .context("Failed to run curl")?;
+
+    if !output.status.success() {
+        bail!(
+            "Could not download {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("The download is not text")
+}
+
+/// Checks one profile of a bundle against the profile format, rejecting
+/// fields affinity-rs doesn't know rather than silently dropping them.
+fn parse_shared_profile(name: &str, value: serde_json::Value) -> Result<Profile> {
+    if name.is_empty() || name.starts_with(TEMP_PROFILE_PREFIX) {
+        bail!("Invalid profile name '{}'", name);
+    }
+
+    let mut profile = parse_profile_strict(name, value)?;
+
+    if profile.cpus.is_empty() {
+        bail!("Profile '{}' has no CPU cores configured", name);
+    }
+
+    let mut stripped = serde_json::to_value(&profile)?;
+    if let serde_json::Value::Object(ref mut fields) = stripped {
+        fields.retain(|key, _| !NOT_SHARED.contains(&key.as_str()));
+    }
+    profile = serde_json::from_value(stripped)?;
+    Ok(profile)
+}
+
+/// Reads a profile from JSON, rejecting fields affinity-rs doesn't know
+/// rather than silently dropping them.
+fn parse_profile_strict(name: &str, value: serde_json::Value) -> Result<Profile> {
+    let serde_json::Value::Object(ref fields) = value else {
+        bail!("Profile '{}' is not an object", name);
+    };
+
+    let profile: Profile = serde_json::from_value(value.clone())
+        .with_context(|| format!("Profile '{}' is invalid", name))?;
+
+    let known = serde_json::to_value(&profile)?;
+    for (key, field) in fields {
+        // Defaults such as false or [] are valid but left out when serialized
+        let default = match field {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => true,
+            serde_json::Value::Array(items) => items.is_empty(),
+            serde_json::Value::Object(items) => items.is_empty(),
+            serde_json::Value::String(text) => text.is_empty(),
+            _ => false,
+        };
+        if known.get(key).is_none() && !default {
+            bail!("Profile '{}' has an unknown option '{}'", name, key);
+        }
+    }
+
+    Ok(profile)
+}
+
+/// Imports a shared profile bundle after showing what it would add or change.
+fn fetch_presets(profiles: &mut Profiles, source: &str, unlock: bool) -> Result<()> {
+    // Not even --yes accepts profiles from the network unseen
+    if non_interactive() && !Path::new(source).is_file() {
+        bail!(
+            "Bundles from the network need someone to confirm them; download the file and import that instead"
+        );
+    }
+    let text = fetch_preset_text(source)?;
+    let bundle: PresetBundle =
+        serde_json::from_str(&text).context("This is not a valid preset bundle")?;
+
+    let mut incoming = bundle
+        .profiles
+        .into_iter()
+        .map(|(name, value)| Ok((name.clone(), parse_shared_profile(&name, value)?)))
+        .collect::<Result<Vec<(String, Profile)>>>()?;
+    incoming.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    if incoming.is_empty() {
+        bail!("The bundle contains no profiles");
+    }
+
+    println!();
+    if let Some(ref name) = bundle.name {
+        println!("Bundle: {}", name);
+    }
+    if let Some(ref description) = bundle.description {
+        println!("{}", description);
+    }
+    if let Some(ref cpu) = bundle.cpu {
+        println!(
+            "Tuned for: {} (this machine has {} logical CPUs)",
+            cpu,
+            num_cpus::get()
+        );
+    }
+
+    for (name, profile) in &incoming {
+        let fields = |profile: &Profile| match serde_json::to_value(profile) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => serde_json::Map::new(),
+        };
+        let new = fields(profile);
+
+        match profiles.get(name) {
+            Some(existing) => {
+                ensure_unlocked(existing, name, unlock)?;
+                println!("\n~ {} (replaces your profile)", name);
+
+                let old = fields(existing);
+                let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    if NOT_SHARED.contains(&key.as_str()) || old.get(key) == new.get(key) {
+                        continue;
+                    }
+                    let show = |value: Option<&serde_json::Value>| {
+                        value.map_or("(not set)".to_string(), |value| value.to_string())
+                    };
+                    println!(
+                        "    {}: {} -> {}",
+                        key,
+                        show(old.get(key)),
+                        show(new.get(key))
+                    );
+                }
+            }
+            None => {
+                println!("\n+ {} (new)", name);
+                for (key, value) in new.iter().filter(|(_, value)| !value.is_null()) {
+                    println!("    {}: {}", key, value);
+                }
+            }
+        }
+
+        if let Some(&cpu) = profile.cpus.iter().max()
+            && cpu >= num_cpus::get()
+        {
+            println!(
+                "    Warning: uses CPU {}, which this machine doesn't have",
+                cpu
+            );
+        }
+    }
+
+    if !confirm(&format!("\nImport {} profile(s)? [y/N]: ", incoming.len()))? {
+        println!("No changes made.");
+        return Ok(());
+    }
+
+    let mut imported = 0;
+    for (name, mut profile) in incoming {
+        // Paths differ between machines, so ask where the program is installed here
+        while !profile.path.exists() {
+            let file_name = profile
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "the executable".to_string());
+            let input = read_line_or(
+                &format!(
+                    "Path to {} for '{}' (Enter to skip this profile): ",
+                    file_name, name
+                ),
+                "",
+            )?;
+            if input.is_empty() {
+                break;
+            }
+            profile.path = PathBuf::from(input.trim_matches('"'));
+        }
+
+        if !profile.path.exists() {
+            println!("Skipped '{}'.", name);
+            continue;
+        }
+        if let Err(e) = validate_profile(&profile) {
+            eprintln!("Skipped '{}': {:#}", name, e);
+            continue;
+        }
+
+        profiles.insert(name, profile);
+        imported += 1;
+    }
+
+    save_profiles(profiles).context("Failed to save profiles")?;
+    println!("Imported {} profile(s).", imported);
+    Ok(())
+}
+
+/// Quotes an argument for a Windows command line the way the C runtime
+/// splits it again.
+fn command_line_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    quoted_arg(arg)
+}
+
+/// `arg` in double quotes, with backslashes and quotes escaped as
+/// CommandLineToArgvW expects.
+fn quoted_arg(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+        } else {
+            if c == '"' {
+                quoted.extend(std::iter::repeat_n('\\', backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        quoted.push(c);
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes));
+    quoted.push('"');
+    quoted
+}
+
+/// A single-quoted PowerShell string for `text`, escaped to also survive
+/// inside the double-quoted `-Command` of powershell.exe.
+#[cfg(target_os = "windows")]
+fn powershell_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''").replace('"', "\\\""))
+}
+
+fn desktop_dir() -> Result<PathBuf> {
+    let user_dirs = UserDirs::new().context("Could not find user directories")?;
+    user_dirs
+        .desktop_dir()
+        .map(Path::to_path_buf)
+        .context("Could not find Desktop directory")
+}
+
+fn start_menu_dir() -> Result<PathBuf> {
+    let base_dirs = BaseDirs::new().context("Could not find user directories")?;
+
+    #[cfg(target_os = "windows")]
+    let dir = base_dirs
+        .data_dir()
+        .join("Microsoft")
+        .join("Windows")
+        .join("Start Menu")
+        .join("Programs");
+
+    #[cfg(target_os = "linux")]
+    let dir = base_dirs.data_dir().join("applications");
+
+    Ok(dir)
+}
+
+/// Directories a profile's shortcuts may live in: the desktop, the start
+/// menu and any custom locations recorded by `shortcut --to`.
+fn shortcut_dirs(profile: Option<&Profile>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = [desktop_dir(), start_menu_dir()]
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect();
+
+    if let Some(profile) = profile {
+        dirs.extend(profile.shortcut_dirs.iter().cloned());
+    }
+
+    dirs
+}
+
+/// Shortcut files for `keyword` within a single directory.
+fn shortcut_files_in(dir: &Path, keyword: &str) -> Vec<PathBuf> {
+    #[cfg(target_os = "windows")]
+    return vec![
+        dir.join(format!("{}.bat", keyword)),
+        dir.join(format!("{}.lnk", keyword)),
+    ];
+
+    #[cfg(target_os = "linux")]
+    return vec![dir.join(format!("{}.desktop", keyword))];
+}
+
+/// Every file a shortcut for `keyword` may have been written to.
+fn shortcut_paths(keyword: &str, profile: Option<&Profile>) -> Vec<PathBuf> {
+    let paths = shortcut_dirs(profile)
+        .into_iter()
+        .flat_map(|dir| shortcut_files_in(&dir, keyword));
+
+    // Scripts behind icon shortcuts live in the config directory
+    #[cfg(target_os = "windows")]
+    let paths = paths.chain(get_config_dir().ok().map(|config_dir| {
+        config_dir
+            .join(SHORTCUT_SCRIPT_DIR)
+            .join(format!("{}.bat", keyword))
+    }));
+
+    paths.collect()
+}
+
+/// Shortcuts written by affinity-rs whose profile no longer exists.
+fn orphaned_shortcuts(profiles: &Profiles) -> Vec<PathBuf> {
+    let mut dirs = shortcut_dirs(None);
+    for profile in profiles.values() {
+        for dir in &profile.shortcut_dirs {
+            if !dirs.contains(dir) {
+                dirs.push(dir.clone());
+            }
+        }
+    }
+    #[cfg(target_os = "windows")]
+    if let Ok(config_dir) = get_config_dir() {
+        dirs.push(config_dir.join(SHORTCUT_SCRIPT_DIR));
+    }
+
+    let mut orphans = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if !profiles.contains_key(name) && is_own_shortcut(&path, name) {
+                orphans.push(path);
+            }
+        }
+    }
+
+    orphans.sort();
+    orphans
+}
+
+/// Whether `path` looks like a shortcut `write_shortcut` made for `keyword`.
+fn is_own_shortcut(path: &Path, keyword: &str) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    #[cfg(target_os = "windows")]
+    {
+        let Ok(content) = std::fs::read(path) else {
+            return false;
+        };
+        let contains = |needle: &[u8]| content.windows(needle.len()).any(|part| part == needle);
+
+        match extension.to_ascii_lowercase().as_str() {
+            // Only the exact scripts `shortcut_script` writes for this keyword,
+            // with any --pause value it may have been given
+            "bat" => {
+                let Ok(exe) = shortcut_exe() else {
+                    return false;
+                };
+                let Ok(content) = String::from_utf8(content) else {
+                    return false;
+                };
+                let Ok(task_args) = task_args_path(keyword) else {
+                    return false;
+                };
+                let pause = content
+                    .split_once("--pause ")
+                    .and_then(|(_, rest)| rest.split_whitespace().next())
+                    .and_then(PausePolicy::parse);
+
+                [None, pause].into_iter().any(|pause| {
+                    [(false, false), (true, false), (true, true)]
+                        .into_iter()
+                        .any(|(needs_admin, elevated_task)| {
+                            content
+                                == shortcut_script(
+                                    &exe,
+                                    keyword,
+                                    pause,
+                                    needs_admin,
+                                    elevated_task,
+                                    &task_args,
+                                )
+                        })
+                })
+            }
+            // Icon shortcuts point at their script, by path in ANSI or UTF-16
+            "lnk" => {
+                let script = format!("{}\\{}.bat", SHORTCUT_SCRIPT_DIR, keyword);
+                let wide: Vec<u8> = script
+                    .encode_utf16()
+                    .flat_map(|unit| unit.to_le_bytes())
+                    .collect();
+                contains(script.as_bytes()) || contains(&wide)
+            }
+            _ => false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let _ = keyword;
+        extension == "desktop"
+            && std::fs::read_to_string(path)
+                .is_ok_and(|content| content.contains("with CPU affinity and priority settings\n"))
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn exe_has_icon(path: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::UI::Shell::ExtractIconExW;
+
+    let file: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+    // An index of -1 with no output buffers returns the number of icons in the file
+    let count = unsafe {
+        ExtractIconExW(
+            file.as_ptr(),
+            -1,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+
+    count > 0
+}
+
+#[cfg(target_os = "windows")]
+fn write_icon_link(link_path: &Path, target: &Path, icon_source: &Path) -> Result<()> {
+    // Single quotes are escaped by doubling inside PowerShell literal strings
+    let quote = |p: &Path| p.display().to_string().replace('\'', "''");
+
+    let script = format!(
+        "$s = (New-Object -ComObject WScript.Shell).CreateShortcut('{}'); \
+         $s.TargetPath = '{}'; \
+         $s.IconLocation = '{},0'; \
+         $s.Save()",
+        quote(link_path),
+        quote(target),
+        quote(icon_source)
+    );
+
+    let status = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()
+        .context("Failed to run PowerShell")?;
+
+    if !status.success() {
+        bail!("PowerShell exited with {}", status);
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn task_name(keyword: &str) -> String {
+    format!("{}\\{}", TASK_FOLDER, keyword)
+}
+
+/// Where a task shortcut leaves its arguments, as `schtasks /Run` takes none.
+#[cfg(target_os = "windows")]
+fn task_args_path(keyword: &str) -> Result<PathBuf> {
+    Ok(get_config_dir()?
+        .join(SHORTCUT_SCRIPT_DIR)
+        .join(format!("{}.args", keyword)))
+}
+
+/// Takes the arguments the shortcut saved for this run of the task.
+#[cfg(target_os = "windows")]
+fn take_task_args(keyword: &str) -> Vec<String> {
+    let Ok(path) = task_args_path(keyword) else {
+        return Vec::new();
+    };
+    let args = std::fs::read_to_string(&path).unwrap_or_default();
+    let _ = std::fs::remove_file(&path);
+    split_args(&args)
+}
+
+/// Runs schtasks with a raw command line, going through a single UAC prompt
+/// when the current process is not already elevated.
+#[cfg(target_os = "windows")]
+fn run_schtasks_elevated(command_line: &str) -> Result<()> {
+    use std::os::windows::process::CommandExt;
+
+    let status = if is_elevated() {
+        Command::new("schtasks")
+            .raw_arg(command_line)
+            .status()
+            .context("Failed to run schtasks")?
+    } else {
+        let script = format!(
+            "$p = Start-Process -FilePath schtasks -ArgumentList '{}' \
+             -Verb RunAs -Wait -PassThru -WindowStyle Hidden; exit $p.ExitCode",
+            command_line.replace('\'', "''")
+        );
+        Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+            .status()
+            .context("Failed to run PowerShell")?
+    };
+
+    if !status.success() {
+        bail!(
+            "schtasks exited with {} (the UAC prompt may have been cancelled)",
+            status
+        );
+    }
+
+    Ok(())
+}
+
+/// Registers an on-demand task that launches `keyword` with highest privileges.
+#[cfg(target_os = "windows")]
+fn register_elevated_task(keyword: &str) -> Result<()> {
+    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
+
+    // /TR takes the whole task command as one argument, with inner quotes escaped
+    let command_line = format!(
+        "/Create /TN \"{}\" /TR \"\\\"{}\\\" {} {}\" /SC ONCE /ST 00:00 /RL HIGHEST /IT /F",
+        task_name(keyword),
+        current_exe.display(),
+        TASK_ARGS_FLAG,
+        keyword
+    );
+
+    println!("Registering elevated scheduled task (one-time administrator approval)...");
+    run_schtasks_elevated(&command_line).context("Failed to register scheduled task")
+}
+
+#[cfg(target_os = "windows")]
+fn delete_elevated_task(keyword: &str) -> Result<()> {
+    run_schtasks_elevated(&format!("/Delete /TN \"{}\" /F", task_name(keyword)))
+        .context("Failed to delete scheduled task")
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_icon(exe: &Path) -> String {
+    // Icons shipped next to the binary take precedence over theme lookups
+    if let Some(dir) = exe.parent() {
+        for candidate in [exe.with_extension("png"), exe.with_extension("svg")]
+            .into_iter()
+            .chain(["icon.png", "icon.svg"].map(|name| dir.join(name)))
+        {
+            if candidate.exists() {
+                return candidate.display().to_string();
+            }
+        }
+    }
+
+    if let Some(stem) = exe.file_stem().and_then(|s| s.to_str()) {
+        let name = stem.to_lowercase();
+        let theme_dirs = [
+            "/usr/share/pixmaps",
+            "/usr/share/icons/hicolor/scalable/apps",
+            "/usr/share/icons/hicolor/256x256/apps",
+            "/usr/share/icons/hicolor/128x128/apps",
+            "/usr/share/icons/hicolor/48x48/apps",
+        ];
+
+        for dir in theme_dirs {
+            for ext in ["png", "svg", "xpm"] {
+                if Path::new(dir).join(format!("{}.{}", name, ext)).exists() {
+                    return name;
+                }
+            }
+        }
+    }
+
+    "application-x-executable".to_string()
+}
+
+/// Creates a shortcut for `keyword` in `dir`, remembering custom locations on
+/// the profile so later refreshes and deletes can find the shortcut again.
+fn create_shortcut(
+    profiles: &mut Profiles,
+    keyword: &str,
+    dir: &Path,
+    elevated_task: bool,
+    pause: Option<PausePolicy>,
+) -> Result<()> {
+    let profile = profiles
+        .get_mut(keyword)
+        .context(format!("Profile '{}' not found", keyword))?;
+
+    let mut changed = false;
+
+    if pause.is_some() && pause != profile.shortcut_pause {
+        profile.shortcut_pause = pause;
+        changed = true;
+    }
+
+    if elevated_task {
+        #[cfg(target_os = "windows")]
+        {
+            let needs_admin = profile
+                .priority
+                .as_ref()
+                .map(|p| p.requires_elevation())
+                .unwrap_or(false);
+
+            if needs_admin {
+                register_elevated_task(keyword)?;
+                changed |= !profile.elevated_task;
+                profile.elevated_task = true;
+            } else {
+                println!(
+                    "Note: '{}' does not need administrator privileges; no scheduled task required.",
+                    keyword
+                );
+            }
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        bail!("Scheduled task shortcuts are only available on Windows");
+    }
+
+    write_shortcut(profile, keyword, dir)?;
+
+    if !shortcut_dirs(Some(profile)).iter().any(|d| d == dir) {
+        profile.shortcut_dirs.push(dir.to_path_buf());
+        changed = true;
+    }
+
+    if changed {
+        save_profiles(profiles).context("Failed to save profile")?;
+    }
+
+    Ok(())
+}
+
+/// Flags placed before the profile name in a shortcut's command.
+fn shortcut_launch_args(keyword: &str, pause: Option<PausePolicy>) -> Vec<String> {
+    let mut launch_args = Vec::new();
+    if let Some(pause) = pause {
+        launch_args.extend(["--pause".to_string(), pause.to_arg()]);
+    }
+    launch_args.push(keyword.to_string());
+    launch_args
+}
+
+/// The .bat a Windows shortcut for `keyword` runs.
+#[cfg(target_os = "windows")]
+fn shortcut_script(
+    current_exe_str: &str,
+    keyword: &str,
+    pause: Option<PausePolicy>,
+    needs_admin: bool,
+    elevated_task: bool,
+    task_args: &Path,
+) -> String {
+    let launch_args = shortcut_launch_args(keyword, pause)
+        .iter()
+        .map(|arg| command_line_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    // cmd reads scripts in the OEM code page unless told otherwise, which
+    // garbles paths under non-ASCII user names
+    if needs_admin && elevated_task {
+        // The scheduled task already runs elevated, so no UAC prompt here.
+        // It cannot be handed arguments, so they are left in a file for it.
+        format!(
+            "@echo off\r\nchcp 65001 >nul\r\n>\"{}\" echo(%*\r\nschtasks /Run /TN \"{}\" >nul\r\n",
+            task_args.display(),
+            task_name(keyword)
+        )
+    } else if needs_admin {
+        // Create elevated shortcut. The forwarded arguments reach PowerShell
+        // through the environment, so quotes and spaces in them are passed
+        // on as typed instead of ending up in its command.
+        format!(
+            "@echo off\r\n\
+             chcp 65001 >nul\r\n\
+             set AFFINITY_RS_ARGS=%*\r\n\
+             echo Requesting administrator privileges for {}...\r\n\
+             powershell -NoProfile -Command \"Start-Process -FilePath {} -ArgumentList ({} + ' ' + $env:AFFINITY_RS_ARGS) -Verb RunAs\"\r\n",
+            keyword,
+            powershell_literal(current_exe_str),
+            powershell_literal(&launch_args)
+        )
+    } else {
+        // %* forwards any arguments given to the .bat on to the profile
+        format!(
+            "@echo off\r\nchcp 65001 >nul\r\nset {}=1\r\n\"{}\" {} %*\r\n",
+            SHORTCUT_ENV_VAR, current_exe_str, launch_args
+        )
+    }
+}
+
+/// Path of affinity-rs as written into shortcuts, which are UTF-8 text. An
+/// executable path that isn't valid Unicode is replaced by its 8.3 short
+/// name on Windows and by a symlink in the config directory on Linux.
+fn shortcut_exe() -> Result<String> {
+    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
+    if let Some(path) = current_exe.to_str() {
+        return Ok(path.to_string());
+    }
+
+    #[cfg(target_os = "windows")]
+    let path = {
+        use std::os::windows::ffi::{OsStrExt, OsStringExt};
+        use windows_sys::Win32::Storage::FileSystem::GetShortPathNameW;
+
+        let wide: Vec<u16> = current_exe
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut short = vec![0u16; 32768];
+        let len =
+            unsafe { GetShortPathNameW(wide.as_ptr(), short.as_mut_ptr(), short.len() as u32) };
+        short.truncate(len as usize);
+        OsString::from_wide(&short).into_string().ok()
+    };
+
+    #[cfg(target_os = "linux")]
+    let path = {
+        let dir = get_config_dir()?.join(SHORTCUT_SCRIPT_DIR);
+        std::fs::create_dir_all(&dir).context("Failed to create shortcut script directory")?;
+        let link = dir.join("affinity-rs");
+        if std::fs::read_link(&link).ok().as_ref() != Some(&current_exe) {
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(&current_exe, &link)
+                .with_context(|| format!("Failed to create {}", link.display()))?;
+        }
+        link.into_os_string().into_string().ok()
+    };
+
+    path.with_context(|| {
+        format!(
+            "The path of affinity-rs can't be written to a shortcut: {}",
+            current_exe.display()
+        )
+    })
+}
+
+fn write_shortcut(profile: &Profile, keyword: &str, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+    let current_exe = shortcut_exe()?;
+    let current_exe_str = current_exe.as_str();
+
+    #[cfg(target_os = "windows")]
+    {
+        // With an icon available, the shortcut gets a .lnk carrying the game's
+        // icon and the script itself is kept out of sight in the config dir
+        let use_icon = exe_has_icon(&profile.path);
+        let bat_path = if use_icon {
+            let script_dir = get_config_dir()?.join(SHORTCUT_SCRIPT_DIR);
+            std::fs::create_dir_all(&script_dir)
+                .context("Failed to create shortcut script directory")?;
+            script_dir.join(format!("{}.bat", keyword))
+        } else {
+            dir.join(format!("{}.bat", keyword))
+        };
+
+        // Check if elevation is needed
+        let needs_admin = profile
+            .priority
+            .as_ref()
+            .map(|p| p.requires_elevation())
+            .unwrap_or(false);
+
+        let content = shortcut_script(
+            current_exe_str,
+            keyword,
+            profile.shortcut_pause,
+            needs_admin,
+            profile.elevated_task,
+            &task_args_path(keyword)?,
+        );
+
+        std::fs::write(&bat_path, content).context("Failed to write shortcut file")?;
+
+        if use_icon {
+            let link_path = dir.join(format!("{}.lnk", keyword));
+            write_icon_link(&link_path, &bat_path, &profile.path)
+                .context("Failed to create desktop shortcut")?;
+
+            // Drop a plain .bat left behind by an earlier version
+            let _ = std::fs::remove_file(dir.join(format!("{}.bat", keyword)));
+            println!("Shortcut created: {}", link_path.display());
+        } else {
+            println!("Shortcut created: {}", bat_path.display());
+        }
+
+        if needs_admin && profile.elevated_task {
+            println!("Note: This shortcut runs the elevated scheduled task without a UAC prompt.");
+            println!("Arguments passed to the shortcut are not forwarded to the task.");
+        } else if needs_admin {
+            println!("Note: This shortcut will request administrator privileges when launched.");
+            println!("Alternatively, you can:");
+            println!(
+                "  - Right-click the .bat file > Properties > Advanced > Run as administrator"
+            );
+            println!(
+                "  - Run 'affinity-rs shortcut {} --task' to launch through a scheduled task without UAC prompts",
+                keyword
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let launch_args = shortcut_launch_args(keyword, profile.shortcut_pause);
+        let shortcut_path = dir.join(format!("{}.desktop", keyword));
+        let exe_name = profile
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| keyword.to_string());
+
+        let action_ids: Vec<String> = profile
+            .shortcut_actions
+            .iter()
+            .map(|action| desktop_action_id(&action.name))
+            .collect();
+
+        let mut content = String::new();
+        content.push_str("[Desktop Entry]\n");
+        content.push_str("Version=1.0\n");
+        content.push_str("Type=Application\n");
+        content.push_str(&format!("Name={}\n", desktop_escape(keyword)));
+        content.push_str(&format!(
+            "Comment=Launch {} with CPU affinity and priority settings\n",
+            desktop_escape(keyword)
+        ));
+        content.push_str(&format!("TryExec={}\n", desktop_escape(current_exe_str)));
+        let mut exec_args = vec![current_exe_str];
+        exec_args.extend(launch_args.iter().map(String::as_str));
+        content.push_str(&format!("Exec={}\n", desktop_exec(&exec_args, Some("%u"))));
+        content.push_str(&format!(
+            "Icon={}\n",
+            desktop_escape(&desktop_icon(&profile.path))
+        ));
+        content.push_str(&format!("StartupWMClass={}\n", desktop_escape(&exe_name)));
+        content.push_str("Terminal=false\n");
+        content.push_str("Categories=Utility;\n");
+        content.push_str(&format!(
+            "Keywords={};{};affinity;\n",
+            desktop_escape(keyword).replace(';', "\\;"),
+            desktop_escape(&exe_name).replace(';', "\\;")
+        ));
+
+        if !action_ids.is_empty() {
+            content.push_str(&format!("Actions={};\n", action_ids.join(";")));
+        }
+
+        for (id, action) in action_ids.iter().zip(&profile.shortcut_actions) {
+            let mut exec_args = vec![current_exe_str];
+            exec_args.extend(launch_args.iter().map(String::as_str));
+            exec_args.extend(action.args.iter().map(String::as_str));
+
+            content.push_str(&format!("\n[Desktop Action {}]\n", id));
+            content.push_str(&format!("Name={}\n", desktop_escape(&action.name)));
+            content.push_str(&format!("Exec={}\n", desktop_exec(&exec_args, None)));
+        }
+
+        validate_desktop_entry(&content).context("Generated .desktop entry is invalid")?;
+
+        std::fs::write(&shortcut_path, &content).context("Failed to write .desktop file")?;
+
+        let mut perms = std::fs::metadata(&shortcut_path)?.permissions();
+        perms.set_mode(0o755);
+        std::fs::set_permissions(&shortcut_path, perms)
+            .context("Failed to set executable permissions")?;
+
+        println!("Shortcut created: {}", shortcut_path.display());
+    }
+
+    Ok(())
+}
+
+/// Escapes a value for a .desktop `string` or `localestring` key.
+#[cfg(target_os = "linux")]
+fn desktop_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Builds an `Exec=` value, quoting arguments as the Desktop Entry spec requires.
+#[cfg(target_os = "linux")]
+fn desktop_exec(args: &[&str], field_code: Option<&str>) -> String {
+    let reserved = |c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c);
+
+    let mut parts: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            let arg = arg.replace('%', "%%");
+            if !arg.is_empty() && !arg.chars().any(reserved) {
+                return arg;
+            }
+
+            let mut quoted = String::from("\"");
+            for c in arg.chars() {
+                if matches!(c, '"' | '`' | '$' | '\\') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        })
+        .collect();
+
+    if let Some(code) = field_code {
+        parts.push(code.to_string());
+    }
+
+    // Exec is itself a string value, so its backslashes are escaped once more
+    desktop_escape(&parts.join(" "))
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_action_id(name: &str) -> String {
+    let id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    id.trim_matches('-').to_string()
+}
+
+/// Checks an entry against the core rules enforced by desktop-file-validate.
+#[cfg(target_os = "linux")]
+fn validate_desktop_entry(content: &str) -> Result<()> {
+    const MAIN_GROUP: &str = "Desktop Entry";
+
+    let mut groups: Vec<(String, Vec<(String, String)>)> = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line_no = index + 1;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if groups.iter().any(|(group, _)| group == name) {
+                bail!("line {}: duplicate group [{}]", line_no, name);
+            }
+            groups.push((name.to_string(), Vec::new()));
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected 'Key=Value'", line_no);
+        };
+        let Some((_, entries)) = groups.last_mut() else {
+            bail!("line {}: key '{}' is outside of any group", line_no, key);
+        };
+
+        let key = key.trim_end();
+        let base_key = key.split('[').next().unwrap_or(key);
+        if base_key.is_empty()
+            || !base_key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!("line {}: invalid key '{}'", line_no, key);
+        }
+        if entries.iter().any(|(existing, _)| existing == key) {
+            bail!("line {}: duplicate key '{}'", line_no, key);
+        }
+
+        entries.push((key.to_string(), value.trim_start().to_string()));
+    }
+
+    let get = |group: &str, key: &str| {
+        groups
+            .iter()
+            .find(|(name, _)| name == group)
+            .and_then(|(_, entries)| entries.iter().find(|(k, _)| k == key))
+            .map(|(_, value)| value.as_str())
+    };
+
+    if groups.first().map(|(name, _)| name.as_str()) != Some(MAIN_GROUP) {
+        bail!("first group must be [{}]", MAIN_GROUP);
+    }
+
+    for key in ["Type", "Name", "Exec"] {
+        if get(MAIN_GROUP, key).is_none() {
+            bail!("[{}] is missing required key '{}'", MAIN_GROUP, key);
+        }
+    }
+
+    for key in ["Categories", "Keywords", "Actions"] {
+        if let Some(value) = get(MAIN_GROUP, key)
+            && !value.is_empty()
+            && !value.ends_with(';')
+        {
+            bail!("list value of '{}' must end with ';'", key);
+        }
+    }
+
+    if let Some(actions) = get(MAIN_GROUP, "Actions") {
+        for id in actions.split(';').filter(|id| !id.is_empty()) {
+            let group = format!("Desktop Action {}", id);
+            if get(&group, "Name").is_none() {
+                bail!("action '{}' has no [{}] group with a Name", id, group);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn refresh_shortcuts(profiles: &Profiles) -> Result<()> {
+    let mut names: Vec<&String> = profiles
+        .keys()
+        .filter(|name| !name.starts_with(TEMP_PROFILE_PREFIX))
+        .collect();
+    names.sort();
+
+    let mut refreshed = 0;
+    let mut failed = 0;
+
+    for name in names {
+        let profile = &profiles[name];
+
+        for dir in shortcut_dirs(Some(profile)) {
+            if !shortcut_files_in(&dir, name).iter().any(|p| p.exists()) {
+                continue;
+            }
+
+            match write_shortcut(profile, name, &dir) {
+                Ok(_) => refreshed += 1,
+                Err(e) => {
+                    eprintln!("Error refreshing shortcut for '{}': {:#}", name, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if refreshed + failed == 0 {
+        println!("No existing shortcuts to refresh.");
+        return Ok(());
+    }
+
+    println!(
+        "\nRefreshed {} of {} shortcut(s).",
+        refreshed,
+        refreshed + failed
+    );
+
+    if failed > 0 {
+        bail!("{} shortcut(s) could not be refreshed", failed);
+    }
+
+    Ok(())
+}
+
+/// Daemon rule: enforce `profile` on matching running processes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Rule {
+    profile: String,
+    /// Process name to match; defaults to the profile's executable path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process: Option<String>,
+    /// Service whose process to match instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    service: Option<String>,
+    /// Controller or other HID device, as `vendor:product` in hex or part of
+    /// its name, that launches the profile when connected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+}
+
+impl Rule {
+    fn matches_device(&self, device: &HidDevice) -> bool {
+        let Some(ref pattern) = self.device else {
+            return false;
+        };
+
+        if let Some((vendor, product)) = pattern.split_once(':')
+            && let (Ok(vendor), Ok(product)) = (
+                u16::from_str_radix(vendor.trim(), 16),
+                u16::from_str_radix(product.trim(), 16),
+            )
+        {
+            return device.vendor == vendor && device.product == product;
+        }
+
+        device
+            .name
+            .to_lowercase()
+            .contains(&pattern.trim().to_lowercase())
+    }
+
+    /// `service_pid` is the current process of the rule's service, if it has one.
+    fn matches(&self, process: &ProcessInfo, profile: &Profile, service_pid: Option<u32>) -> bool {
+        if self.service.is_some() {
+            return service_pid == Some(process.pid);
+        }
+
+        match self.process {
+            Some(ref name) => process.name.eq_ignore_ascii_case(name),
+            None => process.exe.as_deref() == Some(profile.path.as_path()),
+        }
+    }
+}
+
+/// A process the daemon has applied settings to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ManagedProcess {
+    pid: u32,
+    #[serde(default)]
+    start_time: Option<u64>,
+    profile: String,
+    cpus: Vec<usize>,
+    priority: Option<ProcessPriority>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DaemonState {
+    managed: Vec<ManagedProcess>,
+    /// Processes a rule could not be applied to, by PID
+    #[serde(skip)]
+    failed: HashMap<u32, FailedApply>,
+}
+
+/// Longest the daemon waits before retrying a process it failed to apply to.
+const MAX_APPLY_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// A failed apply, retried after a delay that doubles with every attempt.
+#[derive(Debug)]
+struct FailedApply {
+    start_time: Option<u64>,
+    attempts: u32,
+    retry_at: Instant,
+}
+
+impl FailedApply {
+    /// Whether the process may be tried again now.
+    fn is_due(&self) -> bool {
+        Instant::now() >= self.retry_at
+    }
+
+    /// Records one more failure and pushes the next attempt further out.
+    fn failed_again(&mut self, interval: Duration) {
+        self.attempts += 1;
+        let backoff = interval.saturating_mul(1 << self.attempts.min(16));
+        self.retry_at = Instant::now() + backoff.min(MAX_APPLY_BACKOFF);
+    }
+}
+
+fn load_rules() -> Result<Vec<Rule>> {
+    let path = get_config_dir()?.join(RULES_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read rules file")?;
+    serde_json::from_str(&data).context("Failed to parse rules JSON")
+}
+
+fn save_rules(rules: &[Rule]) -> Result<()> {
+    let data = serde_json::to_string_pretty(rules).context("Failed to serialize rules")?;
+    std::fs::write(get_config_dir()?.join(RULES_FILE_NAME), data)
+        .context("Failed to write rules file")?;
+    Ok(())
+}
+
+fn load_daemon_state() -> Result<DaemonState> {
+    let path = get_config_dir()?.join(DAEMON_STATE_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(DaemonState::default());
+    }
+
+    let data = std::fs::read_to_string(&path).context("Failed to read daemon state")?;
+    serde_json::from_str(&data).context("Failed to parse daemon state")
+}
+
+fn save_daemon_state(state: &DaemonState) -> Result<()> {
+    let data = serde_json::to_string_pretty(state).context("Failed to serialize daemon state")?;
+    std::fs::write(get_config_dir()?.join(DAEMON_STATE_FILE_NAME), data)
+        .context("Failed to write daemon state")?;
+    Ok(())
+}
+
+fn apply_managed(managed: &ManagedProcess) -> Result<()> {
+    apply_to_pid(
+        managed.pid,
+        Some(&managed.cpus),
+        managed.priority.as_ref(),
+        "daemon",
+    )
+}
+
+/// Picks up processes managed by a previous daemon run: those still alive
+/// get their settings re-applied, the rest are forgotten.
+fn resume_daemon_state(state: &mut DaemonState) {
+    state.managed.retain(|managed| {
+        if process_start_time(managed.pid) != managed.start_time {
+            return false;
+        }
+
+        match apply_managed(managed) {
+            Ok(_) => {
+                println!(
+                    "Resumed PID {} (profile '{}')",
+                    managed.pid, managed.profile
+                );
+                true
+            }
+            Err(e) => {
+                eprintln!("Warning: Could not re-apply PID {}: {:#}", managed.pid, e);
+                false
+            }
+        }
+    });
+}
+
+/// One pass of the daemon: forget exited processes and apply rules to new ones.
+/// Processes that could not be applied to are retried less and less often,
+/// `interval` being the delay after the first failure. Returns whether the
+/// managed set changed.
+fn daemon_tick(
+    profiles: &Profiles,
+    rules: &[Rule],
+    state: &mut DaemonState,
+    interval: Duration,
+) -> Result<bool> {
+    let processes = list_processes()?;
+    let before = state.managed.len();
+
+    state
+        .managed
+        .retain(|managed| process_start_time(managed.pid) == managed.start_time);
+    state
+        .failed
+        .retain(|&pid, failed| process_start_time(pid) == failed.start_time);
+    // Processes of profiles disabled since are left as they are
+    state.managed.retain(|managed| {
+        profiles
+            .get(&managed.profile)
+            .is_none_or(Profile::is_enabled)
+    });
+    let mut changed = state.managed.len() != before;
+
+    // Device rules launch rather than match processes
+    for rule in rules.iter().filter(|rule| rule.device.is_none()) {
+        if profiles
+            .get(&rule.profile)
+            .is_some_and(|profile| !profile.is_enabled())
+        {
+            continue;
+        }
+
+        let Ok(profile) = inherited_profile(profiles, &rule.profile).map(|p| p.resolved()) else {
+            continue;
+        };
+
+        // Looked up on every pass, as a restarted service runs in a new process
+        let service_pid = match rule.service {
+            Some(ref name) => match service_pid(name) {
+                Ok(pid) => Some(pid),
+                // Stopped services are nothing to enforce
+                Err(_) => continue,
+            },
+            None => None,
+        };
+
+        for process in processes
+            .iter()
+            .filter(|p| rule.matches(p, &profile, service_pid))
+        {
+            if state.managed.iter().any(|m| m.pid == process.pid)
+                || state
+                    .failed
+                    .get(&process.pid)
+                    .is_some_and(|failed| !failed.is_due())
+            {
+                continue;
+            }
+
+            let managed = ManagedProcess {
+                pid: process.pid,
+                start_time: process_start_time(process.pid),
+                profile: rule.profile.clone(),
+                cpus: profile.cpus.clone(),
+                priority: profile.priority.clone(),
+            };
+
+            match apply_managed(&managed) {
+                Ok(_) => println!(
+                    "Applied profile '{}' to PID {} ({})",
+                    rule.profile, process.pid, process.name
+                ),
+                Err(e) => {
+                    let failed = state.failed.entry(process.pid).or_insert(FailedApply {
+                        start_time: managed.start_time,
+                        attempts: 0,
+                        retry_at: Instant::now(),
+                    });
+                    failed.failed_again(interval);
+                    eprintln!(
+                        "Warning: Could not apply '{}' to PID {} (attempt {}, retrying in {}s): {:#}",
+                        rule.profile,
+                        process.pid,
+                        failed.attempts,
+                        failed
+                            .retry_at
+                            .saturating_duration_since(Instant::now())
+                            .as_secs(),
+                        e
+                    );
+                    continue;
+                }
+            }
+
+            state.failed.remove(&process.pid);
+            state.managed.push(managed);
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Re-applies the settings of every managed process after a wake-up from
+/// sleep, whatever its profile's `reapply_interval`.
+fn daemon_resume(state: &mut DaemonState) {
+    // Processes that exited while asleep may have left their PID to another
+    state
+        .managed
+        .retain(|managed| process_start_time(managed.pid) == managed.start_time);
+
+    for managed in &state.managed {
+        if let Ok(true) = fix_drift(
+            managed.pid,
+            &managed.cpus,
+            managed.priority.as_ref(),
+            "resume",
+        ) {
+            println!(
+                "Re-applied profile '{}' to PID {} after resume",
+                managed.profile, managed.pid
+            );
+        }
+    }
+}
+
+/// Fixes drifted settings of managed processes and their descendants for
+/// profiles with a `reapply_interval`. `last_checked` tracks when each
+/// managed PID was last looked at.
+fn daemon_reapply(
+    profiles: &Profiles,
+    state: &DaemonState,
+    last_checked: &mut HashMap<u32, Instant>,
+) -> Result<()> {
+    last_checked.retain(|pid, _| state.managed.iter().any(|m| m.pid == *pid));
+
+    let due: Vec<&ManagedProcess> = state
+        .managed
+        .iter()
+        .filter(|managed| {
+            let Some(interval) = inherited_profile(profiles, &managed.profile)
+                .ok()
+                .and_then(|profile| profile.reapply_interval)
+            else {
+                return false;
+            };
+            last_checked
+                .get(&managed.pid)
+                .is_none_or(|checked| checked.elapsed() >= Duration::from_secs(interval))
+        })
+        .collect();
+
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let processes = list_processes()?;
+
+    for managed in due {
+        last_checked.insert(managed.pid, Instant::now());
+
+        let mut tree = ProcessTree::new(managed.pid);
+        tree.update(&processes);
+
+        for &pid in std::iter::once(&managed.pid).chain(&tree.members) {
+            match fix_drift(pid, &managed.cpus, managed.priority.as_ref(), "daemon") {
+                Ok(true) => println!("Re-applied profile '{}' to PID {}", managed.profile, pid),
+                Ok(false) => {}
+                Err(e) => eprintln!("Warning: Could not re-apply PID {}: {:#}", pid, e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Tells the daemon when profiles.json or rules.json were written, using
+/// inotify / change notifications on the directories holding them. A
+/// notification only prompts a look at the files' modification times, since
+/// other files in the directories change all the time.
+struct ConfigWatcher {
+    files: Vec<PathBuf>,
+    modified: Vec<Option<std::time::SystemTime>>,
+    #[cfg(target_os = "linux")]
+    fd: Option<i32>,
+    #[cfg(target_os = "windows")]
+    handles: Option<Vec<windows_sys::Win32::Foundation::HANDLE>>,
+}
+
+impl ConfigWatcher {
+    fn new(files: Vec<PathBuf>) -> Self {
+        let modified = files.iter().map(|file| file_modified(file)).collect();
+
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        dirs.dedup();
+        // Directories that don't exist (yet) are simply not watched
+        dirs.retain(|dir| dir.is_dir());
+
+        #[cfg(target_os = "linux")]
+        let fd = unsafe {
+            use std::os::unix::ffi::OsStrExt;
+
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+            let watched = fd >= 0
+                && dirs.iter().all(|dir| {
+                    std::ffi::CString::new(dir.as_os_str().as_bytes())
+                        .is_ok_and(|path| libc::inotify_add_watch(fd, path.as_ptr(), mask) >= 0)
+                });
+            if !watched && fd >= 0 {
+                libc::close(fd);
+            }
+            watched.then_some(fd)
+        };
+
+        #[cfg(target_os = "windows")]
+        let handles = {
+            use std::os::windows::ffi::OsStrExt;
+            use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+                FindCloseChangeNotification, FindFirstChangeNotificationW,
+            };
+
+            let handles: Vec<_> = dirs
+                .iter()
+                .map(|dir| {
+                    let path: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+                    unsafe {
+                        FindFirstChangeNotificationW(
+                            path.as_ptr(),
+                            0,
+                            FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                        )
+                    }
+                })
+                .collect();
+
+            if handles.contains(&INVALID_HANDLE_VALUE) {
+                for handle in handles.into_iter().filter(|&h| h != INVALID_HANDLE_VALUE) {
+                    unsafe { FindCloseChangeNotification(handle) };
+                }
+                None
+            } else {
+                Some(handles)
+            }
+        };
+
+        Self {
+            files,
+            modified,
+            #[cfg(target_os = "linux")]
+            fd,
+            #[cfg(target_os = "windows")]
+            handles,
+        }
+    }
+
+    /// Whether anything was written to the config directory since the last
+    /// call. Without a working notification, every call counts as one.
+    #[cfg(target_os = "linux")]
+    fn notified(&mut self) -> bool {
+        let Some(fd) = self.fd else {
+            return true;
+        };
+
+        let mut notified = false;
+        let mut buffer = [0u8; 4096];
+        while unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut _, buffer.len()) } > 0 {
+            notified = true;
+        }
+        notified
+    }
+
+    #[cfg(target_os = "windows")]
+    fn notified(&mut self) -> bool {
+        use windows_sys::Win32::Foundation::WAIT_OBJECT_0;
+        use windows_sys::Win32::Storage::FileSystem::FindNextChangeNotification;
+        use windows_sys::Win32::System::Threading::WaitForSingleObject;
+
+        let Some(ref handles) = self.handles else {
+            return true;
+        };
+
+        let mut notified = false;
+        for &handle in handles {
+            unsafe {
+                if WaitForSingleObject(handle, 0) == WAIT_OBJECT_0 {
+                    FindNextChangeNotification(handle);
+                    notified = true;
+                }
+            }
+        }
+        notified
+    }
+
+    /// Whether one of the watched files changed since the last call.
+    fn changed(&mut self) -> bool {
+        if !self.notified() {
+            return false;
+        }
+
+        let modified: Vec<_> = self.files.iter().map(|file| file_modified(file)).collect();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(fd) = self.fd {
+            unsafe { libc::close(fd) };
+        }
+
+        #[cfg(target_os = "windows")]
+        for &handle in self.handles.iter().flatten() {
+            unsafe { windows_sys::Win32::Storage::FileSystem::FindCloseChangeNotification(handle) };
+        }
+    }
+}
+
+fn file_modified(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// A connected HID device, which includes game controllers.
+struct HidDevice {
+    vendor: u16,
+    product: u16,
+    name: String,
+}
+
+/// HID devices from /sys/bus/hid, whose entries are named
+/// `<bus>:<vendor>:<product>.<instance>`. Covers USB and Bluetooth.
+#[cfg(target_os = "linux")]
+fn list_hid_devices() -> Vec<HidDevice> {
+    let Ok(entries) = std::fs::read_dir("/sys/bus/hid/devices") else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let mut parts = id.split([':', '.']);
+            let _bus = parts.next()?;
+            let vendor = u16::from_str_radix(parts.next()?, 16).ok()?;
+            let product = u16::from_str_radix(parts.next()?, 16).ok()?;
+
+            let name = std::fs::read_to_string(entry.path().join("uevent"))
+                .ok()
+                .and_then(|uevent| {
+                    uevent
+                        .lines()
+                        .find_map(|line| line.strip_prefix("HID_NAME="))
+                        .map(str::to_string)
+                })
+                .unwrap_or_default();
+
+            Some(HidDevice {
+                vendor,
+                product,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Present devices enumerated by the HID driver. Their instance IDs hold the
+/// IDs as `VID_054C&PID_0CE6` (USB) or `VID&0002054C_PID&0CE6` (Bluetooth).
+#[cfg(target_os = "windows")]
+fn list_hid_devices() -> Vec<HidDevice> {
+    use windows_sys::Win32::Devices::DeviceAndDriverInstallation::{
+        DIGCF_ALLCLASSES, DIGCF_PRESENT, SP_DEVINFO_DATA, SPDRP_DEVICEDESC, SPDRP_FRIENDLYNAME,
+        SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW,
+        SetupDiGetDeviceInstanceIdW, SetupDiGetDeviceRegistryPropertyW,
+    };
+
+    let hex_after = |id: &str, marker: &str, digits: usize| {
+        let start = id.find(marker)? + marker.len();
+        let value = id.get(start..start + digits)?;
+        u16::from_str_radix(&value[digits - 4..], 16).ok()
+    };
+
+    let text = |buffer: &[u16]| {
+        let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+        String::from_utf16_lossy(&buffer[..len])
+    };
+
+    let enumerator: Vec<u16> = "HID".encode_utf16().chain(Some(0)).collect();
+    let mut devices = Vec::new();
+
+    unsafe {
+        let set = SetupDiGetClassDevsW(
+            std::ptr::null(),
+            enumerator.as_ptr(),
+            std::ptr::null_mut(),
+            DIGCF_PRESENT | DIGCF_ALLCLASSES,
+        );
+        if set as isize == -1 {
+            return devices;
+        }
+
+        let mut index = 0;
+        loop {
+            let mut info: SP_DEVINFO_DATA = std::mem::zeroed();
+            info.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
+            if SetupDiEnumDeviceInfo(set, index, &mut info) == 0 {
+                break;
+            }
+            index += 1;
+
+            let mut id = [0u16; 512];
+            if SetupDiGetDeviceInstanceIdW(
+                set,
+                &info,
+                id.as_mut_ptr(),
+                id.len() as u32,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                continue;
+            }
+            let id = text(&id).to_uppercase();
+
+            let vendor = hex_after(&id, "VID_", 4).or_else(|| hex_after(&id, "VID&", 8));
+            let product = hex_after(&id, "PID_", 4).or_else(|| hex_after(&id, "PID&", 4));
+            let (Some(vendor), Some(product)) = (vendor, product) else {
+                continue;
+            };
+
+            let mut name = String::new();
+            for property in [SPDRP_FRIENDLYNAME, SPDRP_DEVICEDESC] {
+                let mut buffer = [0u16; 256];
+                if SetupDiGetDeviceRegistryPropertyW(
+                    set,
+                    &info,
+                    property,
+                    std::ptr::null_mut(),
+                    buffer.as_mut_ptr() as *mut u8,
+                    (buffer.len() * 2) as u32,
+                    std::ptr::null_mut(),
+                ) != 0
+                {
+                    name = text(&buffer);
+                    break;
+                }
+            }
+
+            devices.push(HidDevice {
+                vendor,
+                product,
+                name,
+            });
+        }
+
+        SetupDiDestroyDeviceInfoList(set);
+    }
+
+    devices
+}
+
+/// The device rules whose device is connected right now, as (profile, device).
+fn connected_device_rules(rules: &[Rule]) -> std::collections::HashSet<(String, String)> {
+    let device_rules: Vec<&Rule> = rules.iter().filter(|rule| rule.device.is_some()).collect();
+    if device_rules.is_empty() {
+        return Default::default();
+    }
+
+    let devices = list_hid_devices();
+    device_rules
+        .into_iter()
+        .filter(|rule| devices.iter().any(|device| rule.matches_device(device)))
+        .map(|rule| {
+            (
+                rule.profile.clone(),
+                rule.device.clone().unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+/// Launches a profile in a separate affinity-rs process, as the daemon keeps
+/// running. Does nothing while the profile's program already runs.
+fn launch_for_device(profiles: &Profiles, keyword: &str, device: &str) -> Result<()> {
+    let profile = inherited_profile(profiles, keyword)?;
+    if !profile.is_enabled() {
+        return Ok(());
+    }
+    if list_processes()?
+        .iter()
+        .any(|process| process.exe.as_deref() == Some(profile.path.as_path()))
+    {
+        println!(
+            "Device '{}' connected; '{}' is already running",
+            device, keyword
+        );
+        return Ok(());
+    }
+
+    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
+    let mut cmd = Command::new(current_exe);
+    if let Some(path) = PROFILE_FILE.get() {
+        cmd.arg("--profile-file").arg(path);
+    }
+    let mut child = cmd
+        .args(["--non-interactive", "launch", keyword])
+        .stdin(std::process::Stdio::null())
+        .spawn()
+        .context("Failed to start affinity-rs")?;
+    println!("Device '{}' connected: launching '{}'", device, keyword);
+
+    // Reaped in the background, as the launch may stay attached
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn check_rules(profiles: &Profiles, rules: &[Rule]) -> Result<()> {
+    if rules.is_empty() {
+        println!(
+            "No rules defined. Add rules to {}",
+            get_config_dir()?.join(RULES_FILE_NAME).display()
+        );
+    }
+
+    for rule in rules {
+        match profiles.get(&rule.profile) {
+            None => eprintln!("Warning: Rule refers to unknown profile '{}'", rule.profile),
+            Some(profile) if !profile.is_enabled() => eprintln!(
+                "Note: Skipping the rule for '{}' while the profile is disabled",
+                rule.profile
+            ),
+            Some(_) => {}
+        }
+
+        // The daemon skips these quietly on every check
+        #[cfg(target_os = "windows")]
+        if let Some(ref service) = rule.service
+            && service_status(service).is_ok_and(|status| is_shared_service(&status))
+        {
+            eprintln!(
+                "Warning: Service '{}' shares its process with other services and is skipped. \
+                 Run 'sc config {} type= own' and restart it to manage it.",
+                service, service
+            );
+        }
+    }
+    Ok(())
+}
+
+fn run_daemon(interval: Duration) -> Result<()> {
+    let mut profiles = load_profiles()?;
+    let mut rules = load_rules()?;
+    check_rules(&profiles, &rules)?;
+
+    // Edits to either file take effect on the next check, keeping managed processes
+    let mut watched = vec![get_profile_path()?, get_config_dir()?.join(RULES_FILE_NAME)];
+    watched.extend(system_profile_path());
+    watched.extend(profiles_dir_files());
+    let mut watcher = ConfigWatcher::new(watched);
+
+    // State is saved after every change, so a crash loses nothing
+    let mut state = load_daemon_state()?;
+    resume_daemon_state(&mut state);
+    save_daemon_state(&state)?;
+
+    println!(
+        "Daemon running with {} rule(s), checking every {}s. Press Ctrl+C to stop.",
+        rules.len(),
+        interval.as_secs()
+    );
+
+    let mut last_checked = HashMap::new();
+    let mut resume = ResumeDetector::new();
+    // Devices connected before the daemon started don't launch anything
+    let mut connected = connected_device_rules(&rules);
+
+    loop {
+        if resume.resumed() {
+            println!("System resumed from sleep: checking managed processes");
+            daemon_resume(&mut state);
+            save_daemon_state(&state)?;
+        }
+
+        if watcher.changed() {
+            match load_profiles().and_then(|new_profiles| Ok((new_profiles, load_rules()?))) {
+                Ok((new_profiles, new_rules)) => {
+                    profiles = new_profiles;
+                    rules = new_rules;
+                    println!(
+                        "Reloaded {} profile(s) and {} rule(s)",
+                        profiles.len(),
+                        rules.len()
+                    );
+                    check_rules(&profiles, &rules)?;
+                }
+                Err(e) => eprintln!("Warning: Keeping the previous configuration: {:#}", e),
+            }
+        }
+
+        let now_connected = connected_device_rules(&rules);
+        for (keyword, device) in now_connected.difference(&connected) {
+            if let Err(e) = launch_for_device(&profiles, keyword, device) {
+                eprintln!("Warning: Could not launch '{}': {:#}", keyword, e);
+            }
+        }
+        connected = now_connected;
+
+        match daemon_tick(&profiles, &rules, &mut state, interval) {
+            Ok(true) => save_daemon_state(&state)?,
+            Ok(false) => {}
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+
+        if let Err(e) = daemon_reapply(&profiles, &state, &mut last_checked) {
+            eprintln!("Warning: {:#}", e);
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+struct TopRow {
+    process: ProcessInfo,
+    cpu_percent: f64,
+    affinity: Option<Vec<usize>>,
+    priority: Option<ProcessPriority>,
+}
+
+/// Samples every process and returns them sorted by CPU usage since `previous`.
+fn sample_top(
+    previous: &HashMap<u32, Duration>,
+    elapsed: Duration,
+    limit: usize,
+) -> Result<(Vec<TopRow>, HashMap<u32, Duration>)> {
+    let processes = list_processes()?;
+    let cpu_count = num_cpus::get() as f64;
+
+    let current: HashMap<u32, Duration> = processes.iter().map(|p| (p.pid, p.cpu_time)).collect();
+
+    let mut rows: Vec<TopRow> = processes
+        .into_iter()
+        .map(|process| {
+            let used = previous
+                .get(&process.pid)
+                .map(|&before| process.cpu_time.saturating_sub(before))
+                .unwrap_or_default();
+            let cpu_percent =
+                used.as_secs_f64() / elapsed.as_secs_f64().max(0.001) / cpu_count * 100.0;
+
+            TopRow {
+                process,
+                cpu_percent,
+                affinity: None,
+                priority: None,
+            }
+        })
+        .collect();
+
+    rows.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent));
+    rows.truncate(limit);
+
+    // Only query the rows actually shown
+    for row in &mut rows {
+        row.affinity = get_process_affinity(row.process.pid).ok();
+        row.priority = get_process_priority(row.process.pid).ok();
+    }
+
+    Ok((rows, current))
+}
+
+fn print_top(rows: &[TopRow], message: &str) {
+    // Clear the screen and move the cursor home
+    print!("\x1B[2J\x1B[H");
+    println!("affinity-rs top - processes by CPU usage\n");
+    println!(
+        "{:>7}  {:>6}  {:<13} {:<18} Name",
+        "PID", "CPU%", "Priority", "Affinity"
+    );
+
+    let all_cpus: Vec<usize> = (0..num_cpus::get()).collect();
+
+    for row in rows {
+        let affinity = match row.affinity {
+            Some(ref cpus) if *cpus == all_cpus => "all".to_string(),
+            Some(ref cpus) => cpu_mask_string(cpus),
+            None => "-".to_string(),
+        };
+        let priority = row
+            .priority
+            .as_ref()
+            .map(|p| p.display_name())
+            .unwrap_or("-");
+
+        println!(
+            "{:>7}  {:>6.1}  {:<13} {:<18} {}",
+            row.process.pid, row.cpu_percent, priority, affinity, row.process.name
+        );
+    }
+
+    println!();
+    println!("Commands: a <pid> <cpus>   set affinity (e.g. a 4242 0-3,6)");
+    println!("          p <pid> <level>  set priority (1-6 or name, e.g. p 4242 high)");
+    println!("          s <pid> <name>   save process settings as a profile");
+    println!("          Enter            refresh now (the table also refreshes every 2s)");
+    println!("          q                quit");
+
+    if !message.is_empty() {
+        println!("\n{}", message);
+    }
+}
+
+fn run_top(profiles: &mut Profiles) -> Result<()> {
+    const ROWS: usize = 20;
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+    // Prime the CPU counters so the first screen already shows usage
+    let (_, mut previous) = sample_top(&HashMap::new(), Duration::from_secs(1), 0)?;
+    let mut sampled_at = Instant::now();
+    thread::sleep(Duration::from_millis(500));
+
+    // Commands are read on their own thread so the table keeps refreshing
+    // while nothing is typed
+    let (input_tx, input_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            if input_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut message = String::new();
+
+    loop {
+        let (rows, current) = sample_top(&previous, sampled_at.elapsed(), ROWS)?;
+        previous = current;
+        sampled_at = Instant::now();
+
+        print_top(&rows, &message);
+        print!("\n> ");
+        io::stdout().flush().context("Failed to flush stdout")?;
+
+        let input = match input_rx.recv_timeout(REFRESH_INTERVAL) {
+            Ok(line) => line.context("Failed to read input")?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            // stdin was closed
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        message.clear();
+
+        let mut parts = input.split_whitespace();
+        let command = parts.next().unwrap_or("");
+
+        if command.eq_ignore_ascii_case("q") {
+            return Ok(());
+        }
+        if command.is_empty() {
+            continue;
+        }
+
+        // Processes are picked by PID, as rows move around between refreshes
+        let Some(pid) = parts.next().and_then(|pid| pid.parse::<u32>().ok()) else {
+            message = "Error: expected a PID from the table.".to_string();
+            continue;
+        };
+        let argument = parts.collect::<Vec<_>>().join(" ");
+
+        let result = match command.to_lowercase().as_str() {
+            "a" => parse_cpu_list(&argument).and_then(|cpus| {
+                apply_to_pid(pid, Some(&cpus), None, "top")
+                    .map(|_| format!("PID {} affinity set to {:?}", pid, cpus))
+            }),
+            "p" => match ProcessPriority::parse(&argument) {
+                Some(priority) => apply_to_pid(pid, None, Some(&priority), "top")
+                    .map(|_| format!("PID {} priority set to {}", pid, priority.display_name())),
+                None => Err(anyhow::anyhow!("Unknown priority '{}'", argument)),
+            },
+            "s" => match rows.iter().find(|row| row.process.pid == pid) {
+                Some(row) => save_process_as_profile(profiles, &row.process, &argument),
+                None => Err(anyhow::anyhow!("PID {} is not in the table", pid)),
+            },
+            _ => Err(anyhow::anyhow!("Unknown command '{}'", command)),
+        };
+
+        message = match result {
+            Ok(done) => done,
+            Err(e) => format!("Error: {:#}", e),
+        };
+    }
+}
+
+/// Profile values given as command-line flags (`--path`, `--cpus`,
+/// `--priority`, `--retries`) instead of being asked for.
+#[derive(Debug, Default)]
+struct ProfileFlags {
+    path: Option<PathBuf>,
+    cpus: Option<Vec<usize>>,
+    priority: Option<ProcessPriority>,
+    retries: Option<usize>,
+}
+
+impl ProfileFlags {
+    const NAMES: [&str; 4] = ["--path", "--cpus", "--priority", "--retries"];
+
+    /// Takes `flag` and its value from `rest` if it is one of these flags.
+    fn parse<'a>(
+        &mut self,
+        flag: &str,
+        rest: &mut impl Iterator<Item = &'a String>,
+    ) -> Result<bool> {
+        if !Self::NAMES.contains(&flag) {
+            return Ok(false);
+        }
+        let value = rest
+            .next()
+            .with_context(|| format!("{} requires a value", flag))?;
+
+        match flag {
+            "--path" => {
+                let path = std::path::absolute(value.trim_matches('"'))
+                    .with_context(|| format!("Invalid path '{}'", value))?;
+                if !path.exists() {
+                    bail!("Path does not exist: {}", path.display());
+                }
+                self.path = Some(path);
+            }
+            "--cpus" => self.cpus = Some(parse_cpu_list(value)?),
+            "--priority" => {
+                let priority = ProcessPriority::parse(value).with_context(|| {
+                    format!(
+                        "Unknown priority '{}'. Use idle, below_normal, normal, above_normal, high or realtime",
+                        value
+                    )
+                })?;
+                self.priority = Some(priority);
+            }
+            _ => {
+                let retries = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&retries| retries > 0)
+                    .with_context(|| {
+                        format!("--retries needs a positive number, not '{}'", value)
+                    })?;
+                self.retries = Some(retries);
+            }
+        }
+        Ok(true)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.path.is_none()
+            && self.cpus.is_none()
+            && self.priority.is_none()
+            && self.retries.is_none()
+    }
+
+    /// Sets the given values on `profile`, leaving the rest as it is.
+    fn apply_to(&self, profile: &mut Profile) {
+        if let Some(ref path) = self.path {
+            profile.path = path.clone();
+        }
+        if let Some(ref cpus) = self.cpus {
+            profile.cpus = cpus.clone();
+        }
+        if let Some(ref priority) = self.priority {
+            profile.priority = Some(priority.clone());
+        }
+        if let Some(retries) = self.retries {
+            profile.retry_attempts = Some(retries);
+        }
+    }
+}
+
+/// Creates a profile from flags alone, for scripts: nothing is asked, and
+/// anything missing or invalid is an error.
+fn add_profile_from_flags(
+    profiles: &mut Profiles,
+    name: Option<&str>,
+    template: Option<Template>,
+    flags: &ProfileFlags,
+) -> Result<Profile> {
+    let Some(name) = name else {
+        bail!("A profile name is required, e.g. 'affinity-rs add my_game --path <exe> --cpus 0-3'");
+    };
+    if profiles.contains_key(name) {
+        bail!("Profile '{}' already exists", name);
+    }
+    let Some(ref path) = flags.path else {
+        bail!("--path is required when creating a profile from flags");
+    };
+
+    let mut profile = match template {
+        Some(template) => template.profile(path.clone()),
+        None => Profile::default(),
+    };
+    flags.apply_to(&mut profile);
+
+    if profile.cpus.is_empty() {
+        bail!("--cpus is required unless a --template picks the cores");
+    }
+    validate_profile(&profile)?;
+
+    Ok(profile)
+}
+
+/// Saves the settings of `run --save-temp` as a temporary profile expiring
+/// after `days`. An earlier temporary profile of that name is replaced.
+fn save_temp_profile(
+    profiles: &mut Profiles,
+    name: &str,
+    profile: &Profile,
+    days: u64,
+) -> Result<()> {
+    if name.is_empty() || name.starts_with(TEMP_PROFILE_PREFIX) || name.starts_with('-') {
+        bail!("'{}' can't be used as a profile name", name);
+    }
+    if profiles
+        .get(name)
+        .is_some_and(|existing| !existing.is_temporary())
+    {
+        bail!(
+            "Profile '{}' already exists and isn't temporary; pick another name",
+            name
+        );
+    }
+    check_profile_settings(profile)?;
+
+    let expires = days
+        .checked_mul(24 * 60 * 60)
+        .and_then(|secs| unix_now().checked_add(secs))
+        .context("The expiry date is too far in the future")?;
+    profiles.insert(
+        name.to_string(),
+        Profile {
+            expires: Some(expires),
+            ..profile.clone()
+        },
+    );
+    save_profiles(profiles)?;
+
+    println!(
+        "Saved temporary profile '{}' ({}). 'list --all' shows it, 'edit {} --keep' keeps it.",
+        name,
+        describe_expiry(expires),
+        name
+    );
+    Ok(())
+}
+
+/// Makes a temporary profile permanent.
+fn keep_profile(profiles: &mut Profiles, keyword: &str) -> Result<()> {
+    let Some(profile) = profiles.get_mut(keyword) else {
+        bail!("Profile '{}' not found", keyword);
+    };
+    if !profile.is_temporary() {
+        bail!("Profile '{}' isn't temporary", keyword);
+    }
+
+    profile.expires = None;
+    save_profiles(profiles)?;
+    println!("Profile '{}' is kept for good.", keyword);
+    Ok(())
+}
+
+/// Changes only the fields given as flags in an existing profile.
+fn edit_profile(
+    profiles: &mut Profiles,
+    keyword: &str,
+    flags: &ProfileFlags,
+    unlock: bool,
+) -> Result<()> {
+    let Some(original) = profiles.get(keyword).cloned() else {
+        bail!("Profile '{}' not found", keyword);
+    };
+    if flags.is_empty() {
+        bail!("Nothing to change. Give at least one of --path, --cpus, --priority or --retries");
+    }
+    ensure_unlocked(&original, keyword, unlock)?;
+
+    let mut profile = original.clone();
+    flags.apply_to(&mut profile);
+
+    // The recorded checksum belongs to the old executable
+    let path_changed = profile.path != original.path;
+    if path_changed {
+        profile.checksum = None;
+    }
+
+    profiles.insert(keyword.to_string(), profile);
+    if let Err(e) = inherited_profile(profiles, keyword).and_then(|p| validate_profile(&p)) {
+        profiles.insert(keyword.to_string(), original);
+        return Err(e);
+    }
+    save_profiles(profiles)?;
+
+    let profile = &profiles[keyword];
+    println!("Profile '{}' updated:", keyword);
+    if path_changed {
+        println!(
+            "  Path: {} -> {}",
+            original.path.display(),
+            profile.path.display()
+        );
+        if original.checksum.is_some() {
+            println!(
+                "  The recorded checksum was cleared; launch with --rehash to record a new one."
+            );
+        }
+    }
+    if profile.cpus != original.cpus {
+        println!("  CPUs: {:?} -> {:?}", original.cpus, profile.cpus);
+    }
+    if profile.priority != original.priority {
+        let name = |priority: &Option<ProcessPriority>| {
+            priority
+                .as_ref()
+                .map_or("unset", ProcessPriority::display_name)
+                .to_string()
+        };
+        println!(
+            "  Priority: {} -> {}",
+            name(&original.priority),
+            name(&profile.priority)
+        );
+    }
+    if profile.retry_attempts != original.retry_attempts {
+        println!(
+            "  Retry attempts: {} -> {}",
+            original.retry_attempts.unwrap_or(5),
+            profile.retry_attempts.unwrap_or(5)
+        );
+    }
+    Ok(())
+}
+
+/// Opens a file in $VISUAL or $EDITOR, falling back to Notepad on Windows and
+/// vi elsewhere, and waits for the editor to close.
+fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| {
+            if cfg!(target_os = "windows") {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+
+    // Editors such as "code --wait" come with arguments of their own
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or_default();
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to start the editor '{}'", editor))?;
+
+    if !status.success() {
+        bail!("The editor exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Lets the user edit `text` as `file_name` until `validate` accepts it.
+/// Returns the new text, or None if nothing changed or the user gave up, in
+/// which case nothing should be saved.
+fn edit_until_valid(
+    file_name: &str,
+    text: &str,
+    validate: impl Fn(&str) -> Result<()>,
+) -> Result<Option<String>> {
+    use std::io::IsTerminal;
+
+    // The config directory belongs to the user, unlike the shared temp directory
+    let path = get_config_dir()?.join(format!("edit-{}-{}", std::process::id(), file_name));
+    std::fs::write(&path, text).context("Failed to write the file to edit")?;
+
+    let result = loop {
+        if let Err(e) = open_in_editor(&path) {
+            break Err(e);
+        }
+        let edited = match std::fs::read_to_string(&path) {
+            Ok(edited) => edited,
+            Err(e) => break Err(anyhow::Error::from(e).context("Failed to read the edited file")),
+        };
+        if edited == text {
+            println!("No changes.");
+            break Ok(None);
+        }
+
+        match validate(&edited) {
+            Ok(_) => break Ok(Some(edited)),
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                // Without a terminal there is nobody to ask
+                let again = io::stdin().is_terminal()
+                    && read_line("Edit again? Otherwise the changes are discarded [Y/n]: ")
+                        .is_ok_and(|answer| !answer.eq_ignore_ascii_case("n"));
+                if !again {
+                    println!("Changes discarded.");
+                    break Ok(None);
+                }
+            }
+        }
+    };
+
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+/// A user profile as stored: complete, or only the overridden fields of a
+/// shared profile. Unknown options are errors.
+fn parse_stored_profile(
+    name: &str,
+    value: serde_json::Value,
+    shared: Option<&Profile>,
+) -> Result<Profile> {
+    let Some(shared) = shared else {
+        return parse_profile_strict(name, value);
+    };
+
+    let serde_json::Value::Object(overrides) = value else {
+        bail!("Profile '{}' is not an object", name);
+    };
+    let mut fields = match serde_json::to_value(shared)? {
+        serde_json::Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    };
+    for (key, value) in overrides {
+        if value.is_null() {
+            fields.remove(&key);
+        } else {
+            fields.insert(key, value);
+        }
+    }
+    parse_profile_strict(name, serde_json::Value::Object(fields))
+}
+
+/// Opens profiles.json, rules.json or settings.json in the editor and only
+/// writes it back once it parses.
+fn edit_config_file(profiles: &Profiles, which: &str, unlock: bool) -> Result<()> {
+    let path = match which {
+        "profiles" => get_profile_path()?,
+        "rules" => get_config_dir()?.join(RULES_FILE_NAME),
+        "settings" => get_config_dir()?.join(SETTINGS_FILE_NAME),
+        _ => bail!("Unknown file '{}'. Use profiles, rules or settings", which),
+    };
+    let text = if path.exists() {
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+    } else if which == "rules" {
+        "[]\n".to_string()
+    } else {
+        "{}\n".to_string()
+    };
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let edited = match which {
+        "profiles" => edit_until_valid(&file_name, &text, |edited| {
+            let user: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(edited).context("Not valid profiles JSON")?;
+            let shared = load_shared_profiles(false);
+            let mut edited_profiles: Profiles = shared
+                .iter()
+                .map(|(name, shared)| (name.clone(), shared.profile.clone()))
+                .collect();
+            for (name, value) in user {
+                let base = shared.get(&name).map(|shared| &shared.profile);
+                let profile = parse_stored_profile(&name, value, base)?;
+                edited_profiles.insert(name, profile);
+            }
+
+            for name in edited_profiles.keys() {
+                inherited_profile(&edited_profiles, name)?;
+            }
+            for (name, profile) in profiles {
+                let unchanged = edited_profiles.get(name).is_some_and(|edited| {
+                    serde_json::to_value(edited).ok() == serde_json::to_value(profile).ok()
+                });
+                if !unchanged {
+                    ensure_unlocked(profile, name, unlock)?;
+                }
+            }
+            Ok(())
+        })?,
+        "rules" => edit_until_valid(&file_name, &text, |edited| {
+            let rules: Vec<Rule> = serde_json::from_str(edited).context("Not valid rules JSON")?;
+            if let Some(rule) = rules
+                .iter()
+                .find(|rule| !profiles.contains_key(&rule.profile))
+            {
+                bail!("A rule refers to unknown profile '{}'", rule.profile);
+            }
+            Ok(())
+        })?,
+        _ => edit_until_valid(&file_name, &text, |edited| {
+            serde_json::from_str::<Settings>(edited).context("Not valid settings JSON")?;
+            Ok(())
+        })?,
+    };
+
+    let Some(edited) = edited else {
+        return Ok(());
+    };
+    std::fs::write(&path, &edited)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    if which == "profiles"
+        && settings().integrity_check
+        && !INTEGRITY_FAILED.load(std::sync::atomic::Ordering::Relaxed)
+    {
+        sign_profiles(&path, edited.as_bytes())?;
+    }
+    println!("Saved {}", path.display());
+    if which == "settings" {
+        println!("The new settings apply from the next run of affinity-rs.");
+    }
+    Ok(())
+}
+
+/// Opens one profile's JSON in the editor and saves it once it is valid.
+fn edit_profile_raw(profiles: &mut Profiles, keyword: &str, unlock: bool) -> Result<()> {
+    let Some(original) = profiles.get(keyword).cloned() else {
+        bail!("Profile '{}' not found", keyword);
+    };
+    ensure_unlocked(&original, keyword, unlock)?;
+
+    // Shared profiles are edited as the user's overrides, as they are stored
+    let shared = load_shared_profiles(false)
+        .remove(keyword)
+        .map(|shared| shared.profile);
+    let value = match shared {
+        Some(ref shared) => serde_json::Value::Object(profile_overrides(&original, shared)),
+        None => serde_json::to_value(&original)?,
+    };
+    let text = serde_json::to_string_pretty(&value)? + "\n";
+
+    let edited = edit_until_valid(&format!("{}.json", keyword), &text, |edited| {
+        let value: serde_json::Value =
+            serde_json::from_str(edited).context("Not valid profile JSON")?;
+        let profile = parse_stored_profile(keyword, value, shared.as_ref())?;
+        let mut check = profiles.clone();
+        check.insert(keyword.to_string(), profile);
+        validate_profile(&inherited_profile(&check, keyword)?)
+    })?;
+    let Some(edited) = edited else {
+        return Ok(());
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&edited)?;
+    let mut profile = parse_stored_profile(keyword, value, shared.as_ref())?;
+    if profile.path != original.path && profile.checksum == original.checksum {
+        profile.checksum = None;
+    }
+    profiles.insert(keyword.to_string(), profile);
+    save_profiles(profiles)?;
+    println!("Profile '{}' saved.", keyword);
+    Ok(())
+}
+
+/// Saves a copy of a profile under a new name, with the given flags changed.
+fn copy_profile(
+    profiles: &mut Profiles,
+    base: &str,
+    new: &str,
+    flags: &ProfileFlags,
+) -> Result<()> {
+    let Some(mut profile) = profiles.get(base).cloned() else {
+        bail!("Profile '{}' not found", base);
+    };
+    if new.is_empty() || new.starts_with(TEMP_PROFILE_PREFIX) || new.starts_with('-') {
+        bail!("'{}' can't be used as a profile name", new);
+    }
+    if profiles.contains_key(new) {
+        bail!("Profile '{}' already exists", new);
+    }
+
+    let original_path = profile.path.clone();
+    flags.apply_to(&mut profile);
+    if profile.path != original_path {
+        profile.checksum = None;
+    }
+    // The scheduled task is registered under the original name only
+    let had_task = std::mem::take(&mut profile.elevated_task);
+    profile.locked = false;
+
+    profiles.insert(new.to_string(), profile);
+    if let Err(e) = inherited_profile(profiles, new).and_then(|p| validate_profile(&p)) {
+        profiles.remove(new);
+        return Err(e);
+    }
+    save_profiles(profiles)?;
+
+    println!("Profile '{}' copied to '{}'.", base, new);
+    let profile = &profiles[new];
+    println!("  Path: {}", profile.path.display());
+    if profile.cpus.is_empty() {
+        println!("  CPUs: unchanged");
+    } else {
+        println!("  CPUs: {:?}", profile.cpus);
+    }
+    if let Some(ref priority) = profile.priority {
+        println!("  Priority: {}", priority.display_name());
+    }
+    if had_task {
+        println!(
+            "Note: The copy has no elevated scheduled task; create a shortcut for it to add one."
+        );
+    }
+    Ok(())
+}
+
+/// Creates a profile step by step, starting from a template if one is chosen.
+fn add_profile(
+    profiles: &mut Profiles,
+    name: Option<&str>,
+    template: Option<Template>,
+    flags: &ProfileFlags,
+) -> Result<()> {
+    if !flags.is_empty() {
+        let profile = add_profile_from_flags(profiles, name, template, flags)?;
+        let name = name.unwrap_or_default().to_string();
+        profiles.insert(name.clone(), profile);
+        save_profiles(profiles)?;
+        println!("Profile '{}' saved.", name);
+        return Ok(());
+    }
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => read_line("Enter a name for this profile: ")?,
+    };
+    if name.is_empty() {
+        bail!("Profile name cannot be empty");
+    }
+    if profiles.contains_key(&name) {
+        bail!("Profile '{}' already exists", name);
+    }
+
+    let path = read_line("Enter executable path: ")?;
+    let path = PathBuf::from(path.trim_matches('"'));
+    if !path.exists() {
+        bail!("Path does not exist: {}", path.display());
+    }
+
+    let template = match template {
+        Some(template) => Some(template),
+        None => get_template_input()?,
+    };
+
+    let profile = match template {
+        Some(template) => {
+            let mut profile = template.profile(path);
+
+            println!("\nTemplate '{}':", template.name());
+            println!(
+                "  Priority: {}",
+                profile
+                    .priority
+                    .as_ref()
+                    .map_or("Normal", |p| p.display_name())
+            );
+            println!("  Retries:  {}", profile.retry_attempts.unwrap_or(5));
+
+            let cores = read_line(&format!(
+                "CPU cores {:?} (press Enter to accept, or type a list such as 0-3,6): ",
+                profile.cpus
+            ))?;
+            if !cores.trim().is_empty() {
+                profile.cpus = parse_cpu_list(&cores)?;
+            }
+            profile
+        }
+        None => Profile {
+            path,
+            cpus: get_cpu_input()?,
+            priority: get_priority_input()?,
+            ..Default::default()
+        },
+    };
+
+    profiles.insert(name.clone(), profile);
+    save_profiles(profiles)?;
+
+    println!(
+        "\nProfile '{}' saved. Launch it with 'affinity-rs {}'.",
+        name, name
+    );
+
+    if let Some(names) = duplicate_profiles(profiles)
+        .into_iter()
+        .find(|names| names.contains(&name))
+    {
+        println!(
+            "Note: profiles {} launch the same executable; 'affinity-rs merge' can combine them.",
+            names.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn save_process_as_profile(
+    profiles: &mut Profiles,
+    process: &ProcessInfo,
+    name: &str,
+) -> Result<String> {
+    if name.is_empty() {
+        bail!("A profile name is required");
+    }
+    if profiles.contains_key(name) {
+        bail!("Profile '{}' already exists", name);
+    }
+
+    let path = process
+        .exe
+        .clone()
+        .context("Executable path of this process is not accessible")?;
+    let cpus = get_process_affinity(process.pid)?;
+    let priority = get_process_priority(process.pid).ok();
+
+    profiles.insert(
+        name.to_string(),
+        Profile {
+            path,
+            cpus,
+            priority,
+            ..Default::default()
+        },
+    );
+    save_profiles(profiles)?;
+
+    Ok(format!("Profile '{}' saved", name))
+}
+
+fn print_attach(
+    process: &ProcessInfo,
+    cpus: &[usize],
+    priority: Option<&ProcessPriority>,
+    message: &str,
+) {
+    // Clear the screen and move the cursor home
+    print!("\x1B[2J\x1B[H");
+    println!(
+        "affinity-rs attach - PID {} ({})\n",
+        process.pid, process.name
+    );
+
+    if let Some(ref exe) = process.exe {
+        println!("Executable: {}", exe.display());
+    }
+    println!(
+        "Priority:   {}",
+        priority.map_or("-", |priority| priority.display_name())
+    );
+
+    println!("Cores:      (* = allowed)");
+    let cores: Vec<String> = (0..num_cpus::get())
+        .map(|cpu| {
+            let mark = if cpus.contains(&cpu) { '*' } else { ' ' };
+            format!("[{:>2}{}]", cpu, mark)
+        })
+        .collect();
+    for line in cores.chunks(8) {
+        println!("  {}", line.join(" "));
+    }
+
+    println!();
+    println!("Commands: + / -        raise / lower priority");
+    println!("          <cpus>       toggle cores (e.g. 3 or 4-7)");
+    println!("          all          allow all cores");
+    println!("          s <name>     save the current settings as a profile");
+    println!("          Enter        refresh");
+    println!("          q            quit");
+
+    if !message.is_empty() {
+        println!("\n{}", message);
+    }
+}
+
+/// One thread of a process as shown by `inspect`.
+struct ThreadInfo {
+    tid: u32,
+    /// Thread name on Linux, thread description on Windows
+    description: String,
+    cpus: Vec<usize>,
+    priority: String,
+    /// Ideal processor on Windows; Linux has none, so the CPU it last ran on
+    processor: Option<usize>,
+}
+
+#[cfg(target_os = "linux")]
+const THREAD_PROCESSOR_LABEL: &str = "Last CPU";
+#[cfg(target_os = "windows")]
+const THREAD_PROCESSOR_LABEL: &str = "Ideal CPU";
+
+#[cfg(target_os = "linux")]
+fn list_threads(pid: u32) -> Result<Vec<ThreadInfo>> {
+    if !Path::new(&format!("/proc/{}", pid)).exists() {
+        bail!("No process with PID {}", pid);
+    }
+
+    let mut threads: Vec<ThreadInfo> = process_threads(pid)
+        .into_iter()
+        .filter_map(|tid| {
+            let (name, fields) = read_proc_stat_path(&format!("/proc/{}/task/{}/stat", pid, tid))?;
+            Some(ThreadInfo {
+                tid,
+                description: name,
+                cpus: get_process_affinity(tid).unwrap_or_default(),
+                // nice is field 19, the last CPU field 39
+                priority: format!("nice {}", fields.get(16)?),
+                processor: fields.get(36).and_then(|f| f.parse().ok()),
+            })
+        })
+        .collect();
+
+    threads.sort_by_key(|thread| thread.tid);
+    Ok(threads)
+}
+
+#[cfg(target_os = "windows")]
+fn list_threads(pid: u32) -> Result<Vec<ThreadInfo>> {
+    use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE, LocalFree};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, TH32CS_SNAPTHREAD, THREADENTRY32, Thread32First, Thread32Next,
+    };
+    use windows_sys::Win32::System::Kernel::PROCESSOR_NUMBER;
+    use windows_sys::Win32::System::SystemInformation::GROUP_AFFINITY;
+    use windows_sys::Win32::System::Threading::{
+        GetThreadDescription, GetThreadGroupAffinity, GetThreadIdealProcessorEx, GetThreadPriority,
+        OpenThread, THREAD_QUERY_INFORMATION,
+    };
+
+    const THREAD_PRIORITY_ERROR_RETURN: i32 = 0x7fffffff;
+
+    let process_cpus = get_process_affinity(pid)?;
+
+    let mut tids = Vec::new();
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            bail!(
+                "Failed to enumerate threads: {}",
+                io::Error::last_os_error()
+            );
+        }
+
+        let mut entry: THREADENTRY32 = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+
+        let mut more = Thread32First(snapshot, &mut entry) != 0;
+        while more {
+            if entry.th32OwnerProcessID == pid {
+                tids.push(entry.th32ThreadID);
+            }
+            more = Thread32Next(snapshot, &mut entry) != 0;
+        }
+
+        CloseHandle(snapshot);
+    }
+
+    let mut threads = Vec::new();
+    for tid in tids {
+        let mut thread = ThreadInfo {
+            tid,
+            description: String::new(),
+            cpus: process_cpus.clone(),
+            priority: "-".to_string(),
+            processor: None,
+        };
+
+        unsafe {
+            // Only queried, so inspecting never changes the threads
+            let handle = OpenThread(THREAD_QUERY_INFORMATION, 0, tid);
+            if handle.is_null() {
+                threads.push(thread);
+                continue;
+            }
+
+            let mut description: *mut u16 = std::ptr::null_mut();
+            if GetThreadDescription(handle, &mut description) >= 0 && !description.is_null() {
+                let len = (0..).take_while(|&i| *description.add(i) != 0).count();
+                thread.description =
+                    String::from_utf16_lossy(std::slice::from_raw_parts(description, len));
+                LocalFree(description as _);
+            }
+
+            thread.priority = match GetThreadPriority(handle) {
+                THREAD_PRIORITY_ERROR_RETURN => "-".to_string(),
+                -15 => "idle".to_string(),
+                -2 => "lowest".to_string(),
+                -1 => "below normal".to_string(),
+                0 => "normal".to_string(),
+                1 => "above normal".to_string(),
+                2 => "highest".to_string(),
+                15 => "time critical".to_string(),
+                other => other.to_string(),
+            };
+
+            let mut ideal: PROCESSOR_NUMBER = std::mem::zeroed();
+            if GetThreadIdealProcessorEx(handle, &mut ideal) != 0 {
+                thread.processor = Some(ideal.Number as usize);
+            }
+
+            let mut affinity: GROUP_AFFINITY = std::mem::zeroed();
+            if GetThreadGroupAffinity(handle, &mut affinity) != 0 {
+                thread.cpus = (0..usize::BITS as usize)
+                    .filter(|&cpu| affinity.Mask & (1 << cpu) != 0)
+                    .collect();
+            }
+
+            CloseHandle(handle);
+        }
+
+        threads.push(thread);
+    }
+
+    Ok(threads)
+}
+
+/// Lists a process's threads with their affinity, priority and preferred
+/// CPU, as a starting point for per-thread tuning.
+fn inspect_process(pid: u32) -> Result<()> {
+    let threads = list_threads(pid)?;
+    let process = list_processes()?.into_iter().find(|p| p.pid == pid);
+
+    match process {
+        Some(ref process) => println!("Threads of {} (PID {}):\n", process.name, pid),
+        None => println!("Threads of PID {}:\n", pid),
+    }
+
+    println!(
+        "{:>8}  {:<24} {:<20} {:<14} {}",
+        "TID", "Name", "Affinity", "Priority", THREAD_PROCESSOR_LABEL
+    );
+    for thread in &threads {
+        let description = if thread.description.is_empty() {
+            "-".to_string()
+        } else {
+            thread.description.chars().take(24).collect()
+        };
+        println!(
+            "{:>8}  {:<24} {:<20} {:<14} {}",
+            thread.tid,
+            description,
+            cpu_mask_string(&thread.cpus),
+            thread.priority,
+            thread
+                .processor
+                .map_or("-".to_string(), |cpu| cpu.to_string())
+        );
+    }
+
+    println!("\n{} thread(s)", threads.len());
+    Ok(())
+}
+
+/// Interactive view of a single running process for adjusting its priority
+/// and cores, with the result savable as a profile.
+fn run_attach(profiles: &mut Profiles, pid: u32) -> Result<()> {
+    let mut message = String::new();
+
+    loop {
+        let process = list_processes()?
+            .into_iter()
+            .find(|p| p.pid == pid)
+            .with_context(|| format!("No running process with PID {}", pid))?;
+        let cpus = get_process_affinity(pid)?;
+        let priority = get_process_priority(pid).ok();
+
+        print_attach(&process, &cpus, priority.as_ref(), &message);
+        message.clear();
+
+        print!("\n> ");
+        io::stdout().flush().context("Failed to flush stdout")?;
+        let mut input = String::new();
+        // stdin was closed
+        if io::stdin()
+            .read_line(&mut input)
+            .context("Failed to read input")?
+            == 0
+        {
+            return Ok(());
+        }
+        let input = input.trim();
+        let (command, argument) = input.split_once(' ').unwrap_or((input, ""));
+
+        let result = match command.to_lowercase().as_str() {
+            "" => continue,
+            "q" => return Ok(()),
+            "+" | "-" => {
+                let current = priority.clone().unwrap_or(ProcessPriority::Normal);
+                let index = ProcessPriority::ALL
+                    .iter()
+                    .position(|p| *p == current)
+                    .unwrap_or(2);
+                let index = if command == "+" {
+                    (index + 1).min(ProcessPriority::ALL.len() - 1)
+                } else {
+                    index.saturating_sub(1)
+                };
+                let new_priority = &ProcessPriority::ALL[index];
+
+                apply_to_pid(pid, None, Some(new_priority), "attach")
+                    .map(|_| format!("Priority set to {}", new_priority.display_name()))
+            }
+            "all" => {
+                let all_cpus: Vec<usize> = (0..num_cpus::get()).collect();
+                apply_to_pid(pid, Some(&all_cpus), None, "attach")
+                    .map(|_| "All cores allowed".to_string())
+            }
+            "s" => save_process_as_profile(profiles, &process, argument.trim()),
+            _ => parse_cpu_list(command).and_then(|toggled| {
+                // Toggling only cores that are all allowed removes them, otherwise adds them
+                let mut new_cpus = cpus.clone();
+                if toggled.iter().all(|cpu| cpus.contains(cpu)) {
+                    new_cpus.retain(|cpu| !toggled.contains(cpu));
+                } else {
+                    new_cpus.extend(toggled.iter().filter(|cpu| !cpus.contains(cpu)));
+                    new_cpus.sort_unstable();
+                }
+
+                if new_cpus.is_empty() {
+                    bail!("At least one core must stay allowed");
+                }
+                apply_to_pid(pid, Some(&new_cpus), None, "attach")
+                    .map(|_| format!("Affinity set to {}", cpu_mask_string(&new_cpus)))
+            }),
+        };
+
+        message = match result {
+            Ok(done) => done,
+            Err(e) => format!("Error: {:#}", e),
+        };
+    }
+}
+
+fn show_help() {
+    println!();
+    println!("========== affinity-rs v3 ==========");
+    println!("CPU affinity and process priority launcher with profile support.\n");
+    println!("USAGE:");
+    println!("  affinity-rs <command>");
+    println!("  affinity-rs [launch_options] <profile_name> [program_args...]");
+    println!("  affinity-rs <command> --help\n");
+    println!("LAUNCH OPTIONS:");
+    println!("  --plain              Launch without affinity/priority changes (safe mode)");
+    println!("  --rehash             Record the executable's checksum and warn when it changes");
+    println!("  --pid-file <path>    Write the launched process ID to a file");
+    println!("  --json               Print the launched process as JSON");
+    println!("  --unlock             Allow changes to profiles marked as locked");
+    println!("  --timings            Report how long spawning and each retry step took");
+    println!("  --same-args          Reuse the previous launch's arguments when none are given");
+    println!("  --dry-run            Show the mask, priority, elevation and command line only");
+    println!("  --non-interactive    Never wait for input: use each question's default or fail");
+    println!("  --yes                Like --non-interactive, but confirm instead of declining");
+    println!("  --only <affinity|priority>");
+    println!("                       Apply only the cores or only the priority (launch and apply)");
+    println!("  --profile-file <path>");
+    println!("                       Use another profiles file instead of the saved profiles");
+    println!("  --pause <policy>     When to wait for Enter before closing: always, on-error,");
+    println!("                       never, or a number of seconds to show output first");
+    println!("  --no-pause           Never wait for Enter, same as --pause never\n");
+    println!("COMMANDS:");
+    for spec in COMMANDS {
+        // Short usages share a line with the description
+        let inline = spec.usage.len() == 1 && spec.usage[0].len() <= 20;
+        let mut about = spec.about.iter();
+        if inline {
+            println!("  {:<20} {}", spec.usage[0], about.next().unwrap_or(&""));
+        } else {
+            for usage in spec.usage {
+                println!("  {}", usage);
+            }
+        }
+        for line in about {
+            println!("{:23}{}", "", line);
+        }
+    }
+    println!();
+    println!("EXAMPLES:");
+    println!("  affinity-rs list");
+    println!("  affinity-rs my_game");
+    println!("  affinity-rs my_game --windowed");
+    println!("  affinity-rs --plain my_game");
+    println!("  affinity-rs launch --json my_game");
+    println!("  affinity-rs delete my_game");
+    println!("  affinity-rs shortcut my_game");
+    println!("  affinity-rs shortcut my_game --to D:\\Games\\Launchers");
+    println!("  affinity-rs shortcut --refresh-all\n");
+    println!("CREATING PROFILES:");
+    println!("  Run 'affinity-rs <new_name>' to create a new profile interactively.");
+    println!("  You'll be prompted for:");
+    println!("    - Executable path");
+    println!("    - CPU cores to use");
+    println!("    - Process priority level\n");
     println!("TIPS:");
     println!("  - Add affinity-rs to your PATH to use it from anywhere");
     println!("  - High/Realtime priorities require administrator privileges on Windows");
@@ -920,8 +12960,783 @@ fn show_help() {
     println!("  - Profiles are stored in your OS config directory\n");
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
+/// A subcommand, for the help screens and for rejecting options it doesn't know.
+struct CommandSpec {
+    name: &'static str,
+    /// Usage lines without the leading "affinity-rs "
+    usage: &'static [&'static str],
+    /// Description lines shown below the usage
+    about: &'static [&'static str],
+    options: &'static [&'static str],
+    /// Arguments after the command's own go to the launched program
+    passes_args: bool,
+}
+
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "launch",
+        usage: &["launch [launch_options] <profile> [program_args...]"],
+        about: &["Launch a saved profile; fails instead of creating a missing one"],
+        options: &[],
+        passes_args: true,
+    },
+    CommandSpec {
+        name: "list",
+        usage: &[
+            "list [--sort <name|path|priority>] [--filter <text>] [--missing-only] [--all] [--json|--names]",
+            "list --check [--fix] [--filter <text>]",
+        ],
+        about: &[
+            "List saved profiles, optionally sorted and narrowed down, or print",
+            "them as JSON for other tools; --all includes temporary profiles,",
+            "--check compares running processes with their profiles and --fix",
+            "applies the profiles again",
+        ],
+        options: &[
+            "--json",
+            "--names",
+            "--check",
+            "--fix",
+            "--sort",
+            "--filter",
+            "--missing-only",
+            "--all",
+        ],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "show",
+        usage: &["show <profile>"],
+        about: &["Show every setting of one profile, its shortcuts and its CPU mask"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "add",
+        usage: &[
+            "add [profile] [--template <gaming|background|server|benchmark>]",
+            "add <profile> --path <exe> --cpus <list> [--priority <level>] [--retries <n>]",
+        ],
+        about: &[
+            "Create a profile step by step, optionally from a template,",
+            "or from flags alone without any questions (for scripts)",
+        ],
+        options: &["--template", "--path", "--cpus", "--priority", "--retries"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "edit",
+        usage: &[
+            "edit <profile> [--path <exe>] [--cpus <list>] [--priority <level>] [--retries <n>]",
+            "edit <profile> --raw",
+            "edit <profile> --keep",
+        ],
+        about: &[
+            "Change only the given settings of a saved profile, or edit its",
+            "JSON in your editor, which is only saved once it is valid;",
+            "--keep turns a temporary profile into a permanent one",
+        ],
+        options: &[
+            "--path",
+            "--cpus",
+            "--priority",
+            "--retries",
+            "--raw",
+            "--keep",
+            "--unlock",
+        ],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "config",
+        usage: &["config edit [profiles|rules|settings] [--unlock]"],
+        about: &[
+            "Open a configuration file in $EDITOR (Notepad on Windows) and save",
+            "it only if it is still valid; profiles.json by default",
+        ],
+        options: &["--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "top",
+        usage: &["top"],
+        about: &["Live table of processes to adjust and save as profiles"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "attach",
+        usage: &["attach <pid>"],
+        about: &["Adjust one running process interactively and save it"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "inspect",
+        usage: &["inspect <pid>"],
+        about: &["List a process's threads with affinity and priority"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "apply",
+        usage: &["apply <profile> <pid|--service <name>> [--tree] [--migrate-memory]"],
+        about: &[
+            "Apply a profile's settings to a running process or service,",
+            "optionally with every process it started, and move its",
+            "memory to the cores' NUMA node",
+        ],
+        options: &["--service", "--tree", "--migrate-memory"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "boost",
+        usage: &["boost <profile> <pid> [--for <duration>] [--hotkey <keys>]"],
+        about: &["Apply a profile temporarily (default 10m), then revert"],
+        options: &["--for", "--hotkey"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "partition",
+        usage: &["partition --a <profile> --b <profile> [--unlock]"],
+        about: &["Split the cores between two profiles without overlap"],
+        options: &["--a", "--b", "--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "undo",
+        usage: &["undo <pid|--all>"],
+        about: &["Restore settings changed by apply/top/daemon"],
+        options: &["--all"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "stop-all",
+        usage: &["stop-all [--force]"],
+        about: &["Close every program launched by affinity-rs that still runs"],
+        options: &["--force"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "sign-profiles",
+        usage: &["sign-profiles"],
+        about: &["Accept outside changes to profiles.json (integrity_check)"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "verify",
+        usage: &["verify"],
+        about: &[
+            "Check all profiles for missing executables, unusable CPUs and",
+            "shortcuts left behind by deleted profiles; exits with 1 if any",
+        ],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "topology",
+        usage: &["topology [--json]"],
+        about: &["Show sockets, NUMA nodes, cores, P/E cores and shared caches"],
+        options: &["--json"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "selftest",
+        usage: &["selftest [--cpus <list>]"],
+        about: &["Check that the OS honors CPU affinity on this machine"],
+        options: &["--cpus"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "daemon",
+        usage: &["daemon [--interval <secs>]"],
+        about: &["Keep applying profiles to processes matched by rules.json"],
+        options: &["--interval"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "secret",
+        usage: &["secret <set|delete> <name>"],
+        about: &["Store a {secret:<name>} value in the OS keyring"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "delete",
+        usage: &["delete <profile> [--force] [--unlock]"],
+        about: &["Delete a saved profile and its shortcut (asks first)"],
+        options: &["--force", "--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "clean",
+        usage: &["clean [--dry-run] [--force] [--unlock]"],
+        about: &[
+            "Remove profiles whose executable is gone, leftover temporary",
+            "profiles and shortcuts of deleted profiles (asks first)",
+        ],
+        options: &["--dry-run", "--force", "--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "copy",
+        usage: &["copy <profile> <new_name> [--cpus <list>] [--priority <level>] [--retries <n>]"],
+        about: &["Save a copy of a profile under a new name, changing the given settings"],
+        options: &["--path", "--cpus", "--priority", "--retries"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "rename",
+        usage: &["rename <profile> <new_name> [--unlock]"],
+        about: &["Rename a profile and update its shortcuts, rules and dependents"],
+        options: &["--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "undelete",
+        usage: &["undelete [profile]"],
+        about: &["Restore a profile deleted in the last 30 days"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "run",
+        usage: &[
+            "run [--cpus <list>] [--priority <level>] [--retries <n>] [--] <program> [program_args...]",
+            "run --save-temp <name> [--days <n>] [options] [--] <program> [program_args...]",
+            "run --stdin [program_args...]",
+        ],
+        about: &[
+            "Launch a program once with the given settings, without saving a profile,",
+            "or with a one-off profile read as JSON from stdin; --save-temp keeps the",
+            "settings as a temporary profile that expires after 7 days (or --days)",
+        ],
+        options: &[
+            "--stdin",
+            "--save-temp",
+            "--days",
+            "--cpus",
+            "--priority",
+            "--retries",
+        ],
+        passes_args: true,
+    },
+    CommandSpec {
+        name: "stats",
+        usage: &["stats [profile]"],
+        about: &["Summarize launches, or show how long a profile's program ran"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "merge",
+        usage: &["merge <profile> <duplicate> [--unlock]"],
+        about: &["Combine two profiles, choosing each differing option"],
+        options: &["--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "preset",
+        usage: &["preset fetch <url|file|name> [--unlock]"],
+        about: &["Import a shared profile bundle after reviewing the changes"],
+        options: &["--unlock"],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "shortcut",
+        usage: &[
+            "shortcut <profile> [--desktop | --startmenu | --to <dir>]",
+            "shortcut --refresh-all",
+        ],
+        about: &[
+            "Create a shortcut for a profile (default: desktop),",
+            "or regenerate every existing shortcut",
+            "--task: run elevated profiles via Task Scheduler (Windows)",
+            "--pause <policy>: when the shortcut's console closes",
+        ],
+        options: &[
+            "--desktop",
+            "--startmenu",
+            "--to",
+            "--task",
+            "--pause",
+            "--refresh-all",
+        ],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "completions",
+        usage: &["completions <bash|zsh|fish|powershell>"],
+        about: &["Print a shell completion script that also completes profile names"],
+        options: &[],
+        passes_args: false,
+    },
+    CommandSpec {
+        name: "help",
+        usage: &["help [command]"],
+        about: &["Show this help message, or the details of one command"],
+        options: &[],
+        passes_args: false,
+    },
+];
+
+/// Options that go before the profile name, see `show_help`.
+const LAUNCH_OPTIONS: &[&str] = &[
+    "--plain",
+    "--rehash",
+    "--pid-file",
+    "--json",
+    "--unlock",
+    "--timings",
+    "--same-args",
+    "--dry-run",
+    "--yes",
+    "--non-interactive",
+    "--only",
+    "--profile-file",
+    "--pause",
+    "--no-pause",
+];
+
+/// A completion script for `shell`, generated from the command table. Profile
+/// names are completed by asking `affinity-rs list --names` each time.
+fn completion_script(shell: &str) -> Result<String> {
+    let commands: Vec<&str> = COMMANDS.iter().map(|spec| spec.name).collect();
+    let mut script = String::new();
+
+    match shell {
+        "bash" => {
+            script.push_str("_affinity_rs() {\n");
+            script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" opts\n");
+            script.push_str("    local profiles=\"$(affinity-rs list --names 2>/dev/null)\"\n");
+            script.push_str(
+                "    if [[ $COMP_CWORD -eq 1 || ${COMP_WORDS[COMP_CWORD-1]} == --* ]]; then\n",
+            );
+            script.push_str(&format!(
+                "        opts=\"{} {}\"\n",
+                commands.join(" "),
+                LAUNCH_OPTIONS.join(" ")
+            ));
+            script.push_str("    else\n");
+            script.push_str("        case \"${COMP_WORDS[1]}\" in\n");
+            for spec in COMMANDS.iter().filter(|spec| !spec.options.is_empty()) {
+                script.push_str(&format!(
+                    "            {}) opts=\"{}\" ;;\n",
+                    spec.name,
+                    spec.options.join(" ")
+                ));
+            }
+            script.push_str("            *) opts=\"\" ;;\n");
+            script.push_str("        esac\n");
+            script.push_str("    fi\n");
+            script.push_str("    COMPREPLY=($(compgen -W \"$opts $profiles\" -- \"$cur\"))\n");
+            script.push_str("}\n");
+            script.push_str("complete -o default -F _affinity_rs affinity-rs\n");
+        }
+        "zsh" => {
+            let quote = |text: &str| text.replace('\'', "'\\''").replace(':', "\\:");
+            script.push_str("#compdef affinity-rs\n\n");
+            script.push_str("_affinity_rs() {\n");
+            script.push_str("    local -a commands profiles\n");
+            script.push_str("    commands=(\n");
+            for spec in COMMANDS {
+                script.push_str(&format!(
+                    "        '{}:{}'\n",
+                    spec.name,
+                    quote(spec.about.first().unwrap_or(&""))
+                ));
+            }
+            script.push_str("    )\n");
+            script.push_str("    profiles=(${(f)\"$(affinity-rs list --names 2>/dev/null)\"})\n");
+            script.push_str("    if (( CURRENT == 2 )); then\n");
+            script.push_str("        _describe 'command' commands\n");
+            script.push_str(&format!(
+                "        compadd -- {}\n",
+                LAUNCH_OPTIONS.join(" ")
+            ));
+            script.push_str("    else\n");
+            script.push_str("        case $words[2] in\n");
+            for spec in COMMANDS.iter().filter(|spec| !spec.options.is_empty()) {
+                script.push_str(&format!(
+                    "            {}) compadd -- {} ;;\n",
+                    spec.name,
+                    spec.options.join(" ")
+                ));
+            }
+            script.push_str("        esac\n");
+            script.push_str("    fi\n");
+            script.push_str("    compadd -a profiles\n");
+            script.push_str("    _files\n");
+            script.push_str("}\n\n");
+            // Works both from $fpath and when sourced
+            script.push_str("if [[ $zsh_eval_context[-1] == loadautofunc ]]; then\n");
+            script.push_str("    _affinity_rs \"$@\"\n");
+            script.push_str("else\n");
+            script.push_str("    compdef _affinity_rs affinity-rs\n");
+            script.push_str("fi\n");
+        }
+        "fish" => {
+            let quote = |text: &str| text.replace('\\', "\\\\").replace('\'', "\\'");
+            script.push_str("complete -c affinity-rs -f\n");
+            for spec in COMMANDS {
+                script.push_str(&format!(
+                    "complete -c affinity-rs -n __fish_use_subcommand -a {} -d '{}'\n",
+                    spec.name,
+                    quote(spec.about.first().unwrap_or(&""))
+                ));
+            }
+            for option in LAUNCH_OPTIONS {
+                script.push_str(&format!(
+                    "complete -c affinity-rs -n __fish_use_subcommand -l {}\n",
+                    option.trim_start_matches("--")
+                ));
+            }
+            for spec in COMMANDS {
+                for option in spec.options {
+                    script.push_str(&format!(
+                        "complete -c affinity-rs -n '__fish_seen_subcommand_from {}' -l {}\n",
+                        spec.name,
+                        option.trim_start_matches("--")
+                    ));
+                }
+            }
+            script.push_str(
+                "complete -c affinity-rs -a '(affinity-rs list --names 2>/dev/null)' -d Profile\n",
+            );
+        }
+        "powershell" => {
+            script.push_str("Register-ArgumentCompleter -Native -CommandName affinity-rs, affinity-rs.exe -ScriptBlock {\n");
+            script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+            script.push_str(
+                "    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n",
+            );
+            script.push_str("    $options = @{\n");
+            for spec in COMMANDS.iter().filter(|spec| !spec.options.is_empty()) {
+                let options: Vec<String> = spec
+                    .options
+                    .iter()
+                    .map(|option| format!("'{}'", option))
+                    .collect();
+                script.push_str(&format!(
+                    "        '{}' = @({})\n",
+                    spec.name,
+                    options.join(", ")
+                ));
+            }
+            script.push_str("    }\n");
+            let quoted = |words: &[&str]| {
+                words
+                    .iter()
+                    .map(|word| format!("'{}'", word))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            script.push_str(
+                "    $first = $words.Count -lt 2 -or ($words.Count -eq 2 -and $wordToComplete)\n",
+            );
+            script.push_str("    $candidates = if ($first) {\n");
+            script.push_str(&format!(
+                "        @({}, {})\n",
+                quoted(&commands),
+                quoted(LAUNCH_OPTIONS)
+            ));
+            script.push_str("    } else {\n");
+            script.push_str("        @($options[$words[1]])\n");
+            script.push_str("    }\n");
+            script.push_str("    $candidates += @(& affinity-rs list --names 2>$null)\n");
+            script.push_str("    $candidates | Where-Object { $_ -and $_ -like \"$wordToComplete*\" } | ForEach-Object {\n");
+            script.push_str("        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n");
+            script.push_str("    }\n");
+            script.push_str("}\n");
+        }
+        _ => bail!(
+            "Unknown shell '{}'. Use bash, zsh, fish or powershell",
+            shell
+        ),
+    }
+
+    Ok(script)
+}
+
+fn command_spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+fn show_command_help(spec: &CommandSpec) {
+    for (i, usage) in spec.usage.iter().enumerate() {
+        let prefix = if i == 0 { "Usage:" } else { "      " };
+        println!("{} affinity-rs {}", prefix, usage);
+    }
+    println!();
+    for line in spec.about {
+        println!("{}", line);
+    }
+}
+
+/// Rejects options the command doesn't know, so a typo isn't silently
+/// ignored or taken for a profile name.
+fn check_command_options(spec: &CommandSpec, args: &[String]) -> Result<()> {
+    // The program's own arguments are not ours to check
+    let own = if let Some(end) = args.iter().position(|arg| arg == "--")
+        && spec.passes_args
+    {
+        &args[..end]
+    } else if spec.passes_args {
+        &args[..args.len().min(1)]
+    } else {
+        args
+    };
+
+    for option in own.iter().filter(|arg| arg.starts_with("--")) {
+        if !spec.options.contains(&option.as_str()) {
+            match closest_match(option, spec.options.iter().copied()) {
+                Some(known) => bail!(
+                    "Unknown option '{}' for '{}'. Did you mean '{}'?",
+                    option,
+                    spec.name,
+                    known
+                ),
+                None => bail!("Unknown option '{}' for '{}'", option, spec.name),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The candidate within a couple of typos of `input`, if any.
+fn closest_match<'a>(input: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (input.chars().count() / 3).clamp(1, 2);
+    candidates
+        .map(|candidate| (edit_distance(input, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edits needed to turn `a` into `b`, counting a swap of two neighbouring
+/// characters as one edit, as in "lsit".
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/// The profile being worked on, included in crash reports.
+static CRASH_CONTEXT: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Records the profile currently being processed for a possible crash
+/// report. Paths are reduced to file names, and arguments and environment
+/// values are left out, as they may contain personal data or secrets.
+fn set_crash_context(name: Option<&str>, profile: &Profile) {
+    let mut value = serde_json::to_value(profile).unwrap_or_default();
+
+    if let Some(fields) = value.as_object_mut() {
+        for (key, field) in fields.iter_mut() {
+            match key.as_str() {
+                "path" | "log_file" => {
+                    let file_name = field
+                        .as_str()
+                        .and_then(|path| Path::new(path).file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    *field = format!("<redacted>/{}", file_name).into();
+                }
+                "shortcut_dirs" | "args" | "last_args" | "env" | "checksum" => {
+                    *field = "<redacted>".into();
+                }
+                "shortcut_actions" => {
+                    for action in field.as_array_mut().into_iter().flatten() {
+                        if let Some(args) = action.get_mut("args") {
+                            *args = "<redacted>".into();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let context = format!(
+        "Profile: {}\n{}",
+        name.unwrap_or("(unsaved)"),
+        serde_json::to_string_pretty(&value).unwrap_or_default()
+    );
+    if let Ok(mut current) = CRASH_CONTEXT.lock() {
+        *current = Some(context);
+    }
+}
+
+/// Saves a diagnostic report to the config directory when affinity-rs
+/// panics, so bug reports contain something to go on.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        // Output piped into a program that stopped reading, as in `list | head`,
+        // is not a crash worth reporting
+        let message = info
+            .payload()
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or_default();
+        if message.starts_with("failed printing to") {
+            return;
+        }
+
+        let context = CRASH_CONTEXT
+            .lock()
+            .ok()
+            .and_then(|current| current.clone())
+            .unwrap_or_else(|| "No profile was being processed.".to_string());
+
+        let report = format!(
+            "affinity-rs {}\nOS: {} ({})\nLogical CPUs: {}\nCommand: {}\n\nPanic: {}\n\n{}\n\nBacktrace:\n{}\n",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            num_cpus::get(),
+            std::env::args_os()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string_lossy(),
+            info,
+            context,
+            std::backtrace::Backtrace::force_capture()
+        );
+
+        let saved = get_config_dir().and_then(|dir| {
+            let path = dir.join(format!("crash-{}.txt", unix_now()));
+            std::fs::write(&path, report)?;
+            Ok(path)
+        });
+
+        match saved {
+            Ok(path) => {
+                eprintln!("\naffinity-rs crashed. A diagnostic report was saved to:");
+                eprintln!("  {}", path.display());
+                eprintln!("Please attach it when reporting the problem.");
+            }
+            Err(e) => eprintln!(
+                "\naffinity-rs crashed, and the report could not be saved: {:#}",
+                e
+            ),
+        }
+    }));
+}
+
+fn main() {
+    install_panic_hook();
+
+    // Arguments may be in any encoding; only the program's own are kept as is
+    let args: Vec<OsString> = std::env::args_os().collect();
+
+    // Check for cleanup flag (used after elevation)
+    let should_cleanup = args.iter().any(|arg| arg == ELEVATION_CLEANUP_FLAG);
+    #[cfg(target_os = "windows")]
+    let from_task = args.iter().any(|arg| arg == TASK_ARGS_FLAG);
+    let mut args: Vec<OsString> = args
+        .into_iter()
+        .filter(|arg| arg != ELEVATION_CLEANUP_FLAG)
+        .collect();
+    #[cfg(target_os = "windows")]
+    args.retain(|arg| arg != TASK_ARGS_FLAG);
+
+    // Launch flags come before the profile name; everything after it goes to the program
+    let mut launch_options = LaunchOptions::default();
+    let mut explicit_launch = false;
+    while let Some(flag) = args.get(1) {
+        match flag.to_str().unwrap_or_default() {
+            "launch" if !explicit_launch => explicit_launch = true,
+            "--plain" => launch_options.plain = true,
+            "--rehash" => launch_options.rehash = true,
+            "--json" => launch_options.json = true,
+            "--timings" => launch_options.timings = true,
+            "--unlock" => launch_options.unlock = true,
+            "--same-args" => launch_options.same_args = true,
+            "--dry-run" => launch_options.dry_run = true,
+            "--yes" => {
+                ASSUME_YES.store(true, std::sync::atomic::Ordering::Relaxed);
+                NON_INTERACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+            "--non-interactive" => {
+                NON_INTERACTIVE.store(true, std::sync::atomic::Ordering::Relaxed)
+            }
+            "--no-pause" => launch_options.pause = Some(PausePolicy::Never),
+            "--pause" => {
+                let Some(pause) = args
+                    .get(2)
+                    .and_then(|policy| PausePolicy::parse(policy.to_str()?))
+                else {
+                    eprintln!(
+                        "Error: --pause requires always, on-error, never or a number of seconds."
+                    );
+                    return;
+                };
+                launch_options.pause = Some(pause);
+                args.remove(2);
+            }
+            "--pid-file" => {
+                let Some(path) = args.get(2) else {
+                    eprintln!("Error: --pid-file requires a path.");
+                    return;
+                };
+                launch_options.pid_file = Some(PathBuf::from(path));
+                args.remove(2);
+            }
+            "--only" => {
+                let Some(only) = args
+                    .get(2)
+                    .and_then(|only| ApplyOnly::parse(only.to_str()?))
+                else {
+                    eprintln!("Error: --only requires affinity or priority.");
+                    return;
+                };
+                launch_options.only = Some(only);
+                args.remove(2);
+            }
+            "--profile-file" => {
+                // Absolute, as an elevated relaunch starts in another directory
+                let Some(path) = args.get(2).and_then(|path| std::path::absolute(path).ok()) else {
+                    eprintln!("Error: --profile-file requires a path.");
+                    return;
+                };
+                let _ = PROFILE_FILE.set(path);
+                args.remove(2);
+            }
+            _ => break,
+        }
+        args.remove(1);
+    }
+
+    // A scheduled task's launch gets its arguments from the shortcut's file
+    #[cfg(target_os = "windows")]
+    if from_task && let Some(keyword) = args.get(1).and_then(|keyword| keyword.to_str()) {
+        let task_args = take_task_args(keyword);
+        args.extend(task_args.into_iter().map(OsString::from));
+    }
 
     // Load profiles with error handling
     let mut profiles = match load_profiles() {
@@ -933,62 +13748,891 @@ fn main() {
         }
     };
 
-    // Clean up any orphaned temp profiles on startup
+    // Remove expired temporary profiles and the ones older versions left
+    // behind on startup, unless `clean` is to list them or this is a dry run
+    let now = unix_now();
     let temp_keys: Vec<String> = profiles
-        .keys()
-        .filter(|k| k.starts_with(TEMP_PROFILE_PREFIX))
-        .cloned()
+        .iter()
+        .filter(|(name, profile)| {
+            profile.is_expired(now)
+                || (name.starts_with(TEMP_PROFILE_PREFIX) && !profile.is_temporary())
+        })
+        .map(|(name, _)| name.clone())
         .collect();
 
-    if !temp_keys.is_empty() {
+    if !temp_keys.is_empty()
+        && !launch_options.dry_run
+        && args.get(1).is_none_or(|command| command != "clean")
+    {
         for key in temp_keys {
             profiles.remove(&key);
         }
         let _ = save_profiles(&profiles);
     }
 
-    // Check for cleanup flag (used after elevation)
-    let should_cleanup = args.iter().any(|arg| arg == ELEVATION_CLEANUP_FLAG);
-    let args: Vec<String> = args
-        .into_iter()
-        .filter(|arg| arg != ELEVATION_CLEANUP_FLAG)
-        .collect();
+    let program_args: Vec<OsString> = args.iter().skip(2).cloned().collect();
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    if let Some(pause) = launch_options.pause {
+        let _ = PAUSE_POLICY.set(pause);
+    } else if non_interactive() {
+        // Waiting for Enter would hang just the same
+        let _ = PAUSE_POLICY.set(PausePolicy::Never);
+    }
+
+    if explicit_launch {
+        match args.get(1).map(String::as_str) {
+            None | Some("--help" | "-h") => show_command_help(&COMMANDS[0]),
+            Some(keyword) => launch_or_create(
+                &mut profiles,
+                keyword,
+                &program_args,
+                should_cleanup,
+                &launch_options,
+                true,
+            ),
+        }
+        return;
+    }
+
+    if args.len() < 2 {
+        show_help();
+        return;
+    }
+
+    if let Some(spec) = command_spec(&args[1]) {
+        // Past the first argument, --help may be meant for the program
+        let wants_help = match spec.passes_args {
+            true => args
+                .get(2)
+                .is_some_and(|arg| arg == "--help" || arg == "-h"),
+            false => args[2..].iter().any(|arg| arg == "--help" || arg == "-h"),
+        };
+        if wants_help {
+            show_command_help(spec);
+            return;
+        }
+
+        if let Err(e) = check_command_options(spec, &args[2..]) {
+            eprintln!("Error: {:#}", e);
+            eprintln!("Run 'affinity-rs {} --help' for usage.", spec.name);
+            pause_before_exit();
+            exit(1);
+        }
+    }
+
+    match args[1].as_str() {
+        "verify" => {
+            if !verify_profiles(&profiles) {
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "completions" => {
+            let Some(shell) = args.get(2) else {
+                show_command_help(command_spec("completions").unwrap());
+                return;
+            };
+
+            match completion_script(shell) {
+                Ok(script) => print!("{}", script),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    exit(1);
+                }
+            }
+        }
+        "help" | "--help" | "-h" => match args.get(2) {
+            Some(command) => match command_spec(command) {
+                Some(spec) => show_command_help(spec),
+                None => {
+                    eprintln!("Error: Unknown command '{}'.", command);
+                    eprintln!("Run 'affinity-rs help' for the list of commands.");
+                }
+            },
+            None => show_help(),
+        },
+        "list" => {
+            let mut json = launch_options.json;
+            let mut names = false;
+            let mut check = false;
+            let mut fix = false;
+            let mut options = ListOptions::default();
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--json" => json = true,
+                    "--names" => names = true,
+                    "--check" => check = true,
+                    "--fix" => fix = true,
+                    "--missing-only" => options.missing_only = true,
+                    "--all" => options.all = true,
+                    "--sort" => {
+                        let Some(sort) = rest.next().and_then(|sort| ListSort::parse(sort)) else {
+                            eprintln!("Error: --sort requires name, path or priority.");
+                            exit(1);
+                        };
+                        options.sort = sort;
+                    }
+                    "--filter" => {
+                        let Some(filter) = rest.next() else {
+                            eprintln!("Error: --filter requires some text to look for.");
+                            exit(1);
+                        };
+                        options.filter = Some(filter.clone());
+                    }
+                    _ => {
+                        show_command_help(command_spec("list").unwrap());
+                        return;
+                    }
+                }
+            }
+
+            if fix && !check {
+                eprintln!("Error: --fix only works together with --check.");
+                exit(1);
+            }
+
+            if check {
+                match check_running_profiles(&profiles, &options, fix) {
+                    Ok(true) => {}
+                    Ok(false) => exit(1),
+                    Err(e) => {
+                        eprintln!("Error: {:#}", e);
+                        exit(1);
+                    }
+                }
+            } else if names {
+                // One per line, for shell completion and scripts
+                for name in options.select(&profiles) {
+                    println!("{}", name);
+                }
+            } else if json {
+                if let Err(e) = list_profiles_json(&profiles, &options) {
+                    eprintln!("Error: {:#}", e);
+                    exit(1);
+                }
+            } else {
+                list_profiles(&profiles, &options);
+            }
+        }
+        "apply" => {
+            const USAGE: &str = "Usage: affinity-rs apply <profile> <pid|--service <name>> [--tree] [--migrate-memory]";
+
+            let migrate_memory = args[2..].iter().any(|arg| arg == "--migrate-memory");
+            let tree = args[2..].iter().any(|arg| arg == "--tree");
+            let args: Vec<String> = args
+                .iter()
+                .filter(|arg| *arg != "--migrate-memory" && *arg != "--tree")
+                .cloned()
+                .collect();
+
+            let pid = match args.get(3).map(String::as_str) {
+                Some("--service") => {
+                    let Some(service) = args.get(4) else {
+                        eprintln!("{}", USAGE);
+                        return;
+                    };
+                    match service_pid(service) {
+                        Ok(pid) => Some(pid),
+                        Err(e) => {
+                            eprintln!("Error: {:#}", e);
+                            pause_before_exit();
+                            return;
+                        }
+                    }
+                }
+                pid => pid.and_then(|p| p.parse::<u32>().ok()),
+            };
+            let (Some(keyword), Some(pid)) = (args.get(2), pid) else {
+                eprintln!("{}", USAGE);
+                return;
+            };
+
+            if let Err(e) = apply_profile_to_pid(
+                &profiles,
+                keyword,
+                pid,
+                migrate_memory,
+                tree,
+                launch_options.only,
+            ) {
+                eprintln!("Error applying profile: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "boost" => {
+            const USAGE: &str =
+                "Usage: affinity-rs boost <profile> <pid> [--for <duration>] [--hotkey <keys>]";
+
+            let pid = args.get(3).and_then(|p| p.parse::<u32>().ok());
+            let (Some(keyword), Some(pid)) = (args.get(2), pid) else {
+                eprintln!("{}", USAGE);
+                return;
+            };
+
+            let mut duration = Ok(Duration::from_secs(600));
+            let mut hotkey = None;
+            let mut rest = args[4..].iter();
+            while let Some(flag) = rest.next() {
+                match (flag.as_str(), rest.next()) {
+                    ("--for", Some(value)) => duration = parse_duration(value),
+                    ("--hotkey", Some(keys)) => hotkey = Some(keys.as_str()),
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        return;
+                    }
+                }
+            }
+
+            if let Err(e) =
+                duration.and_then(|duration| boost_pid(&profiles, keyword, pid, duration, hotkey))
+            {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "partition" => {
+            let mut a = None;
+            let mut b = None;
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--a" => a = rest.next(),
+                    "--b" => b = rest.next(),
+                    "--unlock" => launch_options.unlock = true,
+                    _ => {
+                        a = None;
+                        break;
+                    }
+                }
+            }
+
+            let (Some(a), Some(b)) = (a, b) else {
+                eprintln!("Usage: affinity-rs partition --a <profile> --b <profile> [--unlock]");
+                return;
+            };
+
+            if let Err(e) = partition_profiles(&mut profiles, a, b, launch_options.unlock) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "topology" => {
+            let json = launch_options.json || args[2..].iter().any(|arg| arg == "--json");
+            show_topology(json);
+        }
+        "selftest" => {
+            let cpus = match (args.get(2).map(String::as_str), args.get(3)) {
+                (None, _) => Ok(topology::current().primary_cache_domain()),
+                (Some("--cpus"), Some(list)) => parse_cpu_list(list),
+                _ => {
+                    eprintln!("Usage: affinity-rs selftest [--cpus <list>]");
+                    return;
+                }
+            };
+
+            match cpus.and_then(|cpus| run_selftest(&cpus, Duration::from_secs(3))) {
+                Ok(true) => {}
+                Ok(false) => exit(1),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    exit(1);
+                }
+            }
+        }
+        "sign-profiles" => {
+            let result = get_profile_path().and_then(|path| {
+                let data = std::fs::read(&path).context("Failed to read profiles file")?;
+                sign_profiles(&path, &data)?;
+                Ok(path)
+            });
+
+            match result {
+                Ok(path) => println!("Accepted the current contents of {}", path.display()),
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    pause_before_exit();
+                }
+            }
+        }
+        "stop-all" => {
+            let force = args[2..].iter().any(|arg| arg == "--force");
+
+            if let Err(e) = stop_all(force) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "undo" => {
+            let target = match args.get(2).map(String::as_str) {
+                Some("--all") => None,
+                Some(pid) => match pid.parse::<u32>() {
+                    Ok(pid) => Some(pid),
+                    Err(_) => {
+                        eprintln!("Usage: affinity-rs undo <pid|--all>");
+                        return;
+                    }
+                },
+                None => {
+                    eprintln!("Usage: affinity-rs undo <pid|--all>");
+                    return;
+                }
+            };
+
+            if let Err(e) = undo_changes(target) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "daemon" => {
+            let interval = match args.get(2).map(String::as_str) {
+                Some("--interval") => match args.get(3).and_then(|s| s.parse::<u64>().ok()) {
+                    Some(secs) if secs > 0 => secs,
+                    _ => {
+                        eprintln!("Usage: affinity-rs daemon [--interval <secs>]");
+                        return;
+                    }
+                },
+                Some(_) => {
+                    eprintln!("Usage: affinity-rs daemon [--interval <secs>]");
+                    return;
+                }
+                None => 5,
+            };
+
+            if let Err(e) = run_daemon(Duration::from_secs(interval)) {
+                eprintln!("Error: {:#}", e);
+                exit(1);
+            }
+        }
+        "preset" => {
+            let unlock = launch_options.unlock || args[2..].iter().any(|arg| arg == "--unlock");
+            let (Some("fetch"), Some(source)) = (args.get(2).map(String::as_str), args.get(3))
+            else {
+                eprintln!("Usage: affinity-rs preset fetch <url|file|name> [--unlock]");
+                return;
+            };
+
+            if let Err(e) = fetch_presets(&mut profiles, source, unlock) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "secret" => {
+            let (Some(action), Some(name)) = (args.get(2), args.get(3)) else {
+                eprintln!("Usage: affinity-rs secret <set|delete> <name>");
+                return;
+            };
+
+            let result = match action.as_str() {
+                "set" => read_secret_line(&format!("Value for '{}': ", name))
+                    .and_then(|value| store_secret(name, &value))
+                    .map(|_| {
+                        println!(
+                            "Secret '{}' stored. Use {{secret:{}}} in args or env.",
+                            name, name
+                        )
+                    }),
+                "delete" => delete_secret(name).map(|_| println!("Secret '{}' removed.", name)),
+                _ => {
+                    eprintln!("Usage: affinity-rs secret <set|delete> <name>");
+                    return;
+                }
+            };
+
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "top" => {
+            if let Err(e) = run_top(&mut profiles) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "attach" => {
+            let Some(pid) = args.get(2).and_then(|pid| pid.parse::<u32>().ok()) else {
+                eprintln!("Usage: affinity-rs attach <pid>");
+                return;
+            };
+
+            if let Err(e) = run_attach(&mut profiles, pid) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "show" => {
+            let Some(keyword) = args.get(2) else {
+                eprintln!("Usage: affinity-rs show <profile>");
+                return;
+            };
+
+            if let Err(e) = show_profile(&profiles, keyword) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "inspect" => {
+            let Some(pid) = args.get(2).and_then(|pid| pid.parse::<u32>().ok()) else {
+                eprintln!("Usage: affinity-rs inspect <pid>");
+                return;
+            };
+
+            if let Err(e) = inspect_process(pid) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "delete" => {
+            let force = args[2..].iter().any(|arg| arg == "--force");
+            let unlock = launch_options.unlock || args[2..].iter().any(|arg| arg == "--unlock");
+            let Some(keyword) = args[2..].iter().find(|arg| !arg.starts_with("--")) else {
+                eprintln!("Usage: affinity-rs delete <profile> [--force] [--unlock]");
+                eprintln!("Run 'affinity-rs list' to see available profiles.");
+                return;
+            };
+
+            if !force
+                && let Some(profile) = profiles.get(keyword)
+                && ensure_unlocked(profile, keyword, unlock).is_ok()
+            {
+                let answer = confirm(&format!("Delete profile '{}'? [y/N]: ", keyword));
+                if !answer.unwrap_or(false) {
+                    println!("Profile not deleted.");
+                    return;
+                }
+            }
+
+            match delete_profile(&mut profiles, keyword, unlock) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error deleting profile: {:#}", e);
+                    pause_before_exit();
+                }
+            }
+        }
+        "clean" => {
+            let mut dry_run = false;
+            let mut force = false;
+            let mut unlock = launch_options.unlock;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--dry-run" => dry_run = true,
+                    "--force" => force = true,
+                    "--unlock" => unlock = true,
+                    _ => {
+                        show_command_help(command_spec("clean").unwrap());
+                        return;
+                    }
+                }
+            }
+
+            if let Err(e) = clean_profiles(&mut profiles, dry_run, force, unlock) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "add" => {
+            let mut name = None;
+            let mut template = None;
+            let mut flags = ProfileFlags::default();
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match flags.parse(arg, &mut rest) {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("Error: {:#}", e);
+                        pause_before_exit();
+                        exit(1);
+                    }
+                }
+
+                match arg.as_str() {
+                    "--template" => {
+                        let Some(value) = rest.next() else {
+                            eprintln!("Error: --template requires a name.");
+                            return;
+                        };
+                        let Some(parsed) = Template::parse(value) else {
+                            let names: Vec<&str> = Template::ALL.iter().map(|t| t.name()).collect();
+                            eprintln!(
+                                "Error: Unknown template '{}'. Available: {}",
+                                value,
+                                names.join(", ")
+                            );
+                            return;
+                        };
+                        template = Some(parsed);
+                    }
+                    _ if name.is_none() => name = Some(arg.as_str()),
+                    _ => {
+                        eprintln!("Usage: affinity-rs add [profile] [--template <name>]");
+                        return;
+                    }
+                }
+            }
+
+            if let Err(e) = add_profile(&mut profiles, name, template, &flags) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "edit" => {
+            let mut keyword = None;
+            let mut flags = ProfileFlags::default();
+            let mut unlock = launch_options.unlock;
+            let mut raw = false;
+            let mut keep = false;
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match flags.parse(arg, &mut rest) {
+                    Ok(true) => {}
+                    Ok(false) if arg == "--unlock" => unlock = true,
+                    Ok(false) if arg == "--raw" => raw = true,
+                    Ok(false) if arg == "--keep" => keep = true,
+                    Ok(false) if keyword.is_none() => keyword = Some(arg.as_str()),
+                    Ok(false) => {
+                        eprintln!(
+                            "Usage: affinity-rs {}",
+                            command_spec("edit").unwrap().usage[0]
+                        );
+                        return;
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {:#}", e);
+                        pause_before_exit();
+                        exit(1);
+                    }
+                }
+            }
+
+            let Some(keyword) = keyword else {
+                show_command_help(command_spec("edit").unwrap());
+                return;
+            };
+
+            let result = if (raw || keep) && !flags.is_empty() || raw && keep {
+                Err(anyhow::anyhow!(
+                    "--raw and --keep can't be combined with other settings"
+                ))
+            } else if keep {
+                keep_profile(&mut profiles, keyword)
+            } else if raw {
+                edit_profile_raw(&mut profiles, keyword, unlock)
+            } else {
+                edit_profile(&mut profiles, keyword, &flags, unlock)
+            };
+
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "config" => {
+            let unlock = launch_options.unlock || args[2..].iter().any(|arg| arg == "--unlock");
+            let words: Vec<&str> = args[2..]
+                .iter()
+                .map(String::as_str)
+                .filter(|arg| !arg.starts_with("--"))
+                .collect();
+            let which = match words[..] {
+                ["edit"] => "profiles",
+                ["edit", which] => which,
+                _ => {
+                    show_command_help(command_spec("config").unwrap());
+                    return;
+                }
+            };
+
+            if let Err(e) = edit_config_file(&profiles, which, unlock) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "copy" => {
+            let mut names = Vec::new();
+            let mut flags = ProfileFlags::default();
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match flags.parse(arg, &mut rest) {
+                    Ok(true) => {}
+                    Ok(false) => names.push(arg.as_str()),
+                    Err(e) => {
+                        eprintln!("Error: {:#}", e);
+                        pause_before_exit();
+                        exit(1);
+                    }
+                }
+            }
+
+            let [base, new] = names[..] else {
+                show_command_help(command_spec("copy").unwrap());
+                return;
+            };
+
+            if let Err(e) = copy_profile(&mut profiles, base, new, &flags) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "rename" => {
+            let unlock = launch_options.unlock || args[2..].iter().any(|arg| arg == "--unlock");
+            let names: Vec<&String> = args[2..]
+                .iter()
+                .filter(|arg| !arg.starts_with("--"))
+                .collect();
+            let [old, new] = names[..] else {
+                eprintln!("Usage: affinity-rs rename <profile> <new_name> [--unlock]");
+                return;
+            };
+
+            if let Err(e) = rename_profile(&mut profiles, old, new, unlock) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+        "merge" => {
+            let unlock = launch_options.unlock || args[2..].iter().any(|arg| arg == "--unlock");
+            let names: Vec<&String> = args[2..]
+                .iter()
+                .filter(|arg| !arg.starts_with("--"))
+                .collect();
+            let [keep, other] = names[..] else {
+                eprintln!("Usage: affinity-rs merge <profile> <duplicate> [--unlock]");
+                return;
+            };
+
+            if let Err(e) = merge_profiles(&mut profiles, keep, other, unlock) {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "stats" => {
+            let result = match args.get(2) {
+                Some(keyword) => show_profile_stats(keyword),
+                None => show_stats(),
+            };
+
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+            }
+        }
+        "run" if args.get(2).map(String::as_str) != Some("--stdin") => {
+            let mut flags = ProfileFlags::default();
+            let mut save_temp = None;
+            let mut days = None;
+            let mut rest = args[2..].iter();
+            let program = loop {
+                let Some(arg) = rest.next() else {
+                    break None;
+                };
+                if arg == "--" {
+                    break rest.next();
+                }
+                if arg == "--save-temp" {
+                    let Some(name) = rest.next() else {
+                        eprintln!("Error: --save-temp requires a profile name.");
+                        pause_before_exit();
+                        exit(1);
+                    };
+                    save_temp = Some(name.clone());
+                    continue;
+                }
+                if arg == "--days" {
+                    let Some(n) = rest
+                        .next()
+                        .and_then(|n| n.parse::<u64>().ok())
+                        .filter(|n| (1..=MAX_TEMP_PROFILE_DAYS).contains(n))
+                    else {
+                        eprintln!(
+                            "Error: --days requires a number of days from 1 to {}.",
+                            MAX_TEMP_PROFILE_DAYS
+                        );
+                        pause_before_exit();
+                        exit(1);
+                    };
+                    days = Some(n);
+                    continue;
+                }
+                if arg == "--path" {
+                    eprintln!("Error: The program goes after the options, not in --path.");
+                    pause_before_exit();
+                    exit(1);
+                }
+                let parsed = flags.parse(arg, &mut rest).and_then(|parsed| {
+                    // Options before the program must all be known
+                    if !parsed && arg.starts_with("--") {
+                        check_command_options(
+                            command_spec("run").unwrap(),
+                            std::slice::from_ref(arg),
+                        )?;
+                    }
+                    Ok(parsed)
+                });
+                match parsed {
+                    Ok(true) => {}
+                    Ok(false) => break Some(arg),
+                    Err(e) => {
+                        eprintln!("Error: {:#}", e);
+                        pause_before_exit();
+                        exit(1);
+                    }
+                }
+            };
+            if program.is_none() {
+                show_command_help(command_spec("run").unwrap());
+                return;
+            }
+
+            // program_args starts after "run", so it is two behind args
+            let program = args.len() - rest.len() - 3;
+            let mut profile = Profile {
+                path: resolve_program(&program_args[program]),
+                ..Default::default()
+            };
+            flags.apply_to(&mut profile);
+            // Without --cpus only the priority is set, the affinity stays as it is
+            if profile.cpus.is_empty() {
+                profile.only = Some(ApplyOnly::Priority);
+            }
 
-    if args.len() < 2 {
-        show_help();
-        return;
-    }
+            if days.is_some() && save_temp.is_none() {
+                eprintln!("Error: --days only works together with --save-temp.");
+                pause_before_exit();
+                exit(1);
+            }
+            // A dry run doesn't save anything
+            let save_temp = save_temp.filter(|_| !launch_options.dry_run);
+            if let Some(ref name) = save_temp {
+                let days = days.unwrap_or(TEMP_PROFILE_DAYS);
+                if let Err(e) = save_temp_profile(&mut profiles, name, &profile, days) {
+                    eprintln!("Error: {:#}", e);
+                    pause_before_exit();
+                    exit(1);
+                }
+            }
 
-    match args[1].as_str() {
-        "help" | "--help" | "-h" => {
-            show_help();
-        }
-        "list" => {
-            list_profiles(&profiles);
+            launch_or_exit(
+                &profile,
+                &program_args[program + 1..],
+                save_temp.as_deref(),
+                should_cleanup,
+                &launch_options,
+            );
         }
-        "delete" => {
-            if args.len() < 3 {
-                eprintln!("Usage: affinity-rs delete <profile>");
-                eprintln!("Run 'affinity-rs list' to see available profiles.");
-                return;
+        "run" => {
+            if launch_options.json
+                && let Err(e) = keep_stdout_for_json()
+            {
+                eprintln!("Warning: Messages may be mixed into the JSON output: {}", e);
             }
 
-            match delete_profile(&mut profiles, &args[2]) {
-                Ok(_) => {}
+            let profile = match read_stdin_profile(&profiles) {
+                Ok(profile) => profile,
                 Err(e) => {
-                    eprintln!("Error deleting profile: {:#}", e);
+                    eprintln!("Error: {:#}", e);
                     pause_before_exit();
+                    exit(1);
                 }
+            };
+
+            launch_or_exit(
+                &profile,
+                &program_args[1..],
+                None,
+                should_cleanup,
+                &launch_options,
+            );
+        }
+        "undelete" => {
+            let result = match args.get(2) {
+                Some(keyword) => undelete_profile(&mut profiles, keyword),
+                None => list_trash(),
+            };
+
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
             }
         }
         "shortcut" => {
-            if args.len() < 3 {
-                eprintln!("Usage: affinity-rs shortcut <profile>");
-                eprintln!("Run 'affinity-rs list' to see available profiles.");
+            let mut keyword = None;
+            let mut target_dir = None;
+            let mut refresh_all = false;
+            let mut elevated_task = false;
+            let mut pause = None;
+
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--refresh-all" => refresh_all = true,
+                    "--task" => elevated_task = true,
+                    "--pause" => match rest.next().and_then(|policy| PausePolicy::parse(policy)) {
+                        Some(policy) => pause = Some(policy),
+                        None => {
+                            eprintln!(
+                                "Error: --pause requires always, on-error, never or a number of seconds."
+                            );
+                            return;
+                        }
+                    },
+                    "--desktop" => target_dir = Some(desktop_dir()),
+                    "--startmenu" => target_dir = Some(start_menu_dir()),
+                    "--to" => match rest.next() {
+                        Some(dir) => {
+                            target_dir = Some(std::path::absolute(dir).with_context(|| {
+                                format!("Could not resolve shortcut directory '{}'", dir)
+                            }))
+                        }
+                        None => {
+                            eprintln!("Error: --to requires a directory.");
+                            return;
+                        }
+                    },
+                    flag if flag.starts_with("--") => {
+                        eprintln!("Error: Unknown shortcut option '{}'.", flag);
+                        return;
+                    }
+                    name => keyword = Some(name.to_string()),
+                }
+            }
+
+            if refresh_all {
+                if let Err(e) = refresh_shortcuts(&profiles) {
+                    eprintln!("Error refreshing shortcuts: {:#}", e);
+                    pause_before_exit();
+                }
                 return;
             }
 
-            match create_shortcut(&profiles, &args[2]) {
+            let Some(keyword) = keyword else {
+                eprintln!(
+                    "Usage: affinity-rs shortcut <profile> [--desktop | --startmenu | --to <dir>]"
+                );
+                eprintln!("       affinity-rs shortcut --refresh-all");
+                eprintln!("Run 'affinity-rs list' to see available profiles.");
+                return;
+            };
+
+            let result = target_dir.unwrap_or_else(desktop_dir).and_then(|dir| {
+                create_shortcut(&mut profiles, &keyword, &dir, elevated_task, pause)
+            });
+
+            match result {
                 Ok(_) => {}
                 Err(e) => {
                     eprintln!("Error creating shortcut: {:#}", e);
@@ -996,112 +14640,686 @@ fn main() {
                 }
             }
         }
-        program_name => {
-            let program_args = if args.len() > 2 { &args[2..] } else { &[] };
+        program_name => launch_or_create(
+            &mut profiles,
+            program_name,
+            &program_args,
+            should_cleanup,
+            &launch_options,
+            false,
+        ),
+    }
+}
+
+/// Launches a saved profile, or offers to create one when `program_name`
+/// isn't a profile. With `explicit` (the `launch` command) nothing is created.
+fn launch_or_create(
+    profiles: &mut Profiles,
+    program_name: &str,
+    program_args: &[OsString],
+    should_cleanup: bool,
+    launch_options: &LaunchOptions,
+    explicit: bool,
+) {
+    if launch_options.json
+        && let Err(e) = keep_stdout_for_json()
+    {
+        eprintln!("Warning: Messages may be mixed into the JSON output: {}", e);
+    }
+
+    if profiles.contains_key(program_name) {
+        match check_executable_checksum(profiles, program_name, launch_options) {
+            Ok(true) => {}
+            Ok(false) => {
+                pause_before_exit();
+                exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error checking executable: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        }
+
+        let profile = match inherited_profile(profiles, program_name) {
+            Ok(profile) => profile,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                pause_before_exit();
+                exit(1);
+            }
+        };
+
+        let mut prompted_args = Vec::new();
+        if !program_args.is_empty() {
+            // Arguments in another encoding can't be stored in profiles.json
+            let args: Option<Vec<String>> = program_args
+                .iter()
+                .map(|arg| arg.to_str().map(String::from))
+                .collect();
+            if let Some(args) = args
+                && !launch_options.dry_run
+                && !program_name.starts_with(TEMP_PROFILE_PREFIX)
+                && let Err(e) = remember_args(profiles, program_name, &args)
+            {
+                eprintln!("Warning: Could not remember the arguments: {:#}", e);
+            }
+        } else if launch_options.same_args {
+            if profile.last_args.is_empty() {
+                println!("No previous arguments to reuse.");
+            } else {
+                println!("Reusing arguments: {}", join_args(&profile.last_args));
+                prompted_args = profile.last_args.iter().map(OsString::from).collect();
+            }
+        } else if profile.prompt_args {
+            match prompt_for_args(profiles, program_name, !launch_options.dry_run) {
+                Ok(args) => prompted_args = args.into_iter().map(OsString::from).collect(),
+                Err(e) => {
+                    eprintln!("Error reading arguments: {:#}", e);
+                    pause_before_exit();
+                    exit(1);
+                }
+            }
+        }
 
-            if let Some(profile) = profiles.get(program_name).cloned() {
-                println!("Loaded profile: '{}'", program_name);
-                launch_or_exit(&profile, program_args, Some(program_name), should_cleanup);
+        println!("Loaded profile: '{}'", program_name);
+        launch_or_exit(
+            &profile,
+            if prompted_args.is_empty() {
+                program_args
             } else {
-                // Create new profile interactively
-                println!(
-                    "No profile found for '{}'. Let's create one!\n",
-                    program_name
-                );
+                &prompted_args
+            },
+            Some(program_name),
+            should_cleanup,
+            launch_options,
+        );
+    } else {
+        if program_name.starts_with('-') {
+            eprintln!("Error: Unknown option '{}'.", program_name);
+            eprintln!("Run 'affinity-rs help' for usage.");
+            pause_before_exit();
+            exit(1);
+        }
 
-                let path_input = match read_line("Enter full program path: ") {
-                    Ok(input) => input.trim_matches('"').to_string(),
-                    Err(e) => {
-                        eprintln!("Error reading input: {}", e);
-                        pause_before_exit();
-                        return;
-                    }
-                };
+        // A typo shouldn't start creating a new profile
+        let command = closest_match(program_name, COMMANDS.iter().map(|spec| spec.name));
+        let similar = closest_match(program_name, profiles.keys().map(String::as_str));
+        // Nor a dry run or a run without anyone to answer the questions
+        if explicit
+            || launch_options.dry_run
+            || non_interactive()
+            || command.is_some()
+            || similar.is_some()
+        {
+            match (command, similar) {
+                (Some(command), _) if !explicit => eprintln!(
+                    "Error: Unknown command '{}'. Did you mean '{}'?",
+                    program_name, command
+                ),
+                (_, Some(similar)) => eprintln!(
+                    "Error: Profile '{}' not found. Did you mean '{}'?",
+                    program_name, similar
+                ),
+                _ => eprintln!("Error: Profile '{}' not found.", program_name),
+            }
+            eprintln!(
+                "Run 'affinity-rs add {}' to create a new profile with this name.",
+                program_name
+            );
+            pause_before_exit();
+            exit(1);
+        }
 
-                if path_input.is_empty() {
-                    eprintln!("Error: Path cannot be empty.");
+        // Create new profile interactively
+        println!(
+            "No profile found for '{}'. Let's create one!\n",
+            program_name
+        );
+
+        let path_input = match read_line("Enter full program path: ") {
+            Ok(input) => input.trim_matches('"').to_string(),
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                pause_before_exit();
+                return;
+            }
+        };
+
+        if path_input.is_empty() {
+            eprintln!("Error: Path cannot be empty.");
+            pause_before_exit();
+            return;
+        }
+
+        let path = PathBuf::from(&path_input);
+        if !path.exists() {
+            eprintln!("Error: File not found: {}", path.display());
+            eprintln!("Please check the path and try again.");
+            pause_before_exit();
+            return;
+        }
+
+        let preset = match get_preset_input() {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                pause_before_exit();
+                return;
+            }
+        };
+
+        // Presets supply cores and priority; manual setup asks for them
+        let (cpus, priority) = if preset.is_some() {
+            (Vec::new(), None)
+        } else {
+            let cpus = match get_cpu_input() {
+                Ok(cpus) => cpus,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
                     pause_before_exit();
                     return;
                 }
+            };
 
-                let path = PathBuf::from(&path_input);
-                if !path.exists() {
-                    eprintln!("Error: File not found: {}", path.display());
-                    eprintln!("Please check the path and try again.");
+            let priority = match get_priority_input() {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
                     pause_before_exit();
                     return;
                 }
+            };
 
-                let cpus = match get_cpu_input() {
-                    Ok(cpus) => cpus,
-                    Err(e) => {
-                        eprintln!("Error: {}", e);
-                        pause_before_exit();
-                        return;
-                    }
-                };
+            (cpus, priority)
+        };
 
-                let priority = match get_priority_input() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!("Error: {}", e);
-                        pause_before_exit();
-                        return;
-                    }
-                };
+        let new_profile = Profile {
+            path,
+            cpus,
+            priority,
+            retry_attempts: None, // Use default
+            preset,
+            ..Default::default()
+        };
 
-                let new_profile = Profile {
-                    path,
-                    cpus,
-                    priority,
-                    retry_attempts: None, // Use default
-                };
+        let save_choice = match read_line_or("\nSave this as a profile? (y/n): ", "n") {
+            Ok(choice) => choice,
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                pause_before_exit();
+                return;
+            }
+        };
+
+        if save_choice.eq_ignore_ascii_case("y") {
+            let mut keyword = program_name.to_string();
 
-                let save_choice = match read_line("\nSave this as a profile? (y/n): ") {
-                    Ok(choice) => choice,
+            if keyword.is_empty() || profiles.contains_key(&keyword) {
+                match read_line("Enter a name for this profile: ") {
+                    Ok(input) => keyword = input,
                     Err(e) => {
                         eprintln!("Error reading input: {}", e);
                         pause_before_exit();
                         return;
                     }
-                };
+                }
+            }
 
-                if save_choice.eq_ignore_ascii_case("y") {
-                    let mut keyword = program_name.to_string();
+            if keyword.is_empty() {
+                eprintln!("Error: Profile name cannot be empty.");
+                pause_before_exit();
+                return;
+            }
 
-                    if keyword.is_empty() || profiles.contains_key(&keyword) {
-                        match read_line("Enter a name for this profile: ") {
-                            Ok(input) => keyword = input,
-                            Err(e) => {
-                                eprintln!("Error reading input: {}", e);
-                                pause_before_exit();
-                                return;
-                            }
-                        }
-                    }
+            if let Some(existing) = profiles.get(&keyword)
+                && let Err(e) = ensure_unlocked(existing, &keyword, launch_options.unlock)
+            {
+                eprintln!("Error: {}", e);
+                pause_before_exit();
+                return;
+            }
 
-                    if keyword.is_empty() {
-                        eprintln!("Error: Profile name cannot be empty.");
-                        pause_before_exit();
-                        return;
-                    }
+            profiles.insert(keyword.clone(), new_profile.clone());
 
-                    profiles.insert(keyword.clone(), new_profile.clone());
+            match save_profiles(profiles) {
+                Ok(_) => println!("\nProfile '{}' saved successfully!", keyword),
+                Err(e) => {
+                    eprintln!("Error saving profile: {:#}", e);
+                    eprintln!("Continuing with launch anyway...");
+                }
+            }
 
-                    match save_profiles(&profiles) {
-                        Ok(_) => println!("\nProfile '{}' saved successfully!", keyword),
-                        Err(e) => {
-                            eprintln!("Error saving profile: {:#}", e);
-                            eprintln!("Continuing with launch anyway...");
-                        }
-                    }
+            launch_or_exit(
+                &new_profile,
+                program_args,
+                Some(&keyword),
+                false,
+                launch_options,
+            );
+        } else {
+            println!("\nLaunching without saving profile...");
+            launch_or_exit(&new_profile, program_args, None, false, launch_options);
+        }
+    }
+}
 
-                    launch_or_exit(&new_profile, program_args, Some(&keyword), false);
-                } else {
-                    println!("\nLaunching without saving profile...");
-                    launch_or_exit(&new_profile, program_args, None, false);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cpu_list_expands_ranges() {
+        assert_eq!(parse_cpu_list("0,2,4-7").unwrap(), vec![0, 2, 4, 5, 6, 7]);
+        assert_eq!(parse_cpu_list(" 3 , 1-2 ,\n").unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn parse_cpu_list_sorts_and_dedups() {
+        assert_eq!(parse_cpu_list("5,1-3,2,5").unwrap(), vec![1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn parse_cpu_list_rejects_bad_input() {
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert!(parse_cpu_list("4-2").is_err());
+        assert!(parse_cpu_list("0-18446744073709551615").is_err());
+        assert!(parse_cpu_list(&MAX_CPU_INDEX.to_string()).is_err());
+    }
+
+    #[test]
+    fn split_args_handles_quotes() {
+        assert_eq!(
+            split_args(r#"-w  --name "Big Map" 'it''s'"#),
+            vec!["-w", "--name", "Big Map", "its"]
+        );
+        assert_eq!(split_args(r#"--title="" x"#), vec!["--title=", "x"]);
+        assert_eq!(split_args(r#""""#), vec![""]);
+        assert!(split_args("   ").is_empty());
+    }
+
+    #[test]
+    fn join_args_round_trips_through_split_args() {
+        let args: Vec<String> = ["-w", "C:\\Program Files\\Game", "", "a\tb"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            join_args(&args),
+            "-w \"C:\\Program Files\\Game\" \"\" \"a\tb\""
+        );
+        assert_eq!(split_args(&join_args(&args)), args);
+    }
+
+    fn profile_extending(parent: Option<&str>) -> Profile {
+        Profile {
+            extends: parent.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn inherited_profile_walks_the_chain() {
+        let mut profiles = Profiles::new();
+        profiles.insert(
+            "base".to_string(),
+            Profile {
+                path: PathBuf::from("/games/base"),
+                cpus: vec![0, 1],
+                priority: Some(ProcessPriority::High),
+                env: HashMap::from([
+                    ("A".to_string(), "1".to_string()),
+                    ("B".to_string(), "1".to_string()),
+                ]),
+                last_args: vec!["--old".to_string()],
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "mid".to_string(),
+            Profile {
+                cpus: vec![2, 3],
+                env: HashMap::from([("B".to_string(), "2".to_string())]),
+                ..profile_extending(Some("base"))
+            },
+        );
+        profiles.insert(
+            "leaf".to_string(),
+            Profile {
+                args: vec!["-x".to_string()],
+                ..profile_extending(Some("mid"))
+            },
+        );
+
+        let leaf = inherited_profile(&profiles, "leaf").unwrap();
+        assert_eq!(leaf.path, PathBuf::from("/games/base"));
+        assert_eq!(leaf.cpus, vec![2, 3]);
+        assert_eq!(leaf.priority, Some(ProcessPriority::High));
+        assert_eq!(leaf.args, vec!["-x"]);
+        assert_eq!(leaf.env["A"], "1");
+        assert_eq!(leaf.env["B"], "2");
+        assert_eq!(leaf.extends.as_deref(), Some("mid"));
+        assert!(leaf.last_args.is_empty());
+    }
+
+    #[test]
+    fn inherited_profile_rejects_cycles_and_unknown_bases() {
+        let mut profiles = Profiles::new();
+        profiles.insert("a".to_string(), profile_extending(Some("b")));
+        profiles.insert("b".to_string(), profile_extending(Some("a")));
+        profiles.insert("c".to_string(), profile_extending(Some("missing")));
+
+        let err = inherited_profile(&profiles, "a").unwrap_err();
+        assert!(err.to_string().contains("a -> b -> a"), "{}", err);
+        assert!(inherited_profile(&profiles, "c").is_err());
+        assert!(inherited_profile(&profiles, "missing").is_err());
+    }
+
+    #[test]
+    fn pause_policy_parses_names_and_seconds() {
+        assert_eq!(PausePolicy::parse("always"), Some(PausePolicy::Always));
+        assert_eq!(PausePolicy::parse(" On_Error "), Some(PausePolicy::OnError));
+        assert_eq!(PausePolicy::parse("NEVER"), Some(PausePolicy::Never));
+        assert_eq!(PausePolicy::parse("5"), Some(PausePolicy::AfterSecs(5)));
+        assert_eq!(PausePolicy::parse("-1"), None);
+        assert_eq!(PausePolicy::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn pause_policy_round_trips_through_to_arg() {
+        for policy in [
+            PausePolicy::Always,
+            PausePolicy::OnError,
+            PausePolicy::Never,
+            PausePolicy::AfterSecs(30),
+        ] {
+            assert_eq!(PausePolicy::parse(&policy.to_arg()), Some(policy));
+        }
+    }
+
+    #[test]
+    fn profile_overrides_round_trip_through_with_overrides() {
+        let system = Profile {
+            path: PathBuf::from("/opt/game"),
+            cpus: vec![0, 1, 2, 3],
+            priority: Some(ProcessPriority::AboveNormal),
+            args: vec!["--fast".to_string()],
+            ..Default::default()
+        };
+        let profile = Profile {
+            cpus: vec![4, 5],
+            priority: None,
+            ..system.clone()
+        };
+
+        let overrides = profile_overrides(&profile, &system);
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides["cpus"], serde_json::json!([4, 5]));
+        assert!(overrides["priority"].is_null());
+
+        let merged = with_overrides(&system, serde_json::Value::Object(overrides)).unwrap();
+        assert_eq!(merged.path, system.path);
+        assert_eq!(merged.cpus, vec![4, 5]);
+        assert_eq!(merged.priority, None);
+        assert_eq!(merged.args, vec!["--fast"]);
+    }
+
+    #[test]
+    fn profile_overrides_are_empty_for_an_unchanged_copy() {
+        let system = Profile {
+            path: PathBuf::from("/opt/game"),
+            cpus: vec![0],
+            ..Default::default()
+        };
+        assert!(profile_overrides(&system.clone(), &system).is_empty());
+        assert!(with_overrides(&system, serde_json::json!(["cpus"])).is_err());
+    }
+
+    #[test]
+    fn edit_distance_counts_swaps_as_one_edit() {
+        assert_eq!(edit_distance("list", "list"), 0);
+        assert_eq!(edit_distance("lsit", "list"), 1);
+        assert_eq!(edit_distance("lst", "list"), 1);
+        assert_eq!(edit_distance("", "top"), 3);
+    }
+
+    #[test]
+    fn closest_match_suggests_near_commands_only() {
+        let names = || COMMANDS.iter().map(|command| command.name);
+        assert_eq!(closest_match("lsit", names()), Some("list"));
+        assert_eq!(closest_match("shortcat", names()), Some("shortcut"));
+        assert_eq!(closest_match("launch", names()), Some("launch"));
+        assert_eq!(closest_match("xyzzy", names()), None);
+    }
+
+    #[test]
+    fn format_cpu_list_compacts_runs() {
+        assert_eq!(format_cpu_list(&[0, 1, 2, 3, 8]), "0-3,8");
+        assert_eq!(format_cpu_list(&[4, 5]), "4,5");
+        assert_eq!(format_cpu_list(&[1, 3, 5]), "1,3,5");
+        assert_eq!(format_cpu_list(&[]), "");
+    }
+
+    #[test]
+    fn format_cpu_list_round_trips_through_parse_cpu_list() {
+        for cpus in [vec![0], vec![0, 1, 2, 3, 8, 10, 11], vec![2, 4, 5, 6, 63]] {
+            assert_eq!(parse_cpu_list(&format_cpu_list(&cpus)).unwrap(), cpus);
+        }
+    }
+
+    #[test]
+    fn profiles_json_fills_in_inherited_fields() {
+        let mut profiles = Profiles::new();
+        profiles.insert(
+            "base".to_string(),
+            Profile {
+                path: PathBuf::from("/nonexistent/game"),
+                cpus: vec![0, 1],
+                ..Default::default()
+            },
+        );
+        profiles.insert("leaf".to_string(), profile_extending(Some("base")));
+        profiles.insert("broken".to_string(), profile_extending(Some("missing")));
+        profiles.insert(format!("{}1", TEMP_PROFILE_PREFIX), profile_extending(None));
+
+        let list = profiles_json(&profiles, &HashMap::new(), &ListOptions::default()).unwrap();
+        let list = list.as_array().unwrap();
+        let names: Vec<&str> = list
+            .iter()
+            .map(|profile| profile["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["base", "broken", "leaf"]);
+
+        let leaf = &list[2];
+        assert_eq!(leaf["cpus"], serde_json::json!([0, 1]));
+        assert_eq!(leaf["extends"], "base");
+        assert_eq!(leaf["path_exists"], false);
+        assert_eq!(leaf["enabled"], true);
+        assert!(leaf.get("error").is_none());
+        assert!(leaf.get("source").is_none());
+
+        assert!(list[1]["error"].as_str().unwrap().contains("missing"));
+    }
+
+    #[test]
+    fn command_table_names_are_unique() {
+        let mut names: Vec<&str> = COMMANDS.iter().map(|spec| spec.name).collect();
+        names.sort_unstable();
+        let count = names.len();
+        names.dedup();
+        assert_eq!(names.len(), count);
+        assert!(command_spec("completions").is_some());
+    }
+
+    #[test]
+    fn completion_scripts_cover_the_command_table() {
+        for shell in ["bash", "zsh", "fish", "powershell"] {
+            let script = completion_script(shell).unwrap();
+            for spec in COMMANDS {
+                assert!(script.contains(spec.name), "{} lacks {}", shell, spec.name);
+                for option in spec.options {
+                    let option = option.trim_start_matches("--");
+                    assert!(script.contains(option), "{} lacks {}", shell, option);
                 }
             }
+            for option in LAUNCH_OPTIONS {
+                let option = option.trim_start_matches("--");
+                assert!(script.contains(option), "{} lacks {}", shell, option);
+            }
+        }
+        assert!(completion_script("tcsh").is_err());
+    }
+
+    #[test]
+    fn failed_apply_backs_off_up_to_the_limit() {
+        let interval = Duration::from_secs(5);
+        let mut failed = FailedApply {
+            start_time: None,
+            attempts: 0,
+            retry_at: Instant::now(),
+        };
+
+        failed.failed_again(interval);
+        assert!(!failed.is_due());
+        let first = failed.retry_at.saturating_duration_since(Instant::now());
+        assert!(first <= Duration::from_secs(10));
+
+        failed.failed_again(interval);
+        let second = failed.retry_at.saturating_duration_since(Instant::now());
+        assert!(second > first);
+
+        for _ in 0..40 {
+            failed.failed_again(interval);
         }
+        let last = failed.retry_at.saturating_duration_since(Instant::now());
+        assert!(last <= MAX_APPLY_BACKOFF);
+        assert!(last > MAX_APPLY_BACKOFF / 2);
+    }
+
+    #[test]
+    fn sha256_matches_nist_vectors() {
+        let hash = |data: &[u8]| to_hex(&sha256(data).unwrap());
+        assert_eq!(
+            hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hash(&vec![b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231_vectors() {
+        let hmac = |key: &[u8], data: &[u8]| to_hex(&hmac_sha256(key, data));
+        assert_eq!(
+            hmac(&[0x0b; 20], b"Hi There"),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hmac(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hmac(&[0xaa; 20], &[0xdd; 50]),
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"
+        );
+        let key: Vec<u8> = (1..=25).collect();
+        assert_eq!(
+            hmac(&key, &[0xcd; 50]),
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"
+        );
+        assert_eq!(
+            hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            hmac(
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger \
+                  than block-size data. The key needs to be hashed before being \
+                  used by the HMAC algorithm."
+            ),
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"
+        );
+    }
+
+    #[test]
+    fn split_args_follows_windows_backslash_rules() {
+        assert_eq!(split_args(r#"a\"b c\\"d e"#), vec![r#"a"b"#, r"c\d e"]);
+        assert_eq!(split_args(r"C:\Games\ x\\y"), vec![r"C:\Games\", r"x\\y"]);
+        assert_eq!(split_args(r#"'a\"b'"#), vec![r#"a\"b"#]);
+    }
+
+    #[test]
+    fn join_args_escapes_quotes_and_backslashes() {
+        let args: Vec<String> = [
+            r#"--title="Big Map""#,
+            r"C:\Program Files\Game\",
+            r#"say \"hi\""#,
+            "it's",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            join_args(&args),
+            r#""--title=\"Big Map\"" "C:\Program Files\Game\\" "say \\\"hi\\\"" "it's""#
+        );
+        assert_eq!(split_args(&join_args(&args)), args);
+    }
+
+    #[test]
+    fn parse_duration_rejects_overflowing_values() {
+        assert_eq!(parse_duration("90m").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration(&format!("{}h", u64::MAX / 60)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn launcher_helpers_match_with_or_without_exe() {
+        let lutris = known_launcher(Path::new("/usr/bin/lutris")).unwrap();
+        assert!(lutris.is_own_process("winedevice.exe"));
+        assert!(lutris.is_own_process("explorer.exe"));
+        assert!(lutris.is_own_process("wineserver"));
+        assert!(!lutris.is_own_process("game.exe"));
+        assert!(KNOWN_LAUNCHERS.iter().all(|launcher| {
+            launcher
+                .helpers
+                .iter()
+                .all(|helper| !helper.ends_with(".exe"))
+        }));
+    }
+
+    #[test]
+    fn crash_context_redacts_log_file_and_shortcut_arguments() {
+        let profile = Profile {
+            path: PathBuf::from("/home/someone/games/game.exe"),
+            log_file: Some(PathBuf::from("/home/someone/logs/game.log")),
+            shortcut_actions: vec![ShortcutAction {
+                name: "Benchmark".to_string(),
+                args: vec!["--token=secret".to_string()],
+            }],
+            ..Default::default()
+        };
+        set_crash_context(Some("game"), &profile);
+
+        let context = CRASH_CONTEXT.lock().unwrap().clone().unwrap();
+        assert!(context.contains("<redacted>/game.log"));
+        assert!(context.contains("Benchmark"));
+        assert!(!context.contains("someone"));
+        assert!(!context.contains("secret"));
     }
 }
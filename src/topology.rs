@@ -0,0 +1,431 @@
+//! Which logical CPUs share a core, a cluster or a cache.
+//!
+//! Presets and profile validation use this map instead of assuming the CPUs
+//! are numbered `0..num_cpus::get()` with no gaps and no structure.
+
+use std::sync::OnceLock;
+
+#[cfg(target_os = "linux")]
+use crate::parse_cpu_list;
+
+/// A cache shared by a group of logical CPUs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cache {
+    pub level: u32,
+    /// "Data", "Instruction" or "Unified"
+    pub kind: String,
+    pub cpus: Vec<usize>,
+}
+
+/// One logical CPU and what it shares with the others.
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    pub id: usize,
+    /// Physical package (socket) number
+    pub package: usize,
+    /// Logical CPUs on the same physical core (SMT siblings), including this one
+    pub threads: Vec<usize>,
+    /// Logical CPUs in the same cluster, e.g. a big.LITTLE core group
+    pub cluster: Vec<usize>,
+    pub caches: Vec<Cache>,
+    /// Relative performance where the platform reports it (ARM, hybrid x86).
+    /// On Windows this is the core's efficiency class.
+    pub capacity: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Topology {
+    /// Online CPUs, ordered by id
+    pub cpus: Vec<Cpu>,
+    /// CPUs that exist but are offline right now
+    pub offline: Vec<usize>,
+    /// NUMA nodes with the CPUs belonging to each, ordered by node number
+    pub nodes: Vec<(usize, Vec<usize>)>,
+    /// Low-power cores as reported directly by the platform
+    efficiency: Option<Vec<usize>>,
+}
+
+/// The topology of this machine, read once per run.
+pub fn current() -> &'static Topology {
+    static TOPOLOGY: OnceLock<Topology> = OnceLock::new();
+    TOPOLOGY.get_or_init(Topology::detect)
+}
+
+impl Topology {
+    #[cfg(target_os = "linux")]
+    fn detect() -> Self {
+        const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+        let read = |path: String| std::fs::read_to_string(path).ok();
+        let read_list = |path: String| read(path).and_then(|list| parse_cpu_list(&list).ok());
+
+        let online = read_list(format!("{}/online", CPU_DIR));
+        let possible = read_list(format!("{}/possible", CPU_DIR));
+        let Some(online) = online else {
+            return Self::fallback();
+        };
+
+        let cpus: Vec<Cpu> = online
+            .iter()
+            .map(|&id| {
+                let topology = format!("{}/cpu{}/topology", CPU_DIR, id);
+                let package = read(format!("{}/physical_package_id", topology))
+                    .and_then(|package| package.trim().parse().ok())
+                    .unwrap_or(0);
+                let threads = read_list(format!("{}/thread_siblings_list", topology))
+                    .unwrap_or_else(|| vec![id]);
+                // Kernels without cluster support report the whole package
+                let cluster = read_list(format!("{}/cluster_cpus_list", topology))
+                    .or_else(|| read_list(format!("{}/core_siblings_list", topology)))
+                    .unwrap_or_else(|| vec![id]);
+                let capacity = read(format!("{}/cpu{}/cpu_capacity", CPU_DIR, id))
+                    .and_then(|capacity| capacity.trim().parse().ok());
+
+                let mut caches: Vec<Cache> =
+                    std::fs::read_dir(format!("{}/cpu{}/cache", CPU_DIR, id))
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .filter(|entry| entry.file_name().to_string_lossy().starts_with("index"))
+                        .filter_map(|entry| {
+                            let file =
+                                |name: &str| read(entry.path().join(name).display().to_string());
+                            Some(Cache {
+                                level: file("level")?.trim().parse().ok()?,
+                                kind: file("type")?.trim().to_string(),
+                                cpus: parse_cpu_list(&file("shared_cpu_list")?).ok()?,
+                            })
+                        })
+                        .collect();
+                caches.sort_by(|a, b| (a.level, &a.kind).cmp(&(b.level, &b.kind)));
+
+                Cpu {
+                    id,
+                    package,
+                    threads,
+                    cluster,
+                    caches,
+                    capacity,
+                }
+            })
+            .collect();
+
+        let offline = possible
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|cpu| !online.contains(cpu))
+            .collect();
+
+        let mut nodes: Vec<(usize, Vec<usize>)> = std::fs::read_dir("/sys/devices/system/node")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let node = entry
+                    .file_name()
+                    .to_str()?
+                    .strip_prefix("node")?
+                    .parse()
+                    .ok()?;
+                let cpulist = std::fs::read_to_string(entry.path().join("cpulist")).ok()?;
+                // Memory-only nodes have an empty CPU list
+                Some((node, parse_cpu_list(&cpulist).unwrap_or_default()))
+            })
+            .collect();
+        nodes.sort_by_key(|(node, _)| *node);
+
+        // Intel hybrid CPUs list their E-cores as a separate PMU
+        let efficiency =
+            read_list("/sys/devices/cpu_atom/cpus".to_string()).filter(|cpus| !cpus.is_empty());
+
+        Self {
+            cpus,
+            offline,
+            nodes,
+            efficiency,
+        }
+    }
+
+    /// CPU numbers are `group * 64 + bit`, so with a single processor group
+    /// they match the bits of an affinity mask.
+    #[cfg(target_os = "windows")]
+    fn detect() -> Self {
+        use windows_sys::Win32::System::SystemInformation::{
+            CacheData, CacheInstruction, CacheTrace, GROUP_AFFINITY,
+            GetLogicalProcessorInformationEx, RelationAll, RelationCache, RelationNumaNode,
+            RelationProcessorCore, RelationProcessorPackage,
+            SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX,
+        };
+
+        let expand = |masks: &[GROUP_AFFINITY]| -> Vec<usize> {
+            let bits = usize::BITS as usize;
+            let mut cpus: Vec<usize> = masks
+                .iter()
+                .flat_map(|mask| {
+                    (0..bits)
+                        .filter(|bit| mask.Mask & (1 << bit) != 0)
+                        .map(|bit| mask.Group as usize * bits + bit)
+                })
+                .collect();
+            cpus.sort_unstable();
+            cpus
+        };
+
+        let mut length = 0u32;
+        unsafe { GetLogicalProcessorInformationEx(RelationAll, std::ptr::null_mut(), &mut length) };
+        // u64 elements keep the records aligned for their pointer-sized masks
+        let mut buffer = vec![0u64; (length as usize).div_ceil(8)];
+        if length == 0
+            || unsafe {
+                GetLogicalProcessorInformationEx(
+                    RelationAll,
+                    buffer.as_mut_ptr().cast(),
+                    &mut length,
+                )
+            } == 0
+        {
+            return Self::fallback();
+        }
+
+        // Physical cores with their efficiency class, higher being faster
+        let mut cores: Vec<(Vec<usize>, u8)> = Vec::new();
+        let mut packages: Vec<Vec<usize>> = Vec::new();
+        let mut caches: Vec<Cache> = Vec::new();
+        let mut nodes: Vec<(usize, Vec<usize>)> = Vec::new();
+
+        let base = buffer.as_ptr().cast::<u8>();
+        let mut offset = 0;
+        while offset < length as usize {
+            let info = unsafe {
+                &*base
+                    .add(offset)
+                    .cast::<SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX>()
+            };
+            // The relationship and cache kinds are plain constants, not an enum
+            match info.Relationship {
+                relationship
+                    if relationship == RelationProcessorCore
+                        || relationship == RelationProcessorPackage =>
+                {
+                    let processor = unsafe { &info.Anonymous.Processor };
+                    let masks = unsafe {
+                        std::slice::from_raw_parts(
+                            processor.GroupMask.as_ptr(),
+                            processor.GroupCount as usize,
+                        )
+                    };
+                    if info.Relationship == RelationProcessorCore {
+                        cores.push((expand(masks), processor.EfficiencyClass));
+                    } else {
+                        packages.push(expand(masks));
+                    }
+                }
+                relationship if relationship == RelationCache => {
+                    let cache = unsafe { &info.Anonymous.Cache };
+                    // GroupCount is only filled in from Windows 11 on
+                    let masks = unsafe {
+                        std::slice::from_raw_parts(
+                            cache.Anonymous.GroupMasks.as_ptr(),
+                            cache.GroupCount.max(1) as usize,
+                        )
+                    };
+                    let kind = match cache.Type {
+                        kind if kind == CacheData => "Data",
+                        kind if kind == CacheInstruction => "Instruction",
+                        kind if kind == CacheTrace => "Trace",
+                        _ => "Unified",
+                    };
+                    caches.push(Cache {
+                        level: cache.Level as u32,
+                        kind: kind.to_string(),
+                        cpus: expand(masks),
+                    });
+                }
+                relationship if relationship == RelationNumaNode => {
+                    let node = unsafe { &info.Anonymous.NumaNode };
+                    let masks = unsafe {
+                        std::slice::from_raw_parts(
+                            node.Anonymous.GroupMasks.as_ptr(),
+                            node.GroupCount.max(1) as usize,
+                        )
+                    };
+                    nodes.push((node.NodeNumber as usize, expand(masks)));
+                }
+                _ => {}
+            }
+            offset += info.Size as usize;
+        }
+
+        if cores.is_empty() {
+            return Self::fallback();
+        }
+
+        caches.sort_by(|a, b| (a.level, &a.kind).cmp(&(b.level, &b.kind)));
+
+        let mut cpus: Vec<Cpu> = Vec::new();
+        for (threads, class) in &cores {
+            for &id in threads {
+                let package = packages.iter().position(|package| package.contains(&id));
+                cpus.push(Cpu {
+                    id,
+                    package: package.unwrap_or(0),
+                    threads: threads.clone(),
+                    // Windows has no clusters, the package is the closest grouping
+                    cluster: package
+                        .map(|package| packages[package].clone())
+                        .unwrap_or_else(|| vec![id]),
+                    caches: caches
+                        .iter()
+                        .filter(|cache| cache.cpus.contains(&id))
+                        .cloned()
+                        .collect(),
+                    capacity: Some(*class as u32),
+                });
+            }
+        }
+        cpus.sort_by_key(|cpu| cpu.id);
+        nodes.sort_by_key(|(node, _)| *node);
+
+        Self {
+            cpus,
+            offline: Vec::new(),
+            nodes,
+            efficiency: None,
+        }
+    }
+
+    /// CPUs `0..n` with no shared caches known. SMT siblings are assumed to
+    /// be numbered next to each other, as Windows does.
+    fn fallback() -> Self {
+        let logical = num_cpus::get();
+        let smt = logical == num_cpus::get_physical() * 2;
+        let all: Vec<usize> = (0..logical).collect();
+
+        let cpus = (0..logical)
+            .map(|id| Cpu {
+                id,
+                package: 0,
+                threads: if smt { vec![id & !1, id | 1] } else { vec![id] },
+                cluster: all.clone(),
+                caches: Vec::new(),
+                capacity: None,
+            })
+            .collect();
+
+        Self {
+            cpus,
+            offline: Vec::new(),
+            nodes: vec![(0, all)],
+            efficiency: None,
+        }
+    }
+
+    /// Ids of all online logical CPUs.
+    pub fn logical_cpus(&self) -> Vec<usize> {
+        self.cpus.iter().map(|cpu| cpu.id).collect()
+    }
+
+    pub fn is_online(&self, cpu: usize) -> bool {
+        self.cpus.iter().any(|c| c.id == cpu)
+    }
+
+    /// Physical packages (sockets) with their CPUs.
+    pub fn packages(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut packages: Vec<(usize, Vec<usize>)> = Vec::new();
+        for cpu in &self.cpus {
+            match packages
+                .iter_mut()
+                .find(|(package, _)| *package == cpu.package)
+            {
+                Some((_, cpus)) => cpus.push(cpu.id),
+                None => packages.push((cpu.package, vec![cpu.id])),
+            }
+        }
+        packages.sort();
+        packages
+    }
+
+    /// Every distinct cache, by level and type.
+    pub fn caches(&self) -> Vec<&Cache> {
+        let mut caches: Vec<&Cache> = Vec::new();
+        for cache in self.cpus.iter().flat_map(|cpu| &cpu.caches) {
+            if !caches.contains(&cache) {
+                caches.push(cache);
+            }
+        }
+        caches.sort_by(|a, b| (a.level, &a.kind, &a.cpus).cmp(&(b.level, &b.kind, &b.cpus)));
+        caches
+    }
+
+    /// Logical CPUs grouped by physical core, so SMT siblings stay together.
+    pub fn physical_cores(&self) -> Vec<Vec<usize>> {
+        self.groups(|cpu| Some(cpu.threads.clone()))
+    }
+
+    /// Groups of CPUs sharing a last-level cache (e.g. one CCD each), ordered
+    /// by their first CPU. Falls back to clusters, then to a single group.
+    pub fn cache_domains(&self) -> Vec<Vec<usize>> {
+        let domains = self.groups(|cpu| {
+            cpu.caches
+                .iter()
+                .max_by_key(|cache| cache.level)
+                .map(|cache| cache.cpus.clone())
+        });
+        if !domains.is_empty() {
+            return domains;
+        }
+
+        let clusters = self.groups(|cpu| Some(cpu.cluster.clone()));
+        if clusters.len() > 1 {
+            return clusters;
+        }
+
+        vec![self.logical_cpus()]
+    }
+
+    /// CPUs sharing the first CPU's last-level cache.
+    pub fn primary_cache_domain(&self) -> Vec<usize> {
+        self.cache_domains().swap_remove(0)
+    }
+
+    /// The low-power cores of a hybrid CPU, or all cores when they are all alike.
+    pub fn efficiency_cores(&self) -> Vec<usize> {
+        if let Some(cpus) = &self.efficiency {
+            return cpus.clone();
+        }
+
+        // ARM big.LITTLE reports a relative capacity per core, Windows an
+        // efficiency class
+        let min = self.cpus.iter().filter_map(|cpu| cpu.capacity).min();
+        let max = self.cpus.iter().filter_map(|cpu| cpu.capacity).max();
+        if let (Some(min), Some(max)) = (min, max)
+            && min < max
+        {
+            return self
+                .cpus
+                .iter()
+                .filter(|cpu| cpu.capacity == Some(min))
+                .map(|cpu| cpu.id)
+                .collect();
+        }
+
+        self.logical_cpus()
+    }
+
+    /// Distinct groups returned by `group` for each CPU, limited to online
+    /// CPUs and sorted.
+    fn groups(&self, group: impl Fn(&Cpu) -> Option<Vec<usize>>) -> Vec<Vec<usize>> {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for cpu in &self.cpus {
+            let Some(mut members) = group(cpu) else {
+                continue;
+            };
+            members.retain(|&id| self.is_online(id));
+            if !members.is_empty() && !groups.contains(&members) {
+                groups.push(members);
+            }
+        }
+        groups.sort();
+        groups
+    }
+}